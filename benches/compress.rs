@@ -0,0 +1,222 @@
+//! Throughput of each compression method over representative inputs.
+//!
+//! Run with `cargo bench --bench compress [filter]`; criterion only measures the benchmarks whose
+//! id matches the filter. Every entry is written to `tokio::io::sink()`, so only the compression and
+//! the archive bookkeeping are timed.
+
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+use rill::{
+    archive::FileOptions,
+    compress::tokio::archive::ZipArchive,
+    compression::{CompressionMethod, Level},
+};
+
+const INPUT_SIZE: usize = 1 << 20;
+/// Number of entries of the small files case, where the per-entry setup dominates.
+const SMALL_FILES: usize = 50_000;
+/// Samples of each benchmark; the slow methods take up to a second per pass.
+const SAMPLE_SIZE: usize = 10;
+
+/// Natural-language text, highly compressible.
+fn text_input() -> Vec<u8> {
+    let sentence = b"The quick brown fox jumps over the lazy dog, then naps in the warm sun. ";
+    sentence.iter().copied().cycle().take(INPUT_SIZE).collect()
+}
+
+/// Structured binary data, little-endian counters.
+fn binary_input() -> Vec<u8> {
+    (0u32..)
+        .flat_map(|i| (i / 3).to_le_bytes())
+        .take(INPUT_SIZE)
+        .collect()
+}
+
+/// Pseudo-random bytes, standing for an already-compressed file.
+fn compressed_input() -> Vec<u8> {
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    (0..INPUT_SIZE)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+async fn compress_once(input: &[u8], options: &FileOptions) {
+    let mut archive = ZipArchive::new(tokio::io::sink());
+    let mut reader = input;
+    archive
+        .append_file("bench.bin", &mut reader, options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+}
+
+/// Same as `compress_once`, through `append_file_buf`: the input slice is handed to the encoder
+/// without the copy to a chunk buffer.
+async fn compress_once_buf(input: &[u8], options: &FileOptions) {
+    let mut archive = ZipArchive::new(tokio::io::sink());
+    let mut reader = input;
+    archive
+        .append_file_buf("bench.bin", &mut reader, options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+}
+
+/// Compress the file at `path`, read through a `BufReader` of `capacity` bytes handed to
+/// `append_file_buf`, or as a plain `File` handed to `append_file` if `capacity` is `None`.
+async fn compress_file_once(path: &Path, options: &FileOptions, capacity: Option<usize>) {
+    let mut archive = ZipArchive::new(tokio::io::sink());
+    let file = tokio::fs::File::open(path).await.unwrap();
+    match capacity {
+        Some(capacity) => {
+            let mut reader = tokio::io::BufReader::with_capacity(capacity, file);
+            archive
+                .append_file_buf("bench.bin", &mut reader, options)
+                .await
+                .unwrap();
+        }
+        None => {
+            let mut reader = file;
+            archive
+                .append_file("bench.bin", &mut reader, options)
+                .await
+                .unwrap();
+        }
+    }
+    archive.finalize().await.unwrap();
+}
+
+/// Append `SMALL_FILES` entries of a few hundred bytes each to a single archive.
+async fn compress_small_files(input: &[u8], options: &FileOptions) {
+    let mut archive = ZipArchive::new(tokio::io::sink());
+    for i in 0..SMALL_FILES {
+        let size = 100 + i % 400;
+        let mut reader = &input[i % 1024..i % 1024 + size];
+        archive
+            .append_file(&format!("file{}.txt", i), &mut reader, options)
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+}
+
+fn bench_methods(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let inputs = [
+        ("text", text_input()),
+        ("binary", binary_input()),
+        ("compressed", compressed_input()),
+    ];
+    let methods = [
+        CompressionMethod::Store(),
+        CompressionMethod::Deflate(),
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZip2(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Lzma(),
+        CompressionMethod::Zstd(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Xz(),
+    ];
+    let levels = [("default", Level::Default), ("best", Level::Best)];
+
+    let mut group = c.benchmark_group("method");
+    group.sample_size(SAMPLE_SIZE);
+    group.throughput(Throughput::Bytes(INPUT_SIZE as u64));
+    for method in methods {
+        for (level_label, level) in levels {
+            // Store has no level
+            if matches!(method, CompressionMethod::Store()) && level != Level::Default {
+                continue;
+            }
+
+            let options = FileOptions::default()
+                .compression_method(method)
+                .compression_level(level);
+            for (input_label, input) in &inputs {
+                let id = BenchmarkId::new(format!("{}/{}", method, level_label), input_label);
+                group.bench_with_input(id, input, |b, input| {
+                    b.to_async(&runtime).iter(|| compress_once(input, &options))
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+/// The copy saved by `append_file_buf` shows the most where compression is cheap.
+fn bench_buffered(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let input = text_input();
+
+    let mut group = c.benchmark_group("buffered");
+    group.sample_size(SAMPLE_SIZE);
+    group.throughput(Throughput::Bytes(INPUT_SIZE as u64));
+    for method in [CompressionMethod::Store(), CompressionMethod::Zstd()] {
+        let options = FileOptions::default().compression_method(method);
+        let id = BenchmarkId::new(method.label(), "text");
+        group.bench_with_input(id, &input, |b, input| {
+            b.to_async(&runtime)
+                .iter(|| compress_once_buf(input, &options))
+        });
+    }
+    group.finish();
+}
+
+/// A file source, read in chunks by `append_file` or through the buffer of a `BufReader`.
+fn bench_file(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let path = std::env::temp_dir().join(format!("rill-bench-{}.txt", std::process::id()));
+    std::fs::write(&path, text_input()).unwrap();
+
+    let mut group = c.benchmark_group("file");
+    group.sample_size(SAMPLE_SIZE);
+    group.throughput(Throughput::Bytes(INPUT_SIZE as u64));
+    for method in [CompressionMethod::Store(), CompressionMethod::Zstd()] {
+        let options = FileOptions::default().compression_method(method);
+        for (source_label, capacity) in [("file", None), ("file-buf", Some(64 * 1024))] {
+            let id = BenchmarkId::new(method.label(), source_label);
+            group.bench_with_input(id, &capacity, |b, &capacity| {
+                b.to_async(&runtime)
+                    .iter(|| compress_file_once(&path, &options, capacity))
+            });
+        }
+    }
+    group.finish();
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn bench_small_files(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let input = text_input();
+
+    let mut group = c.benchmark_group("small-files");
+    group.sample_size(SAMPLE_SIZE);
+    group.throughput(Throughput::Elements(SMALL_FILES as u64));
+    for method in [CompressionMethod::Deflate(), CompressionMethod::Zstd()] {
+        let options = FileOptions::default().compression_method(method);
+        group.bench_function(method.label(), |b| {
+            b.to_async(&runtime)
+                .iter(|| compress_small_files(&input, &options))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_methods,
+    bench_buffered,
+    bench_file,
+    bench_small_files
+);
+criterion_main!(benches);
@@ -0,0 +1,32 @@
+use rill::{
+    archive::FileOptions, compress::tokio::archive::ZipArchive, compression::CompressionMethod,
+};
+use tokio::{fs::File, io::AsyncWriteExt};
+
+/// Copy a file into an archive entry with `tokio::io::copy`, as between any reader and writer.
+///
+/// Usage: `cargo run --example copy [file]`, the archive is written to the temporary directory.
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "tests/resources/file1.txt".to_owned());
+    let archive_path = std::env::temp_dir().join("copy.zip");
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    let mut archive = ZipArchive::new(File::create(&archive_path).await?);
+
+    let mut entry = archive.start_entry("bigfile.bin", &options).await?;
+    let copied = tokio::io::copy(&mut File::open(&path).await?, &mut entry).await?;
+    // shutting the entry down writes its data descriptor, the archive file stays open
+    entry.shutdown().await?;
+
+    archive.finalize().await?;
+    println!(
+        "{} bytes copied to {}, {} bytes",
+        copied,
+        archive_path.display(),
+        archive.get_archive_size()
+    );
+    Ok(())
+}
@@ -7,6 +7,8 @@ use tokio::fs::File;
 
 #[tokio::main]
 async fn main() {
+    // The file is passed as is: the OS buffers file writes itself. Network sinks are better
+    // wrapped in a `BufWriter`, see `ZipArchive::with_buffer_size`.
     let file = File::create("archive.zip").await.unwrap();
 
     let options = FileOptions::default()
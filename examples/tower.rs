@@ -0,0 +1,40 @@
+use rill::archive::FileOptions;
+use rill::compress::tokio::archive::ZipArchive;
+use rill::compress::tokio::service::{ZipArchiveService, ZipRequest};
+use rill::compression::CompressionMethod;
+use rill::error::ArchiveError;
+use std::time::{Duration, Instant};
+use tower::{Service, ServiceBuilder, ServiceExt};
+
+/// Write an archive through a rate limit of two entries per 100 ms.
+#[tokio::main]
+async fn main() -> Result<(), ArchiveError> {
+    let file = tokio::fs::File::create("archive_tower.zip").await?;
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+
+    let mut service = ServiceBuilder::new()
+        .rate_limit(2, Duration::from_millis(100))
+        .service(ZipArchiveService::new(ZipArchive::new(file)));
+
+    let start = Instant::now();
+    for i in 0..6 {
+        let request = ZipRequest::new(
+            &format!("file{}.txt", i),
+            format!("content of entry {}\n", i),
+            options.clone(),
+        );
+        service.ready().await?.call(request).await?;
+        println!("file{}.txt appended after {:?}", i, start.elapsed());
+    }
+
+    let mut archive = service
+        .into_inner()
+        .into_archive()
+        .expect("every call is complete");
+    archive.finalize().await?;
+    println!(
+        "archive_tower.zip written, {} bytes",
+        archive.get_archive_size()
+    );
+    Ok(())
+}
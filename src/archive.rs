@@ -1,18 +1,102 @@
+use tokio::sync::mpsc::Sender;
+
 use crate::{
     compression::{CompressionMethod, Level},
+    constants::{SHA256_MANIFEST_NAME, VERSION_MADE_BY},
+    error::ArchiveError,
     types::FileDateTime,
 };
 
-/// Metadata for a file to be written
+/// Mask of the file type bits of a Unix mode.
+const S_IFMT: u32 = 0o170000;
+/// File type bits of a regular file.
+const S_IFREG: u32 = 0o100000;
+
+/// Statistics of an entry, reported once its payload is written. See
+/// `FileOptions::with_stats_callback`.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone)]
+pub struct EntryStats {
+    pub name: String,
+    pub uncompressed: u64,
+    pub compressed: u64,
+    /// Time taken to read and compress the payload.
+    pub duration: std::time::Duration,
+    pub method: CompressionMethod,
+}
+
+/// Callback receiving the statistics of an entry.
+#[cfg(feature = "stats")]
 #[derive(Clone)]
+pub struct StatsCallback(pub std::sync::Arc<dyn Fn(EntryStats) + Send + Sync>);
+
+#[cfg(feature = "stats")]
+impl std::fmt::Debug for StatsCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StatsCallback")
+    }
+}
+
+/// Metadata for a file to be written
+///
+/// Cloning copies every option; the callbacks and the progress sink are shared handles. Entries
+/// are not encrypted, so the options hold no key material: encryption keys, once supported,
+/// should be kept in a zeroized-on-drop type whose cloning is explicit rather than derived.
+#[derive(Debug, Clone)]
 pub struct FileOptions {
     pub compressor: CompressionMethod,
     pub compression_level: Level,
     pub last_modified_time: FileDateTime,
     pub permissions: Option<u32>,
+    pub unix_mode: Option<u32>,
+    pub external_attributes: Option<u32>,
+    pub unix_mtime: Option<u32>,
+    pub unix_atime: Option<u32>,
+    pub unix_ctime: Option<u32>,
+    pub progress_sink: Option<Sender<u8>>,
+    pub expected_ratio: f64,
+    #[cfg(feature = "stats")]
+    pub stats_callback: Option<StatsCallback>,
 }
 
 impl FileOptions {
+    /// Default options with the compression method suited to the MIME type of the file, see
+    /// `CompressionMethod::for_mime_type`.
+    pub fn from_mimetype(mime: &str) -> FileOptions {
+        FileOptions::default().compression_method(CompressionMethod::for_mime_type(mime))
+    }
+
+    /// The compression method of the new file
+    #[inline]
+    pub fn compressor(&self) -> CompressionMethod {
+        self.compressor
+    }
+
+    /// The compression method the new file is written with: `Store` for `Level::None`, see
+    /// `CompressionMethod::effective_method`
+    #[inline]
+    pub fn effective_compressor(&self) -> CompressionMethod {
+        self.compressor.effective_method(self.compression_level)
+    }
+
+    /// The compression level of the new file
+    #[inline]
+    pub fn level(&self) -> Level {
+        self.compression_level
+    }
+
+    /// The last modified time of the new file
+    #[inline]
+    pub fn modified_time(&self) -> &FileDateTime {
+        &self.last_modified_time
+    }
+
+    /// The Unix permissions of the new file, if set
+    #[inline]
+    pub fn permissions(&self) -> Option<u32> {
+        self.permissions
+    }
+
     /// Set the compression method for the new file
     ///
     /// The default is `CompressionMethod::Deflated`. If the deflate compression feature is
@@ -29,13 +113,32 @@ impl FileOptions {
     /// Range of values depends on compression method:
     /// * `Deflated`: 0 - 9. Default is 6
     /// * `Bzip2`: 0 - 9. Default is 6
+    /// * `Lzma`, `Xz`: 0 - 9. Default is 6
     /// * `Zstd`: -7 - 22, with zero being mapped to default level. Default is 3
-    /// * others: only `None` is allowed
+    /// * others: only `Default` and `None` are allowed
+    ///
+    /// `None` stores the file whatever the compression method: the entry is recorded with
+    /// `Store`.
+    ///
+    /// The combination with the compression method is checked by `validate`, which every append
+    /// function calls before reading the entry.
     pub fn compression_level(mut self, level: Level) -> FileOptions {
         self.compression_level = level;
         self
     }
 
+    /// Check that the compression method can be written and that the compression level is valid for it.
+    ///
+    /// # Error
+    ///
+    /// * `ArchiveError::UnsuportedCompressionMethod` for a legacy or `Unknown` method.
+    /// * `ArchiveError::UnsuportedCompressionLevel` if the level is out of the method's range, e.g.
+    ///   any level other than `Default` or `None` on `Store`.
+    pub fn validate(&self) -> Result<(), ArchiveError> {
+        self.compressor.check_writable()?;
+        self.compressor.check_level(self.compression_level)
+    }
+
     /// Set the last modified time
     ///
     /// The default is the current timestamp if the 'time' feature is enabled, and 1980-01-01
@@ -53,11 +156,109 @@ impl FileOptions {
     ///
     /// This method only preserves the file permissions bits (via a `& 0o777`) and discards
     /// higher file mode bits. So it cannot be used to denote an entry as a directory,
-    /// symlink, or other special file type.
+    /// symlink, or other special file type; see `unix_mode` for that.
     pub fn unix_permissions(mut self, mode: u32) -> FileOptions {
         self.permissions = Some(mode & 0o777);
         self
     }
+
+    /// Set the full Unix mode of the new file, file type bits included.
+    ///
+    /// Unlike `unix_permissions`, the type bits (`mode & 0o170000`) are kept, so special files
+    /// can be recorded faithfully, e.g. a FIFO (`0o010644`) or a character device (`0o020600`).
+    /// Such entries are expected to have a zero-length payload, e.g. appended from
+    /// `tokio::io::empty()`; whether they are recreated on extraction depends on the extractor.
+    /// Without type bits, the mode denotes a regular file. Takes precedence over
+    /// `unix_permissions`.
+    pub fn unix_mode(mut self, mode: u32) -> FileOptions {
+        self.unix_mode = Some(mode & 0o177777);
+        self
+    }
+
+    /// Set the raw 32 bits external file attributes of the central directory entry.
+    ///
+    /// The value is written verbatim, bypassing the Unix mode derived from `unix_mode` and
+    /// `unix_permissions`, e.g. to reproduce the bytes of another producer, such as MS-DOS
+    /// attributes in the low byte. Whether it makes sense for the host set by
+    /// `ArchiveOptions::version_made_by` is up to the caller.
+    pub fn external_attributes(mut self, attributes: u32) -> FileOptions {
+        self.external_attributes = Some(attributes);
+        self
+    }
+
+    /// External file attributes of the entry: the Unix mode in the upper 16 bits, unless set
+    /// by `external_attributes`.
+    pub(crate) fn external_file_attributes(&self) -> u32 {
+        if let Some(attributes) = self.external_attributes {
+            return attributes;
+        }
+        let mode = match self.unix_mode {
+            Some(mode) if mode & S_IFMT != 0 => mode,
+            Some(mode) => S_IFREG | mode,
+            None => S_IFREG | self.permissions.unwrap_or(0o644),
+        };
+        mode << 16
+    }
+
+    /// Set the last modified time to the modification time of a file, keeping the configured one
+    /// if the platform does not record it.
+    pub fn last_modified_time_from_metadata(self, metadata: &std::fs::Metadata) -> FileOptions {
+        match metadata.modified() {
+            Ok(modified) => self.last_modified_time(FileDateTime::from_system_time(modified)),
+            Err(_) => self,
+        }
+    }
+
+    /// Set the Unix modification, access and creation times, in seconds since the Unix epoch.
+    ///
+    /// The timestamps are written in an extended timestamp extra field (0x5455). Only the
+    /// values that are `Some` are included; if all three are `None`, no extra field is written.
+    /// The central directory only carries the modification time, as specified.
+    pub fn with_unix_timestamps(
+        mut self,
+        mtime: Option<u32>,
+        atime: Option<u32>,
+        ctime: Option<u32>,
+    ) -> FileOptions {
+        self.unix_mtime = mtime;
+        self.unix_atime = atime;
+        self.unix_ctime = ctime;
+        self
+    }
+
+    /// Set a channel receiving the progress of the entry, as a percentage (0..=100).
+    ///
+    /// Updates are sent every 5% of the estimated compressed size, and a final 100 once the
+    /// entry is written. Progress is only reported when the reader size is known, i.e. by
+    /// `append_file_with_progress`.
+    pub fn with_progress_sink(mut self, sender: Sender<u8>) -> FileOptions {
+        self.progress_sink = Some(sender);
+        self
+    }
+
+    /// Set the expected compression ratio (uncompressed size / compressed size) used to
+    /// estimate the progress.
+    ///
+    /// The default is `1.0`.
+    pub fn expected_ratio(mut self, ratio: f64) -> FileOptions {
+        self.expected_ratio = ratio;
+        self
+    }
+
+    /// Set a callback receiving the statistics of the entry: sizes, method and time taken.
+    ///
+    /// The callback is invoked once the payload is written, before the data descriptor or the
+    /// patched local header, from the task appending the entry; it should return quickly.
+    ///
+    /// Requires the `stats` feature.
+    #[cfg(feature = "stats")]
+    pub fn with_stats_callback(
+        mut self,
+        callback: std::sync::Arc<dyn Fn(EntryStats) + Send + Sync>,
+    ) -> FileOptions {
+        self.stats_callback = Some(StatsCallback(callback));
+        self
+    }
 }
 
 impl Default for FileOptions {
@@ -68,6 +269,279 @@ impl Default for FileOptions {
             compression_level: Level::Default,
             last_modified_time: FileDateTime::default(),
             permissions: None,
+            unix_mode: None,
+            external_attributes: None,
+            unix_mtime: None,
+            unix_atime: None,
+            unix_ctime: None,
+            progress_sink: None,
+            expected_ratio: 1.0,
+            #[cfg(feature = "stats")]
+            stats_callback: None,
+        }
+    }
+}
+
+/// Host system recorded in the upper byte of the "version made by" field.
+///
+/// Extractors use it to interpret the external file attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OsMadeBy {
+    MsDos,
+    Unix,
+    Windows,
+    MacOsx,
+    Custom(u8),
+}
+
+impl OsMadeBy {
+    pub fn code(&self) -> u8 {
+        match self {
+            OsMadeBy::MsDos => 0,
+            OsMadeBy::Unix => 3,
+            OsMadeBy::Windows => 10,
+            OsMadeBy::MacOsx => 19,
+            OsMadeBy::Custom(code) => *code,
+        }
+    }
+}
+
+/// Settings applying to the whole archive
+#[derive(Debug, Clone)]
+pub struct ArchiveOptions {
+    pub version_made_by: u16,
+    pub max_concurrent_compressions: usize,
+    pub sha256_manifest: bool,
+    pub sha256_manifest_name: String,
+    pub sha256_manifest_options: FileOptions,
+    pub flush_every: Option<u64>,
+    pub zip64: bool,
+    pub strict_entry_names: bool,
+    pub allow_empty_entry_names: bool,
+    pub spanning_marker: bool,
+    pub strict: bool,
+}
+
+impl ArchiveOptions {
+    /// Set the "version made by" field written for every entry of the central directory
+    ///
+    /// The default is `OsMadeBy::Unix` with version 4.6 (`46`).
+    pub fn version_made_by(mut self, os: OsMadeBy, version: u8) -> ArchiveOptions {
+        self.version_made_by = (os.code() as u16) << 8 | version as u16;
+        self
+    }
+
+    /// Set the maximum number of entries compressed at the same time by `append_file_concurrent`
+    ///
+    /// The default is the available parallelism of the host.
+    pub fn max_concurrent_compressions(mut self, max: usize) -> ArchiveOptions {
+        self.max_concurrent_compressions = max;
+        self
+    }
+
+    /// Hash every entry with SHA-256 and append a `MANIFEST.sha256` entry on finalize
+    ///
+    /// The manifest lists one `"<hex>  <name>"` line per entry, as `sha256sum` does. Its name
+    /// and compression are set with `sha256_manifest_entry`.
+    /// The hash is computed in the same pass as the CRC.
+    #[cfg(feature = "sha2")]
+    pub fn sha256_manifest(mut self, enabled: bool) -> ArchiveOptions {
+        self.sha256_manifest = enabled;
+        self
+    }
+
+    /// Set the name and the options of the entry written by `sha256_manifest`
+    ///
+    /// The default is `MANIFEST.sha256` with `FileOptions::default()`.
+    #[cfg(feature = "sha2")]
+    pub fn sha256_manifest_entry(mut self, name: &str, options: FileOptions) -> ArchiveOptions {
+        self.sha256_manifest_name = name.to_owned();
+        self.sha256_manifest_options = options;
+        self
+    }
+
+    /// Flush the sink of the streaming archives each time `bytes` have been written since the last flush
+    ///
+    /// Meant for buffered network sinks, to bound the latency without paying a syscall per write.
+    /// By default the sink is only flushed on finalize.
+    pub fn flush_every(mut self, bytes: u64) -> ArchiveOptions {
+        self.flush_every = Some(bytes);
+        self
+    }
+
+    /// Allow the archive to grow past the 4 GiB and 65535 entries limits with the ZIP64 extensions
+    ///
+    /// The streaming archives then write every entry with a ZIP64 local extra field and 8-byte sizes
+    /// in the data descriptor, as the entry size is not known upfront. The central directory uses
+    /// ZIP64 records only for the values that overflow. The seekable archives still limit an entry
+    /// to 4 GiB, its sizes being patched in the 32 bits fields of the local header.
+    ///
+    /// By default ZIP64 is disabled and overflowing values fail with `ArchiveError::Zip64Required`.
+    pub fn zip64(mut self, enabled: bool) -> ArchiveOptions {
+        self.zip64 = enabled;
+        self
+    }
+
+    /// Reject entry names containing a backslash instead of converting it to a forward slash
+    ///
+    /// ZIP only knows `/` as a path separator, so by default a Windows-style name such as
+    /// `dir\file.txt` is written as `dir/file.txt`. In strict mode, such a name fails with
+    /// `ArchiveError::InvalidEntryName`.
+    pub fn strict_entry_names(mut self, enabled: bool) -> ArchiveOptions {
+        self.strict_entry_names = enabled;
+        self
+    }
+
+    /// Accept empty entry names and names made only of `/`
+    ///
+    /// Such an entry has no path to be extracted to, so many tools reject the archive. By default
+    /// it fails with `ArchiveError::EmptyEntryName`.
+    pub fn allow_empty_entry_names(mut self, enabled: bool) -> ArchiveOptions {
+        self.allow_empty_entry_names = enabled;
+        self
+    }
+
+    /// Start the archive with the `PK00` temporary spanning signature, as some spanning-capable
+    /// writers do for an archive that ended up in a single part
+    ///
+    /// The marker takes the first 4 bytes, the entry offsets count it. It is written before the
+    /// first entry, or before the central directory of an empty archive. Disabled by default.
+    pub fn spanning_marker(mut self, enabled: bool) -> ArchiveOptions {
+        self.spanning_marker = enabled;
+        self
+    }
+
+    /// Check the archive against the ZIP specification (APPNOTE) on finalize, before the central
+    /// directory is written
+    ///
+    /// Every entry is checked: version needed to extract against its compression method, name
+    /// and extra field lengths against their 16 bits fields and their content, sizes against
+    /// their fields, and local header offsets. The first inconsistency fails the finalize with
+    /// `ArchiveError::SpecViolation`, nothing of the central directory being written. Disabled by
+    /// default, as the checks walk every entry.
+    pub fn strict(mut self, enabled: bool) -> ArchiveOptions {
+        self.strict = enabled;
+        self
+    }
+}
+
+impl Default for ArchiveOptions {
+    /// Construct a new ArchiveOptions object
+    fn default() -> Self {
+        Self {
+            version_made_by: VERSION_MADE_BY,
+            max_concurrent_compressions: std::thread::available_parallelism()
+                .map_or(1, |parallelism| parallelism.get()),
+            sha256_manifest: false,
+            sha256_manifest_name: SHA256_MANIFEST_NAME.to_owned(),
+            sha256_manifest_options: FileOptions::default(),
+            flush_every: None,
+            zip64: false,
+            strict_entry_names: false,
+            allow_empty_entry_names: false,
+            spanning_marker: false,
+            strict: false,
+        }
+    }
+}
+
+/// Entry planned to be appended, for `ZipArchive::estimated_size`
+#[derive(Clone, Copy)]
+pub struct PlannedEntry<'a> {
+    pub name: &'a str,
+    pub size: u64,
+    pub options: &'a FileOptions,
+}
+
+impl<'a> PlannedEntry<'a> {
+    pub fn new(name: &'a str, size: u64, options: &'a FileOptions) -> PlannedEntry<'a> {
+        Self {
+            name,
+            size,
+            options,
+        }
+    }
+}
+
+/// Digest listed for every entry by `ZipArchive::add_manifest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// The CRC-32 already stored for every entry
+    Crc32,
+    /// Requires the `sha2` feature. A streaming archive also requires
+    /// `ArchiveOptions::sha256_manifest`, the SHA-256 being computed while the entry is written; a
+    /// seekable archive reads the entries it did not hash back from the sink.
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Name of the algorithm in the `<name>-Digest` attribute of a JAR manifest
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Crc32 => "CRC-32",
+            HashAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+}
+
+/// Layout of the entry listing printed by `ZipArchive::list_with_options`
+#[derive(Debug, Clone)]
+pub struct ListOptions {
+    pub width: usize,
+}
+
+impl ListOptions {
+    /// Set the width, in characters, the listing is padded to
+    ///
+    /// The default is `80`, a standard terminal width.
+    pub fn width(mut self, width: usize) -> ListOptions {
+        self.width = width;
+        self
+    }
+}
+
+impl Default for ListOptions {
+    /// Construct a new ListOptions object
+    fn default() -> Self {
+        Self { width: 80 }
+    }
+}
+
+/// Limits applied while extracting untrusted archives, see `extract_entry_to_vec_with_options`
+///
+/// Both limits are checked as the output grows, so a decompression bomb is aborted before it is
+/// inflated entirely.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    pub max_ratio: Option<u64>,
+    pub max_output: Option<u64>,
+}
+
+impl ExtractOptions {
+    /// Set the maximum size of an extracted entry, as a multiple of its compressed size
+    ///
+    /// The default is no limit.
+    pub fn max_ratio(mut self, ratio: u64) -> ExtractOptions {
+        self.max_ratio = Some(ratio);
+        self
+    }
+
+    /// Set the maximum size, in bytes, of an extracted entry
+    ///
+    /// The default is no limit.
+    pub fn max_output(mut self, size: u64) -> ExtractOptions {
+        self.max_output = Some(size);
+        self
+    }
+
+    /// Largest output allowed for an entry of `compressed_size` bytes, if any.
+    pub(crate) fn output_limit(&self, compressed_size: u64) -> Option<u64> {
+        let ratio_limit = self
+            .max_ratio
+            .map(|ratio| ratio.saturating_mul(compressed_size));
+        match (ratio_limit, self.max_output) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (limit, None) | (None, limit) => limit,
         }
     }
 }
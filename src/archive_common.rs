@@ -1,27 +1,47 @@
 #![allow(dead_code)]
+use std::borrow::Cow;
 use std::str;
-use std::u32;
-use std::u8;
 
-use super::compression::CompressionMethod;
-use crate::archive::FileOptions;
+use super::compression::{CompressionMethod, Level};
+use crate::archive::{
+    ArchiveOptions, FileOptions, HashAlgorithm, ListOptions, OsMadeBy, PlannedEntry,
+};
 
 use crate::constants::CENTRAL_DIRECTORY_END_SIGNATURE;
+use crate::constants::CENTRAL_DIRECTORY_ENTRY_BASE_SIZE;
 use crate::constants::CENTRAL_DIRECTORY_ENTRY_SIGNATURE;
+use crate::constants::DIGITAL_SIGNATURE_SIGNATURE;
 use crate::constants::END_OF_CENTRAL_DIRECTORY_SIZE;
+use crate::constants::EXTENDED_TIMESTAMP_EXTRA_FIELD_ID;
 use crate::constants::FILE_HEADER_BASE_SIZE;
+use crate::constants::GROWTH_HINT_EXTRA_FIELD_ID;
+use crate::constants::GROWTH_HINT_MIN_SIZE;
+use crate::constants::GROWTH_HINT_SIGNATURE;
 use crate::constants::LOCAL_FILE_HEADER_SIGNATURE;
+use crate::constants::TEMPORARY_SPANNING_SIGNATURE;
 use crate::constants::VERSION_MADE_BY;
+use crate::constants::ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE;
+use crate::constants::ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIZE;
+use crate::constants::ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE;
+use crate::constants::ZIP64_CENTRAL_DIRECTORY_END_SIZE;
+use crate::constants::ZIP64_DESCRIPTOR_SIZE;
+use crate::constants::ZIP64_EXTRA_FIELD_ID;
+use crate::constants::ZIP64_VERSION_NEEDED;
+use crate::constants::{DATA_DESCRIPTOR_SIGNATURE, DESCRIPTOR_SIZE};
+use crate::crc::Crc32Hasher;
 use crate::error::ArchiveError;
-use crate::types::ArchiveFileEntry;
+use crate::types::{ArchiveFileEntry, DateTimeCS};
+#[cfg(feature = "sha2")]
+use sha2::{Digest, Sha256};
 
 pub trait ZipArchiveCommon {
     fn get_archive_size(&self) -> u64;
     fn get_data(&self) -> &SubZipArchiveData;
     fn get_mut_data(&mut self) -> &mut SubZipArchiveData;
 
-    fn set_archive_comment(&mut self, comment: &str) {
-        self.get_mut_data().set_archive_comment(comment);
+    fn set_version_made_by(&mut self, os: OsMadeBy, version: u8) {
+        let options = self.get_data().options.clone();
+        self.get_mut_data().options = options.version_made_by(os, version);
     }
 }
 
@@ -29,8 +49,9 @@ pub fn build_file_header(
     file_name: &str,
     options: &FileOptions,
     compressor: CompressionMethod,
-    offset: u32,
+    offset: u64,
     data_descriptor: bool,
+    zip64: bool,
 ) -> (ArchiveDescriptor, ArchiveFileEntry) {
     let file_nameas_bytes = file_name.as_bytes();
     let file_name_as_bytes_own = file_nameas_bytes.to_owned();
@@ -38,7 +59,9 @@ pub fn build_file_header(
 
     let (date, time) = options.last_modified_time.ms_dos();
     let mut general_purpose_flags: u16 = 0;
-    if file_name_as_bytes_own.len() > file_name.len() {
+    // A `&str` is always UTF-8: the flag is only needed when the name holds a byte >= 0x80, ASCII
+    // names read the same with or without it.
+    if !file_name.is_ascii() {
         general_purpose_flags |= 1 << 11; //set utf8 flag
     }
 
@@ -49,9 +72,28 @@ pub fn build_file_header(
         general_purpose_flags |= 1 << 3; //create a data descriptor
     }
 
-    let version_needed = compressor.zip_version_needed();
+    let (mut local_extra_field, central_extra_field) = build_extended_timestamp(options);
+
+    // sizes are unknown yet: the extra field holds placeholders, the data descriptor the 8-byte values
+    let (version_needed, placeholder_size) = if zip64 {
+        let mut zip64_extra_field = ArchiveDescriptor::new(20);
+        zip64_extra_field.write_u16(ZIP64_EXTRA_FIELD_ID);
+        zip64_extra_field.write_u16(16);
+        zip64_extra_field.write_u64(0); // Uncompressed size.
+        zip64_extra_field.write_u64(0); // Compressed size.
+        local_extra_field.extend_from_slice(zip64_extra_field.buffer());
+
+        (
+            compressor.zip_version_needed().max(ZIP64_VERSION_NEEDED),
+            u32::MAX,
+        )
+    } else {
+        (compressor.zip_version_needed(), 0)
+    };
     let compression_method = compressor.zip_code();
-    let mut file_header = ArchiveDescriptor::new(FILE_HEADER_BASE_SIZE + file_name_len as u64);
+    let file_header_size =
+        FILE_HEADER_BASE_SIZE + file_name_len as u64 + local_extra_field.len() as u64;
+    let mut file_header = ArchiveDescriptor::new(file_header_size);
     file_header.write_u32(LOCAL_FILE_HEADER_SIGNATURE);
     file_header.write_u16(version_needed);
     file_header.write_u16(general_purpose_flags);
@@ -59,11 +101,13 @@ pub fn build_file_header(
     file_header.write_u16(time);
     file_header.write_u16(date);
     file_header.write_u32(0);
-    file_header.write_u32(0);
-    file_header.write_u32(0);
+    file_header.write_u32(placeholder_size);
+    file_header.write_u32(placeholder_size);
     file_header.write_u16(file_name_len);
-    file_header.write_u16(0);
+    file_header.write_u16(local_extra_field.len() as u16);
     file_header.write_bytes(&file_name_as_bytes_own);
+    file_header.write_bytes(&local_extra_field);
+    debug_assert_eq!(file_header.buffer().len() as u64, file_header_size);
 
     let archive_file_entry = ArchiveFileEntry {
         version_made_by: VERSION_MADE_BY,
@@ -73,15 +117,19 @@ pub fn build_file_header(
         last_mod_file_time: time,
         last_mod_file_date: date,
         crc32: 0,
+        sha256: None,
         compressed_size: 0,
         uncompressed_size: 0,
         file_name_len,
-        extra_field_length: 0,
+        extra_field_length: central_extra_field.len() as u16,
         file_name_as_bytes: file_name.as_bytes().to_owned(),
+        extra_field: central_extra_field,
         offset,
+        zip64,
         compressor,
+        compression_level: compressor.effective_level(options.compression_level),
         internal_file_attributes: 0,
-        external_file_attributes: 0,
+        external_file_attributes: options.external_file_attributes(),
         file_comment_length: 0,
         file_disk_number: 0,
     };
@@ -89,46 +137,672 @@ pub fn build_file_header(
     (file_header, archive_file_entry)
 }
 
+/// Build the extended timestamp extra field (0x5455) for the local header and the central directory.
+///
+/// The local version holds every timestamp set, the central version only the modification time.
+fn build_extended_timestamp(options: &FileOptions) -> (Vec<u8>, Vec<u8>) {
+    let timestamps = [options.unix_mtime, options.unix_atime, options.unix_ctime];
+
+    let mut flags: u8 = 0;
+    for (bit, timestamp) in timestamps.iter().enumerate() {
+        if timestamp.is_some() {
+            flags |= 1 << bit;
+        }
+    }
+
+    if flags == 0 {
+        return (Vec::new(), Vec::new());
+    }
+
+    let local_times: Vec<u32> = timestamps.iter().flatten().copied().collect();
+    let mut local = ArchiveDescriptor::new(5 + 4 * local_times.len() as u64);
+    local.write_u16(EXTENDED_TIMESTAMP_EXTRA_FIELD_ID);
+    local.write_u16(1 + 4 * local_times.len() as u16);
+    local.write_bytes(&[flags]);
+    for time in local_times {
+        local.write_u32(time);
+    }
+
+    let mut central = ArchiveDescriptor::new(9);
+    central.write_u16(EXTENDED_TIMESTAMP_EXTRA_FIELD_ID);
+    match options.unix_mtime {
+        Some(mtime) => {
+            central.write_u16(5);
+            central.write_bytes(&[flags]);
+            central.write_u32(mtime);
+        }
+        None => {
+            central.write_u16(1);
+            central.write_bytes(&[flags]);
+        }
+    }
+
+    (local.finish(), central.finish())
+}
+
+/// Exact size the archive will have once the planned entries are appended and it is finalized.
+///
+/// `archive_size` is the number of bytes written so far, `data_descriptor` whether the writer follows
+/// each entry with a data descriptor. Only stored entries have a predictable size, hence `None` as
+/// soon as a planned entry is compressed, as well as when the SHA-256 manifest is enabled (it is
+/// deflated) or an entry would fail to be appended.
+pub fn estimate_archive_size(
+    data: &SubZipArchiveData,
+    archive_size: u64,
+    data_descriptor: bool,
+    planned_entries: &[PlannedEntry],
+) -> Option<u64> {
+    if data.options.sha256_manifest {
+        return None;
+    }
+
+    let mut data = data.clone();
+    let mut archive_size = archive_size;
+    if let Some(marker) = spanning_marker(&data) {
+        archive_size += marker.len() as u64;
+        data.spanning_marker_written = true;
+    }
+    for planned_entry in planned_entries {
+        let options = planned_entry.options;
+        if !matches!(options.effective_compressor(), CompressionMethod::Store())
+            || options.validate().is_err()
+        {
+            return None;
+        }
+
+        let file_name = normalize_entry_name(&data.options, planned_entry.name).ok()?;
+        let (file_header, mut entry) = build_file_header(
+            &file_name,
+            options,
+            options.effective_compressor(),
+            check_header_offset(&data.options, archive_size).ok()?,
+            data_descriptor,
+            data_descriptor && data.options.zip64,
+        );
+        entry.compressed_size = planned_entry.size;
+        entry.uncompressed_size = planned_entry.size;
+        check_zip32_sizes(&entry).ok()?;
+
+        archive_size += file_header.buffer().len() as u64 + planned_entry.size;
+        if data_descriptor {
+            archive_size += build_data_descriptor(&entry).buffer().len() as u64;
+        }
+        data.files_info.push(entry);
+    }
+
+    let central_directory_size = data
+        .files_info
+        .iter()
+        .map(central_directory_entry_size)
+        .sum();
+    let end_of_central_directory =
+        build_central_directory_end(&data, archive_size, central_directory_size).ok()?;
+
+    Some(archive_size + central_directory_size + end_of_central_directory.buffer().len() as u64)
+}
+
+/// Build the whole central directory in a buffer of its exact size, so it is written at once.
+pub fn build_central_directory(data: &SubZipArchiveData) -> ArchiveDescriptor {
+    let size = data
+        .files_info
+        .iter()
+        .map(central_directory_entry_size)
+        .sum();
+
+    let mut central_directory = ArchiveDescriptor::new(size);
+    for file_info in &data.files_info {
+        build_central_directory_file_header(file_info, &data.options, &mut central_directory);
+    }
+    debug_assert_eq!(central_directory.buffer().len() as u64, size);
+    central_directory
+}
+
+/// Build the digital signature record, closing the central directory, holding `signature`.
+pub fn build_digital_signature(signature: &[u8]) -> ArchiveDescriptor {
+    let mut record = ArchiveDescriptor::new(6 + signature.len() as u64);
+    record.write_u32(DIGITAL_SIGNATURE_SIGNATURE);
+    record.write_u16(signature.len() as u16);
+    record.write_bytes(signature);
+    record
+}
+
+/// Values of an entry overflowing their 32 bits field, which go in the ZIP64 extra field, in this order.
+fn zip64_values(file_info: &ArchiveFileEntry) -> Vec<u64> {
+    [
+        file_info.uncompressed_size,
+        file_info.compressed_size,
+        file_info.offset,
+    ]
+    .into_iter()
+    .filter(|value| *value >= u32::MAX as u64)
+    .collect()
+}
+
+/// Size of the central directory header of an entry.
+pub fn central_directory_entry_size(file_info: &ArchiveFileEntry) -> u64 {
+    let zip64_values = zip64_values(file_info).len() as u64;
+    let zip64_extra_field_size = if zip64_values > 0 {
+        4 + 8 * zip64_values
+    } else {
+        0
+    };
+
+    CENTRAL_DIRECTORY_ENTRY_BASE_SIZE
+        + file_info.file_name_as_bytes.len() as u64
+        + file_info.extra_field.len() as u64
+        + zip64_extra_field_size
+}
+
 pub fn build_central_directory_file_header(
-    central_directory_header: &mut ArchiveDescriptor,
     file_info: &ArchiveFileEntry,
+    options: &ArchiveOptions,
+    central_directory_header: &mut ArchiveDescriptor,
 ) {
+    let zip32_value = |value: u64| u32::try_from(value).unwrap_or(u32::MAX);
+    let uncompressed_size = zip32_value(file_info.uncompressed_size);
+    let compressed_size = zip32_value(file_info.compressed_size);
+    let offset = zip32_value(file_info.offset);
+    let zip64_values = zip64_values(file_info);
+
+    let mut extra_field = file_info.extra_field.clone();
+    if !zip64_values.is_empty() {
+        let mut zip64_extra_field = ArchiveDescriptor::new(4 + 8 * zip64_values.len() as u64);
+        zip64_extra_field.write_u16(ZIP64_EXTRA_FIELD_ID);
+        zip64_extra_field.write_u16(8 * zip64_values.len() as u16);
+        for value in zip64_values {
+            zip64_extra_field.write_u64(value);
+        }
+        extra_field.extend_from_slice(zip64_extra_field.buffer());
+    }
+
     central_directory_header.write_u32(CENTRAL_DIRECTORY_ENTRY_SIGNATURE); // Central directory entry signature.
-    central_directory_header.write_u16(file_info.version_made_by()); // Version made by.
+    central_directory_header.write_u16(options.version_made_by); // Version made by.
     central_directory_header.write_u16(file_info.version_needed()); // Version needed to extract.
     central_directory_header.write_u16(file_info.general_purpose_flags); // General purpose flag (temporary crc and sizes + UTF-8 filename).
     central_directory_header.write_u16(file_info.compression_method); // Compression method .
     central_directory_header.write_u16(file_info.last_mod_file_time); // Modification time.
     central_directory_header.write_u16(file_info.last_mod_file_date); // Modification date.
     central_directory_header.write_u32(file_info.crc32); // CRC32.
-    central_directory_header.write_u32(file_info.compressed_size as u32); // Compressed size.
-    central_directory_header.write_u32(file_info.uncompressed_size as u32); // Uncompressed size.
+    central_directory_header.write_u32(compressed_size); // Compressed size.
+    central_directory_header.write_u32(uncompressed_size); // Uncompressed size.
     central_directory_header.write_u16(file_info.file_name_len); // Filename length.
-    central_directory_header.write_u16(0u16); // Extra field length.
+    central_directory_header.write_u16(extra_field.len() as u16); // Extra field length.
     central_directory_header.write_u16(0u16); // File comment length.
     central_directory_header.write_u16(0u16); // File's Disk number.
     central_directory_header.write_u16(0u16); // Internal file attributes.
-    central_directory_header.write_u32((0o100644 << 16) as u32); // External file attributes (regular file / rw-r--r--).
-    central_directory_header.write_u32(file_info.offset); // Offset from start of file to local file header.
+    central_directory_header.write_u32(file_info.external_file_attributes); // External file attributes (Unix mode).
+    central_directory_header.write_u32(offset); // Offset from start of file to local file header.
     central_directory_header.write_bytes(&file_info.file_name_as_bytes); // Filename.
+    central_directory_header.write_bytes(&extra_field); // Extra field.
+}
+
+/// Narrow a value to a 32 bits field.
+///
+/// # Error
+///
+/// `ArchiveError::Zip64Required` if the value does not fit, rather than silently wrapping.
+pub fn zip32(value: u64, field: &'static str) -> Result<u32, ArchiveError> {
+    u32::try_from(value).map_err(|_| ArchiveError::Zip64Required { field })
+}
+
+/// Check that the offset of a local header can be recorded in the central directory.
+pub fn check_header_offset(options: &ArchiveOptions, offset: u64) -> Result<u64, ArchiveError> {
+    if !options.zip64 {
+        zip32(offset, "local header offset")?;
+    }
+    Ok(offset)
+}
+
+/// Convert the backslashes of an entry name to the forward slashes ZIP uses as path separator.
+///
+/// # Error
+///
+/// `ArchiveError::InvalidEntryName` if the name contains a backslash and `strict_entry_names` is set,
+/// `ArchiveError::EmptyEntryName` if the name is empty or only made of `/` and
+/// `allow_empty_entry_names` is not set.
+pub fn normalize_entry_name<'a>(
+    options: &ArchiveOptions,
+    file_name: &'a str,
+) -> Result<Cow<'a, str>, ArchiveError> {
+    let normalized = if !file_name.contains('\\') {
+        Cow::Borrowed(file_name)
+    } else if options.strict_entry_names {
+        return Err(ArchiveError::InvalidEntryName(file_name.to_owned()));
+    } else {
+        Cow::Owned(file_name.replace('\\', "/"))
+    };
+
+    if !options.allow_empty_entry_names && normalized.chars().all(|c| c == '/') {
+        return Err(ArchiveError::EmptyEntryName(normalized.into_owned()));
+    }
+    Ok(normalized)
+}
+
+/// Temporary spanning marker to write first, if `ArchiveOptions::spanning_marker` is set and
+/// neither an entry nor the marker is written yet. Whoever writes it sets
+/// `SubZipArchiveData::spanning_marker_written`.
+pub fn spanning_marker(data: &SubZipArchiveData) -> Option<[u8; 4]> {
+    (data.options.spanning_marker && !data.spanning_marker_written && data.files_info.is_empty())
+        .then(|| TEMPORARY_SPANNING_SIGNATURE.to_le_bytes())
+}
+
+/// Check that the sizes of an entry fit in the 32 bits fields of the headers, unless the entry is ZIP64.
+pub fn check_zip32_sizes(file_info: &ArchiveFileEntry) -> Result<(), ArchiveError> {
+    if !file_info.zip64 {
+        zip32(file_info.compressed_size, "compressed size")?;
+        zip32(file_info.uncompressed_size, "uncompressed size")?;
+    }
+    Ok(())
+}
+
+/// Check that an entry can be recompressed in place: its local header must hold its CRC and its
+/// sizes, i.e. the entry has neither a data descriptor nor ZIP64 fields.
+pub fn check_recompressible(file_info: &ArchiveFileEntry) -> Result<(), ArchiveError> {
+    if file_info.general_purpose_flags & (1 << 3) != 0
+        || file_info.zip64
+        || file_info.needs_zip64_extra_field()
+    {
+        return Err(ArchiveError::BadArchiveStructure(
+            "Only entries without data descriptor nor ZIP64 fields can be recompressed in place"
+                .to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Update an entry whose payload is recompressed in place, and build the local header fields to
+/// overwrite, from the version needed to extract (offset 4) to the extra field length, given the
+/// fixed size part of the local header.
+///
+/// A smaller payload is preceded by a growth hint extra field, appended to the local extra field
+/// and filled with zeros, so that the payload still ends where the previous one did. The padding
+/// is returned with the fields, to be written where the previous payload started.
+///
+/// # Error
+///
+/// * `ArchiveError::RecompressedEntryTooLarge` if the new payload is larger than the previous one.
+/// * `ArchiveError::RecompressedEntryGap` if the room left cannot be padded.
+pub fn recompress_entry_header(
+    file_info: &mut ArchiveFileEntry,
+    local_header: &[u8],
+    compressor: CompressionMethod,
+    compression_level: Level,
+    compressed_size: u64,
+) -> Result<(ArchiveDescriptor, ArchiveDescriptor), ArchiveError> {
+    if compressed_size > file_info.compressed_size {
+        return Err(ArchiveError::RecompressedEntryTooLarge {
+            available: file_info.compressed_size,
+            required: compressed_size,
+        });
+    }
+
+    let file_name_len = u16::from_le_bytes([local_header[26], local_header[27]]);
+    let extra_field_length = u16::from_le_bytes([local_header[28], local_header[29]]);
+    let gap = file_info.compressed_size - compressed_size;
+    if gap > 0 && (gap < GROWTH_HINT_MIN_SIZE || extra_field_length as u64 + gap > u16::MAX as u64)
+    {
+        return Err(ArchiveError::RecompressedEntryGap {
+            available: file_info.compressed_size,
+            required: compressed_size,
+        });
+    }
+
+    let mut padding = ArchiveDescriptor::new(gap);
+    if gap > 0 {
+        padding.write_u16(GROWTH_HINT_EXTRA_FIELD_ID);
+        padding.write_u16((gap - 4) as u16);
+        padding.write_u16(GROWTH_HINT_SIGNATURE);
+        padding.write_u16((gap - GROWTH_HINT_MIN_SIZE) as u16); // Initial padding value.
+        padding.write_bytes(&vec![0; (gap - GROWTH_HINT_MIN_SIZE) as usize]);
+    }
+
+    // the deflate option bits of the previous method are cleared
+    let general_purpose_flags = compressor.update_general_purpose_bit_flag(
+        file_info.general_purpose_flags & !0b110,
+        compression_level,
+    );
+
+    file_info.version_needed = compressor.zip_version_needed();
+    file_info.general_purpose_flags = general_purpose_flags;
+    file_info.compression_method = compressor.zip_code();
+    file_info.compressor = compressor;
+    file_info.compression_level = compressor.effective_level(compression_level);
+    file_info.compressed_size = compressed_size;
+
+    let mut fields = ArchiveDescriptor::new(7 * 2 + 3 * 4);
+    fields.write_u16(file_info.version_needed);
+    fields.write_u16(file_info.general_purpose_flags);
+    fields.write_u16(file_info.compression_method);
+    fields.write_u16(file_info.last_mod_file_time);
+    fields.write_u16(file_info.last_mod_file_date);
+    fields.write_u32(file_info.crc32);
+    fields.write_u32(file_info.compressed_size as u32);
+    fields.write_u32(file_info.uncompressed_size as u32);
+    fields.write_u16(file_name_len);
+    fields.write_u16(extra_field_length + gap as u16);
+    Ok((fields, padding))
+}
+
+/// Build the data descriptor following the payload of a streamed entry.
+pub fn build_data_descriptor(file_info: &ArchiveFileEntry) -> ArchiveDescriptor {
+    if file_info.zip64 {
+        let mut file_descriptor = ArchiveDescriptor::new(ZIP64_DESCRIPTOR_SIZE);
+        file_descriptor.write_u32(DATA_DESCRIPTOR_SIGNATURE);
+        file_descriptor.write_u32(file_info.crc32);
+        file_descriptor.write_u64(file_info.compressed_size);
+        file_descriptor.write_u64(file_info.uncompressed_size);
+        file_descriptor
+    } else {
+        let mut file_descriptor = ArchiveDescriptor::new(DESCRIPTOR_SIZE);
+        file_descriptor.write_u32(DATA_DESCRIPTOR_SIGNATURE);
+        file_descriptor.write_u32(file_info.crc32);
+        file_descriptor.write_u32(file_info.compressed_size as u32);
+        file_descriptor.write_u32(file_info.uncompressed_size as u32);
+        file_descriptor
+    }
+}
+
+/// Whether the end of central directory needs the ZIP64 records.
+fn needs_zip64_end(
+    number_of_entries: u64,
+    central_directory_offset: u64,
+    central_directory_size: u64,
+) -> bool {
+    number_of_entries >= u16::MAX as u64
+        || central_directory_size >= u32::MAX as u64
+        || central_directory_offset >= u32::MAX as u64
+}
+
+/// Size of the central directory and of the end records written after it, for the entries
+/// recorded so far and an archive finalized at `archive_size`. The spanning marker of an archive
+/// without entries, written by `finalize` before the central directory, is included.
+pub fn central_directory_total_size(data: &SubZipArchiveData, archive_size: u64) -> u64 {
+    let marker_size = spanning_marker(data).map_or(0, |marker| marker.len() as u64);
+    let central_directory_offset = archive_size + marker_size;
+    let central_directory_size: u64 = data
+        .files_info
+        .iter()
+        .map(central_directory_entry_size)
+        .sum();
+    let zip64_records = if data.options.zip64
+        && needs_zip64_end(
+            data.files_info.len() as u64,
+            central_directory_offset,
+            central_directory_size,
+        ) {
+        ZIP64_CENTRAL_DIRECTORY_END_SIZE + ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIZE
+    } else {
+        0
+    };
+
+    marker_size
+        + central_directory_size
+        + zip64_records
+        + END_OF_CENTRAL_DIRECTORY_SIZE
+        + data.archive_comment.len() as u64
+}
+
+/// Number of zero bytes `finalize` writes before the central directory of an archive finalized
+/// at `archive_size`, so that the end records reach past every byte written to the sink.
+///
+/// Once `replace_last_entry` wrote a shorter entry, the tail of the previous one would otherwise
+/// be left after the end of central directory record, where readers look for it.
+pub fn stale_bytes_gap(data: &SubZipArchiveData, archive_size: u64) -> u64 {
+    data.written_end
+        .saturating_sub(archive_size + central_directory_total_size(data, archive_size))
+}
+
+/// Minimum "version needed to extract" of the compression method of an entry, as listed by
+/// APPNOTE 4.4.3.2, or `None` for a method the crate does not know.
+fn spec_version_needed(compressor: CompressionMethod) -> Option<u16> {
+    match compressor {
+        CompressionMethod::Store() => Some(10),
+        CompressionMethod::Unknown(_) => None,
+        _ => Some(compressor.zip_version_needed()),
+    }
+}
+
+/// Check that the records of an extra field add up to its length.
+fn check_extra_field_records(extra_field: &[u8]) -> Result<(), String> {
+    let mut remaining = extra_field;
+    while !remaining.is_empty() {
+        if remaining.len() < 4 {
+            return Err(format!(
+                "the extra field ends with a truncated record header of {} bytes",
+                remaining.len()
+            ));
+        }
+        let id = u16::from_le_bytes([remaining[0], remaining[1]]);
+        let data_size = u16::from_le_bytes([remaining[2], remaining[3]]) as usize;
+        if remaining.len() < 4 + data_size {
+            return Err(format!(
+                "the extra field record 0x{:04x} declares {} bytes, only {} are left",
+                id,
+                data_size,
+                remaining.len() - 4
+            ));
+        }
+        remaining = &remaining[4 + data_size..];
+    }
+    Ok(())
+}
+
+/// Check an entry against the APPNOTE rules its headers must follow.
+fn check_entry_conformance(
+    file_info: &ArchiveFileEntry,
+    options: &ArchiveOptions,
+) -> Result<(), String> {
+    let name_len = file_info.file_name_as_bytes.len();
+    if name_len > u16::MAX as usize {
+        return Err(format!(
+            "the name of {} bytes exceeds its 16 bits length field",
+            name_len
+        ));
+    }
+    if file_info.file_name_len as usize != name_len {
+        return Err(format!(
+            "the name length field is {}, the name has {} bytes",
+            file_info.file_name_len, name_len
+        ));
+    }
+    let utf8_flag = file_info.general_purpose_flags & (1 << 11) != 0;
+    if !utf8_flag && !file_info.file_name_as_bytes.is_ascii() {
+        return Err("the name is not ASCII but the UTF-8 flag (bit 11) is not set".to_owned());
+    }
+    if utf8_flag && str::from_utf8(&file_info.file_name_as_bytes).is_err() {
+        return Err("the UTF-8 flag (bit 11) is set but the name is not UTF-8".to_owned());
+    }
+
+    if file_info.extra_field_length as usize != file_info.extra_field.len() {
+        return Err(format!(
+            "the extra field length field is {}, the extra field has {} bytes",
+            file_info.extra_field_length,
+            file_info.extra_field.len()
+        ));
+    }
+    check_extra_field_records(&file_info.extra_field)?;
+    let central_extra_field_size = central_directory_entry_size(file_info)
+        - CENTRAL_DIRECTORY_ENTRY_BASE_SIZE
+        - name_len as u64;
+    if central_extra_field_size > u16::MAX as u64 {
+        return Err(format!(
+            "the central extra field of {} bytes exceeds its 16 bits length field",
+            central_extra_field_size
+        ));
+    }
+
+    if file_info.compression_method != file_info.compressor.zip_code() {
+        return Err(format!(
+            "the method field is {}, the payload is written with {}",
+            file_info.compression_method, file_info.compressor
+        ));
+    }
+    if let Some(required) = spec_version_needed(file_info.compressor) {
+        let local_required = if file_info.zip64 {
+            required.max(ZIP64_VERSION_NEEDED)
+        } else {
+            required
+        };
+        if file_info.version_needed & 0xFF < local_required {
+            return Err(format!(
+                "the local version needed is {}, {} requires at least {}",
+                file_info.version_needed & 0xFF,
+                file_info.compressor,
+                local_required
+            ));
+        }
+        if file_info.version_needed() < required {
+            return Err(format!(
+                "the central version needed is {}, {} requires at least {}",
+                file_info.version_needed(),
+                file_info.compressor,
+                required
+            ));
+        }
+    }
+
+    if matches!(file_info.compressor, CompressionMethod::Store())
+        && file_info.compressed_size != file_info.uncompressed_size
+    {
+        return Err(format!(
+            "the entry is stored but its compressed size {} differs from its size {}",
+            file_info.compressed_size, file_info.uncompressed_size
+        ));
+    }
+    if file_info.uncompressed_size == 0 && file_info.crc32 != 0 {
+        return Err(format!(
+            "the entry is empty but its CRC-32 is {:08x}",
+            file_info.crc32
+        ));
+    }
+    if !file_info.zip64 {
+        // the sizes are in the 32 bits fields of the local header or of the data descriptor
+        zip32(file_info.compressed_size, "compressed size").map_err(|e| e.to_string())?;
+        zip32(file_info.uncompressed_size, "uncompressed size").map_err(|e| e.to_string())?;
+    }
+    if !options.zip64 {
+        zip32(file_info.offset, "local header offset").map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Check the entries and the archive comment against the APPNOTE rules before the central
+/// directory starting at `central_directory_offset` is written, for `ArchiveOptions::strict`.
+///
+/// # Error
+///
+/// `ArchiveError::SpecViolation` on the first inconsistency found, wrapped in the context of the
+/// entry it was found in.
+pub fn check_spec_conformance(
+    data: &SubZipArchiveData,
+    central_directory_offset: u64,
+) -> Result<(), ArchiveError> {
+    let mut previous_header_end = 0;
+    for file_info in &data.files_info {
+        let name = String::from_utf8_lossy(&file_info.file_name_as_bytes);
+        let violation = |detail: String| {
+            ArchiveError::SpecViolation { detail }.in_entry(&name, file_info.offset)
+        };
+
+        check_entry_conformance(file_info, &data.options).map_err(violation)?;
+
+        // the local header holds at least its fixed fields and the name
+        if file_info.offset < previous_header_end {
+            return Err(violation(format!(
+                "the local header overlaps the previous entry, which ends at offset {} at least",
+                previous_header_end
+            )));
+        }
+        previous_header_end =
+            file_info.offset + FILE_HEADER_BASE_SIZE + file_info.file_name_as_bytes.len() as u64;
+        if previous_header_end > central_directory_offset {
+            return Err(violation(format!(
+                "the local header overlaps the central directory at offset {}",
+                central_directory_offset
+            )));
+        }
+    }
+
+    if data.archive_comment.len() > u16::MAX as usize {
+        return Err(ArchiveError::SpecViolation {
+            detail: format!(
+                "the archive comment of {} bytes exceeds its 16 bits length field",
+                data.archive_comment.len()
+            ),
+        });
+    }
+    Ok(())
 }
 
 pub fn build_central_directory_end(
     data: &SubZipArchiveData,
-    central_directory_offset: u32,
-    central_directory_size: u32,
-) -> ArchiveDescriptor {
-    let dir_end = CentralDirectoryEnd {
-        disk_number: 0,
-        disk_with_central_directory: 0,
-        total_number_of_entries_on_this_disk: data.files_info.len() as u16,
-        total_number_of_entries: data.files_info.len() as u16,
+    central_directory_offset: u64,
+    central_directory_size: u64,
+) -> Result<ArchiveDescriptor, ArchiveError> {
+    let number_of_entries = data.files_info.len() as u64;
+    let zip64 = needs_zip64_end(
+        number_of_entries,
+        central_directory_offset,
         central_directory_size,
-        offset_of_start_of_central_directory: central_directory_offset,
-        zip_file_comment_length: data.archive_comment.len() as u16,
+    );
+
+    let mut end_of_central_directory = ArchiveDescriptor::new(
+        END_OF_CENTRAL_DIRECTORY_SIZE
+            + ZIP64_CENTRAL_DIRECTORY_END_SIZE
+            + ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIZE,
+    );
+
+    if zip64 && data.options.zip64 {
+        let zip64_central_directory_end_offset = central_directory_offset + central_directory_size;
+
+        end_of_central_directory.write_u32(ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE);
+        end_of_central_directory.write_u64(ZIP64_CENTRAL_DIRECTORY_END_SIZE - 12); // Size of the remaining record.
+        end_of_central_directory.write_u16(data.options.version_made_by); // Version made by.
+        end_of_central_directory.write_u16(ZIP64_VERSION_NEEDED); // Version needed to extract.
+        end_of_central_directory.write_u32(0); // Number of this disk.
+        end_of_central_directory.write_u32(0); // Disk with the central directory.
+        end_of_central_directory.write_u64(number_of_entries); // Entries on this disk.
+        end_of_central_directory.write_u64(number_of_entries); // Total entries.
+        end_of_central_directory.write_u64(central_directory_size);
+        end_of_central_directory.write_u64(central_directory_offset);
+
+        end_of_central_directory.write_u32(ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE);
+        end_of_central_directory.write_u32(0); // Disk with the ZIP64 end of central directory.
+        end_of_central_directory.write_u64(zip64_central_directory_end_offset);
+        end_of_central_directory.write_u32(1); // Total number of disks.
+    }
+
+    let dir_end = if data.options.zip64 {
+        CentralDirectoryEnd {
+            disk_number: 0,
+            disk_with_central_directory: 0,
+            total_number_of_entries_on_this_disk: number_of_entries.min(u16::MAX as u64) as u16,
+            total_number_of_entries: number_of_entries.min(u16::MAX as u64) as u16,
+            central_directory_size: central_directory_size.min(u32::MAX as u64) as u32,
+            offset_of_start_of_central_directory: central_directory_offset.min(u32::MAX as u64)
+                as u32,
+            zip_file_comment_length: data.archive_comment.len() as u16,
+        }
+    } else {
+        let number_of_entries =
+            u16::try_from(number_of_entries).map_err(|_| ArchiveError::Zip64Required {
+                field: "number of entries",
+            })?;
+
+        CentralDirectoryEnd {
+            disk_number: 0,
+            disk_with_central_directory: 0,
+            total_number_of_entries_on_this_disk: number_of_entries,
+            total_number_of_entries: number_of_entries,
+            central_directory_size: zip32(central_directory_size, "central directory size")?,
+            offset_of_start_of_central_directory: zip32(
+                central_directory_offset,
+                "central directory offset",
+            )?,
+            zip_file_comment_length: data.archive_comment.len() as u16,
+        }
     };
 
-    let mut end_of_central_directory = ArchiveDescriptor::new(END_OF_CENTRAL_DIRECTORY_SIZE);
     end_of_central_directory.write_u32(CENTRAL_DIRECTORY_END_SIGNATURE);
     end_of_central_directory.write_u16(dir_end.disk_number);
     end_of_central_directory.write_u16(dir_end.disk_with_central_directory);
@@ -141,22 +815,328 @@ pub fn build_central_directory_end(
     if dir_end.zip_file_comment_length > 0 {
         end_of_central_directory.write_bytes(&data.archive_comment);
     }
-    end_of_central_directory
+    Ok(end_of_central_directory)
+}
+
+/// Print the entries as a table, in the manner of `unzip -l`, followed by a line of totals.
+///
+/// The name column takes the width left by the other columns; longer names overflow it.
+pub fn write_entry_listing<T: std::io::Write>(
+    files_info: &[ArchiveFileEntry],
+    writer: &mut T,
+    options: &ListOptions,
+) -> Result<(), ArchiveError> {
+    const FIXED_COLUMNS_WIDTH: usize = 10 + 10 + 7 + 12 + 7;
+    let name_width = options.width.saturating_sub(FIXED_COLUMNS_WIDTH).max(4);
+    let separator = "-".repeat(FIXED_COLUMNS_WIDTH + name_width);
+
+    writeln!(
+        writer,
+        "{:>10}{:>10}{:>7}{:>12}{:>7}  Name",
+        "Length", "Size", "Ratio", "Date", "Time"
+    )?;
+    writeln!(writer, "{}", separator)?;
+
+    let mut total_uncompressed_size = 0;
+    let mut total_compressed_size = 0;
+    for file_info in files_info {
+        let info = file_info.to_display_info();
+        let time =
+            DateTimeCS::from_msdos(file_info.last_mod_file_date, file_info.last_mod_file_time)
+                .to_time()
+                .format("%H:%M")
+                .to_string();
+
+        writeln!(
+            writer,
+            "{:>10}{:>10}{:>7.2}{:>12}{:>7}  {}",
+            info.uncompressed_size,
+            info.compressed_size,
+            info.compression_ratio,
+            info.last_modified,
+            time,
+            info.name
+        )?;
+
+        total_uncompressed_size += info.uncompressed_size;
+        total_compressed_size += info.compressed_size;
+    }
+
+    let total_ratio = if total_compressed_size == 0 {
+        1.0
+    } else {
+        total_uncompressed_size as f64 / total_compressed_size as f64
+    };
+    let entries = if files_info.len() == 1 {
+        "file"
+    } else {
+        "files"
+    };
+
+    writeln!(writer, "{}", separator)?;
+    writeln!(
+        writer,
+        "{:>10}{:>10}{:>7.2}{:>19}  {} {}",
+        total_uncompressed_size,
+        total_compressed_size,
+        total_ratio,
+        "",
+        files_info.len(),
+        entries
+    )?;
+    Ok(())
+}
+
+/// Start of an entry, to report its statistics to the callback of its options. Does nothing
+/// without the `stats` feature.
+#[derive(Debug)]
+pub struct EntryStatsTimer {
+    #[cfg(feature = "stats")]
+    started: Option<(std::time::Instant, crate::archive::StatsCallback)>,
+}
+
+impl EntryStatsTimer {
+    #[allow(unused_variables)]
+    pub fn start(options: &FileOptions) -> EntryStatsTimer {
+        EntryStatsTimer {
+            #[cfg(feature = "stats")]
+            started: options
+                .stats_callback
+                .clone()
+                .map(|callback| (std::time::Instant::now(), callback)),
+        }
+    }
+
+    /// Report the statistics of the entry, once its sizes are known.
+    #[allow(unused_variables)]
+    pub fn report(&self, file_info: &ArchiveFileEntry) {
+        #[cfg(feature = "stats")]
+        if let Some((started, callback)) = &self.started {
+            (callback.0)(crate::archive::EntryStats {
+                name: String::from_utf8_lossy(&file_info.file_name_as_bytes).into_owned(),
+                uncompressed: file_info.uncompressed_size,
+                compressed: file_info.compressed_size,
+                duration: started.elapsed(),
+                method: file_info.compressor,
+            });
+        }
+    }
 }
 
-#[derive(Debug, Default)]
+/// Checksums of the uncompressed bytes of an entry, updated in the compression read loop.
+#[derive(Debug)]
+pub struct EntryHasher {
+    crc32: Crc32Hasher,
+    #[cfg(feature = "sha2")]
+    sha256: Option<Sha256>,
+}
+
+pub struct EntryDigest {
+    pub crc32: u32,
+    pub sha256: Option<[u8; 32]>,
+}
+
+impl EntryHasher {
+    pub fn new() -> EntryHasher {
+        Self::with_sha256(false)
+    }
+
+    /// Also compute the SHA-256, if `sha256` is set and the `sha2` feature is enabled.
+    #[allow(unused_variables)]
+    pub fn with_sha256(sha256: bool) -> EntryHasher {
+        EntryHasher {
+            crc32: Crc32Hasher::new(),
+            #[cfg(feature = "sha2")]
+            sha256: sha256.then(Sha256::new),
+        }
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.crc32.update(buf);
+        #[cfg(feature = "sha2")]
+        if let Some(sha256) = &mut self.sha256 {
+            sha256.update(buf);
+        }
+    }
+
+    pub fn finalize(self) -> EntryDigest {
+        #[cfg(feature = "sha2")]
+        let sha256 = self.sha256.map(|sha256| sha256.finalize().into());
+        #[cfg(not(feature = "sha2"))]
+        let sha256 = None;
+
+        EntryDigest {
+            crc32: self.crc32.finalize(),
+            sha256,
+        }
+    }
+}
+
+/// Build the content of the SHA-256 manifest, one `"<hex>  <name>"` line per hashed entry.
+pub fn build_sha256_manifest(files_info: &[ArchiveFileEntry]) -> Vec<u8> {
+    let mut manifest = String::new();
+
+    for file_info in files_info {
+        if let Some(sha256) = file_info.sha256 {
+            for byte in sha256 {
+                manifest.push_str(&format!("{:02x}", byte));
+            }
+            manifest.push_str("  ");
+            manifest.push_str(&String::from_utf8_lossy(&file_info.file_name_as_bytes));
+            manifest.push('\n');
+        }
+    }
+
+    manifest.into_bytes()
+}
+
+/// Build the content of a JAR-style manifest (`META-INF/MANIFEST.MF`), one section with the
+/// name and the digest of every entry, from the digests computed while the entries were written.
+///
+/// See `jar_manifest`.
+pub fn build_jar_manifest(
+    files_info: &[ArchiveFileEntry],
+    algorithm: HashAlgorithm,
+) -> Result<Vec<u8>, ArchiveError> {
+    let digests = files_info
+        .iter()
+        .map(|file_info| stored_digest(file_info, algorithm))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(jar_manifest(files_info, algorithm, &digests))
+}
+
+/// Digest of an entry computed while it was written: the CRC-32 is always stored, the SHA-256
+/// only when `ArchiveOptions::sha256_manifest` is enabled.
+pub fn stored_digest(
+    file_info: &ArchiveFileEntry,
+    algorithm: HashAlgorithm,
+) -> Result<Vec<u8>, ArchiveError> {
+    let digest = match algorithm {
+        HashAlgorithm::Crc32 => Some(file_info.crc32.to_be_bytes().to_vec()),
+        HashAlgorithm::Sha256 => file_info.sha256.map(|sha256| sha256.to_vec()),
+    };
+    digest.ok_or_else(|| ArchiveError::DigestUnavailable {
+        algorithm,
+        entry: String::from_utf8_lossy(&file_info.file_name_as_bytes).into_owned(),
+    })
+}
+
+/// Build the content of a JAR-style manifest, `digests` holding the digest of every entry of
+/// `files_info`, in the same order.
+///
+/// Digests are base64 encoded, and lines are folded at 72 bytes, as the JAR specification
+/// requires.
+pub fn jar_manifest(
+    files_info: &[ArchiveFileEntry],
+    algorithm: HashAlgorithm,
+    digests: &[Vec<u8>],
+) -> Vec<u8> {
+    let mut manifest = b"Manifest-Version: 1.0\r\nCreated-By: rill\r\n\r\n".to_vec();
+    let attribute = format!("{}-Digest", algorithm.name());
+
+    for (file_info, digest) in files_info.iter().zip(digests) {
+        write_manifest_header(&mut manifest, "Name", &file_info.file_name_as_bytes);
+        write_manifest_header(&mut manifest, &attribute, base64(digest).as_bytes());
+        manifest.extend_from_slice(b"\r\n");
+    }
+
+    manifest
+}
+
+/// Write a `key: value` manifest header, continuing the lines longer than 72 bytes on lines
+/// starting with a space.
+///
+/// Lines are folded on character boundaries, as Java's `Manifest` does, so that a multi-byte
+/// UTF-8 character of a name is never split across two lines.
+fn write_manifest_header(manifest: &mut Vec<u8>, key: &str, value: &[u8]) {
+    const MAX_LINE_LENGTH: usize = 72;
+
+    let mut line = [key.as_bytes(), b": ", value].concat();
+    let mut first = true;
+    while !line.is_empty() {
+        let available = if first {
+            MAX_LINE_LENGTH
+        } else {
+            manifest.push(b' ');
+            MAX_LINE_LENGTH - 1
+        };
+        let mut split = available.min(line.len());
+        // back off over UTF-8 continuation bytes
+        while split < line.len() && split > 1 && line[split] & 0xc0 == 0x80 {
+            split -= 1;
+        }
+        let rest = line.split_off(split);
+        manifest.extend_from_slice(&line);
+        manifest.extend_from_slice(b"\r\n");
+        line = rest;
+        first = false;
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct SubZipArchiveData {
     pub files_info: Vec<ArchiveFileEntry>,
     archive_comment: Vec<u8>,
     pub archive_size: u64,
     pub data_descriptor: bool,
+    pub options: ArchiveOptions,
+    /// Options of the entries appended without explicit ones.
+    pub default_file_options: FileOptions,
+    /// Whether the central directory is written and the sink flushed.
+    pub finalized: bool,
+    /// Whether the temporary spanning marker is written, see `spanning_marker`.
+    pub spanning_marker_written: bool,
+    /// End of the bytes written to the sink, past `archive_size` once `replace_last_entry`
+    /// rewound over a longer entry.
+    pub written_end: u64,
 }
 
 impl SubZipArchiveData {
-    fn set_archive_comment(&mut self, comment: &str) {
-        let bytes = comment.as_bytes();
-        let len = std::cmp::min(bytes.len(), u16::MAX as usize);
-        self.archive_comment = bytes[0..len].to_owned();
+    pub fn set_archive_comment_bytes(&mut self, comment: Vec<u8>) {
+        self.archive_comment = comment;
+    }
+
+    /// Set the archive comment, truncated to at most 65535 bytes on a character boundary, so it
+    /// stays valid UTF-8; returns the length kept.
+    pub fn set_archive_comment(&mut self, comment: &str) -> usize {
+        let mut len = std::cmp::min(comment.len(), u16::MAX as usize);
+        while !comment.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.archive_comment = comment.as_bytes()[0..len].to_owned();
+        len
+    }
+
+    pub fn archive_comment_bytes(&self) -> &[u8] {
+        &self.archive_comment
+    }
+
+    /// The archive comment, `None` if there is none or it is not UTF-8.
+    pub fn archive_comment(&self) -> Option<&str> {
+        if self.archive_comment.is_empty() {
+            None
+        } else {
+            str::from_utf8(&self.archive_comment).ok()
+        }
     }
 }
 
@@ -179,6 +1159,26 @@ impl ArchiveDescriptor {
         self.buffer.extend_from_slice(&val.to_le_bytes());
     }
 
+    pub fn write_u64(&mut self, val: u64) {
+        self.buffer.extend_from_slice(&val.to_le_bytes());
+    }
+
+    /// Read the little-endian `u64` at `offset`, e.g. a ZIP64 size written earlier.
+    ///
+    /// Panics if the 8 bytes are not all within the buffer.
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        let bytes: [u8; 8] = self.buffer[offset..offset + 8].try_into().unwrap();
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Overwrite the 8 bytes at `offset` with `val` in little-endian order, to patch a field
+    /// once its value is known.
+    ///
+    /// Panics if the 8 bytes are not all within the buffer.
+    pub fn write_u64_at(&mut self, offset: usize, val: u64) {
+        self.buffer[offset..offset + 8].copy_from_slice(&val.to_le_bytes());
+    }
+
     pub fn write_str(&mut self, val: &str) {
         self.write_bytes(val.as_bytes());
     }
@@ -225,6 +1225,7 @@ impl ArchiveDescriptor {
 
         let file_name_as_bytes = file_name.as_bytes().to_owned();
 
+        let compressor = CompressionMethod::from_compression_method(compression_method)?;
         let archive_file_entry = ArchiveFileEntry {
             version_made_by: 0,
             version_needed,
@@ -232,18 +1233,23 @@ impl ArchiveDescriptor {
             last_mod_file_time: time,
             last_mod_file_date: date,
             crc32: crc,
+            sha256: None,
             compressed_size,
             uncompressed_size,
             file_name_len,
             extra_field_length,
             file_name_as_bytes,
+            extra_field: Vec::new(),
             offset: 0,
+            zip64: false,
             internal_file_attributes: 0,
             external_file_attributes: 0,
             file_comment_length: 0,
             file_disk_number: 0,
             compression_method,
-            compressor: CompressionMethod::from_compression_method(compression_method)?,
+            compressor,
+            compression_level: compressor
+                .level_from_general_purpose_bit_flag(general_purpose_flags),
         };
 
         Ok(archive_file_entry)
@@ -258,6 +1264,7 @@ pub struct ArchiveDescriptorReader {
     index: usize,
 }
 
+const U_64_LEN: usize = ::std::mem::size_of::<u64>();
 const U_32_LEN: usize = ::std::mem::size_of::<u32>();
 const U_16_LEN: usize = ::std::mem::size_of::<u16>();
 
@@ -266,6 +1273,10 @@ impl ArchiveDescriptorReader {
         ArchiveDescriptorReader { index: 0 }
     }
 
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
     pub fn read_u32(&mut self, stream: &[u8]) -> u32 {
         let upper_bound = self.index + U_32_LEN;
 
@@ -274,7 +1285,15 @@ impl ArchiveDescriptorReader {
 
         self.index = upper_bound;
 
-        println!("read_u32 value: {:} new index {:}", value, self.index);
+        value
+    }
+
+    pub fn read_u64(&mut self, stream: &[u8]) -> u64 {
+        let upper_bound = self.index + U_64_LEN;
+        let read: [u8; U_64_LEN] = stream[self.index..upper_bound].try_into().unwrap();
+        let value = u64::from_le_bytes(read);
+
+        self.index = upper_bound;
 
         value
     }
@@ -286,22 +1305,12 @@ impl ArchiveDescriptorReader {
 
         self.index = upper_bound;
 
-        println!("read_u16 value: {:?} new index {:}", value, self.index);
-
         value
     }
 
     pub fn read_utf8_string(&mut self, stream: &[u8], string_len: usize) -> String {
         let upper_bound = self.index + string_len;
 
-        println!(
-            "read_utf8_string lb: {:?} up: {:} ({:} bytes) from a {:} length array.",
-            self.index,
-            upper_bound,
-            string_len,
-            stream.len()
-        );
-
         let value = match str::from_utf8(&stream[self.index..upper_bound]) {
             Ok(v) => v.to_owned(),
             Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
@@ -309,28 +1318,16 @@ impl ArchiveDescriptorReader {
 
         self.index = upper_bound;
 
-        println!("read_u16 value: {:?} new index {:}", value, self.index);
-
         value
     }
 
     pub fn read_bytes(&mut self, stream: &[u8], len: usize) -> Vec<u8> {
         let upper_bound = self.index + len;
 
-        println!(
-            "read_utf8_string lb: {:?} up: {:} ({:} bytes) from a {:} length array.",
-            self.index,
-            upper_bound,
-            len,
-            stream.len()
-        );
-
         let value = stream[self.index..upper_bound].to_owned();
 
         self.index = upper_bound;
 
-        println!("read_u16 value: {:?} new index {:}", value, self.index);
-
         value
     }
 }
@@ -352,6 +1349,98 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn normalize_entry_name_backslashes() {
+        let options = ArchiveOptions::default();
+        assert!(matches!(
+            normalize_entry_name(&options, "a/b.txt").unwrap(),
+            Cow::Borrowed("a/b.txt")
+        ));
+        assert_eq!(
+            normalize_entry_name(&options, "a\\b\\c.txt").unwrap(),
+            "a/b/c.txt"
+        );
+
+        let strict = ArchiveOptions::default().strict_entry_names(true);
+        assert!(normalize_entry_name(&strict, "a/b.txt").is_ok());
+        assert!(matches!(
+            normalize_entry_name(&strict, "a\\b.txt"),
+            Err(ArchiveError::InvalidEntryName(name)) if name == "a\\b.txt"
+        ));
+    }
+
+    #[test]
+    fn normalize_entry_name_empty() {
+        let options = ArchiveOptions::default();
+        for name in ["", "/", "//", "\\"] {
+            assert!(
+                matches!(
+                    normalize_entry_name(&options, name),
+                    Err(ArchiveError::EmptyEntryName(_))
+                ),
+                "name {:?}",
+                name
+            );
+        }
+        assert!(normalize_entry_name(&options, "dir/").is_ok());
+
+        let allowed = ArchiveOptions::default().allow_empty_entry_names(true);
+        assert_eq!(normalize_entry_name(&allowed, "").unwrap(), "");
+        assert_eq!(normalize_entry_name(&allowed, "/").unwrap(), "/");
+    }
+
+    #[test]
+    fn test_spec_conformance() {
+        let options = FileOptions::default().compression_method(CompressionMethod::Store());
+        let (_, mut file_info) =
+            build_file_header("file.txt", &options, options.compressor, 0, true, false);
+        file_info.crc32 = 0x12345678;
+        file_info.compressed_size = 5;
+        file_info.uncompressed_size = 5;
+
+        let mut data = SubZipArchiveData {
+            files_info: vec![file_info.clone()],
+            ..Default::default()
+        };
+        assert!(check_spec_conformance(&data, 100).is_ok());
+
+        let violations: [fn(&mut ArchiveFileEntry); 5] = [
+            |entry| entry.compressed_size = 4,
+            |entry| entry.file_name_len = 3,
+            |entry| entry.version_needed = 9,
+            |entry| {
+                entry.extra_field = vec![0x55, 0x54, 5, 0, 1];
+                entry.extra_field_length = 5;
+            },
+            |entry| {
+                entry.uncompressed_size = 0;
+                entry.compressed_size = 0;
+            },
+        ];
+        for violation in violations {
+            let mut entry = file_info.clone();
+            violation(&mut entry);
+            data.files_info = vec![entry];
+            let error = check_spec_conformance(&data, 100).unwrap_err();
+            assert!(
+                matches!(error.root_cause(), ArchiveError::SpecViolation { .. }),
+                "{}",
+                error
+            );
+        }
+
+        // the local header of the second entry overlaps the first one
+        let mut second = file_info.clone();
+        second.offset = 10;
+        data.files_info = vec![file_info.clone(), second];
+        assert!(check_spec_conformance(&data, 100).is_err());
+
+        data.files_info = vec![file_info];
+        assert!(check_spec_conformance(&data, 20).is_err());
+        data.set_archive_comment_bytes(vec![b'c'; u16::MAX as usize + 1]);
+        assert!(check_spec_conformance(&data, 100).is_err());
+    }
+
     #[test]
     fn test_write_file_header() {
         let version_needed = CompressionMethod::Deflate().zip_version_needed();
@@ -363,7 +1452,7 @@ mod test {
         let compressed_size = 0u32;
         let uncompressed_size = 0u32;
         let file_name = "file1.txt";
-        let file_name_len = file_name.as_bytes().len() as u16;
+        let file_name_len = file_name.len() as u16;
 
         println!("file_name {:?} length: {:}", file_name, file_name_len);
         let extra_field_length = 0u16;
@@ -390,6 +1479,354 @@ mod test {
         print!("{:#?}", entry)
     }
 
+    #[test]
+    fn test_utf8_flag() {
+        let options = FileOptions::default();
+        let (_, entry) = build_file_header(
+            "café.txt",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+        assert!(entry.is_utf8());
+    }
+
+    #[test]
+    fn test_utf8_flag_regression() {
+        let options = FileOptions::default();
+
+        let (file_header, entry) = build_file_header(
+            "smile_😀.txt",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+        assert_ne!(entry.general_purpose_flags & (1 << 11), 0);
+        assert_eq!(&file_header.buffer()[6..8], &(1u16 << 11).to_le_bytes());
+
+        let (file_header, entry) = build_file_header(
+            "plain.txt",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+        assert_eq!(entry.general_purpose_flags & (1 << 11), 0);
+        assert_eq!(&file_header.buffer()[6..8], &0u16.to_le_bytes());
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn test_sha256_manifest() {
+        let options = FileOptions::default();
+        let (_, mut entry) = build_file_header(
+            "file1.txt",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+
+        let mut hasher = EntryHasher::with_sha256(true);
+        hasher.update(b"abc");
+        let digest = hasher.finalize();
+        entry.crc32 = digest.crc32;
+        entry.sha256 = digest.sha256;
+
+        assert_eq!(
+            build_sha256_manifest(&[entry]),
+            b"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  file1.txt\n"
+        );
+    }
+
+    #[test]
+    fn test_descriptor_u64() {
+        let mut descriptor = ArchiveDescriptor::new(20);
+        descriptor.write_u32(ZIP64_EXTRA_FIELD_ID as u32);
+        descriptor.write_u64(0);
+        descriptor.write_u64(u32::MAX as u64 + 1);
+        assert_eq!(descriptor.len(), 20);
+        assert_eq!(descriptor.read_u64(12), 1 << 32);
+
+        descriptor.write_u64_at(4, 0x0102_0304_0506_0708);
+        assert_eq!(descriptor.read_u64(4), 0x0102_0304_0506_0708);
+        assert_eq!(&descriptor.buffer()[4..12], &[8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(descriptor.read_u64(12), 1 << 32);
+    }
+
+    #[test]
+    fn test_jar_manifest() {
+        let name = format!("dir/{}.txt", "a".repeat(70));
+        let options = FileOptions::default();
+        let (_, mut entry) =
+            build_file_header(&name, &options, CompressionMethod::Store(), 0, false, false);
+        entry.crc32 = 0x352441c2;
+
+        let manifest = build_jar_manifest(&[entry.clone()], HashAlgorithm::Crc32).unwrap();
+        let expected = format!(
+            "Manifest-Version: 1.0\r\nCreated-By: rill\r\n\r\nName: {}\r\n {}\r\nCRC-32-Digest: NSRBwg==\r\n\r\n",
+            &name[..66],
+            &name[66..]
+        );
+        assert_eq!(String::from_utf8(manifest).unwrap(), expected);
+
+        assert!(matches!(
+            build_jar_manifest(&[entry], HashAlgorithm::Sha256),
+            Err(ArchiveError::DigestUnavailable {
+                algorithm: HashAlgorithm::Sha256,
+                ..
+            })
+        ));
+
+        // "é" is 2 bytes, the 72-byte line cannot end in its middle
+        let name = format!("{}{}", "a".repeat(65), "é".repeat(3));
+        let mut manifest = Vec::new();
+        write_manifest_header(&mut manifest, "Name", name.as_bytes());
+        assert_eq!(
+            String::from_utf8(manifest).unwrap(),
+            format!("Name: {}\r\n ééé\r\n", "a".repeat(65))
+        );
+        assert_eq!(base64(b"abcd"), "YWJjZA==");
+        assert_eq!(base64(b"abcde"), "YWJjZGU=");
+        assert_eq!(base64(b"abcdef"), "YWJjZGVm");
+    }
+
+    #[test]
+    fn test_zip64_central_directory_entry() {
+        let options = FileOptions::default();
+        let (file_header, mut entry) = build_file_header(
+            "big.bin",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            true,
+            true,
+        );
+        assert_eq!(
+            &file_header.buffer()[4..6],
+            &ZIP64_VERSION_NEEDED.to_le_bytes()
+        );
+        assert_eq!(&file_header.buffer()[18..26], &[0xff; 8]);
+        assert_eq!(&file_header.buffer()[37..41], &[0x01, 0x00, 16, 0]);
+
+        entry.uncompressed_size = 5 << 30;
+        entry.compressed_size = 1 << 30;
+        entry.offset = 6 << 30;
+        let mut central_directory_header =
+            ArchiveDescriptor::new(CENTRAL_DIRECTORY_ENTRY_BASE_SIZE);
+        build_central_directory_file_header(
+            &entry,
+            &ArchiveOptions::default(),
+            &mut central_directory_header,
+        );
+
+        let header = central_directory_header.buffer();
+        assert_eq!(
+            &header[20..28],
+            &[0x00, 0x00, 0x00, 0x40, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(&header[42..46], &[0xff; 4]);
+
+        let extra_field = &header[46 + 7..];
+        assert_eq!(&extra_field[..4], &[0x01, 0x00, 16, 0]);
+        assert_eq!(&extra_field[4..12], &(5u64 << 30).to_le_bytes());
+        assert_eq!(&extra_field[12..20], &(6u64 << 30).to_le_bytes());
+    }
+
+    #[test]
+    fn test_recorded_compression_level() {
+        let options = FileOptions::default().compression_level(Level::Best);
+        let (file_header, mut entry) = build_file_header(
+            "a.txt",
+            &options,
+            CompressionMethod::Deflate(),
+            0,
+            false,
+            false,
+        );
+        assert!(matches!(entry.compressor(), CompressionMethod::Deflate()));
+        assert_eq!(entry.compression_level(), Level::Best);
+
+        // the level is read back from the Deflate option bits
+        let read = ArchiveDescriptor::read_file_descriptor(file_header.buffer()).unwrap();
+        assert_eq!(read.compression_level(), Level::Best);
+
+        recompress_entry_header(
+            &mut entry,
+            file_header.buffer(),
+            CompressionMethod::Store(),
+            Level::Best,
+            0,
+        )
+        .unwrap();
+        assert!(matches!(entry.compressor(), CompressionMethod::Store()));
+        assert_eq!(entry.compression_level(), Level::None);
+    }
+
+    #[test]
+    fn test_recompress_entry_padding() {
+        let options = FileOptions::default();
+        let (file_header, mut entry) = build_file_header(
+            "a.txt",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+        let extra_field_length = entry.extra_field_length;
+        entry.compressed_size = 100_000;
+
+        for gap in [1, 7, 100_000] {
+            let result = recompress_entry_header(
+                &mut entry.clone(),
+                file_header.buffer(),
+                CompressionMethod::Deflate(),
+                Level::Default,
+                100_000 - gap,
+            );
+            assert!(matches!(
+                result,
+                Err(ArchiveError::RecompressedEntryGap { .. })
+            ));
+        }
+
+        let (fields, padding) = recompress_entry_header(
+            &mut entry,
+            file_header.buffer(),
+            CompressionMethod::Deflate(),
+            Level::Default,
+            100_000 - 40,
+        )
+        .unwrap();
+        assert_eq!(padding.buffer().len(), 40);
+        assert_eq!(
+            &padding.buffer()[..8],
+            &[0x20, 0xa2, 36, 0, 0x28, 0xa0, 32, 0]
+        );
+        assert!(padding.buffer()[8..].iter().all(|&byte| byte == 0));
+        // the extra field length, the last of the fields, covers the padding
+        let fields = fields.buffer();
+        assert_eq!(
+            u16::from_le_bytes([fields[fields.len() - 2], fields[fields.len() - 1]]),
+            extra_field_length + 40
+        );
+    }
+
+    #[test]
+    fn test_crc_field_offset() {
+        let options = FileOptions::default();
+        let (file_header, entry) = build_file_header(
+            "a.txt",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+
+        let offset = entry.crc_field_offset() as usize;
+        let header = file_header.buffer();
+        assert_eq!(
+            &header[offset - 2..offset],
+            &entry.last_mod_file_date.to_le_bytes()
+        );
+        assert_eq!(&header[offset..offset + 12], &[0; 12]);
+    }
+
+    #[test]
+    fn test_central_directory_exact_size() {
+        let options = FileOptions::default();
+        let mut data = SubZipArchiveData::default();
+        for (i, name) in ["a.txt", "dir/b.txt", "big.bin"].iter().enumerate() {
+            let (_, mut entry) =
+                build_file_header(name, &options, CompressionMethod::Store(), 0, true, true);
+            entry.uncompressed_size = (i as u64) << 31;
+            entry.offset = (i as u64) << 31;
+            data.files_info.push(entry);
+        }
+
+        let central_directory = build_central_directory(&data);
+        let expected_size: u64 = data
+            .files_info
+            .iter()
+            .map(central_directory_entry_size)
+            .sum();
+        assert_eq!(central_directory.buffer().len() as u64, expected_size);
+        assert_eq!(
+            central_directory.buffer().len(),
+            central_directory.buffer.capacity()
+        );
+    }
+
+    #[test]
+    fn test_zip64_central_directory_end() {
+        let mut data = SubZipArchiveData::default();
+        assert!(matches!(
+            build_central_directory_end(&data, 5 << 30, 100),
+            Err(ArchiveError::Zip64Required {
+                field: "central directory offset"
+            })
+        ));
+
+        data.options = ArchiveOptions::default().zip64(true);
+        let end = build_central_directory_end(&data, 5 << 30, 100).unwrap();
+        let end = end.buffer();
+        assert_eq!(
+            end.len() as u64,
+            ZIP64_CENTRAL_DIRECTORY_END_SIZE
+                + ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIZE
+                + END_OF_CENTRAL_DIRECTORY_SIZE
+        );
+        assert_eq!(&end[48..56], &(5u64 << 30).to_le_bytes());
+        assert_eq!(&end[64..72], &((5u64 << 30) + 100).to_le_bytes());
+        assert_eq!(&end[92..96], &[0xff; 4]);
+
+        // no ZIP64 record when nothing overflows
+        let end = build_central_directory_end(&data, 1000, 100).unwrap();
+        assert_eq!(end.len() as u64, END_OF_CENTRAL_DIRECTORY_SIZE);
+    }
+
+    #[test]
+    fn test_extended_timestamp() {
+        let options = FileOptions::default().with_unix_timestamps(Some(1), None, Some(3));
+        let (file_header, entry) = build_file_header(
+            "a.txt",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+
+        let header = file_header.buffer();
+        assert_eq!(header.len() as u64, FILE_HEADER_BASE_SIZE + 5 + 13);
+        assert_eq!(&header[28..30], &13u16.to_le_bytes());
+        assert_eq!(
+            &header[35..],
+            &[0x55, 0x54, 9, 0, 0b101, 1, 0, 0, 0, 3, 0, 0, 0]
+        );
+        assert_eq!(entry.extra_field, vec![0x55, 0x54, 5, 0, 0b101, 1, 0, 0, 0]);
+
+        let options = FileOptions::default().with_unix_timestamps(None, None, None);
+        let (file_header, entry) = build_file_header(
+            "a.txt",
+            &options,
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+        assert_eq!(file_header.len() as u64, FILE_HEADER_BASE_SIZE + 5);
+        assert!(entry.extra_field.is_empty());
+    }
+
     #[test]
     fn test_mem_dump() {
         let vec: Vec<u8> = vec![
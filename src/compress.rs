@@ -1,3 +1,5 @@
+#[cfg(feature = "futures")]
+pub mod futures;
 #[cfg(feature = "std")]
 pub mod std;
 #[cfg(feature = "tokio")]
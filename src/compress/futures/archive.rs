@@ -0,0 +1,119 @@
+//! Streaming archive over the `futures::io` traits, for the `async-std` or `smol` runtimes.
+//!
+//! The archive drives the tokio backend through the `tokio-util` compatibility adapters: the
+//! tokio IO traits and compressors do not need a tokio runtime, so no tokio runtime is started.
+
+use crate::archive::{ArchiveOptions, FileOptions, ListOptions};
+use crate::compress::tokio::archive::ZipArchive as TokioZipArchive;
+use crate::error::ArchiveError;
+
+use futures_io::{AsyncRead, AsyncWrite};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt};
+
+/// Streaming zip archive writing to a `futures::io::AsyncWrite`.
+///
+/// Same as the tokio `ZipArchive`: entries are written with data descriptors, so the sink does
+/// not need to seek.
+///
+/// Requires the `futures` feature.
+#[derive(Debug)]
+pub struct ZipArchive<W: AsyncWrite + Unpin> {
+    archive: TokioZipArchive<Compat<W>>,
+}
+
+impl<W: AsyncWrite + Unpin> ZipArchive<W> {
+    /// Create a new zip archive, using the underlying `AsyncWrite` to write files' header and payload.
+    pub fn new(sink: W) -> Self {
+        Self::with_options(sink, ArchiveOptions::default())
+    }
+
+    /// Create a new zip archive with the given archive-wide options.
+    pub fn with_options(sink: W, options: ArchiveOptions) -> Self {
+        Self {
+            archive: TokioZipArchive::with_options(sink.compat_write(), options),
+        }
+    }
+
+    /// Append a new file to the archive, see the tokio `ZipArchive::append_file`.
+    pub async fn append_file<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.archive
+            .append_file(file_name, &mut reader.compat(), options)
+            .await
+    }
+
+    /// Set the options used by `append_file_default`.
+    pub fn set_default_options(&mut self, options: FileOptions) {
+        self.archive.set_default_options(options);
+    }
+
+    /// Append a new file to the archive with the options set by `set_default_options`.
+    pub async fn append_file_default<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.archive
+            .append_file_default(file_name, &mut reader.compat())
+            .await
+    }
+
+    /// Set the archive comment, truncated to 65535 bytes; returns the length kept.
+    pub fn set_archive_comment(&mut self, comment: &str) -> usize {
+        self.archive.set_archive_comment(comment)
+    }
+
+    /// Print the entries appended so far, see the tokio `ZipArchive::list`.
+    pub fn list<T: std::io::Write>(&self, writer: &mut T) -> Result<(), ArchiveError> {
+        self.archive.list(writer)
+    }
+
+    /// Print the entries appended so far, with the given layout options.
+    pub fn list_with_options<T: std::io::Write>(
+        &self,
+        writer: &mut T,
+        options: &ListOptions,
+    ) -> Result<(), ArchiveError> {
+        self.archive.list_with_options(writer, options)
+    }
+
+    /// Finalize the archive by writing the central directory and flushing the sink.
+    pub async fn finalize(&mut self) -> Result<(), ArchiveError> {
+        self.archive.finalize().await
+    }
+
+    pub fn get_archive_size(&self) -> u64 {
+        self.archive.get_archive_size()
+    }
+
+    /// Whether `finalize` completed, i.e. the archive is complete and the sink flushed.
+    pub fn is_finalized(&self) -> bool {
+        self.archive.is_finalized()
+    }
+
+    /// Give back the underlying writer, see the tokio `ZipArchive::retrieve_writer`.
+    pub fn retrieve_writer(self) -> W {
+        self.archive.retrieve_writer().into_inner()
+    }
+
+    /// Reference to the underlying writer.
+    pub fn writer_ref(&self) -> &W {
+        self.archive.writer_ref().get_ref()
+    }
+
+    /// Mutable reference to the underlying writer. Bytes written directly to it are not counted
+    /// and corrupt the archive.
+    pub fn writer_mut(&mut self) -> &mut W {
+        self.archive.writer_mut().get_mut()
+    }
+}
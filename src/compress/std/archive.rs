@@ -1,18 +1,20 @@
 use super::compressor::compress;
 use super::write_wrapper::{BytesCounter, WriteSeekWrapper, WriteWrapper};
 
-use crate::archive::FileOptions;
+use crate::archive::{ArchiveOptions, ExtractOptions, FileOptions, ListOptions, PlannedEntry};
 use crate::archive_common::{
-    build_central_directory_end, build_central_directory_file_header, build_file_header,
-    ArchiveDescriptor, SubZipArchiveData, ZipArchiveCommon,
-};
-use crate::compression::Level;
-use crate::constants::{
-    CENTRAL_DIRECTORY_ENTRY_BASE_SIZE, DATA_DESCRIPTOR_SIGNATURE, DESCRIPTOR_SIZE,
-    FILE_HEADER_CRC_OFFSET,
+    build_central_directory, build_central_directory_end, build_data_descriptor, build_file_header,
+    build_sha256_manifest, central_directory_total_size, check_header_offset, check_recompressible,
+    check_spec_conformance, check_zip32_sizes, estimate_archive_size, normalize_entry_name,
+    recompress_entry_header, spanning_marker, stale_bytes_gap, write_entry_listing, zip32,
+    ArchiveDescriptor, EntryHasher, EntryStatsTimer, SubZipArchiveData, ZipArchiveCommon,
 };
+use crate::compression::CompressionMethod;
+use crate::constants::{ARCHIVE_TAIL_MAX_SIZE, FILE_HEADER_BASE_SIZE};
 use crate::error::ArchiveError;
-use crc32fast::Hasher;
+use crate::uncompress::{
+    decompress_entry, local_header_size, read_archive_tail, read_central_directory_entries,
+};
 use std::io::{Read, Seek, SeekFrom, Write};
 
 #[derive(Debug)]
@@ -44,45 +46,186 @@ impl<W: Write> ZipArchiveCommon for ZipArchive<W> {
 impl<W: Write> ZipArchive<W> {
     /// Create a new zip archive, using the underlying `Write` to write files' header and payload.
     pub fn new(sink_: W) -> Self {
+        Self::with_options(sink_, ArchiveOptions::default())
+    }
+
+    /// Create a new zip archive with the given archive-wide options.
+    pub fn with_options(sink_: W, options: ArchiveOptions) -> Self {
+        let sink = WriteWrapper::new(sink_).with_flush_every(options.flush_every);
         let mut data = SubZipArchiveData::default();
         data.data_descriptor = true;
-        Self {
-            sink: WriteWrapper::new(sink_),
-            data,
-        }
+        data.options = options;
+        Self { sink, data }
     }
 
     pub fn get_archive_size(&mut self) -> Result<u64, ArchiveError> {
         Ok(self.sink.get_written_bytes_count()?)
     }
 
+    /// Exact number of bytes `finalize` adds for the entries appended so far: the central
+    /// directory, the end of central directory record (ZIP64 records included when needed) and
+    /// the archive comment.
+    ///
+    /// The SHA-256 manifest entry, appended by `finalize` when enabled, is not accounted for.
+    pub fn estimate_central_directory_size(&self) -> u64 {
+        central_directory_total_size(&self.data, self.data.archive_size)
+    }
+
     pub fn retrieve_writer(self) -> W {
         self.sink.get_into()
     }
 
+    /// Reference to the underlying writer, e.g. to inspect what is written so far without
+    /// consuming the archive.
+    pub fn writer_ref(&self) -> &W {
+        self.sink.get_ref()
+    }
+
+    /// Mutable reference to the underlying writer.
+    ///
+    /// The archive keeps its own count of the bytes written, from which the entry offsets are
+    /// computed: bytes written directly to the writer are not counted and corrupt the archive.
+    pub fn writer_mut(&mut self) -> &mut W {
+        self.sink.get_mut()
+    }
+
+    /// Exact size of the archive once the planned entries are appended and it is finalized, e.g.
+    /// to send a `Content-Length` header before streaming it.
+    ///
+    /// The entries already appended are accounted for. Returns `None` unless every planned entry
+    /// is stored (`CompressionMethod::Store`), as the size of compressed data cannot be known
+    /// upfront; also when the SHA-256 manifest is enabled.
+    pub fn estimated_size(&self, planned_entries: &[PlannedEntry]) -> Option<u64> {
+        estimate_archive_size(
+            &self.data,
+            self.data.archive_size,
+            self.data.data_descriptor,
+            planned_entries,
+        )
+    }
+
+    /// Print the entries appended so far as a table, in the manner of `unzip -l`.
+    ///
+    /// Columns are the uncompressed size, compressed size, compression ratio, date, time and name,
+    /// followed by a line of totals. The listing is padded to 80 characters.
+    ///
+    /// # Error
+    ///
+    /// This function will forward any error found while writing to `writer`.
+    pub fn list<T: std::io::Write>(&self, writer: &mut T) -> Result<(), ArchiveError> {
+        self.list_with_options(writer, &ListOptions::default())
+    }
+
+    /// Print the entries appended so far, with the given layout options. See `list`.
+    pub fn list_with_options<T: std::io::Write>(
+        &self,
+        writer: &mut T,
+        options: &ListOptions,
+    ) -> Result<(), ArchiveError> {
+        write_entry_listing(&self.data.files_info, writer, options)
+    }
+
     /// Append a new file to the archive using the provided name, date/time and `AsyncRead` object.  
     /// Filename must be valid UTF-8. Some (very) old zip utilities might mess up filenames during extraction if they contain non-ascii characters.  
     /// File's payload is not compressed and is given `rw-r--r--` permissions.
     ///
+    /// Returns the CRC-32 of the uncompressed payload, as recorded in the archive, e.g. to check
+    /// the entry against an external checksum without reading the archive back.
+    ///
     /// # Error
     ///
-    /// This function will forward any error found while trying to read from the file stream or while writing to the underlying sink.
+    /// This function will forward any error found while trying to read from the file stream or while writing to the underlying sink,
+    /// wrapped in an `ArchiveError::Entry` giving the entry name and the offset of its local header.
+    ///
+    /// All writes go through `std::io::Write::write_all`, which retries a write failing with
+    /// `ErrorKind::Interrupted`. A sink that keeps accepting no bytes fails the entry with
+    /// `ArchiveError::WriteZero`.
     ///
     /// # Features
     ///
-    /// Requires `tokio-async-io` feature. `futures-async-io` is also available.
-
+    /// Requires the `std` feature.
     pub fn append_file<R>(
         &mut self,
         file_name: &str,
         reader: &mut R,
         options: &FileOptions,
-    ) -> Result<(), ArchiveError>
+    ) -> Result<u32, ArchiveError>
     where
         W: Write,
         R: Read,
     {
+        let file_header_offset = self.data.archive_size;
         append_file_std_common(&mut self.sink, &mut self.data, file_name, reader, options)
+            .map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    /// Append a new file to the archive, taking ownership of the reader, which is dropped once
+    /// the entry is written.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub fn append_file_owned<R>(
+        &mut self,
+        file_name: &str,
+        mut reader: R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: Write,
+        R: Read,
+    {
+        self.append_file(file_name, &mut reader, options)
+    }
+
+    /// Set the archive comment, written in the end of central directory record. It is truncated
+    /// to the last whole character within 65535 bytes, the largest comment the record can hold.
+    ///
+    /// Returns the length in bytes of the comment kept, shorter than `comment.len()` when it was
+    /// truncated.
+    pub fn set_archive_comment(&mut self, comment: &str) -> usize {
+        self.data.set_archive_comment(comment)
+    }
+
+    /// The archive comment, as set by `set_archive_comment`; empty if there is none.
+    pub fn comment_bytes(&self) -> &[u8] {
+        self.data.archive_comment_bytes()
+    }
+
+    /// The archive comment, `None` if there is none or it is not valid UTF-8.
+    pub fn comment(&self) -> Option<&str> {
+        self.data.archive_comment()
+    }
+
+    /// The archive comment, or an empty string if there is none or it is not valid UTF-8.
+    pub fn comment_or_empty(&self) -> &str {
+        self.comment().unwrap_or_default()
+    }
+
+    /// Set the options used by `append_file_default`, e.g. to share a compression method and level
+    /// between most entries while a few are appended with their own options by `append_file`.
+    ///
+    /// The default is `FileOptions::default()`.
+    pub fn set_default_options(&mut self, options: FileOptions) {
+        self.data.default_file_options = options;
+    }
+
+    /// Append a new file to the archive with the options set by `set_default_options`.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub fn append_file_default<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: Write,
+        R: Read,
+    {
+        let options = self.data.default_file_options.clone();
+        self.append_file(file_name, reader, &options)
     }
 
     /// Finalize the archive by writing the necessary metadata to the end of the archive.
@@ -93,12 +236,12 @@ impl<W: Write> ZipArchive<W> {
     ///
     /// # Features
     ///
-    /// Requires `tokio-async-io` feature. `futures-async-io` is also available.
+    /// Requires the `std` feature.
     pub fn finalize(mut self) -> Result<(u64, W), ArchiveError>
     where
         W: Write,
     {
-        self.data.archive_size = finalize_std_comon(&mut self.sink, &self.data)?;
+        self.data.archive_size = finalize_std_comon(&mut self.sink, &mut self.data)?;
 
         Ok((self.data.archive_size, self.sink.get_into()))
     }
@@ -106,24 +249,158 @@ impl<W: Write> ZipArchive<W> {
 
 impl<W: Write + Seek> ZipArchiveNoStream<W> {
     pub fn new(sink: W) -> Self {
-        //let buf = BufWriter::new(sink_);
+        Self::with_options(sink, ArchiveOptions::default())
+    }
+
+    /// Create a new zip archive with the given archive-wide options.
+    pub fn with_options(sink: W, options: ArchiveOptions) -> Self {
+        let mut data = SubZipArchiveData::default();
+        data.options = options;
         Self {
             sink: WriteSeekWrapper::new(sink),
-            data: SubZipArchiveData::default(),
+            data,
         }
     }
 
+    /// Open an existing archive to append new entries to it.
+    ///
+    /// The central directory of the archive is read back, and the sink is positioned at its start
+    /// so it gets overwritten, with the new entries, on `finalize`.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::BadArchiveStructure` if the end of central directory record cannot be found.
+    pub fn from_existing(sink: W) -> Result<Self, ArchiveError>
+    where
+        W: Read,
+    {
+        Self::from_existing_with_options(sink, ArchiveOptions::default())
+    }
+
+    /// Open an existing archive to append new entries to it, with the given archive-wide options.
+    ///
+    /// See `from_existing`.
+    pub fn from_existing_with_options(
+        mut sink: W,
+        options: ArchiveOptions,
+    ) -> Result<Self, ArchiveError>
+    where
+        W: Read,
+    {
+        let file_length = sink.seek(SeekFrom::End(0))?;
+        let tail_length = file_length.min(ARCHIVE_TAIL_MAX_SIZE);
+        sink.seek(SeekFrom::Start(file_length - tail_length))?;
+        let mut tail = vec![0; tail_length as usize];
+        sink.read_exact(&mut tail)?;
+
+        let (central_directory_end, comment) = read_archive_tail(&tail)?;
+        let central_directory_offset = central_directory_end.offset;
+
+        sink.seek(SeekFrom::Start(central_directory_offset))?;
+        let mut central_directory = vec![0; central_directory_end.size as usize];
+        sink.read_exact(&mut central_directory)?;
+        let files_info =
+            read_central_directory_entries(&central_directory, central_directory_end.entries)?;
+
+        sink.seek(SeekFrom::Start(central_directory_offset))?;
+
+        let mut archive = Self::with_options(sink, options);
+        archive.data.files_info = files_info;
+        archive.data.set_archive_comment_bytes(comment);
+        archive.data.archive_size = central_directory_offset;
+
+        Ok(archive)
+    }
+
+    /// Append a new file to the archive using the provided name, options and `Read` object.
+    /// Returns the CRC-32 of the payload.
+    ///
+    /// # Error
+    ///
+    /// Same as the streaming `ZipArchive::append_file`.
     pub fn append_file<R>(
         &mut self,
         file_name: &str,
         reader: &mut R,
         options: &FileOptions,
-    ) -> Result<(), ArchiveError>
+    ) -> Result<u32, ArchiveError>
     where
         W: Write + Seek,
         R: Read,
     {
+        let file_header_offset = self.data.archive_size;
         append_file_std_common(&mut self.sink, &mut self.data, file_name, reader, options)
+            .map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    /// Append a new file to the archive, taking ownership of the reader, which is dropped once
+    /// the entry is written.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub fn append_file_owned<R>(
+        &mut self,
+        file_name: &str,
+        mut reader: R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: Write + Seek,
+        R: Read,
+    {
+        self.append_file(file_name, &mut reader, options)
+    }
+
+    /// Set the archive comment, written in the end of central directory record. It is truncated
+    /// to the last whole character within 65535 bytes, the largest comment the record can hold.
+    ///
+    /// Returns the length in bytes of the comment kept, shorter than `comment.len()` when it was
+    /// truncated.
+    pub fn set_archive_comment(&mut self, comment: &str) -> usize {
+        self.data.set_archive_comment(comment)
+    }
+
+    /// The archive comment, as set by `set_archive_comment` or read by `from_existing`; empty if
+    /// there is none.
+    pub fn comment_bytes(&self) -> &[u8] {
+        self.data.archive_comment_bytes()
+    }
+
+    /// The archive comment, `None` if there is none or it is not valid UTF-8.
+    pub fn comment(&self) -> Option<&str> {
+        self.data.archive_comment()
+    }
+
+    /// The archive comment, or an empty string if there is none or it is not valid UTF-8.
+    pub fn comment_or_empty(&self) -> &str {
+        self.comment().unwrap_or_default()
+    }
+
+    /// Set the options used by `append_file_default`, e.g. to share a compression method and level
+    /// between most entries while a few are appended with their own options by `append_file`.
+    ///
+    /// The default is `FileOptions::default()`.
+    pub fn set_default_options(&mut self, options: FileOptions) {
+        self.data.default_file_options = options;
+    }
+
+    /// Append a new file to the archive with the options set by `set_default_options`.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub fn append_file_default<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: Write + Seek,
+        R: Read,
+    {
+        let options = self.data.default_file_options.clone();
+        self.append_file(file_name, reader, &options)
     }
 
     /// Finalize the archive by writing the necessary metadata to the end of the archive.
@@ -134,12 +411,12 @@ impl<W: Write + Seek> ZipArchiveNoStream<W> {
     ///
     /// # Features
     ///
-    /// Requires `tokio-async-io` feature. `futures-async-io` is also available.
+    /// Requires the `std` feature.
     pub fn finalize(mut self) -> Result<(u64, W), ArchiveError>
     where
         W: Write,
     {
-        self.data.archive_size = finalize_std_comon(&mut self.sink, &self.data)?;
+        self.data.archive_size = finalize_std_comon(&mut self.sink, &mut self.data)?;
 
         Ok((self.data.archive_size, self.sink.get_into()))
     }
@@ -147,6 +424,156 @@ impl<W: Write + Seek> ZipArchiveNoStream<W> {
     pub fn get_archive_size(&mut self) -> Result<u64, ArchiveError> {
         Ok(self.sink.get_written_bytes_count()?)
     }
+
+    /// Exact number of bytes `finalize` adds for the entries appended so far: the central
+    /// directory, the end of central directory record (ZIP64 records included when needed) and
+    /// the archive comment.
+    ///
+    /// The SHA-256 manifest entry, appended by `finalize` when enabled, is not accounted for.
+    pub fn estimate_central_directory_size(&self) -> u64 {
+        central_directory_total_size(&self.data, self.data.archive_size)
+    }
+
+    /// Reference to the underlying writer, e.g. to inspect what is written so far without
+    /// consuming the archive.
+    pub fn writer_ref(&self) -> &W {
+        self.sink.get_ref()
+    }
+
+    /// Mutable reference to the underlying writer.
+    ///
+    /// The archive keeps its own count of the bytes written, from which the entry offsets are
+    /// computed: bytes written directly to the writer are not counted and corrupt the archive.
+    /// Moving its position corrupts the archive as well.
+    pub fn writer_mut(&mut self) -> &mut W {
+        self.sink.get_mut()
+    }
+
+    /// Replace the last appended entry, keeping its name.
+    ///
+    /// The sink is rewound to the local header of the last entry, which is written again with the
+    /// new payload and options. If the new entry is shorter, `finalize` zero-fills the rest of the
+    /// previous one before the central directory, so that no stale byte is left after the end of
+    /// central directory record.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::BadArchiveStructure` if no entry was appended yet, otherwise same as `append_file`.
+    pub fn replace_last_entry<R>(
+        &mut self,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: Read,
+    {
+        let last_entry =
+            self.data.files_info.pop().ok_or_else(|| {
+                ArchiveError::BadArchiveStructure("No entry to replace".to_owned())
+            })?;
+        let file_name = String::from_utf8_lossy(&last_entry.file_name_as_bytes).into_owned();
+
+        self.data.written_end = self.data.written_end.max(self.data.archive_size);
+        self.sink.seek(SeekFrom::Start(last_entry.offset))?;
+        self.data.archive_size = last_entry.offset;
+
+        self.append_file(&file_name, reader, options)
+    }
+
+    /// Recompress an entry in place with another compression method, e.g. from `Store` to
+    /// `Deflate`, without rebuilding the archive.
+    ///
+    /// The payload is read back, decompressed, compressed again with `new_method` and the level of
+    /// `options`, then written over the previous payload; the local header and the central
+    /// directory record are updated. The following entries do not move, so the new payload must
+    /// fit in place of the previous one; a smaller payload is moved forward behind a zero-filled
+    /// padding extra field of the local header, so that no stale byte is left.
+    ///
+    /// # Error
+    ///
+    /// * `ArchiveError::EntryNotFound` if no entry has this name.
+    /// * `ArchiveError::RecompressedEntryTooLarge` if the new payload is larger than the previous
+    ///   one, in which case the archive is left unchanged.
+    /// * `ArchiveError::RecompressedEntryGap` if the new payload is smaller by less than 8 bytes,
+    ///   or by more than the local extra field can hold, the archive being left unchanged too.
+    /// * `ArchiveError::UnsuportedCompressionMethod` if the entry is neither `Store` nor `Deflate`.
+    /// * `ArchiveError::BadArchiveStructure` if the entry has a data descriptor or ZIP64 fields.
+    ///
+    /// Errors other than `EntryNotFound` are wrapped in an `ArchiveError::Entry`.
+    pub fn recompress_entry(
+        &mut self,
+        file_name: &str,
+        new_method: CompressionMethod,
+        options: &FileOptions,
+    ) -> Result<(), ArchiveError>
+    where
+        W: Read,
+    {
+        let index = self
+            .data
+            .files_info
+            .iter()
+            .position(|entry| entry.file_name_as_bytes == file_name.as_bytes())
+            .ok_or_else(|| ArchiveError::EntryNotFound(file_name.to_owned()))?;
+        let file_header_offset = self.data.files_info[index].offset;
+
+        let result = self.recompress_entry_in_place(index, new_method, options);
+        self.sink.seek(SeekFrom::Start(self.data.archive_size))?;
+        result.map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    fn recompress_entry_in_place(
+        &mut self,
+        index: usize,
+        new_method: CompressionMethod,
+        options: &FileOptions,
+    ) -> Result<(), ArchiveError>
+    where
+        W: Read,
+    {
+        let options = options.clone().compression_method(new_method);
+        options.validate()?;
+
+        let mut entry = self.data.files_info[index].clone();
+        check_recompressible(&entry)?;
+
+        let reader = self.sink.get_mut();
+        let mut header = [0; FILE_HEADER_BASE_SIZE as usize];
+        reader.seek(SeekFrom::Start(entry.offset))?;
+        reader.read_exact(&mut header)?;
+        let data_offset = entry.offset + local_header_size(&header)?;
+
+        let mut compressed_data = vec![0; entry.compressed_size as usize];
+        reader.seek(SeekFrom::Start(data_offset))?;
+        reader.read_exact(&mut compressed_data)?;
+        let data = decompress_entry(&entry, &compressed_data, &ExtractOptions::default())?;
+
+        let new_method = options.effective_compressor();
+        let mut recompressed = Vec::new();
+        compress(
+            new_method,
+            &mut recompressed,
+            &mut data.as_slice(),
+            &mut EntryHasher::new(),
+            options.compression_level,
+        )?;
+        let (header_fields, padding) = recompress_entry_header(
+            &mut entry,
+            &header,
+            new_method,
+            options.compression_level,
+            recompressed.len() as u64,
+        )?;
+
+        self.sink.seek(SeekFrom::Start(data_offset))?;
+        self.sink.write_all(padding.buffer())?;
+        self.sink.write_all(&recompressed)?;
+        self.sink.seek(SeekFrom::Start(entry.offset + 4))?;
+        self.sink.write_all(header_fields.buffer())?;
+
+        self.data.files_info[index] = entry;
+        Ok(())
+    }
 }
 
 fn append_file_std_common<W, R>(
@@ -155,46 +582,60 @@ fn append_file_std_common<W, R>(
     file_name: &str,
     reader: &mut R,
     options: &FileOptions,
-) -> Result<(), ArchiveError>
+) -> Result<u32, ArchiveError>
 where
     W: BytesCounter + Write + Seek,
     R: Read,
 {
+    options.validate()?;
+    let mut hasher = EntryHasher::with_sha256(data.options.sha256_manifest);
+    let compressor = options.effective_compressor();
+    let stats_timer = EntryStatsTimer::start(options);
+
+    let file_name = normalize_entry_name(&data.options, file_name)?;
+
+    if let Some(marker) = spanning_marker(data) {
+        sink.write_all(&marker)?;
+        data.archive_size += marker.len() as u64;
+        data.spanning_marker_written = true;
+    }
     let file_header_offset = data.archive_size;
-    let mut hasher = Hasher::new();
-    let compressor = options.compressor;
 
     let (file_header, mut archive_file_entry) = build_file_header(
-        file_name,
+        &file_name,
         options,
         compressor,
-        file_header_offset as u32,
+        check_header_offset(&data.options, file_header_offset)?,
         data.data_descriptor,
+        data.data_descriptor && data.options.zip64,
     );
 
     sink.write_all(file_header.buffer())?;
 
     let file_begin = sink.stream_position()?;
 
-    let uncompressed_size = compress(compressor, sink, reader, &mut hasher, Level::Default)?;
+    let uncompressed_size = compress(
+        compressor,
+        sink,
+        reader,
+        &mut hasher,
+        options.compression_level,
+    )?;
 
     let archive_size = sink.stream_position()?;
     let compressed_size = archive_size - file_begin;
 
-    let crc32 = hasher.finalize();
+    let digest = hasher.finalize();
+    let crc32 = digest.crc32;
     archive_file_entry.crc32 = crc32;
+    archive_file_entry.sha256 = digest.sha256;
     archive_file_entry.compressed_size = compressed_size;
     archive_file_entry.uncompressed_size = uncompressed_size;
+    check_zip32_sizes(&archive_file_entry)?;
+    stats_timer.report(&archive_file_entry);
 
     if data.data_descriptor {
-        let mut file_descriptor = ArchiveDescriptor::new(DESCRIPTOR_SIZE);
-        file_descriptor.write_u32(DATA_DESCRIPTOR_SIGNATURE);
-        set_sizes(
-            &mut file_descriptor,
-            crc32,
-            compressed_size,
-            uncompressed_size,
-        );
+        sink.write_all(build_data_descriptor(&archive_file_entry).buffer())?;
     } else {
         let mut file_descriptor = ArchiveDescriptor::new(3 * 4);
         set_sizes(
@@ -202,10 +643,12 @@ where
             crc32,
             compressed_size,
             uncompressed_size,
-        );
+        )?;
 
         //position in the the file header
-        sink.seek(SeekFrom::Start(file_header_offset + FILE_HEADER_CRC_OFFSET))?;
+        sink.seek(SeekFrom::Start(
+            file_header_offset + archive_file_entry.crc_field_offset(),
+        ))?;
 
         sink.write_all(file_descriptor.buffer())?;
 
@@ -216,7 +659,7 @@ where
 
     data.archive_size = sink.get_written_bytes_count()?;
 
-    Ok(())
+    Ok(crc32)
 }
 
 fn set_sizes(
@@ -224,33 +667,42 @@ fn set_sizes(
     crc32: u32,
     compressed_size: u64,
     uncompressed_size: u64,
-) {
+) -> Result<(), ArchiveError> {
     file_descriptor.write_u32(crc32);
-    file_descriptor.write_u32(compressed_size as u32);
-    file_descriptor.write_u32(uncompressed_size as u32);
+    file_descriptor.write_u32(zip32(compressed_size, "compressed size")?);
+    file_descriptor.write_u32(zip32(uncompressed_size, "uncompressed size")?);
+    Ok(())
 }
 
-fn finalize_std_comon<T>(sink: &mut T, data: &SubZipArchiveData) -> Result<u64, ArchiveError>
+fn finalize_std_comon<T>(sink: &mut T, data: &mut SubZipArchiveData) -> Result<u64, ArchiveError>
 where
-    T: BytesCounter + Write,
+    T: BytesCounter + Write + Seek,
 {
-    let central_directory_offset = sink.get_written_bytes_count()? as u32;
-
-    let mut central_directory_header =
-        ArchiveDescriptor::new(CENTRAL_DIRECTORY_ENTRY_BASE_SIZE + 200);
-
-    for file_info in &data.files_info {
-        build_central_directory_file_header(&mut central_directory_header, file_info);
+    if data.options.sha256_manifest {
+        let manifest = build_sha256_manifest(&data.files_info);
+        let name = data.options.sha256_manifest_name.clone();
+        let options = data.options.sha256_manifest_options.clone();
+        append_file_std_common(sink, data, &name, &mut manifest.as_slice(), &options)
+            .map_err(|e| e.in_entry(&name, data.archive_size))?;
+    }
 
-        sink.write_all(central_directory_header.buffer())?;
-        central_directory_header.clear();
+    if let Some(marker) = spanning_marker(data) {
+        sink.write_all(&marker)?;
+        data.spanning_marker_written = true;
+    }
+    let gap = stale_bytes_gap(data, sink.get_written_bytes_count()?);
+    std::io::copy(&mut std::io::repeat(0).take(gap), sink)?;
+    let central_directory_offset = sink.get_written_bytes_count()?;
+    if data.options.strict {
+        check_spec_conformance(data, central_directory_offset)?;
     }
 
-    let current_archive_size = sink.get_written_bytes_count()?;
-    let central_directory_size: u32 = current_archive_size as u32 - central_directory_offset;
+    let central_directory = build_central_directory(data);
+    sink.write_all(central_directory.buffer())?;
+    let central_directory_size = central_directory.buffer().len() as u64;
 
     let end_of_central_directory =
-        build_central_directory_end(data, central_directory_offset, central_directory_size);
+        build_central_directory_end(data, central_directory_offset, central_directory_size)?;
 
     sink.write_all(end_of_central_directory.buffer())?;
 
@@ -1,13 +1,16 @@
 use std::io::{Read, Write};
 
+#[cfg(feature = "bzip2")]
 use bzip2::write::BzEncoder;
-use crc32fast::Hasher;
 use flate2::{write::DeflateEncoder, Compression};
+#[cfg(feature = "xz")]
 use xz2::stream::{LzmaOptions, Stream};
 
+#[cfg(feature = "xz")]
 use xz2::write::XzEncoder;
 
 use crate::{
+    archive_common::EntryHasher,
     compression::{CompressionMethod, Level},
     error::ArchiveError,
 };
@@ -45,6 +48,7 @@ impl From<Level> for flate2::Compression {
     }
 }
 
+#[cfg(feature = "bzip2")]
 impl From<Level> for bzip2::Compression {
     fn from(level: Level) -> Self {
         match level {
@@ -73,7 +77,7 @@ pub fn compress<'a, R, W>(
     compressor: CompressionMethod,
     writer: &'a mut W,
     reader: &'a mut R,
-    hasher: &'a mut Hasher,
+    hasher: &'a mut EntryHasher,
     compression_level: Level,
 ) -> Result<u64, ArchiveError>
 where
@@ -107,6 +111,7 @@ where
             Ok(total_read)
         }
 
+        #[cfg(feature = "bzip2")]
         CompressionMethod::BZip2() => {
             let mut encoder = BzEncoder::new(writer, compression_level.into());
 
@@ -114,6 +119,7 @@ where
 
             Ok(total_read)
         }
+        #[cfg(feature = "xz")]
         CompressionMethod::Lzma() => {
             let stream = compress_lzma(compression_level)?;
             let mut encoder = XzEncoder::new_stream(writer, stream);
@@ -121,6 +127,7 @@ where
             let total_read = compress_common!(encoder, hasher, reader);
             Ok(total_read)
         }
+        #[cfg(feature = "zstd")]
         CompressionMethod::Zstd() => {
             let zstd_compression_level = match compression_level {
                 Level::Fastest => Ok(1),
@@ -135,6 +142,7 @@ where
 
             Ok(total_read)
         }
+        #[cfg(feature = "xz")]
         CompressionMethod::Xz() => {
             let mut encoder = XzEncoder::new(writer, compression_level.into());
 
@@ -147,6 +155,7 @@ where
     }
 }
 
+#[cfg(feature = "xz")]
 fn compress_lzma(compression_level: Level) -> Result<Stream, ArchiveError> {
     let lzma_compression_level: u32 = match compression_level {
         Level::Fastest => 1,
@@ -203,7 +212,7 @@ mod test {
         let x = b"example";
 
         let compressor = CompressionMethod::Deflate();
-        let mut hasher = Hasher::new();
+        let mut hasher = EntryHasher::new();
 
         //let a: AsyncRead = &x;
         let mut writer = WriteWrapper::new(Vec::new());
@@ -222,6 +231,7 @@ mod test {
         println!("{:X?}", temp);
     }
 
+    #[cfg(feature = "zstd")]
     #[test]
     fn test_zstd_level() {
         let range = zstd::compression_level_range();
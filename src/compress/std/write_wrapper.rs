@@ -1,13 +1,18 @@
-use std::io::{Error, Seek, Write};
+use std::io::{Error, ErrorKind, Seek, Write};
+
+use crate::constants::WRITE_ZERO_MAX_RETRY;
 
 #[derive(Debug)]
 pub struct WriteWrapper<W: Write> {
     writer: W,
     written_bytes_count: u64,
+    flush_every: Option<u64>,
+    flushed_bytes_count: u64,
 }
 
 pub trait BytesCounter {
     fn get_written_bytes_count(&mut self) -> Result<u64, Error>;
+    #[allow(dead_code)]
     fn set_written_bytes_count(&mut self, count: u64);
 }
 
@@ -16,12 +21,29 @@ impl<W: Write> WriteWrapper<W> {
         Self {
             writer: w,
             written_bytes_count: 0,
+            flush_every: None,
+            flushed_bytes_count: 0,
         }
     }
 
+    /// Flush the inner writer each time the given number of bytes has been written since the last flush.
+    pub fn with_flush_every(mut self, flush_every: Option<u64>) -> WriteWrapper<W> {
+        self.flush_every = flush_every;
+        self
+    }
+
     pub fn get_into(self) -> W {
         self.writer
     }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Mutable access to the inner writer; bytes written through it are not counted.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
 }
 
 impl<W: Write> BytesCounter for WriteWrapper<W> {
@@ -42,7 +64,14 @@ impl<W: Write> Seek for WriteWrapper<W> {
 
 impl<W: Write> Write for WriteWrapper<W> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self.writer.write(buf) {
+        // Flushing before the write keeps a failed flush from hiding bytes already accepted.
+        if let Some(flush_every) = self.flush_every {
+            if self.written_bytes_count - self.flushed_bytes_count >= flush_every {
+                self.flush()?;
+            }
+        }
+
+        match write_retry(&mut self.writer, buf) {
             Ok(nb_byte_written) => {
                 self.written_bytes_count += nb_byte_written as u64;
                 Ok(nb_byte_written)
@@ -52,7 +81,9 @@ impl<W: Write> Write for WriteWrapper<W> {
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.writer.flush()
+        self.writer.flush()?;
+        self.flushed_bytes_count = self.written_bytes_count;
+        Ok(())
     }
 }
 
@@ -83,11 +114,20 @@ impl<W: Write + Seek> WriteSeekWrapper<W> {
     pub fn get_into(self) -> W {
         self.writer
     }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Mutable access to the inner writer; bytes written through it are not counted.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
 }
 
 impl<W: Write + Seek> Write for WriteSeekWrapper<W> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.writer.write(buf)
+        write_retry(&mut self.writer, buf)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -110,3 +150,24 @@ impl<W: Write + Seek> BytesCounter for WriteSeekWrapper<W> {
         self.written_bytes_count = count;
     }
 }
+
+/// Write to the inner writer, retrying a bounded number of times when it accepts no bytes.
+///
+/// A sink that keeps returning `Ok(0)` (e.g. a full disk) yields an `ErrorKind::WriteZero` error instead of spinning.
+fn write_retry<W: Write>(writer: &mut W, buf: &[u8]) -> std::io::Result<usize> {
+    if buf.is_empty() {
+        return writer.write(buf);
+    }
+
+    for _ in 0..WRITE_ZERO_MAX_RETRY {
+        let nb_byte_written = writer.write(buf)?;
+        if nb_byte_written > 0 {
+            return Ok(nb_byte_written);
+        }
+    }
+
+    Err(Error::new(
+        ErrorKind::WriteZero,
+        "the sink did not accept any byte",
+    ))
+}
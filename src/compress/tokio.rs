@@ -1,3 +1,9 @@
+pub mod appendable;
 pub mod archive;
 mod async_wrapper;
+pub mod auto_archive;
 mod compressor;
+pub mod entry_writer;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod split_archive;
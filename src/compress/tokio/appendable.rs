@@ -0,0 +1,50 @@
+//! In-memory payloads accepted by `ZipArchive::append_data`.
+
+use std::io::Cursor;
+
+use tokio::io::AsyncBufRead;
+
+/// Data whose bytes can be appended as an entry without building a reader by hand.
+///
+/// Implemented for the usual owned and borrowed byte containers. The reader is buffered, so its
+/// bytes go straight to the encoder as with `append_file_buf`.
+pub trait AppendableData {
+    fn into_reader(self) -> impl AsyncBufRead + Unpin;
+}
+
+impl AppendableData for &[u8] {
+    fn into_reader(self) -> impl AsyncBufRead + Unpin {
+        self
+    }
+}
+
+impl<const N: usize> AppendableData for &[u8; N] {
+    fn into_reader(self) -> impl AsyncBufRead + Unpin {
+        self.as_slice()
+    }
+}
+
+impl AppendableData for Vec<u8> {
+    fn into_reader(self) -> impl AsyncBufRead + Unpin {
+        Cursor::new(self)
+    }
+}
+
+impl AppendableData for &str {
+    fn into_reader(self) -> impl AsyncBufRead + Unpin {
+        self.as_bytes()
+    }
+}
+
+impl AppendableData for String {
+    fn into_reader(self) -> impl AsyncBufRead + Unpin {
+        Cursor::new(self.into_bytes())
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl AppendableData for bytes::Bytes {
+    fn into_reader(self) -> impl AsyncBufRead + Unpin {
+        Cursor::new(self)
+    }
+}
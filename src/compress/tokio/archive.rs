@@ -1,27 +1,119 @@
-use super::async_wrapper::{AsyncWriteWrapper, BytesCounter};
-use super::compressor::{self, compress};
+use super::appendable::AppendableData;
+use super::async_wrapper::{AsyncWriteWrapper, BytesCounter, ProgressWriter};
+use super::auto_archive::{ArchiveSink, ZipArchiveAuto};
+use super::compressor::{self, compress, ZstdContext};
+use super::entry_writer::EntryWriter;
+use super::split_archive::ZipArchiveSplit;
 
-use crate::archive::FileOptions;
-use crate::archive_common::{
-    build_central_directory_end, build_central_directory_file_header, build_file_header,
-    ArchiveDescriptor, SubZipArchiveData, ZipArchiveCommon,
+use crate::archive::{
+    ArchiveOptions, ExtractOptions, FileOptions, HashAlgorithm, ListOptions, PlannedEntry,
 };
-use crate::compression::Level;
-use crate::constants::{
-    CENTRAL_DIRECTORY_ENTRY_BASE_SIZE, DATA_DESCRIPTOR_SIGNATURE, DESCRIPTOR_SIZE,
-    FILE_HEADER_CRC_OFFSET,
+use crate::archive_common::{
+    build_central_directory, build_central_directory_end, build_data_descriptor, build_file_header,
+    build_jar_manifest, build_sha256_manifest, central_directory_total_size, check_header_offset,
+    check_recompressible, check_spec_conformance, check_zip32_sizes, estimate_archive_size,
+    jar_manifest, normalize_entry_name, recompress_entry_header, spanning_marker, stale_bytes_gap,
+    stored_digest, write_entry_listing, zip32, ArchiveDescriptor, EntryHasher, EntryStatsTimer,
+    SubZipArchiveData, ZipArchiveCommon,
 };
+use crate::compression::{CompressionMethod, Level};
+use crate::constants::{ARCHIVE_TAIL_MAX_SIZE, FILE_HEADER_BASE_SIZE};
 use crate::error::ArchiveError;
+use crate::types::ArchiveFileEntry;
+use crate::uncompress::{
+    decompress_entry, local_header_size, read_archive_tail, read_central_directory_entries,
+};
 
-use crc32fast::Hasher;
-use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use futures_util::{Stream, StreamExt};
+#[cfg(feature = "sha2")]
+use sha2::{Digest, Sha256};
+use tokio::io::{
+    AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt,
+    BufWriter, DuplexStream,
+};
+use tokio::sync::Semaphore;
 
 use std::io::SeekFrom;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct ZipArchive<W: tokio::io::AsyncWrite + Unpin> {
     sink: AsyncWriteWrapper<W>,
     data: SubZipArchiveData,
+    zstd_context: ZstdContext,
+    /// Key the archive is signed with on `finalize`, see `sign`.
+    #[cfg(feature = "signature")]
+    signing_key: Option<Box<p256::ecdsa::SigningKey>>,
+}
+
+/// Fill in the sizes and checksums of an entry once its payload is written, and build its data descriptor.
+pub(super) fn complete_entry(
+    mut archive_file_entry: ArchiveFileEntry,
+    crc32: u32,
+    sha256: Option<[u8; 32]>,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    stats_timer: &EntryStatsTimer,
+) -> Result<(ArchiveFileEntry, ArchiveDescriptor), ArchiveError> {
+    archive_file_entry.crc32 = crc32;
+    archive_file_entry.sha256 = sha256;
+    archive_file_entry.compressed_size = compressed_size;
+    archive_file_entry.uncompressed_size = uncompressed_size;
+    check_zip32_sizes(&archive_file_entry)?;
+    stats_timer.report(&archive_file_entry);
+
+    let file_descriptor = build_data_descriptor(&archive_file_entry);
+    Ok((archive_file_entry, file_descriptor))
+}
+
+/// Entry payload compressed ahead of being written to the sink.
+struct CompressedEntry {
+    data: Vec<u8>,
+    crc32: u32,
+    sha256: Option<[u8; 32]>,
+    uncompressed_size: u64,
+}
+
+impl CompressedEntry {
+    async fn compress<R>(
+        compressor: CompressionMethod,
+        compression_level: Level,
+        reader: &mut R,
+        sha256: bool,
+    ) -> Result<CompressedEntry, ArchiveError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut data = Vec::new();
+        let mut hasher = EntryHasher::with_sha256(sha256);
+        let uncompressed_size = compress(
+            compressor,
+            &mut data,
+            reader,
+            &mut hasher,
+            compression_level,
+            &mut ZstdContext::default(),
+        )
+        .await?;
+
+        let digest = hasher.finalize();
+        Ok(CompressedEntry {
+            data,
+            crc32: digest.crc32,
+            sha256: digest.sha256,
+            uncompressed_size,
+        })
+    }
+}
+
+/// Compression task aborted when dropped: the tasks of `append_file_concurrent` must not keep
+/// running detached once it returns early on an error or is cancelled.
+struct CompressionTask(tokio::task::JoinHandle<Result<CompressedEntry, ArchiveError>>);
+
+impl Drop for CompressionTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 #[derive(Debug)]
@@ -29,6 +121,12 @@ pub struct ZipArchiveNoStream<W: AsyncWrite + AsyncSeek + Unpin> {
     sink: W,
     data: SubZipArchiveData,
     archive_size: u64,
+    zstd_context: ZstdContext,
+    /// Offset of the central directory once `finalize` started writing it, to write it again
+    /// from there if it is retried.
+    central_directory_offset: Option<u64>,
+    /// Whether `finalize` appended the SHA-256 manifest, so that a retry does not append it again.
+    sha256_manifest_appended: bool,
 }
 
 impl<W: tokio::io::AsyncWrite + Unpin> ZipArchiveCommon for ZipArchive<W> {
@@ -45,92 +143,1016 @@ impl<W: tokio::io::AsyncWrite + Unpin> ZipArchiveCommon for ZipArchive<W> {
     }
 }
 
+impl ZipArchive<DuplexStream> {
+    /// Create a new zip archive writing into an in-memory pipe of the given capacity, and the
+    /// pipe's other end, from which the archive bytes can be read while it is being written.
+    ///
+    /// `ZipArchiveNoStream` needs a seekable sink, hence the streaming archive is used. Once the
+    /// pipe is full, writes wait for the other end to be read, so both ends must be driven
+    /// concurrently, e.g. from separate tasks. The reading end gets EOF when the archive (or the
+    /// writer retrieved from it) is dropped.
+    pub fn into_pipe(capacity: usize) -> (ZipArchive<DuplexStream>, DuplexStream) {
+        let (sink, reader) = tokio::io::duplex(capacity);
+        (ZipArchive::new(sink), reader)
+    }
+}
+
 impl<W: AsyncWrite + Unpin> ZipArchive<W> {
     /// Create a new zip archive, using the underlying `AsyncWrite` to write files' header and payload.
+    ///
+    /// The sink is written to as is, without any buffering added: a sink that is already
+    /// buffered, e.g. a `BufWriter` or a `Vec`, is not buffered twice. See `with_buffer_size` to
+    /// buffer an unbuffered one.
     pub fn new(sink_: W) -> Self {
-        //let buf = BufWriter::new(sink_);
+        Self::with_options(sink_, ArchiveOptions::default())
+    }
+
+    /// Create a new zip archive with the given archive-wide options.
+    pub fn with_options(sink_: W, options: ArchiveOptions) -> Self {
+        let sink = AsyncWriteWrapper::new(sink_).with_flush_every(options.flush_every);
+        let mut data = SubZipArchiveData::default();
+        data.options = options;
         Self {
-            sink: AsyncWriteWrapper::new(sink_),
-            data: SubZipArchiveData::default(),
+            sink,
+            data,
+            zstd_context: ZstdContext::default(),
+            #[cfg(feature = "signature")]
+            signing_key: None,
         }
     }
 
+    /// Create a new zip archive whose first byte lands at offset `base` of the file the sink writes
+    /// to, e.g. after the stub of a self-extracting executable already written to it.
+    ///
+    /// The entry offsets and the central directory offset are recorded from the start of the file,
+    /// as readers expect for an archive with a prefix, and `get_archive_size` counts the `base` bytes.
+    pub fn with_base_offset(sink: W, base: u64) -> Self {
+        let mut archive = Self::new(sink);
+        archive.sink = archive.sink.with_base_offset(base);
+        archive
+    }
+
+    /// Create a new zip archive writing through a `BufWriter` of the given capacity.
+    ///
+    /// Useful for network sinks (TCP, HTTP) where the many small writes of the compressors
+    /// would otherwise translate into as many syscalls, e.g. as many small segments on an
+    /// unbuffered `tokio::net::TcpStream`; 64 KiB is a sensible capacity there. The archive size
+    /// still reflects the logical position, buffered bytes included. `finalize` flushes the
+    /// buffer, the stream itself is shut down by the caller.
+    ///
+    /// Wrap network sinks this way; don't for sinks already buffered, such as a `BufWriter` (every
+    /// byte would be copied twice) or a `Vec`, nor for file sinks, which the OS buffers itself.
+    pub fn with_buffer_size(sink: W, capacity: usize) -> ZipArchive<BufWriter<W>> {
+        ZipArchive::new(BufWriter::with_capacity(capacity, sink))
+    }
+
+    /// Create an archive split into standalone parts of at most `max_size` bytes, each written to
+    /// the sink `sink_factory` returns for the part index. See `ZipArchiveSplit`.
+    pub fn split<F>(sink_factory: F, max_size: u64) -> ZipArchiveSplit<W, F>
+    where
+        F: FnMut(usize) -> W,
+    {
+        ZipArchiveSplit::new(sink_factory, max_size)
+    }
+
+    /// Create a new zip archive using the seekable code path when the sink can seek, and the
+    /// streaming one otherwise. See `ZipArchiveAuto`.
+    pub fn auto(sink: W) -> ZipArchiveAuto<W>
+    where
+        W: ArchiveSink,
+    {
+        ZipArchiveAuto::new(sink)
+    }
+
     pub fn get_archive_size(&self) -> u64 {
         self.sink.get_written_bytes_count()
     }
 
+    /// Whether `finalize` completed, i.e. the archive is complete and the sink flushed.
+    pub fn is_finalized(&self) -> bool {
+        self.data.finalized
+    }
+
+    /// Give back the underlying writer.
+    ///
+    /// The writer holds a complete archive only once `finalize` completed: before, the central
+    /// directory is missing and bytes buffered by the writer may not be flushed. In debug builds,
+    /// retrieving the writer of an archive holding entries but not finalized panics; use
+    /// `writer_ref` or `writer_mut` to inspect the writer meanwhile.
     pub fn retrieve_writer(self) -> W {
+        debug_assert!(
+            self.data.finalized || self.data.files_info.is_empty(),
+            "the writer is retrieved before the archive is finalized"
+        );
         self.sink.retrieve_writer()
     }
 
+    /// Reference to the underlying writer, e.g. to inspect what is written so far without
+    /// consuming the archive, or to query a socket with `TcpStream::peer_addr`.
+    pub fn writer_ref(&self) -> &W {
+        self.sink.get_ref()
+    }
+
+    /// Mutable reference to the underlying writer, e.g. to set socket options between entries.
+    ///
+    /// The archive keeps its own count of the bytes written, from which the entry offsets are
+    /// computed: bytes written directly to the writer are not counted and corrupt the archive.
+    pub fn writer_mut(&mut self) -> &mut W {
+        self.sink.get_mut()
+    }
+
+    /// Exact number of bytes `finalize` adds for the entries appended so far: the central
+    /// directory, the end of central directory record (ZIP64 records included when needed) and
+    /// the archive comment.
+    ///
+    /// The SHA-256 manifest entry, appended by `finalize` when enabled, is not accounted for.
+    pub fn estimate_central_directory_size(&self) -> u64 {
+        central_directory_total_size(&self.data, self.sink.get_written_bytes_count())
+    }
+
+    /// Exact size of the archive once the planned entries are appended and it is finalized, e.g.
+    /// to send a `Content-Length` header before streaming it.
+    ///
+    /// The entries already appended are accounted for. Returns `None` unless every planned entry
+    /// is stored (`CompressionMethod::Store`), as the size of compressed data cannot be known
+    /// upfront; also when the SHA-256 manifest is enabled.
+    pub fn estimated_size(&self, planned_entries: &[PlannedEntry]) -> Option<u64> {
+        estimate_archive_size(
+            &self.data,
+            self.sink.get_written_bytes_count(),
+            true,
+            planned_entries,
+        )
+    }
+
+    /// Print the entries appended so far as a table, in the manner of `unzip -l`.
+    ///
+    /// Columns are the uncompressed size, compressed size, compression ratio, date, time and name,
+    /// followed by a line of totals. The listing is padded to 80 characters.
+    ///
+    /// # Error
+    ///
+    /// This function will forward any error found while writing to `writer`.
+    pub fn list<T: std::io::Write>(&self, writer: &mut T) -> Result<(), ArchiveError> {
+        self.list_with_options(writer, &ListOptions::default())
+    }
+
+    /// Print the entries appended so far, with the given layout options. See `list`.
+    pub fn list_with_options<T: std::io::Write>(
+        &self,
+        writer: &mut T,
+        options: &ListOptions,
+    ) -> Result<(), ArchiveError> {
+        write_entry_listing(&self.data.files_info, writer, options)
+    }
+
     /// Append a new file to the archive using the provided name, date/time and `AsyncRead` object.  
     /// Filename must be valid UTF-8. Some (very) old zip utilities might mess up filenames during extraction if they contain non-ascii characters.  
     /// File's payload is not compressed and is given `rw-r--r--` permissions.
     ///
+    /// Returns the CRC-32 of the uncompressed payload, as recorded in the archive, e.g. to check
+    /// the entry against an external checksum without reading the archive back.
+    ///
     /// # Error
     ///
-    /// This function will forward any error found while trying to read from the file stream or while writing to the underlying sink.
+    /// This function will forward any error found while trying to read from the file stream or while writing to the underlying sink,
+    /// wrapped in an `ArchiveError::Entry` giving the entry name and the offset of its local header.
+    ///
+    /// All writes go through `AsyncWriteExt::write_all`, which resumes partial writes. A sink reporting
+    /// `ErrorKind::WouldBlock` as an error is polled again after a short, increasing delay instead
+    /// of failing the entry (this needs the time driver of the Tokio runtime), and a write failing
+    /// with `ErrorKind::Interrupted` is retried, up to 8 times in a row.
+    ///
+    /// # Cancellation
+    ///
+    /// This future is not cancellation safe: dropping it mid-entry leaves the bytes already written
+    /// in the sink while the entry is not recorded, so the archive is corrupted. Use
+    /// `append_file_cancellable` when the call may be cancelled, e.g. by `tokio::select!` or a timeout.
+    ///
+    /// # Laziness
+    ///
+    /// The returned future does nothing until polled: neither the reader nor the sink is touched,
+    /// so dropping it unpolled leaves the archive unchanged. It borrows the archive mutably until it
+    /// completes, hence entries cannot be queued as several pending futures; queue the readers
+    /// instead and feed them to `append_from_stream` or `append_file_concurrent`.
     ///
     /// # Features
     ///
-    /// Requires `tokio-async-io` feature. `futures-async-io` is also available.
-
+    /// Requires the `tokio` feature. The `futures` feature provides the streaming archive over
+    /// `futures::io`, see `compress::futures::archive::ZipArchive`.
     pub async fn append_file<R>(
         &mut self,
         file_name: &str,
         reader: &mut R,
         options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        self.append_file_buf(file_name, &mut compressor::buffered(reader), options)
+            .await
+    }
+
+    /// Append a new file to the archive from in-memory data, e.g. a byte slice, a `Vec<u8>`, a
+    /// `String` or, with the `bytes` feature, a `Bytes`. Returns the CRC-32 of the payload.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_data(
+        &mut self,
+        file_name: &str,
+        data: impl AppendableData,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        self.append_file_buf(file_name, &mut data.into_reader(), options)
+            .await
+    }
+
+    /// Append a new file to the archive from a buffered reader, e.g. a `BufReader<File>` or a byte
+    /// slice. The slices of its buffer go straight to the hasher and the encoder, saving the copy
+    /// `append_file` makes to its own chunk buffer. Returns the CRC-32 of the payload.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_buf<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + Unpin,
+        R: AsyncBufRead + Unpin,
+    {
+        let file_header_offset = self.sink.get_written_bytes_count();
+        self.append_entry(file_name, reader, options, None)
+            .await
+            .map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    /// Append a new file to the archive, taking ownership of the reader, which is dropped once
+    /// the entry is written.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_owned<R>(
+        &mut self,
+        file_name: &str,
+        mut reader: R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        self.append_file(file_name, &mut reader, options).await
+    }
+
+    /// Append the file at `path` to the archive, with the modification time of the file as last
+    /// modified time (see `FileOptions::last_modified_time_from_metadata`). Returns the CRC-32
+    /// of the payload.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`, plus `ArchiveError::IoError` if the file cannot be opened.
+    pub async fn append_path(
+        &mut self,
+        file_name: &str,
+        path: impl AsRef<std::path::Path>,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let file_header_offset = self.sink.get_written_bytes_count();
+        let in_entry =
+            |e: std::io::Error| ArchiveError::from(e).in_entry(file_name, file_header_offset);
+
+        let mut file = tokio::fs::File::open(path).await.map_err(in_entry)?;
+        let metadata = file.metadata().await.map_err(in_entry)?;
+        let options = options.clone().last_modified_time_from_metadata(&metadata);
+        self.append_file(file_name, &mut file, &options).await
+    }
+
+    /// Append a JAR-style manifest (e.g. `META-INF/MANIFEST.MF`) listing every entry written so
+    /// far with its digest.
+    ///
+    /// The CRC-32 stored for every entry is always available. The SHA-256 is available only when
+    /// `ArchiveOptions::sha256_manifest` is enabled, as the entries cannot be read back from the
+    /// sink to hash them.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::DigestUnavailable` if the digest of an entry is missing, nothing being
+    /// written then. Otherwise same as `append_file`.
+    pub async fn add_manifest(
+        &mut self,
+        manifest_name: &str,
+        hash_algorithm: HashAlgorithm,
     ) -> Result<(), ArchiveError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let manifest = build_jar_manifest(&self.data.files_info, hash_algorithm)?;
+        self.append_file(
+            manifest_name,
+            &mut manifest.as_slice(),
+            &FileOptions::default(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Set the archive comment, written in the end of central directory record. It is truncated
+    /// to the last whole character within 65535 bytes, the largest comment the record can hold.
+    ///
+    /// Returns the length in bytes of the comment kept, shorter than `comment.len()` when it was
+    /// truncated.
+    pub fn set_archive_comment(&mut self, comment: &str) -> usize {
+        self.data.set_archive_comment(comment)
+    }
+
+    /// The archive comment, as set by `set_archive_comment`; empty if there is none.
+    pub fn comment_bytes(&self) -> &[u8] {
+        self.data.archive_comment_bytes()
+    }
+
+    /// The archive comment, `None` if there is none or it is not valid UTF-8.
+    pub fn comment(&self) -> Option<&str> {
+        self.data.archive_comment()
+    }
+
+    /// The archive comment, or an empty string if there is none or it is not valid UTF-8.
+    pub fn comment_or_empty(&self) -> &str {
+        self.comment().unwrap_or_default()
+    }
+
+    /// Set the options used by `append_file_default`, e.g. to share a compression method and level
+    /// between most entries while a few are appended with their own options by `append_file`.
+    ///
+    /// The default is `FileOptions::default()`.
+    pub fn set_default_options(&mut self, options: FileOptions) {
+        self.data.default_file_options = options;
+    }
+
+    /// Append a new file to the archive with the options set by `set_default_options`.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_default<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+    ) -> Result<u32, ArchiveError>
     where
         W: AsyncWrite + Unpin,
         R: AsyncRead + Unpin,
     {
-        let compressor = options.compressor;
+        let options = self.data.default_file_options.clone();
+        self.append_file(file_name, reader, &options).await
+    }
+
+    /// Download a URL with an HTTP GET and stream the response body into a new entry.
+    ///
+    /// `http` and `https` URLs are supported, redirects are followed. The entry is compressed
+    /// into memory as with `append_file_cancellable`, so a body failing midway leaves the archive
+    /// as it was.
+    ///
+    /// # Error
+    ///
+    /// An invalid URL, a connection failure or a final response status other than 2xx fails with
+    /// `ArchiveError::IoError`, giving the status, before anything is written. So does a body
+    /// interrupted midway. The other errors are the ones of `append_file`.
+    ///
+    /// # Features
+    ///
+    /// Requires the `http` feature.
+    #[cfg(feature = "http")]
+    pub async fn append_file_from_url(
+        &mut self,
+        file_name: &str,
+        url: &str,
+        options: &FileOptions,
+    ) -> Result<ArchiveFileEntry, ArchiveError> {
+        use futures_util::TryStreamExt;
 
         let file_header_offset = self.sink.get_written_bytes_count();
+        let in_entry =
+            |e: std::io::Error| ArchiveError::from(e).in_entry(file_name, file_header_offset);
 
-        let (file_header, mut archive_file_entry) = build_file_header(
+        let response = reqwest::get(url)
+            .await
+            .map_err(std::io::Error::other)
+            .map_err(in_entry)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(in_entry(std::io::Error::other(format!(
+                "HTTP status {} for {}",
+                status, url
+            ))));
+        }
+
+        let body = response.bytes_stream().map_err(std::io::Error::other);
+        let mut reader = tokio_util::io::StreamReader::new(body);
+        self.append_file_cancellable(file_name, &mut reader, options)
+            .await?;
+
+        Ok(self.last_entry().clone())
+    }
+
+    /// Append a new file read from the standard input of the process, until its end.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    ///
+    /// # Features
+    ///
+    /// Requires the `stdin` feature.
+    #[cfg(feature = "stdin")]
+    pub async fn append_file_from_stdin(
+        &mut self,
+        file_name: &str,
+        options: &FileOptions,
+    ) -> Result<ArchiveFileEntry, ArchiveError> {
+        self.append_file(file_name, &mut tokio::io::stdin(), options)
+            .await?;
+        Ok(self.last_entry().clone())
+    }
+
+    /// Spawn the command and append a new file read from its standard output, until it exits.
+    ///
+    /// The standard output of `command` is replaced by a pipe; its standard input and error are
+    /// left as configured.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`, plus `ArchiveError::IoError` if the command cannot be spawned or
+    /// exits with a failure status. In the latter case, the entry holding the output is already
+    /// in the archive.
+    ///
+    /// # Features
+    ///
+    /// Requires the `process` feature.
+    #[cfg(feature = "process")]
+    pub async fn append_file_from_process_stdout(
+        &mut self,
+        file_name: &str,
+        command: &mut tokio::process::Command,
+        options: &FileOptions,
+    ) -> Result<ArchiveFileEntry, ArchiveError> {
+        let file_header_offset = self.sink.get_written_bytes_count();
+        let in_entry =
+            |e: std::io::Error| ArchiveError::from(e).in_entry(file_name, file_header_offset);
+
+        let mut child = command
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .map_err(in_entry)?;
+        let mut stdout = child.stdout.take().expect("the standard output is piped");
+
+        self.append_file(file_name, &mut stdout, options).await?;
+        drop(stdout);
+
+        let status = child.wait().await.map_err(in_entry)?;
+        if !status.success() {
+            return Err(in_entry(std::io::Error::other(format!(
+                "the process exited with {}",
+                status
+            ))));
+        }
+
+        Ok(self.last_entry().clone())
+    }
+
+    #[cfg(any(feature = "stdin", feature = "process", feature = "http"))]
+    fn last_entry(&self) -> &ArchiveFileEntry {
+        self.data
+            .files_info
+            .last()
+            .expect("an entry was just appended")
+    }
+
+    /// Start a new entry whose payload is then written chunk by chunk, e.g. with `tokio::io::copy`.
+    ///
+    /// The local header is written right away. The returned `EntryWriter` implements `AsyncWrite`:
+    /// every byte written to it is compressed into the entry, and shutting it down finishes the
+    /// entry without closing the archive sink.
+    ///
+    /// ```no_run
+    /// # use rill::{archive::FileOptions, compress::tokio::archive::ZipArchive};
+    /// # use tokio::io::AsyncWriteExt;
+    /// # async fn run(mut source: tokio::fs::File) -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut archive = ZipArchive::new(Vec::new());
+    /// let mut entry = archive.start_entry("file.txt", &FileOptions::default()).await?;
+    /// tokio::io::copy(&mut source, &mut entry).await?;
+    /// entry.shutdown().await?;
+    /// archive.finalize().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn start_entry(
+        &mut self,
+        file_name: &str,
+        options: &FileOptions,
+    ) -> Result<EntryWriter<'_, W>, ArchiveError> {
+        EntryWriter::new(self, file_name, options, None).await
+    }
+
+    /// Start a new entry of `size` uncompressed bytes, whose payload is then written chunk by chunk.
+    ///
+    /// The local header is written right away; the returned `EntryWriter` compresses each chunk
+    /// passed to `EntryWriter::write` and `EntryWriter::finish` records the entry. It must be
+    /// finished before anything else is appended.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`. Fails with `ArchiveError::Zip64Required` if `size` exceeds 4 GiB and
+    /// ZIP64 is not enabled.
+    pub async fn reserve_entry(
+        &mut self,
+        file_name: &str,
+        size: u64,
+        options: &FileOptions,
+    ) -> Result<EntryWriter<'_, W>, ArchiveError> {
+        if !self.data.options.zip64 {
+            zip32(size, "uncompressed size")
+                .map_err(|e| e.in_entry(file_name, self.sink.get_written_bytes_count()))?;
+        }
+        EntryWriter::new(self, file_name, options, Some(size)).await
+    }
+
+    /// Append a new file to the archive, reporting the progress to the options' progress sink.
+    ///
+    /// The reader size is determined by seeking to its end, so the progress can be expressed as a
+    /// percentage of the estimated compressed size (see `FileOptions::expected_ratio`).
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_with_progress<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+    {
+        let file_header_offset = self.sink.get_written_bytes_count();
+        let size = async {
+            let start = reader.stream_position().await?;
+            let end = reader.seek(SeekFrom::End(0)).await?;
+            reader.seek(SeekFrom::Start(start)).await?;
+            Ok::<u64, ArchiveError>(end - start)
+        }
+        .await
+        .map_err(|e| e.in_entry(file_name, file_header_offset))?;
+
+        self.append_entry(
             file_name,
+            &mut compressor::buffered(reader),
             options,
-            compressor,
-            file_header_offset as u32,
+            Some(size),
+        )
+        .await
+        .map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    async fn append_entry<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+        reader_size: Option<u64>,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        let compressor = options.effective_compressor();
+        let stats_timer = EntryStatsTimer::start(options);
+        let archive_file_entry = self.begin_entry(file_name, options).await?;
+
+        let mut hasher = EntryHasher::with_sha256(self.data.options.sha256_manifest);
+        let cur_size = self.sink.get_written_bytes_count();
+
+        let uncompressed_size = match (&options.progress_sink, reader_size) {
+            (Some(sender), Some(size)) => {
+                let expected_size = (size as f64 / options.expected_ratio) as u64;
+                let mut progress_writer =
+                    ProgressWriter::new(&mut self.sink, sender.clone(), expected_size);
+
+                let uncompressed_size = compressor::compress_buf(
+                    compressor,
+                    &mut progress_writer,
+                    reader,
+                    &mut hasher,
+                    options.compression_level,
+                    &mut self.zstd_context,
+                )
+                .await?;
+
+                progress_writer.done().await;
+                uncompressed_size
+            }
+            _ => {
+                compressor::compress_buf(
+                    compressor,
+                    &mut self.sink,
+                    reader,
+                    &mut hasher,
+                    options.compression_level,
+                    &mut self.zstd_context,
+                )
+                .await?
+            }
+        };
+
+        let compressed_size = self.sink.get_written_bytes_count() - cur_size;
+        let digest = hasher.finalize();
+
+        let archive_file_entry = self
+            .end_entry(
+                archive_file_entry,
+                digest.crc32,
+                digest.sha256,
+                compressed_size,
+                uncompressed_size,
+                &stats_timer,
+            )
+            .await?;
+
+        Ok(archive_file_entry.crc32)
+    }
+
+    /// Validate the options and write the local header of a new entry.
+    pub(super) async fn begin_entry(
+        &mut self,
+        file_name: &str,
+        options: &FileOptions,
+    ) -> Result<ArchiveFileEntry, ArchiveError> {
+        options.validate()?;
+
+        let file_name = normalize_entry_name(&self.data.options, file_name)?;
+
+        self.write_spanning_marker().await?;
+        let file_header_offset = self.sink.get_written_bytes_count();
+
+        let (file_header, archive_file_entry) = build_file_header(
+            &file_name,
+            options,
+            options.effective_compressor(),
+            check_header_offset(&self.data.options, file_header_offset)?,
             true,
+            self.data.options.zip64,
         );
 
         self.sink.write_all(file_header.buffer()).await?;
 
-        let mut hasher = Hasher::new();
-        let cur_size = self.sink.get_written_bytes_count();
+        Ok(archive_file_entry)
+    }
 
-        let uncompressed_size = compressor::compress(
-            compressor,
-            &mut self.sink,
-            reader,
-            &mut hasher,
+    /// Write the temporary spanning marker if it is enabled and nothing is written yet.
+    async fn write_spanning_marker(&mut self) -> Result<(), ArchiveError> {
+        if let Some(marker) = spanning_marker(&self.data) {
+            self.sink.write_all(&marker).await?;
+            self.data.spanning_marker_written = true;
+        }
+        Ok(())
+    }
+
+    /// Sink the payload of the entry begun with `begin_entry` is written to.
+    pub(super) fn sink_mut(&mut self) -> &mut AsyncWriteWrapper<W> {
+        &mut self.sink
+    }
+
+    /// Write the data descriptor of the entry begun with `begin_entry` once its payload is written,
+    /// and record the entry for the central directory.
+    pub(super) async fn end_entry(
+        &mut self,
+        archive_file_entry: ArchiveFileEntry,
+        crc32: u32,
+        sha256: Option<[u8; 32]>,
+        compressed_size: u64,
+        uncompressed_size: u64,
+        stats_timer: &EntryStatsTimer,
+    ) -> Result<&ArchiveFileEntry, ArchiveError> {
+        let (archive_file_entry, file_descriptor) = complete_entry(
+            archive_file_entry,
+            crc32,
+            sha256,
+            compressed_size,
+            uncompressed_size,
+            stats_timer,
+        )?;
+
+        self.sink.write_all(file_descriptor.buffer()).await?;
+
+        Ok(self.record_entry(archive_file_entry))
+    }
+
+    /// Record an entry whose data descriptor is written, for the central directory.
+    pub(super) fn record_entry(
+        &mut self,
+        archive_file_entry: ArchiveFileEntry,
+    ) -> &ArchiveFileEntry {
+        self.data.files_info.push(archive_file_entry);
+        self.data
+            .files_info
+            .last()
+            .expect("the entry was just pushed")
+    }
+
+    /// Copy an entry of another archive as is: `entry_bytes` holds its local header, its payload
+    /// and its data descriptor if any, and `archive_file_entry` its central directory record, e.g.
+    /// as returned by `uncompress::raw_entries`. Returns the CRC-32 of the entry.
+    ///
+    /// Nothing is recomputed: the version needed, the general purpose flags and the extra fields
+    /// of the source are kept, so entries the archive cannot write itself, e.g. ZIP64 or
+    /// encrypted ones, are copied faithfully. Only the offset of the local header is updated.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::BadArchiveStructure` if `entry_bytes` does not start with a local header or
+    /// is shorter than the local header and the payload, wrapped in an `ArchiveError::Entry`.
+    pub async fn append_raw(
+        &mut self,
+        mut archive_file_entry: ArchiveFileEntry,
+        entry_bytes: &[u8],
+    ) -> Result<u32, ArchiveError> {
+        let file_name =
+            String::from_utf8_lossy(&archive_file_entry.file_name_as_bytes).into_owned();
+        let file_header_offset = self.sink.get_written_bytes_count();
+        let in_entry = |e: ArchiveError| e.in_entry(&file_name, file_header_offset);
+
+        let header = entry_bytes
+            .get(..FILE_HEADER_BASE_SIZE as usize)
+            .ok_or_else(|| ArchiveError::BadArchiveStructure("Local header truncated!".to_owned()))
+            .map_err(in_entry)?;
+        let entry_size =
+            local_header_size(header).map_err(in_entry)? + archive_file_entry.compressed_size;
+        if (entry_bytes.len() as u64) < entry_size {
+            return Err(in_entry(ArchiveError::BadArchiveStructure(
+                "Entry data truncated!".to_owned(),
+            )));
+        }
+
+        self.write_spanning_marker().await.map_err(in_entry)?;
+        archive_file_entry.offset =
+            check_header_offset(&self.data.options, self.sink.get_written_bytes_count())
+                .map_err(in_entry)?;
+        self.sink
+            .write_all(entry_bytes)
+            .await
+            .map_err(|e| in_entry(e.into()))?;
+        Ok(self.record_entry(archive_file_entry).crc32)
+    }
+
+    /// Writer and entry of an archive holding a single entry, not finalized.
+    pub(super) fn into_single_entry(mut self) -> (W, ArchiveFileEntry) {
+        let archive_file_entry = self
+            .data
+            .files_info
+            .pop()
+            .expect("the archive holds an entry");
+        (self.sink.retrieve_writer(), archive_file_entry)
+    }
+
+    /// Whether the SHA-256 of the entries is computed for the manifest.
+    pub(super) fn sha256_manifest(&self) -> bool {
+        self.data.options.sha256_manifest
+    }
+
+    /// Append a new file to the archive, in a way that can be safely cancelled while the reader is drained.
+    ///
+    /// The entry is first compressed into an in-memory buffer; the sink and the archive entries are
+    /// only updated once the whole entry is ready. Dropping the future before that point leaves the
+    /// archive as it was, so appending can resume with another entry. Once the buffer is being written
+    /// to the sink, the future must be driven to completion.
+    ///
+    /// This costs holding the whole compressed entry in memory.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_cancellable<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let file_header_offset = self.sink.get_written_bytes_count();
+        options
+            .validate()
+            .map_err(|e| e.in_entry(file_name, file_header_offset))?;
+        let stats_timer = EntryStatsTimer::start(options);
+        let compressed_entry = CompressedEntry::compress(
+            options.effective_compressor(),
             options.compression_level,
+            reader,
+            self.data.options.sha256_manifest,
+        )
+        .await
+        .map_err(|e| e.in_entry(file_name, file_header_offset))?;
+
+        self.write_compressed_entry(file_name, options, compressed_entry, &stats_timer)
+            .await
+            .map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    /// Compress the entries in parallel tokio tasks, then write them to the sink in the given order.
+    ///
+    /// At most `ArchiveOptions::max_concurrent_compressions` entries are compressed at the same time.
+    /// Each compressed payload is held in memory until its turn to be written comes.
+    ///
+    /// # Error
+    ///
+    /// Stops at the first entry failing to be compressed or written, wrapped in an `ArchiveError::Entry`.
+    /// The compressions still running are then aborted, as they are if the future is dropped.
+    pub async fn append_file_concurrent<R>(
+        &mut self,
+        entries: Vec<(String, R, FileOptions)>,
+    ) -> Result<(), ArchiveError>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let max_concurrent_compressions = self.data.options.max_concurrent_compressions.max(1);
+        let semaphore = Arc::new(Semaphore::new(max_concurrent_compressions));
+        let sha256 = self.data.options.sha256_manifest;
+
+        // Reject invalid options before any entry is compressed.
+        for (file_name, _, options) in &entries {
+            let file_header_offset = self.sink.get_written_bytes_count();
+            options
+                .validate()
+                .map_err(|e| e.in_entry(file_name, file_header_offset))?;
+        }
+
+        let mut tasks = Vec::with_capacity(entries.len());
+        for (file_name, mut reader, options) in entries {
+            let semaphore = semaphore.clone();
+            let compressor = options.effective_compressor();
+            let compression_level = options.compression_level;
+            let stats_timer = EntryStatsTimer::start(&options);
+
+            let task = CompressionTask(tokio::spawn(async move {
+                let _permit = semaphore
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed");
+
+                CompressedEntry::compress(compressor, compression_level, &mut reader, sha256).await
+            }));
+
+            tasks.push((file_name, options, task, stats_timer));
+        }
+
+        // On error the remaining tasks are dropped, hence aborted.
+        for (file_name, options, mut task, stats_timer) in tasks {
+            let file_header_offset = self.sink.get_written_bytes_count();
+
+            let compressed_entry = (&mut task.0)
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e).into()));
+
+            match compressed_entry {
+                Ok(compressed_entry) => {
+                    self.write_compressed_entry(
+                        &file_name,
+                        &options,
+                        compressed_entry,
+                        &stats_timer,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            }
+            .map_err(|e| e.in_entry(&file_name, file_header_offset))?;
+        }
+
+        Ok(())
+    }
+
+    async fn write_compressed_entry(
+        &mut self,
+        file_name: &str,
+        options: &FileOptions,
+        compressed_entry: CompressedEntry,
+        stats_timer: &EntryStatsTimer,
+    ) -> Result<u32, ArchiveError> {
+        let archive_file_entry = self.begin_entry(file_name, options).await?;
+        self.sink.write_all(&compressed_entry.data).await?;
+
+        self.end_entry(
+            archive_file_entry,
+            compressed_entry.crc32,
+            compressed_entry.sha256,
+            compressed_entry.data.len() as u64,
+            compressed_entry.uncompressed_size,
+            stats_timer,
         )
         .await?;
 
-        let compressed_size = self.sink.get_written_bytes_count() - cur_size;
-        let crc32 = hasher.finalize();
+        Ok(compressed_entry.crc32)
+    }
 
-        archive_file_entry.crc32 = crc32;
-        archive_file_entry.compressed_size = compressed_size;
-        archive_file_entry.uncompressed_size = uncompressed_size;
+    /// Append every `(name, reader)` pair of the stream, in order, then finalize the archive.
+    ///
+    /// The next item is only pulled once the previous entry is written to the sink, so a slow
+    /// sink applies backpressure to the producer (e.g. a bounded `mpsc` channel).
+    ///
+    /// # Error
+    ///
+    /// Stops at the first entry failing to be appended, see `append_file`.
+    pub async fn append_from_stream<S, R>(
+        &mut self,
+        entries: S,
+        options: &FileOptions,
+    ) -> Result<(), ArchiveError>
+    where
+        S: Stream<Item = (String, R)>,
+        R: AsyncRead + Unpin,
+    {
+        tokio::pin!(entries);
 
-        let mut file_descriptor = ArchiveDescriptor::new(DESCRIPTOR_SIZE);
-        file_descriptor.write_u32(DATA_DESCRIPTOR_SIGNATURE);
-        file_descriptor.write_u32(crc32);
-        file_descriptor.write_u32(compressed_size as u32);
-        file_descriptor.write_u32(uncompressed_size as u32);
+        while let Some((file_name, mut reader)) = entries.next().await {
+            self.append_file(&file_name, &mut reader, options).await?;
+        }
 
-        self.sink.write_all(file_descriptor.buffer()).await?;
+        self.finalize().await
+    }
 
-        self.data.files_info.push(archive_file_entry);
+    /// Sign the archive with an ECDSA P-256 key, given as its 32-byte big-endian scalar.
+    ///
+    /// From now on, every byte written is hashed. `finalize` signs the SHA-256 digest of the
+    /// entries and of the central directory headers, and writes the DER encoded signature in
+    /// the digital signature record (`0x05054b50`) closing the central directory. Verifying it
+    /// takes the archive bytes up to that record and the public key of the signer.
+    ///
+    /// The record makes the central directory longer than `estimate_central_directory_size`
+    /// tells, by at most 78 bytes. Few readers expect it: Info-ZIP's `unzip` extracts the entries
+    /// but warns about it, and Python's `zipfile` rejects the archive.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::InvalidSigningKey` if `private_key` is not a P-256 private key, and
+    /// `ArchiveError::BadArchiveStructure` if an entry is already appended, as the signature
+    /// would not cover it.
+    ///
+    /// # Features
+    ///
+    /// Requires the `signature` feature.
+    #[cfg(feature = "signature")]
+    pub fn sign(&mut self, private_key: &[u8]) -> Result<(), ArchiveError> {
+        if !self.data.files_info.is_empty() {
+            return Err(ArchiveError::BadArchiveStructure(
+                "The archive must be signed before any entry is appended".to_owned(),
+            ));
+        }
 
+        let signing_key = p256::ecdsa::SigningKey::from_slice(private_key)
+            .map_err(|_| ArchiveError::InvalidSigningKey)?;
+        self.signing_key = Some(Box::new(signing_key));
+        self.sink.start_digest();
         Ok(())
     }
 
+    /// Write the digital signature record if the archive is signed, and return its size.
+    #[cfg(feature = "signature")]
+    async fn write_digital_signature(&mut self) -> Result<u64, ArchiveError> {
+        use p256::ecdsa::{signature::DigestSigner, Signature};
+
+        let (Some(signing_key), Some(digest)) = (&self.signing_key, self.sink.take_digest()) else {
+            return Ok(0);
+        };
+        let signature: Signature = signing_key.sign_digest(digest);
+        let record = crate::archive_common::build_digital_signature(signature.to_der().as_bytes());
+        self.sink.write_all(record.buffer()).await?;
+        Ok(record.buffer().len() as u64)
+    }
+
+    /// Unsigned archives have no digital signature record.
+    #[cfg(not(feature = "signature"))]
+    async fn write_digital_signature(&mut self) -> Result<u64, ArchiveError> {
+        Ok(0)
+    }
+
     /// Finalize the archive by writing the necessary metadata to the end of the archive.
     ///
     /// # Error
@@ -139,39 +1161,42 @@ impl<W: AsyncWrite + Unpin> ZipArchive<W> {
     ///
     /// # Features
     ///
-    /// Requires `tokio-async-io` feature. `futures-async-io` is also available.
+    /// Requires the `tokio` feature. The `futures` feature provides the streaming archive over
+    /// `futures::io`, see `compress::futures::archive::ZipArchive`.
     pub async fn finalize(&mut self) -> Result<(), ArchiveError>
     where
         W: AsyncWrite + Unpin,
     {
-        let central_directory_offset = self.sink.get_written_bytes_count() as u32;
-
-        let mut central_directory_header =
-            ArchiveDescriptor::new(CENTRAL_DIRECTORY_ENTRY_BASE_SIZE + 200);
-
-        for file_info in &self.data.files_info {
-            build_central_directory_file_header(&mut central_directory_header, file_info);
-
-            self.sink
-                .write_all(central_directory_header.buffer())
+        if self.data.options.sha256_manifest {
+            let manifest = build_sha256_manifest(&self.data.files_info);
+            let name = self.data.options.sha256_manifest_name.clone();
+            let options = self.data.options.sha256_manifest_options.clone();
+            self.append_file(&name, &mut manifest.as_slice(), &options)
                 .await?;
+        }
 
-            central_directory_header.clear();
+        self.write_spanning_marker().await?;
+        let central_directory_offset = self.sink.get_written_bytes_count();
+        if self.data.options.strict {
+            check_spec_conformance(&self.data, central_directory_offset)?;
         }
 
-        let current_archive_size = self.sink.get_written_bytes_count();
-        let central_directory_size: u32 = current_archive_size as u32 - central_directory_offset;
+        let central_directory = build_central_directory(&self.data);
+        self.sink.write_all(central_directory.buffer()).await?;
+        let central_directory_size =
+            central_directory.buffer().len() as u64 + self.write_digital_signature().await?;
         let end_of_central_directory = build_central_directory_end(
             &self.data,
             central_directory_offset,
             central_directory_size,
-        );
+        )?;
 
         self.sink
             .write_all(end_of_central_directory.buffer())
             .await?;
 
         self.sink.flush().await?;
+        self.data.finalized = true;
         //println!("CentralDirectoryEnd {:#?}", dir_end);
         Ok(())
     }
@@ -179,74 +1204,436 @@ impl<W: AsyncWrite + Unpin> ZipArchive<W> {
 
 impl<W: AsyncWrite + AsyncSeek + Unpin> ZipArchiveNoStream<W> {
     pub fn new(sink: W) -> Self {
-        //let buf = BufWriter::new(sink_);
+        Self::with_options(sink, ArchiveOptions::default())
+    }
+
+    /// Create a new zip archive with the given archive-wide options.
+    pub fn with_options(sink: W, options: ArchiveOptions) -> Self {
+        let mut data = SubZipArchiveData::default();
+        data.options = options;
         Self {
             sink,
-            data: SubZipArchiveData::default(),
+            data,
             archive_size: 0,
+            zstd_context: ZstdContext::default(),
+            central_directory_offset: None,
+            sha256_manifest_appended: false,
         }
     }
 
+    /// Open an existing archive to append new entries to it.
+    ///
+    /// The central directory of the archive is read back, and the sink is positioned at its start
+    /// so it gets overwritten, with the new entries, on the next `finalize`.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::BadArchiveStructure` if the end of central directory record cannot be found.
+    pub async fn from_existing(sink: W) -> Result<Self, ArchiveError>
+    where
+        W: AsyncRead,
+    {
+        Self::from_existing_with_options(sink, ArchiveOptions::default()).await
+    }
+
+    /// Open an existing archive to append new entries to it, with the given archive-wide options.
+    ///
+    /// See `from_existing`.
+    pub async fn from_existing_with_options(
+        mut sink: W,
+        options: ArchiveOptions,
+    ) -> Result<Self, ArchiveError>
+    where
+        W: AsyncRead,
+    {
+        let file_length = sink.seek(SeekFrom::End(0)).await?;
+        let tail_length = file_length.min(ARCHIVE_TAIL_MAX_SIZE);
+        sink.seek(SeekFrom::Start(file_length - tail_length))
+            .await?;
+        let mut tail = vec![0; tail_length as usize];
+        sink.read_exact(&mut tail).await?;
+
+        let (central_directory_end, comment) = read_archive_tail(&tail)?;
+        let central_directory_offset = central_directory_end.offset;
+
+        sink.seek(SeekFrom::Start(central_directory_offset)).await?;
+        let mut central_directory = vec![0; central_directory_end.size as usize];
+        sink.read_exact(&mut central_directory).await?;
+        let files_info =
+            read_central_directory_entries(&central_directory, central_directory_end.entries)?;
+
+        sink.seek(SeekFrom::Start(central_directory_offset)).await?;
+
+        let mut archive = Self::with_options(sink, options);
+        archive.data.files_info = files_info;
+        archive.data.set_archive_comment_bytes(comment);
+        archive.archive_size = central_directory_offset;
+
+        Ok(archive)
+    }
+
+    /// Append a new file to the archive using the provided name, options and `AsyncRead` object.
+    /// Returns the CRC-32 of the payload.
+    ///
+    /// # Error
+    ///
+    /// This function will forward any error found while trying to read from the file stream or while writing to the underlying sink,
+    /// wrapped in an `ArchiveError::Entry` giving the entry name and the offset of its local header.
+    ///
+    /// Unlike the streaming `ZipArchive`, the archive writes straight to the sink: a write failing
+    /// with `ErrorKind::Interrupted` or `ErrorKind::WouldBlock` fails the entry.
+    ///
+    /// `ArchiveError::ArchiveFinalized` once `finalize` completed.
     pub async fn append_file<R>(
         &mut self,
         file_name: &str,
         reader: &mut R,
         options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        self.append_file_buf(file_name, &mut compressor::buffered(reader), options)
+            .await
+    }
+
+    /// Append a new file to the archive from in-memory data (see `ZipArchive::append_data`).
+    /// Returns the CRC-32 of the payload.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_data(
+        &mut self,
+        file_name: &str,
+        data: impl AppendableData,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+    {
+        self.append_file_buf(file_name, &mut data.into_reader(), options)
+            .await
+    }
+
+    /// Append a new file to the archive from a buffered reader, without copying its buffer to a
+    /// chunk buffer (see `ZipArchive::append_file_buf`). Returns the CRC-32 of the payload.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_buf<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+        R: AsyncBufRead + Unpin,
+    {
+        let file_header_offset = self.archive_size;
+        self.append_entry(file_name, reader, options, None)
+            .await
+            .map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    /// Append a new file of `size` bytes to the archive, e.g. a file whose size is known from its
+    /// metadata. Returns the CRC-32 of the payload.
+    ///
+    /// A `Store` entry gets its sizes in the local header as it is written, so readers walking the
+    /// local headers can skip to the next entry even before the CRC is patched in. Other methods
+    /// are written as with `append_file`. The sizes actually read are the ones recorded, should
+    /// `size` be wrong.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_sized<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        size: u64,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        let file_header_offset = self.archive_size;
+        self.append_entry(
+            file_name,
+            &mut compressor::buffered(reader),
+            options,
+            Some(size),
+        )
+        .await
+        .map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    /// Append a new file to the archive, taking ownership of the reader, which is dropped once
+    /// the entry is written.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_owned<R>(
+        &mut self,
+        file_name: &str,
+        mut reader: R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        self.append_file(file_name, &mut reader, options).await
+    }
+
+    /// Append the file at `path` to the archive, with the modification time of the file as last
+    /// modified time (see `FileOptions::last_modified_time_from_metadata`). Returns the CRC-32
+    /// of the payload.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`, plus `ArchiveError::IoError` if the file cannot be opened.
+    pub async fn append_path(
+        &mut self,
+        file_name: &str,
+        path: impl AsRef<std::path::Path>,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        W: AsyncWrite + AsyncSeek + Unpin,
+    {
+        let file_header_offset = self.archive_size;
+        let in_entry =
+            |e: std::io::Error| ArchiveError::from(e).in_entry(file_name, file_header_offset);
+
+        let mut file = tokio::fs::File::open(path).await.map_err(in_entry)?;
+        let metadata = file.metadata().await.map_err(in_entry)?;
+        let options = options.clone().last_modified_time_from_metadata(&metadata);
+        self.append_file_sized(file_name, &mut file, metadata.len(), &options)
+            .await
+    }
+
+    /// Append a JAR-style manifest (e.g. `META-INF/MANIFEST.MF`) listing every entry written so
+    /// far with its digest.
+    ///
+    /// The CRC-32 stored for every entry is always available. With the `sha2` feature, the
+    /// SHA-256 of an entry not already hashed by `ArchiveOptions::sha256_manifest` is computed
+    /// by reading the entry back from the sink and decompressing it in memory, so only `Store`,
+    /// `Deflate` and, with the `zstd` feature, `Zstd` entries can be hashed.
+    ///
+    /// # Error
+    ///
+    /// * `ArchiveError::DigestUnavailable` if the digest of an entry cannot be computed, nothing
+    ///   being written then.
+    /// * `ArchiveError::UnsuportedCompressionMethod` or `ArchiveError::CrcMismatch`, wrapped in an
+    ///   `ArchiveError::Entry`, if an entry read back cannot be decompressed.
+    ///
+    /// Otherwise same as `append_file`.
+    pub async fn add_manifest(
+        &mut self,
+        manifest_name: &str,
+        hash_algorithm: HashAlgorithm,
     ) -> Result<(), ArchiveError>
+    where
+        W: AsyncRead,
+    {
+        let mut digests = Vec::with_capacity(self.data.files_info.len());
+        for index in 0..self.data.files_info.len() {
+            let file_info = &self.data.files_info[index];
+            #[cfg(feature = "sha2")]
+            if hash_algorithm == HashAlgorithm::Sha256 && file_info.sha256.is_none() {
+                let file_name = String::from_utf8_lossy(&file_info.file_name_as_bytes).into_owned();
+                let file_header_offset = file_info.offset;
+                let result = self.read_entry_sha256(index).await;
+                self.sink.seek(SeekFrom::Start(self.archive_size)).await?;
+                digests.push(result.map_err(|e| e.in_entry(&file_name, file_header_offset))?);
+                continue;
+            }
+            digests.push(stored_digest(file_info, hash_algorithm)?);
+        }
+
+        let manifest = jar_manifest(&self.data.files_info, hash_algorithm, &digests);
+        self.append_file(
+            manifest_name,
+            &mut manifest.as_slice(),
+            &FileOptions::default(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Read an entry back from the sink and compute the SHA-256 of its decompressed data.
+    #[cfg(feature = "sha2")]
+    async fn read_entry_sha256(&mut self, index: usize) -> Result<Vec<u8>, ArchiveError>
+    where
+        W: AsyncRead,
+    {
+        let entry = &self.data.files_info[index];
+
+        let mut header = [0; FILE_HEADER_BASE_SIZE as usize];
+        self.sink.seek(SeekFrom::Start(entry.offset)).await?;
+        self.sink.read_exact(&mut header).await?;
+        let data_offset = entry.offset + local_header_size(&header)?;
+
+        let mut compressed_data = vec![0; entry.compressed_size as usize];
+        self.sink.seek(SeekFrom::Start(data_offset)).await?;
+        self.sink.read_exact(&mut compressed_data).await?;
+        let data = decompress_entry(entry, &compressed_data, &ExtractOptions::default())?;
+
+        Ok(Sha256::digest(&data).to_vec())
+    }
+
+    /// Set the archive comment, written in the end of central directory record. It is truncated
+    /// to the last whole character within 65535 bytes, the largest comment the record can hold.
+    ///
+    /// Returns the length in bytes of the comment kept, shorter than `comment.len()` when it was
+    /// truncated.
+    pub fn set_archive_comment(&mut self, comment: &str) -> usize {
+        self.data.set_archive_comment(comment)
+    }
+
+    /// The archive comment, as set by `set_archive_comment` or read by `from_existing`; empty if
+    /// there is none.
+    pub fn comment_bytes(&self) -> &[u8] {
+        self.data.archive_comment_bytes()
+    }
+
+    /// The archive comment, `None` if there is none or it is not valid UTF-8.
+    pub fn comment(&self) -> Option<&str> {
+        self.data.archive_comment()
+    }
+
+    /// The archive comment, or an empty string if there is none or it is not valid UTF-8.
+    pub fn comment_or_empty(&self) -> &str {
+        self.comment().unwrap_or_default()
+    }
+
+    /// Set the options used by `append_file_default`, e.g. to share a compression method and level
+    /// between most entries while a few are appended with their own options by `append_file`.
+    ///
+    /// The default is `FileOptions::default()`.
+    pub fn set_default_options(&mut self, options: FileOptions) {
+        self.data.default_file_options = options;
+    }
+
+    /// Append a new file to the archive with the options set by `set_default_options`.
+    ///
+    /// # Error
+    ///
+    /// Same as `append_file`.
+    pub async fn append_file_default<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+    ) -> Result<u32, ArchiveError>
     where
         W: AsyncWrite + AsyncSeek + Unpin,
         R: AsyncRead + Unpin,
     {
+        let options = self.data.default_file_options.clone();
+        self.append_file(file_name, reader, &options).await
+    }
+
+    async fn append_entry<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+        reader_size: Option<u64>,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        if self.data.finalized {
+            return Err(ArchiveError::ArchiveFinalized);
+        }
+        options.validate()?;
+        // a new entry moves the central directory
+        self.central_directory_offset = None;
+        let mut hasher = EntryHasher::with_sha256(self.data.options.sha256_manifest);
+        let compressor = options.effective_compressor();
+        let stats_timer = EntryStatsTimer::start(options);
+
+        let file_name = normalize_entry_name(&self.data.options, file_name)?;
+
+        if let Some(marker) = spanning_marker(&self.data) {
+            self.sink.write_all(&marker).await?;
+            self.archive_size += marker.len() as u64;
+            self.data.spanning_marker_written = true;
+        }
         let file_header_offset = self.archive_size;
-        let mut hasher = Hasher::new();
-        let compressor = options.compressor;
 
         let (file_header, mut archive_file_entry) = build_file_header(
-            file_name,
+            &file_name,
             options,
             compressor,
-            file_header_offset as u32,
+            check_header_offset(&self.data.options, file_header_offset)?,
+            false,
             false,
         );
 
-        self.sink.write_all(file_header.buffer()).await?;
+        let mut file_header = file_header.finish();
+        // a stored payload is as large as its input: only the CRC is left to patch
+        let stored_size = reader_size
+            .filter(|_| matches!(compressor, CompressionMethod::Store()))
+            .and_then(|size| u32::try_from(size).ok());
+        if let Some(size) = stored_size {
+            let sizes_offset = archive_file_entry.crc_field_offset() as usize + 4;
+            file_header[sizes_offset..sizes_offset + 4].copy_from_slice(&size.to_le_bytes());
+            file_header[sizes_offset + 4..sizes_offset + 8].copy_from_slice(&size.to_le_bytes());
+        }
+        self.sink.write_all(&file_header).await?;
 
         let file_begin = self.sink.stream_position().await?;
         //println!("after header put: {file_begin} {file_begin:0X}");
 
-        let uncompressed_size = compress(
+        let uncompressed_size = compressor::compress_buf(
             compressor,
             &mut self.sink,
             reader,
             &mut hasher,
-            Level::Default,
+            options.compression_level,
+            &mut self.zstd_context,
         )
         .await?;
 
         self.archive_size = self.sink.stream_position().await?;
         let compressed_size = self.archive_size - file_begin;
 
-        let crc32 = hasher.finalize();
+        let digest = hasher.finalize();
+        let crc32 = digest.crc32;
         archive_file_entry.crc32 = crc32;
+        archive_file_entry.sha256 = digest.sha256;
         archive_file_entry.compressed_size = compressed_size;
         archive_file_entry.uncompressed_size = uncompressed_size;
+        check_zip32_sizes(&archive_file_entry)?;
+        stats_timer.report(&archive_file_entry);
 
         let mut file_data = ArchiveDescriptor::new(3 * 4);
         file_data.write_u32(crc32);
-        file_data.write_u32(compressed_size as u32);
-        file_data.write_u32(uncompressed_size as u32);
+        file_data.write_u32(zip32(compressed_size, "compressed size")?);
+        file_data.write_u32(zip32(uncompressed_size, "uncompressed size")?);
 
         self.sink
-            .seek(SeekFrom::Start(file_header_offset + FILE_HEADER_CRC_OFFSET))
+            .seek(SeekFrom::Start(
+                file_header_offset + archive_file_entry.crc_field_offset(),
+            ))
             .await?;
 
         self.sink.write_all(file_data.buffer()).await?;
+        // commit the patch before moving on, a buffered sink may otherwise still hold it
+        self.sink.flush().await?;
 
         self.sink.seek(SeekFrom::Start(self.archive_size)).await?;
 
         self.data.files_info.push(archive_file_entry);
 
-        Ok(())
+        Ok(crc32)
     }
 
     /// Finalize the archive by writing the necessary metadata to the end of the archive.
@@ -255,36 +1642,62 @@ impl<W: AsyncWrite + AsyncSeek + Unpin> ZipArchiveNoStream<W> {
     ///
     /// This function will forward any error found while trying to read from the file stream or while writing to the underlying sink.
     ///
+    /// # Retry
+    ///
+    /// The offset of the central directory is recorded before it is written. If writing it fails,
+    /// e.g. on a transient I/O error, calling `finalize` again seeks back to that offset and writes
+    /// the central directory and its end record over the partial ones, without duplicates; the
+    /// SHA-256 manifest, once appended, is not appended again. Calling it again once it succeeded
+    /// writes the same bytes over. The archive is not reported finalized while a call is failing.
+    ///
     /// # Features
     ///
-    /// Requires `tokio-async-io` feature. `futures-async-io` is also available.
+    /// Requires the `tokio` feature. The `futures` feature provides the streaming archive over
+    /// `futures::io`, see `compress::futures::archive::ZipArchive`.
     pub async fn finalize(&mut self) -> Result<(), ArchiveError>
     where
         W: AsyncWrite + Unpin,
     {
-        let central_directory_offset = self.sink.stream_position().await? as u32;
-
-        let mut central_directory_header =
-            ArchiveDescriptor::new(CENTRAL_DIRECTORY_ENTRY_BASE_SIZE + 200);
-
-        for file_info in &self.data.files_info {
-            build_central_directory_file_header(&mut central_directory_header, file_info);
-
-            self.sink
-                .write_all(central_directory_header.buffer())
-                .await?;
+        self.data.finalized = false;
+        let central_directory_offset = match self.central_directory_offset {
+            Some(offset) => {
+                self.sink.seek(SeekFrom::Start(offset)).await?;
+                offset
+            }
+            None => {
+                if self.data.options.sha256_manifest && !self.sha256_manifest_appended {
+                    let manifest = build_sha256_manifest(&self.data.files_info);
+                    let name = self.data.options.sha256_manifest_name.clone();
+                    let options = self.data.options.sha256_manifest_options.clone();
+                    self.append_file(&name, &mut manifest.as_slice(), &options)
+                        .await?;
+                    self.sha256_manifest_appended = true;
+                }
 
-            central_directory_header.clear();
+                if let Some(marker) = spanning_marker(&self.data) {
+                    self.sink.write_all(&marker).await?;
+                    self.data.spanning_marker_written = true;
+                }
+                let gap = stale_bytes_gap(&self.data, self.sink.stream_position().await?);
+                tokio::io::copy(&mut tokio::io::repeat(0).take(gap), &mut self.sink).await?;
+                let offset = self.sink.stream_position().await?;
+                self.central_directory_offset = Some(offset);
+                offset
+            }
+        };
+        if self.data.options.strict {
+            check_spec_conformance(&self.data, central_directory_offset)?;
         }
 
-        let current_archive_size = self.sink.stream_position().await?;
-        let central_directory_size: u32 = current_archive_size as u32 - central_directory_offset;
+        let central_directory = build_central_directory(&self.data);
+        self.sink.write_all(central_directory.buffer()).await?;
+        let central_directory_size = central_directory.buffer().len() as u64;
 
         let end_of_central_directory = build_central_directory_end(
             &self.data,
             central_directory_offset,
             central_directory_size,
-        );
+        )?;
 
         self.sink
             .write_all(end_of_central_directory.buffer())
@@ -292,6 +1705,7 @@ impl<W: AsyncWrite + AsyncSeek + Unpin> ZipArchiveNoStream<W> {
 
         self.sink.flush().await?;
         self.archive_size = self.sink.stream_position().await?;
+        self.data.finalized = true;
         //println!("CentralDirectoryEnd {:#?}", dir_end);
         Ok(())
     }
@@ -299,6 +1713,183 @@ impl<W: AsyncWrite + AsyncSeek + Unpin> ZipArchiveNoStream<W> {
     pub fn get_archive_size(&self) -> u64 {
         self.archive_size
     }
+
+    /// Exact number of bytes `finalize` adds for the entries appended so far: the central
+    /// directory, the end of central directory record (ZIP64 records included when needed) and
+    /// the archive comment.
+    ///
+    /// The SHA-256 manifest entry, appended by `finalize` when enabled, is not accounted for.
+    pub fn estimate_central_directory_size(&self) -> u64 {
+        central_directory_total_size(&self.data, self.archive_size)
+    }
+
+    /// Whether `finalize` completed, i.e. the archive is complete and the sink flushed.
+    pub fn is_finalized(&self) -> bool {
+        self.data.finalized
+    }
+
+    /// Give back the underlying writer.
+    ///
+    /// The writer holds a complete archive only once `finalize` completed: before, the central
+    /// directory is missing and bytes buffered by the writer may not be flushed. In debug builds,
+    /// retrieving the writer of an archive holding entries but not finalized panics; use
+    /// `writer_ref` or `writer_mut` to inspect the writer meanwhile.
+    pub fn retrieve_writer(self) -> W {
+        debug_assert!(
+            self.data.finalized || self.data.files_info.is_empty(),
+            "the writer is retrieved before the archive is finalized"
+        );
+        self.sink
+    }
+
+    /// Reference to the underlying writer, e.g. to inspect what is written so far without
+    /// consuming the archive.
+    pub fn writer_ref(&self) -> &W {
+        &self.sink
+    }
+
+    /// Mutable reference to the underlying writer.
+    ///
+    /// The archive keeps its own count of the bytes written, from which the entry offsets are
+    /// computed: bytes written directly to the writer are not counted and corrupt the archive.
+    /// Moving its position corrupts the archive as well.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.sink
+    }
+
+    /// Replace the last appended entry, keeping its name.
+    ///
+    /// The sink is rewound to the local header of the last entry, which is written again with the
+    /// new payload and options. If the new entry is shorter, `finalize` zero-fills the rest of the
+    /// previous one before the central directory, so that no stale byte is left after the end of
+    /// central directory record.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::BadArchiveStructure` if no entry was appended yet, otherwise same as `append_file`.
+    pub async fn replace_last_entry<R>(
+        &mut self,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if self.data.finalized {
+            return Err(ArchiveError::ArchiveFinalized);
+        }
+        let last_entry =
+            self.data.files_info.pop().ok_or_else(|| {
+                ArchiveError::BadArchiveStructure("No entry to replace".to_owned())
+            })?;
+        let file_name = String::from_utf8_lossy(&last_entry.file_name_as_bytes).into_owned();
+
+        self.data.written_end = self.data.written_end.max(self.archive_size);
+        self.sink.seek(SeekFrom::Start(last_entry.offset)).await?;
+        self.archive_size = last_entry.offset;
+
+        self.append_file(&file_name, reader, options).await
+    }
+
+    /// Recompress an entry in place with another compression method, e.g. from `Store` to
+    /// `Deflate`, without rebuilding the archive.
+    ///
+    /// The payload is read back, decompressed, compressed again with `new_method` and the level of
+    /// `options`, then written over the previous payload; the local header and the central
+    /// directory record are updated. The following entries do not move, so the new payload must
+    /// fit in place of the previous one; a smaller payload is moved forward behind a zero-filled
+    /// padding extra field of the local header, so that no stale byte is left.
+    ///
+    /// # Error
+    ///
+    /// * `ArchiveError::EntryNotFound` if no entry has this name.
+    /// * `ArchiveError::RecompressedEntryTooLarge` if the new payload is larger than the previous
+    ///   one, in which case the archive is left unchanged.
+    /// * `ArchiveError::RecompressedEntryGap` if the new payload is smaller by less than 8 bytes,
+    ///   or by more than the local extra field can hold, the archive being left unchanged too.
+    /// * `ArchiveError::UnsuportedCompressionMethod` if the entry is neither `Store` nor `Deflate`.
+    /// * `ArchiveError::BadArchiveStructure` if the entry has a data descriptor or ZIP64 fields.
+    /// * `ArchiveError::ArchiveFinalized` once `finalize` completed.
+    ///
+    /// Errors other than `EntryNotFound` and `ArchiveFinalized` are wrapped in an
+    /// `ArchiveError::Entry`.
+    pub async fn recompress_entry(
+        &mut self,
+        file_name: &str,
+        new_method: CompressionMethod,
+        options: &FileOptions,
+    ) -> Result<(), ArchiveError>
+    where
+        W: AsyncRead,
+    {
+        if self.data.finalized {
+            return Err(ArchiveError::ArchiveFinalized);
+        }
+        let index = self
+            .data
+            .files_info
+            .iter()
+            .position(|entry| entry.file_name_as_bytes == file_name.as_bytes())
+            .ok_or_else(|| ArchiveError::EntryNotFound(file_name.to_owned()))?;
+        let file_header_offset = self.data.files_info[index].offset;
+
+        let result = self
+            .recompress_entry_in_place(index, new_method, options)
+            .await;
+        self.sink.seek(SeekFrom::Start(self.archive_size)).await?;
+        result.map_err(|e| e.in_entry(file_name, file_header_offset))
+    }
+
+    async fn recompress_entry_in_place(
+        &mut self,
+        index: usize,
+        new_method: CompressionMethod,
+        options: &FileOptions,
+    ) -> Result<(), ArchiveError>
+    where
+        W: AsyncRead,
+    {
+        let options = options.clone().compression_method(new_method);
+        options.validate()?;
+
+        let mut entry = self.data.files_info[index].clone();
+        check_recompressible(&entry)?;
+
+        let mut header = [0; FILE_HEADER_BASE_SIZE as usize];
+        self.sink.seek(SeekFrom::Start(entry.offset)).await?;
+        self.sink.read_exact(&mut header).await?;
+        let data_offset = entry.offset + local_header_size(&header)?;
+
+        let mut compressed_data = vec![0; entry.compressed_size as usize];
+        self.sink.seek(SeekFrom::Start(data_offset)).await?;
+        self.sink.read_exact(&mut compressed_data).await?;
+        let data = decompress_entry(&entry, &compressed_data, &ExtractOptions::default())?;
+
+        let new_method = options.effective_compressor();
+        let recompressed = CompressedEntry::compress(
+            new_method,
+            options.compression_level,
+            &mut data.as_slice(),
+            false,
+        )
+        .await?;
+        let (header_fields, padding) = recompress_entry_header(
+            &mut entry,
+            &header,
+            new_method,
+            options.compression_level,
+            recompressed.data.len() as u64,
+        )?;
+
+        self.sink.seek(SeekFrom::Start(data_offset)).await?;
+        self.sink.write_all(padding.buffer()).await?;
+        self.sink.write_all(&recompressed.data).await?;
+        self.sink.seek(SeekFrom::Start(entry.offset + 4)).await?;
+        self.sink.write_all(header_fields.buffer()).await?;
+
+        self.data.files_info[index] = entry;
+        Ok(())
+    }
 }
 
 impl<W: AsyncWrite + AsyncSeek + Unpin> ZipArchiveCommon for ZipArchiveNoStream<W> {
@@ -1,14 +1,35 @@
+use std::future::Future;
+use std::io::{Error, ErrorKind};
 use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
 use tokio::io::AsyncWrite;
+use tokio::sync::mpsc::Sender;
+
+use crate::constants::{INTERRUPTED_MAX_RETRY, WRITE_ZERO_MAX_RETRY};
+
+/// First delay before polling again a writer that reported `WouldBlock`, doubled on each
+/// consecutive `WouldBlock` up to `WOULD_BLOCK_MAX_DELAY`.
+const WOULD_BLOCK_MIN_DELAY: Duration = Duration::from_millis(1);
+const WOULD_BLOCK_MAX_DELAY: Duration = Duration::from_millis(100);
 
 #[derive(Debug)]
 pub struct AsyncWriteWrapper<W: AsyncWrite + Unpin> {
     writer: W,
     written_bytes_count: u64,
+    flush_every: Option<u64>,
+    flushed_bytes_count: u64,
+    /// Back-off armed when the writer reported `WouldBlock`, waited out before polling it again.
+    would_block_sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+    would_block_delay: Duration,
+    /// SHA-256 of the bytes written since `start_digest`, to sign the archive.
+    #[cfg(feature = "signature")]
+    digest: Option<Box<sha2::Sha256>>,
 }
 
 pub trait BytesCounter {
     fn get_written_bytes_count(&self) -> u64;
+    #[allow(dead_code)]
     fn set_written_bytes_count(&mut self, count: u64);
 }
 
@@ -17,12 +38,52 @@ impl<W: AsyncWrite + Unpin> AsyncWriteWrapper<W> {
         Self {
             writer: w,
             written_bytes_count: 0,
+            flush_every: None,
+            flushed_bytes_count: 0,
+            would_block_sleep: None,
+            would_block_delay: Duration::ZERO,
+            #[cfg(feature = "signature")]
+            digest: None,
         }
     }
 
+    /// Flush the inner writer each time the given number of bytes has been written since the last flush.
+    pub fn with_flush_every(mut self, flush_every: Option<u64>) -> AsyncWriteWrapper<W> {
+        self.flush_every = flush_every;
+        self
+    }
+
+    /// Start counting at `offset`, the position of the inner writer in the file it writes to.
+    pub fn with_base_offset(mut self, offset: u64) -> AsyncWriteWrapper<W> {
+        self.written_bytes_count = offset;
+        self.flushed_bytes_count = offset;
+        self
+    }
+
+    /// Hash every byte written from now on, until `take_digest`.
+    #[cfg(feature = "signature")]
+    pub fn start_digest(&mut self) {
+        self.digest = Some(Box::new(sha2::Digest::new()));
+    }
+
+    /// SHA-256 of the bytes written since `start_digest`, if it was called.
+    #[cfg(feature = "signature")]
+    pub fn take_digest(&mut self) -> Option<sha2::Sha256> {
+        self.digest.take().map(|digest| *digest)
+    }
+
     pub fn retrieve_writer(self) -> W {
         self.writer
     }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Mutable access to the inner writer; bytes written through it are not counted.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
 }
 
 impl<W: AsyncWrite + Unpin> BytesCounter for AsyncWriteWrapper<W> {
@@ -42,12 +103,81 @@ impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncWriteWrapper<W> {
         buf: &[u8],
     ) -> std::task::Poll<Result<usize, std::io::Error>> {
         let wrapper = self.get_mut();
-        let results: std::task::Poll<Result<usize, std::io::Error>> =
+
+        if let Some(sleep) = &mut wrapper.would_block_sleep {
+            match sleep.as_mut().poll(cx) {
+                Poll::Ready(()) => wrapper.would_block_sleep = None,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        // The threshold is checked before writing: a pending flush can then be resumed by the
+        // next poll without having consumed any byte of `buf`.
+        if let Some(flush_every) = wrapper.flush_every {
+            if wrapper.written_bytes_count - wrapper.flushed_bytes_count >= flush_every {
+                match Pin::new(&mut wrapper.writer).poll_flush(cx) {
+                    Poll::Ready(Ok(())) => {
+                        wrapper.flushed_bytes_count = wrapper.written_bytes_count
+                    }
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+
+        let mut results: std::task::Poll<Result<usize, std::io::Error>> =
             Pin::new(&mut wrapper.writer).poll_write(cx, buf);
 
+        // A write interrupted by a signal is retried, as `std::io::Write::write_all` does, but a
+        // bounded number of times: a sink failing with `Interrupted` every time must not hang.
+        let mut attempt = 1;
+        while attempt < INTERRUPTED_MAX_RETRY
+            && matches!(&results, Poll::Ready(Err(e)) if e.kind() == ErrorKind::Interrupted)
+        {
+            attempt += 1;
+            results = Pin::new(&mut wrapper.writer).poll_write(cx, buf);
+        }
+
+        // A sink that keeps accepting no bytes (e.g. a full disk) must not make the caller spin.
+        let mut attempt = 1;
+        while !buf.is_empty() && matches!(results, Poll::Ready(Ok(0))) {
+            if attempt >= WRITE_ZERO_MAX_RETRY {
+                return Poll::Ready(Err(Error::new(
+                    ErrorKind::WriteZero,
+                    "the sink did not accept any byte",
+                )));
+            }
+            attempt += 1;
+            results = Pin::new(&mut wrapper.writer).poll_write(cx, buf);
+        }
+
+        // Some non-blocking sinks report `WouldBlock` as an error instead of returning `Pending`,
+        // without registering the waker. Rather than failing the entry or waking the task at once,
+        // which would spin, poll again once an increasing delay has elapsed: the timer wakes it.
+        // Without a Tokio runtime, e.g. under the `futures` backend, there is no timer and the
+        // error is returned as is.
+        if let Poll::Ready(Err(e)) = &results {
+            if e.kind() == ErrorKind::WouldBlock && tokio::runtime::Handle::try_current().is_ok() {
+                wrapper.would_block_delay = (wrapper.would_block_delay * 2)
+                    .clamp(WOULD_BLOCK_MIN_DELAY, WOULD_BLOCK_MAX_DELAY);
+                let mut sleep = Box::pin(tokio::time::sleep(wrapper.would_block_delay));
+                if sleep.as_mut().poll(cx).is_ready() {
+                    cx.waker().wake_by_ref();
+                } else {
+                    wrapper.would_block_sleep = Some(sleep);
+                }
+                return Poll::Pending;
+            }
+        }
+
         results.map(|pool_result| match pool_result {
             Ok(nb_byte_written) => {
+                wrapper.would_block_delay = Duration::ZERO;
                 wrapper.written_bytes_count += nb_byte_written as u64;
+                #[cfg(feature = "signature")]
+                if let Some(digest) = &mut wrapper.digest {
+                    sha2::Digest::update(digest.as_mut(), &buf[..nb_byte_written]);
+                }
                 Ok(nb_byte_written)
             }
             Err(e) => Err(e),
@@ -58,7 +188,12 @@ impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncWriteWrapper<W> {
         self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), std::io::Error>> {
-        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+        let wrapper = self.get_mut();
+        let results = Pin::new(&mut wrapper.writer).poll_flush(cx);
+        if let Poll::Ready(Ok(())) = results {
+            wrapper.flushed_bytes_count = wrapper.written_bytes_count;
+        }
+        results
     }
 
     fn poll_shutdown(
@@ -68,3 +203,76 @@ impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncWriteWrapper<W> {
         Pin::new(&mut self.get_mut().writer).poll_shutdown(cx)
     }
 }
+
+const PROGRESS_STEP: u8 = 5;
+
+/// Writer reporting, as a percentage, the bytes written against an expected total.
+pub struct ProgressWriter<'a, W: AsyncWrite + Unpin> {
+    writer: &'a mut W,
+    sender: Sender<u8>,
+    expected_size: u64,
+    written_bytes_count: u64,
+    last_sent: u8,
+}
+
+impl<'a, W: AsyncWrite + Unpin> ProgressWriter<'a, W> {
+    pub fn new(writer: &'a mut W, sender: Sender<u8>, expected_size: u64) -> Self {
+        Self {
+            writer,
+            sender,
+            expected_size: expected_size.max(1),
+            written_bytes_count: 0,
+            last_sent: 0,
+        }
+    }
+
+    /// Report the completion of the entry.
+    pub async fn done(self) {
+        // the receiver may have been dropped, progress is best effort
+        let _ = self.sender.send(100).await;
+    }
+
+    fn report(&mut self) {
+        let percentage = (self.written_bytes_count * 100 / self.expected_size).min(99) as u8;
+        let step = percentage - percentage % PROGRESS_STEP;
+
+        if step >= self.last_sent + PROGRESS_STEP {
+            // never block the write on a slow receiver, a skipped update is caught up by the next one
+            if self.sender.try_send(step).is_ok() {
+                self.last_sent = step;
+            }
+        }
+    }
+}
+
+impl<'a, W: AsyncWrite + Unpin> AsyncWrite for ProgressWriter<'a, W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        let progress_writer = self.get_mut();
+        let results = Pin::new(&mut *progress_writer.writer).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(nb_byte_written)) = results {
+            progress_writer.written_bytes_count += nb_byte_written as u64;
+            progress_writer.report();
+        }
+
+        results
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut *self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut *self.get_mut().writer).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,176 @@
+use super::archive::{ZipArchive, ZipArchiveNoStream};
+
+use crate::archive::{ArchiveOptions, FileOptions};
+use crate::error::ArchiveError;
+
+use std::io::{Cursor, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
+
+/// Sink of a `ZipArchiveAuto`, telling whether it supports seeking.
+///
+/// Stable Rust cannot tell from a generic parameter whether a type implements `AsyncSeek`, so the
+/// sink declares it. The default implementation is not seekable; seekable sinks return themselves
+/// wrapped with `SeekableSink::new`.
+pub trait ArchiveSink: AsyncWrite + Unpin + Sized {
+    /// The sink wrapped for seeking, or the sink itself if it cannot seek.
+    fn into_seekable(self) -> Result<SeekableSink<Self>, Self> {
+        Err(self)
+    }
+}
+
+impl ArchiveSink for tokio::fs::File {
+    fn into_seekable(self) -> Result<SeekableSink<Self>, Self> {
+        Ok(SeekableSink::new(self))
+    }
+}
+
+impl ArchiveSink for Cursor<Vec<u8>> {
+    fn into_seekable(self) -> Result<SeekableSink<Self>, Self> {
+        Ok(SeekableSink::new(self))
+    }
+}
+
+impl ArchiveSink for Vec<u8> {}
+impl ArchiveSink for tokio::io::DuplexStream {}
+impl ArchiveSink for tokio::io::Sink {}
+
+/// Sink whose `AsyncSeek` implementation was captured when it was known to be seekable.
+#[derive(Debug)]
+pub struct SeekableSink<W: AsyncWrite + Unpin> {
+    writer: W,
+    start_seek: fn(Pin<&mut W>, SeekFrom) -> std::io::Result<()>,
+    poll_complete: fn(Pin<&mut W>, &mut Context<'_>) -> Poll<std::io::Result<u64>>,
+}
+
+impl<W: AsyncWrite + Unpin> SeekableSink<W> {
+    pub fn new(writer: W) -> SeekableSink<W>
+    where
+        W: AsyncSeek,
+    {
+        Self {
+            writer,
+            start_seek: <W as AsyncSeek>::start_seek,
+            poll_complete: <W as AsyncSeek>::poll_complete,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for SeekableSink<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Pin::new(&mut self.get_mut().writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().writer).poll_shutdown(cx)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncSeek for SeekableSink<W> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        let sink = self.get_mut();
+        (sink.start_seek)(Pin::new(&mut sink.writer), position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        let sink = self.get_mut();
+        (sink.poll_complete)(Pin::new(&mut sink.writer), cx)
+    }
+}
+
+#[derive(Debug)]
+enum Strategy<W: ArchiveSink> {
+    Stream(ZipArchive<W>),
+    Seekable(ZipArchiveNoStream<SeekableSink<W>>),
+}
+
+/// Zip archive picking the seekable code path (headers patched in place, no data descriptor)
+/// when the sink can seek, and the streaming one otherwise.
+#[derive(Debug)]
+pub struct ZipArchiveAuto<W: ArchiveSink> {
+    strategy: Strategy<W>,
+}
+
+impl<W: ArchiveSink> ZipArchiveAuto<W> {
+    /// Create a new zip archive, choosing the code path from the sink.
+    pub fn new(sink: W) -> Self {
+        Self::with_options(sink, ArchiveOptions::default())
+    }
+
+    /// Create a new zip archive with the given archive-wide options, choosing the code path from the sink.
+    pub fn with_options(sink: W, options: ArchiveOptions) -> Self {
+        let strategy = match sink.into_seekable() {
+            Ok(sink) => Strategy::Seekable(ZipArchiveNoStream::with_options(sink, options)),
+            Err(sink) => Strategy::Stream(ZipArchive::with_options(sink, options)),
+        };
+        Self { strategy }
+    }
+
+    /// Whether the seekable code path was chosen.
+    pub fn is_seekable(&self) -> bool {
+        matches!(self.strategy, Strategy::Seekable(_))
+    }
+
+    /// Append a new file to the archive, see `ZipArchive::append_file`.
+    pub async fn append_file<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        match &mut self.strategy {
+            Strategy::Stream(archive) => archive.append_file(file_name, reader, options).await,
+            Strategy::Seekable(archive) => archive.append_file(file_name, reader, options).await,
+        }
+    }
+
+    /// Finalize the archive by writing the necessary metadata to the end of the archive.
+    pub async fn finalize(&mut self) -> Result<(), ArchiveError> {
+        match &mut self.strategy {
+            Strategy::Stream(archive) => archive.finalize().await,
+            Strategy::Seekable(archive) => archive.finalize().await,
+        }
+    }
+
+    pub fn get_archive_size(&self) -> u64 {
+        match &self.strategy {
+            Strategy::Stream(archive) => archive.get_archive_size(),
+            Strategy::Seekable(archive) => archive.get_archive_size(),
+        }
+    }
+
+    /// Whether `finalize` completed, i.e. the archive is complete and the sink flushed.
+    pub fn is_finalized(&self) -> bool {
+        match &self.strategy {
+            Strategy::Stream(archive) => archive.is_finalized(),
+            Strategy::Seekable(archive) => archive.is_finalized(),
+        }
+    }
+
+    /// Give back the underlying writer, see `ZipArchive::retrieve_writer`.
+    pub fn retrieve_writer(self) -> W {
+        match self.strategy {
+            Strategy::Stream(archive) => archive.retrieve_writer(),
+            Strategy::Seekable(archive) => archive.retrieve_writer().into_inner(),
+        }
+    }
+}
@@ -1,124 +1,517 @@
-use async_compression::tokio::write::{
-    BzEncoder, DeflateEncoder, LzmaEncoder, XzEncoder, ZstdEncoder,
-};
-use crc32fast::Hasher;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "bzip2")]
+use async_compression::tokio::write::BzEncoder;
+use async_compression::tokio::write::DeflateEncoder;
+#[cfg(feature = "xz")]
+use async_compression::tokio::write::{LzmaEncoder, XzEncoder};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+#[cfg(feature = "zstd")]
+use zstd::stream::raw::{Encoder as RawZstdEncoder, InBuffer, Operation, OutBuffer};
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 use crate::{
+    archive_common::EntryHasher,
     compression::{CompressionMethod, Level},
     error::ArchiveError,
 };
 
 macro_rules! compress_tokio {
-    ( $encoder:expr, $hasher:expr, $reader:expr) => {{
-        let mut buf = vec![0; 4096];
+    ( $encoder:expr, $codec:expr, $hasher:expr, $reader:expr) => {{
+        let encoder_error = |encoder: &_, e| codec_error($codec, encoder, e);
         let mut total_read: u64 = 0;
 
         loop {
-            let read = $reader.read(&mut buf).await?;
-            if read == 0 {
+            let chunk = $reader.fill_buf().await?;
+            if chunk.is_empty() {
                 break;
             }
 
+            let read = chunk.len();
             total_read += read as u64;
-            $hasher.update(&buf[..read]);
-            $encoder.write_all(&buf[..read]).await?;
-            //self.sink.write_all(&buf[..read]).await?; // Payload chunk.
+            $hasher.update(chunk);
+            $encoder
+                .write_all(chunk)
+                .await
+                .map_err(|e| encoder_error($encoder.get_ref(), e))?;
+            $reader.consume(read);
         }
-        $encoder.flush().await?;
-        $encoder.shutdown().await?;
+        $encoder
+            .flush()
+            .await
+            .map_err(|e| encoder_error($encoder.get_ref(), e))?;
+        $encoder
+            .shutdown()
+            .await
+            .map_err(|e| encoder_error($encoder.get_ref(), e))?;
 
         total_read
     }};
 }
 
+/// Writer handed to the encoders: shutting an encoder down finishes the compressed stream but only
+/// flushes the sink, which stays open for the next entries (e.g. a pipe or a socket).
+///
+/// It also records whether the sink failed, to tell the sink errors from the codec ones.
+struct KeepOpen<'a, W: AsyncWrite + Unpin> {
+    sink: &'a mut W,
+    failed: bool,
+}
+
+impl<'a, W: AsyncWrite + Unpin> KeepOpen<'a, W> {
+    fn new(sink: &'a mut W) -> Self {
+        KeepOpen {
+            sink,
+            failed: false,
+        }
+    }
+
+    fn track<T>(&mut self, poll: Poll<std::io::Result<T>>) -> Poll<std::io::Result<T>> {
+        if let Poll::Ready(Err(_)) = poll {
+            self.failed = true;
+        }
+        poll
+    }
+}
+
+impl<'a, W: AsyncWrite + Unpin> AsyncWrite for KeepOpen<'a, W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut *this.sink).poll_write(cx, buf);
+        this.track(poll)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut *this.sink).poll_flush(cx);
+        this.track(poll)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut *this.sink).poll_flush(cx);
+        this.track(poll)
+    }
+}
+
+/// Error raised by an encoder: a failure of the sink stays an I/O error, anything else is
+/// attributed to the codec.
+fn codec_error<W: AsyncWrite + Unpin>(
+    codec: &'static str,
+    sink: &KeepOpen<'_, W>,
+    e: std::io::Error,
+) -> ArchiveError {
+    if sink.failed {
+        ArchiveError::from(e)
+    } else {
+        ArchiveError::Compression { codec, source: e }
+    }
+}
+
+/// zstd compression context reused across the entries of an archive: resetting it for a new
+/// frame is much cheaper than allocating it again, which dominates for many small entries.
+#[derive(Default)]
+pub struct ZstdContext {
+    /// Encoder of the previous zstd entry, with its level.
+    #[cfg(feature = "zstd")]
+    encoder: Option<(i32, RawZstdEncoder<'static>)>,
+}
+
+#[cfg(feature = "zstd")]
+impl ZstdContext {
+    /// Encoder starting a new frame at `level`, reset from the previous entry if it has the same
+    /// level. The reset keeps the parameters, so the frame is the same as with a new encoder.
+    fn encoder(&mut self, level: i32) -> std::io::Result<&mut RawZstdEncoder<'static>> {
+        let encoder = match self.encoder.take() {
+            Some((previous_level, mut encoder)) if previous_level == level => {
+                encoder.reinit()?;
+                encoder
+            }
+            _ => RawZstdEncoder::new(level)?,
+        };
+        Ok(&mut self.encoder.insert((level, encoder)).1)
+    }
+}
+
+impl std::fmt::Debug for ZstdContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("ZstdContext");
+        #[cfg(feature = "zstd")]
+        debug.field("level", &self.encoder.as_ref().map(|(level, _)| level));
+        debug.finish()
+    }
+}
+
+/// zstd level of a compression level, as `async_compression` maps the named ones. A precise
+/// level is clamped to the levels zstd supports, negative ones being its fast modes.
+#[cfg(feature = "zstd")]
+fn zstd_level(level: Level) -> i32 {
+    match level {
+        Level::Fastest => 1,
+        Level::Best => 21,
+        Level::Default => zstd::DEFAULT_COMPRESSION_LEVEL,
+        Level::Precise(val) => {
+            let range = zstd::compression_level_range();
+            val.clamp(*range.start(), *range.end())
+        }
+        Level::None => 0,
+    }
+}
+
+/// Compress with a zstd encoder driven directly, rather than through `ZstdEncoder`, so its
+/// context can be reused. The calls are the ones `ZstdEncoder` makes: the input chunks, then a
+/// flush and the end of the frame, hence the same output.
+#[cfg(feature = "zstd")]
+async fn compress_zstd<R, W>(
+    encoder: &mut RawZstdEncoder<'static>,
+    writer: &mut W,
+    reader: &mut R,
+    hasher: &mut EntryHasher,
+) -> Result<u64, ArchiveError>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let codec_error = |source| ArchiveError::Compression {
+        codec: CompressionMethod::Zstd().label(),
+        source,
+    };
+    let mut out = vec![0; 8 * 1024];
+    let mut total_read: u64 = 0;
+
+    loop {
+        let chunk = reader.fill_buf().await?;
+        if chunk.is_empty() {
+            break;
+        }
+
+        let read = chunk.len();
+        total_read += read as u64;
+        hasher.update(chunk);
+        let mut input = InBuffer::around(chunk);
+        while input.pos < read {
+            let mut output = OutBuffer::around(out.as_mut_slice());
+            encoder.run(&mut input, &mut output).map_err(codec_error)?;
+            writer.write_all(output.as_slice()).await?;
+        }
+        reader.consume(read);
+    }
+
+    loop {
+        let mut output = OutBuffer::around(out.as_mut_slice());
+        let remaining = encoder.flush(&mut output).map_err(codec_error)?;
+        writer.write_all(output.as_slice()).await?;
+        if remaining == 0 {
+            break;
+        }
+    }
+    loop {
+        let mut output = OutBuffer::around(out.as_mut_slice());
+        let remaining = encoder.finish(&mut output, true).map_err(codec_error)?;
+        writer.write_all(output.as_slice()).await?;
+        if remaining == 0 {
+            break;
+        }
+    }
+    writer.flush().await?;
+
+    Ok(total_read)
+}
+
+/// zstd encoder of an entry written chunk by chunk, driven directly rather than through
+/// `ZstdEncoder`, which cannot take the negative levels: it takes the levels of `compress`. It
+/// writes to memory, hence never pends.
+#[cfg(feature = "zstd")]
+pub struct ZstdEntryEncoder(zstd::stream::write::Encoder<'static, Vec<u8>>);
+
+#[cfg(feature = "zstd")]
+impl ZstdEntryEncoder {
+    fn new(level: Level) -> std::io::Result<ZstdEntryEncoder> {
+        zstd::stream::write::Encoder::new(Vec::new(), zstd_level(level)).map(ZstdEntryEncoder)
+    }
+
+    fn get_mut(&mut self) -> &mut Vec<u8> {
+        self.0.get_mut()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl std::fmt::Debug for ZstdEntryEncoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZstdEntryEncoder").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl AsyncWrite for ZstdEntryEncoder {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        Poll::Ready(std::io::Write::write(&mut self.get_mut().0, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Poll::Ready(std::io::Write::flush(&mut self.get_mut().0))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Poll::Ready(self.get_mut().0.do_finish())
+    }
+}
+
+/// Encoder of an entry written chunk by chunk, buffering its compressed output until drained.
+#[derive(Debug)]
+pub enum EntryEncoder {
+    Store(Vec<u8>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+    #[cfg(feature = "bzip2")]
+    BZip2(BzEncoder<Vec<u8>>),
+    #[cfg(feature = "xz")]
+    Lzma(LzmaEncoder<Vec<u8>>),
+    #[cfg(feature = "zstd")]
+    Zstd(ZstdEntryEncoder),
+    #[cfg(feature = "xz")]
+    Xz(XzEncoder<Vec<u8>>),
+}
+
+macro_rules! entry_encoder_dispatch {
+    ( $encoder:expr, $inner:ident => $body:expr ) => {
+        match $encoder {
+            EntryEncoder::Store($inner) => $body,
+            EntryEncoder::Deflate($inner) => $body,
+            #[cfg(feature = "bzip2")]
+            EntryEncoder::BZip2($inner) => $body,
+            #[cfg(feature = "xz")]
+            EntryEncoder::Lzma($inner) => $body,
+            #[cfg(feature = "zstd")]
+            EntryEncoder::Zstd($inner) => $body,
+            #[cfg(feature = "xz")]
+            EntryEncoder::Xz($inner) => $body,
+        }
+    };
+}
+
+impl EntryEncoder {
+    pub fn new(compressor: CompressionMethod, level: Level) -> Result<EntryEncoder, ArchiveError> {
+        let output = Vec::new();
+        match compressor {
+            CompressionMethod::Store() => Ok(EntryEncoder::Store(output)),
+            CompressionMethod::Deflate() => Ok(EntryEncoder::Deflate(
+                DeflateEncoder::with_quality(output, level.into()),
+            )),
+            #[cfg(feature = "bzip2")]
+            CompressionMethod::BZip2() => Ok(EntryEncoder::BZip2(BzEncoder::with_quality(
+                output,
+                level.into(),
+            ))),
+            #[cfg(feature = "xz")]
+            CompressionMethod::Lzma() => Ok(EntryEncoder::Lzma(LzmaEncoder::with_quality(
+                output,
+                level.into(),
+            ))),
+            #[cfg(feature = "zstd")]
+            CompressionMethod::Zstd() => ZstdEntryEncoder::new(level)
+                .map(EntryEncoder::Zstd)
+                .map_err(|source| ArchiveError::Compression {
+                    codec: compressor.label(),
+                    source,
+                }),
+            #[cfg(feature = "xz")]
+            CompressionMethod::Xz() => Ok(EntryEncoder::Xz(XzEncoder::with_quality(
+                output,
+                level.into(),
+            ))),
+            _ => Err(ArchiveError::UnsuportedCompressionMethod(compressor)),
+        }
+    }
+
+    /// Compressed bytes produced so far and not yet drained.
+    pub fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            EntryEncoder::Store(output) => output,
+            EntryEncoder::Deflate(encoder) => encoder.get_mut(),
+            #[cfg(feature = "bzip2")]
+            EntryEncoder::BZip2(encoder) => encoder.get_mut(),
+            #[cfg(feature = "xz")]
+            EntryEncoder::Lzma(encoder) => encoder.get_mut(),
+            #[cfg(feature = "zstd")]
+            EntryEncoder::Zstd(encoder) => encoder.get_mut(),
+            #[cfg(feature = "xz")]
+            EntryEncoder::Xz(encoder) => encoder.get_mut(),
+        }
+    }
+}
+
+impl AsyncWrite for EntryEncoder {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        entry_encoder_dispatch!(self.get_mut(), inner => Pin::new(inner).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        entry_encoder_dispatch!(self.get_mut(), inner => Pin::new(inner).poll_flush(cx))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        entry_encoder_dispatch!(self.get_mut(), inner => Pin::new(inner).poll_shutdown(cx))
+    }
+}
+
 impl From<Level> for async_compression::Level {
     fn from(level: Level) -> Self {
         match level {
             Level::Fastest => async_compression::Level::Fastest,
             Level::Best => async_compression::Level::Best,
             Level::Default => async_compression::Level::Default,
-            Level::Precise(val) => async_compression::Level::Precise(val as u32),
+            // negative levels are only valid for zstd, which does not go through this mapping
+            Level::Precise(val) => async_compression::Level::Precise(val.max(0) as u32),
             Level::None => async_compression::Level::Precise(0),
         }
     }
 }
 
+/// Size of the chunks a reader is read by, when it is not already buffered.
+const CHUNK_SIZE: usize = 4096;
+
+/// Buffer a reader into chunks of `CHUNK_SIZE`, to feed it to `compress_buf`.
+pub fn buffered<R: AsyncRead + Unpin>(reader: R) -> BufReader<R> {
+    BufReader::with_capacity(CHUNK_SIZE, reader)
+}
+
 pub async fn compress<'a, R, W>(
     compressor: CompressionMethod,
     writer: &'a mut W,
     reader: &'a mut R,
-    hasher: &'a mut Hasher,
+    hasher: &'a mut EntryHasher,
     compression_level: Level,
+    zstd_context: &'a mut ZstdContext,
 ) -> Result<u64, ArchiveError>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    let method = if compression_level == Level::None {
-        CompressionMethod::Store()
-    } else {
-        compressor
-    };
+    compress_buf(
+        compressor,
+        writer,
+        &mut buffered(reader),
+        hasher,
+        compression_level,
+        zstd_context,
+    )
+    .await
+}
 
-    match method {
+/// Compress a buffered reader, handing the slices of its buffer straight to the hasher and the
+/// encoder: there is no copy to a staging buffer, e.g. for a `BufReader<File>` or a byte slice.
+pub async fn compress_buf<'a, R, W>(
+    compressor: CompressionMethod,
+    writer: &'a mut W,
+    reader: &'a mut R,
+    hasher: &'a mut EntryHasher,
+    compression_level: Level,
+    zstd_context: &'a mut ZstdContext,
+) -> Result<u64, ArchiveError>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    #[cfg(not(feature = "zstd"))]
+    let _ = zstd_context;
+
+    match compressor {
         CompressionMethod::Store() => {
-            let mut buf = vec![0; 4096];
             let mut total_read: u64 = 0;
 
             loop {
-                let read = reader.read(&mut buf).await?;
-                if read == 0 {
+                let chunk = reader.fill_buf().await?;
+                if chunk.is_empty() {
                     break;
                 }
 
+                let read = chunk.len();
                 total_read += read as u64;
-                hasher.update(&buf[..read]);
-                writer.write_all(&buf[..read]).await?;
+                hasher.update(chunk);
+                writer.write_all(chunk).await?;
+                reader.consume(read);
             }
             writer.flush().await?;
 
             Ok(total_read)
         }
         CompressionMethod::Deflate() => {
-            let mut zencoder = DeflateEncoder::with_quality(writer, compression_level.into());
+            let mut zencoder =
+                DeflateEncoder::with_quality(KeepOpen::new(writer), compression_level.into());
 
-            let total_read = compress_tokio!(zencoder, hasher, reader);
+            let total_read = compress_tokio!(zencoder, compressor.label(), hasher, reader);
 
             Ok(total_read)
         }
 
+        #[cfg(feature = "bzip2")]
         CompressionMethod::BZip2() => {
-            let mut zencoder = BzEncoder::with_quality(writer, compression_level.into());
+            let mut zencoder =
+                BzEncoder::with_quality(KeepOpen::new(writer), compression_level.into());
 
-            let total_read = compress_tokio!(zencoder, hasher, reader);
+            let total_read = compress_tokio!(zencoder, compressor.label(), hasher, reader);
 
             Ok(total_read)
         }
+        #[cfg(feature = "xz")]
         CompressionMethod::Lzma() => {
-            let mut zencoder = LzmaEncoder::with_quality(writer, compression_level.into());
+            let mut zencoder =
+                LzmaEncoder::with_quality(KeepOpen::new(writer), compression_level.into());
 
-            let total_read = compress_tokio!(zencoder, hasher, reader);
+            let total_read = compress_tokio!(zencoder, compressor.label(), hasher, reader);
 
             Ok(total_read)
         }
+        #[cfg(feature = "zstd")]
         CompressionMethod::Zstd() => {
-            let mut zencoder = ZstdEncoder::with_quality(writer, compression_level.into());
-
-            let total_read = compress_tokio!(zencoder, hasher, reader);
-
-            Ok(total_read)
+            let encoder = zstd_context
+                .encoder(zstd_level(compression_level))
+                .map_err(|source| ArchiveError::Compression {
+                    codec: compressor.label(),
+                    source,
+                })?;
+
+            compress_zstd(encoder, writer, reader, hasher).await
         }
+        #[cfg(feature = "xz")]
         CompressionMethod::Xz() => {
-            let mut zencoder = XzEncoder::with_quality(writer, compression_level.into());
+            let mut zencoder =
+                XzEncoder::with_quality(KeepOpen::new(writer), compression_level.into());
 
-            let total_read = compress_tokio!(zencoder, hasher, reader);
+            let total_read = compress_tokio!(zencoder, compressor.label(), hasher, reader);
 
             Ok(total_read)
         }
+        #[allow(deprecated)]
+        CompressionMethod::Shrink() | CompressionMethod::Reduce(_) => {
+            Err(ArchiveError::UnsuportedCompressionMethod(compressor))
+        }
         CompressionMethod::Unknown(compression_method_code) => Err(
             ArchiveError::UnsuportedCompressionMethodCode(compression_method_code),
         ),
+        #[allow(unreachable_patterns)]
+        _ => Err(ArchiveError::UnsuportedCompressionMethod(compressor)),
     }
 }
 
@@ -129,6 +522,8 @@ mod test {
 
     use super::*;
     use async_compression::tokio::write::ZlibEncoder;
+    #[cfg(feature = "zstd")]
+    use async_compression::tokio::write::ZstdEncoder;
     use flate2::write::DeflateEncoder as DeflateEncoderFlate2;
     use flate2::write::ZlibEncoder as ZlibEncoderFlate;
     use std::io::Write;
@@ -185,7 +580,7 @@ mod test {
         let x = b"example";
 
         let compressor = CompressionMethod::Deflate();
-        let mut hasher = Hasher::new();
+        let mut hasher = EntryHasher::new();
 
         //let a: AsyncRead = &x;
         let mut writer = AsyncWriteWrapper::new(Vec::new());
@@ -196,6 +591,7 @@ mod test {
             &mut x.as_ref(),
             &mut hasher,
             Level::Default,
+            &mut ZstdContext::default(),
         )
         .await
         .unwrap();
@@ -204,6 +600,131 @@ mod test {
         println!("compress len {:?}", temp.len());
         println!("{:X?}", temp);
     }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn test_zstd_context_reuse_same_output() {
+        let text: Vec<u8> = b"The quick brown fox jumps over the lazy dog. "
+            .iter()
+            .copied()
+            .cycle()
+            .take(300 * 1024)
+            .collect();
+        let inputs: [&[u8]; 4] = [b"example", b"", &text, &text[..5000]];
+        let levels = [
+            Level::Default,
+            Level::Default,
+            Level::Fastest,
+            Level::Best,
+            Level::Precise(7),
+            Level::Precise(7),
+            Level::Precise(-5),
+            Level::Precise(-5),
+        ];
+
+        let mut context = ZstdContext::default();
+        for level in levels {
+            for input in inputs {
+                let mut reused = Vec::new();
+                compress(
+                    CompressionMethod::Zstd(),
+                    &mut reused,
+                    &mut &input[..],
+                    &mut EntryHasher::new(),
+                    level,
+                    &mut context,
+                )
+                .await
+                .unwrap();
+
+                let expected = match level {
+                    // `ZstdEncoder` cannot take the negative levels, the fast modes
+                    Level::Precise(val) if val < 0 => {
+                        let mut encoder =
+                            zstd::stream::write::Encoder::new(Vec::new(), val).unwrap();
+                        for chunk in input.chunks(4096) {
+                            encoder.write_all(chunk).unwrap();
+                        }
+                        encoder.flush().unwrap();
+                        encoder.finish().unwrap()
+                    }
+                    _ => {
+                        let mut encoder = ZstdEncoder::with_quality(Vec::new(), level.into());
+                        for chunk in input.chunks(4096) {
+                            encoder.write_all(chunk).await.unwrap();
+                        }
+                        encoder.flush().await.unwrap();
+                        encoder.shutdown().await.unwrap();
+                        encoder.into_inner()
+                    }
+                };
+
+                assert_eq!(reused, expected, "{:?}, {} bytes", level, input.len());
+            }
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    struct BrokenSink;
+
+    #[cfg(feature = "zstd")]
+    impl AsyncWrite for BrokenSink {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &[u8],
+        ) -> Poll<Result<usize, std::io::Error>> {
+            Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), std::io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), std::io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[tokio::test]
+    async fn test_sink_error_not_attributed_to_codec() {
+        let error = compress(
+            CompressionMethod::Zstd(),
+            &mut BrokenSink,
+            &mut b"example".as_ref(),
+            &mut EntryHasher::new(),
+            Level::Default,
+            &mut ZstdContext::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(
+            matches!(error, ArchiveError::IoError(e) if e.kind() == std::io::ErrorKind::BrokenPipe)
+        );
+    }
+
+    #[test]
+    fn test_codec_error() {
+        let mut sink = Vec::new();
+        let error = codec_error(
+            "zstd",
+            &KeepOpen::new(&mut sink),
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid level"),
+        );
+
+        assert_eq!(
+            error.to_string(),
+            "compression failed (zstd): invalid level"
+        );
+    }
 }
 
 //74 78 9C 4A AD 48 CC 2D C8 49 05 00 00 00 FF FF 03 00 0B C0 02 ED
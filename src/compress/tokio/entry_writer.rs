@@ -0,0 +1,250 @@
+use super::archive::{complete_entry, ZipArchive};
+use super::compressor::EntryEncoder;
+
+use crate::archive::FileOptions;
+use crate::archive_common::{EntryHasher, EntryStatsTimer};
+use crate::error::ArchiveError;
+use crate::types::ArchiveFileEntry;
+
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Entry of a `ZipArchive` being written chunk by chunk, see `ZipArchive::start_entry`.
+///
+/// The local header is already in the sink. Each `write` compresses a chunk and forwards the
+/// compressed bytes to the sink; `finish` writes the data descriptor and records the entry. The
+/// archive stays mutably borrowed meanwhile, so no other entry can be interleaved.
+///
+/// The writer also implements `AsyncWrite`, so `tokio::io::copy` can feed it; shutting it down
+/// (`AsyncWriteExt::shutdown`) finishes the entry, the archive sink itself staying open.
+///
+/// Dropping the writer before the entry is finished leaves a partial entry in the sink, which is
+/// then corrupted.
+#[derive(Debug)]
+pub struct EntryWriter<'a, W: AsyncWrite + Unpin> {
+    archive: &'a mut ZipArchive<W>,
+    file_name: String,
+    file_header_offset: u64,
+    encoder: EntryEncoder,
+    /// Bytes of the encoder output already written to the sink.
+    drained: usize,
+    payload_offset: u64,
+    uncompressed_size: u64,
+    expected_size: Option<u64>,
+    stats_timer: EntryStatsTimer,
+    state: EntryState,
+}
+
+#[derive(Debug)]
+enum EntryState {
+    Writing {
+        entry: ArchiveFileEntry,
+        hasher: EntryHasher,
+    },
+    /// The payload is written, the data descriptor is being written.
+    Closing {
+        entry: ArchiveFileEntry,
+        descriptor: Vec<u8>,
+        written: usize,
+    },
+    Finished(ArchiveFileEntry),
+    Failed,
+}
+
+impl<'a, W: AsyncWrite + Unpin> EntryWriter<'a, W> {
+    pub(super) async fn new(
+        archive: &'a mut ZipArchive<W>,
+        file_name: &str,
+        options: &FileOptions,
+        expected_size: Option<u64>,
+    ) -> Result<EntryWriter<'a, W>, ArchiveError> {
+        let file_header_offset = archive.get_archive_size();
+        let in_entry = |e: ArchiveError| e.in_entry(file_name, file_header_offset);
+        let stats_timer = EntryStatsTimer::start(options);
+
+        let encoder = EntryEncoder::new(options.effective_compressor(), options.compression_level)
+            .map_err(in_entry)?;
+        let entry = archive
+            .begin_entry(file_name, options)
+            .await
+            .map_err(in_entry)?;
+
+        Ok(EntryWriter {
+            state: EntryState::Writing {
+                entry,
+                hasher: EntryHasher::with_sha256(archive.sha256_manifest()),
+            },
+            payload_offset: archive.get_archive_size(),
+            archive,
+            file_name: file_name.to_owned(),
+            file_header_offset,
+            encoder,
+            drained: 0,
+            uncompressed_size: 0,
+            expected_size,
+            stats_timer,
+        })
+    }
+
+    /// Compress a chunk of the entry and write the compressed bytes produced to the sink.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<(), ArchiveError> {
+        match AsyncWriteExt::write_all(self, buf).await {
+            Ok(()) => Ok(()),
+            Err(e) => Err(self.in_entry(e)),
+        }
+    }
+
+    /// Finish the compression, write the data descriptor and record the entry in the archive.
+    ///
+    /// # Error
+    ///
+    /// Besides the sink errors, fails with `ArchiveError::BadArchiveStructure` if the entry was
+    /// reserved with a size that differs from the number of bytes written.
+    pub async fn finish(mut self) -> Result<ArchiveFileEntry, ArchiveError> {
+        if let Err(e) = AsyncWriteExt::shutdown(&mut self).await {
+            return Err(self.in_entry(e));
+        }
+
+        match self.state {
+            EntryState::Finished(entry) => Ok(entry),
+            _ => unreachable!("the entry is finished once shut down"),
+        }
+    }
+
+    /// Write the compressed bytes buffered by the encoder to the sink.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let output = self.encoder.output();
+        while self.drained < output.len() {
+            let written =
+                ready!(Pin::new(self.archive.sink_mut()).poll_write(cx, &output[self.drained..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.drained += written;
+        }
+
+        output.clear();
+        self.drained = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Once the payload is complete, check its size and build the data descriptor.
+    fn close_payload(&mut self) -> Result<(), ArchiveError> {
+        let (entry, hasher) = match std::mem::replace(&mut self.state, EntryState::Failed) {
+            EntryState::Writing { entry, hasher } => (entry, hasher),
+            _ => unreachable!("the payload is closed once"),
+        };
+
+        if let Some(expected_size) = self.expected_size {
+            if expected_size != self.uncompressed_size {
+                return Err(ArchiveError::BadArchiveStructure(format!(
+                    "entry reserved for {} bytes, {} written",
+                    expected_size, self.uncompressed_size
+                )));
+            }
+        }
+
+        let digest = hasher.finalize();
+        let compressed_size = self.archive.get_archive_size() - self.payload_offset;
+        let (entry, descriptor) = complete_entry(
+            entry,
+            digest.crc32,
+            digest.sha256,
+            compressed_size,
+            self.uncompressed_size,
+            &self.stats_timer,
+        )?;
+
+        self.state = EntryState::Closing {
+            entry,
+            descriptor: descriptor.buffer().to_vec(),
+            written: 0,
+        };
+        Ok(())
+    }
+
+    fn in_entry(&self, e: Error) -> ArchiveError {
+        // Archive errors raised while polling are carried by an `std::io::Error`.
+        let e = match e.get_ref().map(|inner| inner.is::<ArchiveError>()) {
+            Some(true) => *e
+                .into_inner()
+                .and_then(|inner| inner.downcast::<ArchiveError>().ok())
+                .expect("the inner error is an ArchiveError"),
+            _ => ArchiveError::from(e),
+        };
+        e.in_entry(&self.file_name, self.file_header_offset)
+    }
+}
+
+fn entry_closed() -> Error {
+    Error::other("the entry is already finished")
+}
+
+impl<'a, W: AsyncWrite + Unpin> AsyncWrite for EntryWriter<'a, W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let writer = self.get_mut();
+        ready!(writer.poll_drain(cx))?;
+
+        let hasher = match &mut writer.state {
+            EntryState::Writing { hasher, .. } => hasher,
+            _ => return Poll::Ready(Err(entry_closed())),
+        };
+
+        // The encoder writes to memory, hence never pends.
+        let read = ready!(Pin::new(&mut writer.encoder).poll_write(cx, buf))?;
+        hasher.update(&buf[..read]);
+        writer.uncompressed_size += read as u64;
+        Poll::Ready(Ok(read))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let writer = self.get_mut();
+        ready!(writer.poll_drain(cx))?;
+        Pin::new(writer.archive.sink_mut()).poll_flush(cx)
+    }
+
+    /// Finish the entry; the archive sink is only flushed, not shut down.
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let writer = self.get_mut();
+        loop {
+            match &mut writer.state {
+                EntryState::Writing { .. } => {
+                    ready!(Pin::new(&mut writer.encoder).poll_shutdown(cx))?;
+                    ready!(writer.poll_drain(cx))?;
+                    writer.close_payload().map_err(Error::other)?;
+                }
+                EntryState::Closing {
+                    descriptor,
+                    written,
+                    ..
+                } => {
+                    while *written < descriptor.len() {
+                        let count = ready!(Pin::new(writer.archive.sink_mut())
+                            .poll_write(cx, &descriptor[*written..]))?;
+                        if count == 0 {
+                            return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+                        }
+                        *written += count;
+                    }
+
+                    let entry = match std::mem::replace(&mut writer.state, EntryState::Failed) {
+                        EntryState::Closing { entry, .. } => entry,
+                        _ => unreachable!(),
+                    };
+                    let entry = writer.archive.record_entry(entry).clone();
+                    writer.state = EntryState::Finished(entry);
+                }
+                EntryState::Finished(_) => {
+                    return Pin::new(writer.archive.sink_mut()).poll_flush(cx);
+                }
+                EntryState::Failed => return Poll::Ready(Err(entry_closed())),
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! `tower::Service` adapter of the archive, to compose it with middleware such as rate limiting,
+//! retries or tracing.
+
+use super::archive::ZipArchive;
+
+use crate::archive::FileOptions;
+use crate::error::ArchiveError;
+
+use bytes::Bytes;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+use tokio::sync::Mutex;
+use tower_service::Service;
+
+/// Entry to append to the archive of a `ZipArchiveService`.
+#[derive(Debug, Clone)]
+pub struct ZipRequest {
+    pub name: String,
+    pub data: Bytes,
+    pub options: FileOptions,
+}
+
+impl ZipRequest {
+    pub fn new(name: &str, data: impl Into<Bytes>, options: FileOptions) -> ZipRequest {
+        ZipRequest {
+            name: name.to_owned(),
+            data: data.into(),
+            options,
+        }
+    }
+}
+
+/// `ZipArchive` as a `tower::Service`, each request appending an entry.
+///
+/// The archive is shared with the pending calls behind a lock, so the entries are appended one
+/// at a time, in the order their futures are first polled.
+///
+/// Once an entry fails to be appended, the archive is left with a partial entry: `poll_ready`
+/// then fails, so middleware such as retries do not append to a corrupted archive.
+///
+/// Requires the `tower` feature.
+#[derive(Debug)]
+pub struct ZipArchiveService<W: AsyncWrite + Unpin> {
+    archive: Arc<Mutex<ZipArchive<W>>>,
+    failed: Arc<AtomicBool>,
+}
+
+impl<W: AsyncWrite + Unpin> ZipArchiveService<W> {
+    pub fn new(archive: ZipArchive<W>) -> ZipArchiveService<W> {
+        ZipArchiveService {
+            archive: Arc::new(Mutex::new(archive)),
+            failed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Whether an entry failed to be appended, leaving the archive corrupted.
+    pub fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+
+    /// Get the archive back, e.g. to finalize it.
+    ///
+    /// # Error
+    ///
+    /// Gives the service back while calls are still pending.
+    pub fn into_archive(self) -> Result<ZipArchive<W>, ZipArchiveService<W>> {
+        match Arc::try_unwrap(self.archive) {
+            Ok(archive) => Ok(archive.into_inner()),
+            Err(archive) => Err(ZipArchiveService {
+                archive,
+                failed: self.failed,
+            }),
+        }
+    }
+}
+
+impl<W> Service<ZipRequest> for ZipArchiveService<W>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    /// CRC-32 of the appended entry.
+    type Response = u32;
+    type Error = ArchiveError;
+    type Future = Pin<Box<dyn Future<Output = Result<u32, ArchiveError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), ArchiveError>> {
+        if self.is_failed() {
+            return Poll::Ready(Err(ArchiveError::BadArchiveStructure(
+                "a previous entry failed to be appended, the archive is corrupted".to_owned(),
+            )));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: ZipRequest) -> Self::Future {
+        let archive = self.archive.clone();
+        let failed = self.failed.clone();
+        Box::pin(async move {
+            let mut archive = archive.lock().await;
+            let result = archive
+                .append_file(&request.name, &mut request.data.as_ref(), &request.options)
+                .await;
+            if result.is_err() {
+                failed.store(true, Ordering::Release);
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use std::task::ready;
+
+    use crate::uncompress::extract_entry_to_vec;
+
+    /// Sink accepting `capacity` bytes, then failing.
+    struct FailingSink {
+        capacity: usize,
+    }
+
+    impl AsyncWrite for FailingSink {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, std::io::Error>> {
+            let sink = self.get_mut();
+            if sink.capacity == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+            }
+            let count = buf.len().min(sink.capacity);
+            sink.capacity -= count;
+            Poll::Ready(Ok(count))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), std::io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<(), std::io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn ready<S: Service<ZipRequest>>(service: &mut S) -> Result<(), S::Error> {
+        std::future::poll_fn(|cx| {
+            ready!(service.poll_ready(cx))?;
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_service_appends_entries() -> Result<(), ArchiveError> {
+        let mut service = ZipArchiveService::new(ZipArchive::new(Cursor::new(Vec::new())));
+        for (name, data) in [("first.txt", "first"), ("second.txt", "second")] {
+            ready(&mut service).await?;
+            service
+                .call(ZipRequest::new(name, data, FileOptions::default()))
+                .await?;
+        }
+
+        let mut archive = service.into_archive().expect("no call is pending");
+        archive.finalize().await?;
+        let out = archive.writer_ref().get_ref();
+        assert_eq!(extract_entry_to_vec(out, "first.txt")?, b"first");
+        assert_eq!(extract_entry_to_vec(out, "second.txt")?, b"second");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_service_not_ready_after_failure() {
+        let mut service = ZipArchiveService::new(ZipArchive::new(FailingSink { capacity: 10 }));
+        ready(&mut service).await.unwrap();
+        let request = ZipRequest::new("first.txt", "first", FileOptions::default());
+        assert!(service.call(request).await.is_err());
+
+        assert!(service.is_failed());
+        assert!(matches!(
+            ready(&mut service).await,
+            Err(ArchiveError::BadArchiveStructure(_))
+        ));
+    }
+}
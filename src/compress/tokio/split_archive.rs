@@ -0,0 +1,207 @@
+use super::archive::ZipArchive;
+
+use crate::archive::FileOptions;
+use crate::archive_common::central_directory_entry_size;
+use crate::constants::END_OF_CENTRAL_DIRECTORY_SIZE;
+use crate::error::ArchiveError;
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Archive split into standalone parts of at most a given size, e.g. for email attachments or
+/// FAT32 drives. See `ZipArchive::split`.
+///
+/// Each part is a complete ZIP archive on its own, not a multi-disk archive: its entries can be
+/// extracted without the other parts. When an entry would make the current part exceed the
+/// maximum size, the part is finalized and the entry goes in a new one.
+///
+/// The size of an entry is known only once compressed, so each entry is compressed in memory
+/// before being copied to its part: appending holds up to `max_size` bytes in memory. An entry
+/// outgrowing a part is not compressed further.
+pub struct ZipArchiveSplit<W, F>
+where
+    W: AsyncWrite + Unpin,
+    F: FnMut(usize) -> W,
+{
+    sink_factory: F,
+    max_size: u64,
+    /// Part being written, with the size of its central directory so far.
+    current: Option<(ZipArchive<W>, u64)>,
+    /// Writers of the finalized parts.
+    parts: Vec<W>,
+}
+
+impl<W, F> ZipArchiveSplit<W, F>
+where
+    W: AsyncWrite + Unpin,
+    F: FnMut(usize) -> W,
+{
+    pub fn new(sink_factory: F, max_size: u64) -> ZipArchiveSplit<W, F> {
+        ZipArchiveSplit {
+            sink_factory,
+            max_size,
+            current: None,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Append a new file to the current part, or to a new part if it does not fit. Returns the
+    /// CRC-32 of the payload.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::EntryExceedsPartSize` if the entry alone exceeds the maximum size of a part,
+    /// in which case no part is changed. If the compressed entry outgrew the maximum size of a
+    /// part, `required` is the size reached when its compression was stopped.
+    pub async fn append_file<R>(
+        &mut self,
+        file_name: &str,
+        reader: &mut R,
+        options: &FileOptions,
+    ) -> Result<u32, ArchiveError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut scratch = ZipArchive::new(EntryBuffer::new(self.max_size));
+        if let Err(error) = scratch.append_file(file_name, reader, options).await {
+            return match scratch.writer_ref().exceeded {
+                Some(required) => Err(ArchiveError::EntryExceedsPartSize {
+                    required,
+                    max_size: self.max_size,
+                }
+                .in_entry(file_name, 0)),
+                None => Err(error),
+            };
+        }
+        let (
+            EntryBuffer {
+                bytes: entry_bytes, ..
+            },
+            entry,
+        ) = scratch.into_single_entry();
+
+        let central_directory_entry = central_directory_entry_size(&entry);
+        let part_size = |archive_size: u64, central_directory_size: u64| {
+            archive_size
+                + entry_bytes.len() as u64
+                + central_directory_size
+                + central_directory_entry
+                + END_OF_CENTRAL_DIRECTORY_SIZE
+        };
+
+        let required = part_size(0, 0);
+        if required > self.max_size {
+            return Err(ArchiveError::EntryExceedsPartSize {
+                required,
+                max_size: self.max_size,
+            }
+            .in_entry(file_name, 0));
+        }
+
+        if let Some((archive, central_directory_size)) = &self.current {
+            if part_size(archive.get_archive_size(), *central_directory_size) > self.max_size {
+                self.close_part().await?;
+            }
+        }
+
+        let (archive, central_directory_size) = match &mut self.current {
+            Some(current) => current,
+            current @ None => {
+                let sink = (self.sink_factory)(self.parts.len());
+                current.insert((ZipArchive::new(sink), 0))
+            }
+        };
+        let crc32 = entry.crc32;
+        archive.append_raw(entry, &entry_bytes).await?;
+        *central_directory_size += central_directory_entry;
+        Ok(crc32)
+    }
+
+    /// Finalize the last part and return the number of parts, at least one.
+    pub async fn finalize(&mut self) -> Result<usize, ArchiveError> {
+        if self.current.is_none() && self.parts.is_empty() {
+            let sink = (self.sink_factory)(0);
+            self.current = Some((ZipArchive::new(sink), 0));
+        }
+        if self.current.is_some() {
+            self.close_part().await?;
+        }
+        Ok(self.parts.len())
+    }
+
+    /// Writers of the finalized parts, in order.
+    pub fn into_parts(self) -> Vec<W> {
+        self.parts
+    }
+
+    async fn close_part(&mut self) -> Result<(), ArchiveError> {
+        let (mut archive, _) = self.current.take().expect("a part is being written");
+        archive.finalize().await?;
+        self.parts.push(archive.retrieve_writer());
+        Ok(())
+    }
+}
+
+impl<W, F> std::fmt::Debug for ZipArchiveSplit<W, F>
+where
+    W: AsyncWrite + Unpin + std::fmt::Debug,
+    F: FnMut(usize) -> W,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZipArchiveSplit")
+            .field("max_size", &self.max_size)
+            .field("current", &self.current)
+            .field("parts", &self.parts)
+            .finish_non_exhaustive()
+    }
+}
+
+/// In-memory buffer of an entry, failing the writes past `limit` bytes.
+struct EntryBuffer {
+    bytes: Vec<u8>,
+    limit: u64,
+    /// Size the buffer would have reached with the write refused.
+    exceeded: Option<u64>,
+}
+
+impl EntryBuffer {
+    fn new(limit: u64) -> EntryBuffer {
+        EntryBuffer {
+            bytes: Vec::new(),
+            limit,
+            exceeded: None,
+        }
+    }
+}
+
+impl AsyncWrite for EntryBuffer {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        let size = (this.bytes.len() + buf.len()) as u64;
+        if size > this.limit {
+            this.exceeded = Some(size);
+            return Poll::Ready(Err(std::io::Error::other(
+                "the entry exceeds the maximum size of a part",
+            )));
+        }
+        this.bytes.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
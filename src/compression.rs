@@ -1,14 +1,43 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 
 use crate::error::ArchiveError;
 
 pub const STORE: u16 = 0;
+pub const SHRINK: u16 = 1;
+pub const REDUCE_1: u16 = 2;
+pub const REDUCE_4: u16 = 5;
 pub const DEFALTE: u16 = 8;
 pub const BZIP2: u16 = 12;
 pub const LZMA: u16 = 14;
 pub const ZSTD: u16 = 93;
 pub const XZ: u16 = 95;
 
+/// Extensions of already-compressed formats (archives, images, audio, video and zip-based
+/// documents), stored as is by `CompressionMethod::for_extension`.
+pub const STORED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "avif", "br", "bz2", "cab", "docx", "epub", "flac", "gif", "gz",
+    "heic", "heif", "jar", "jpeg", "jpg", "lz", "lz4", "lzma", "m4a", "m4v", "mkv", "mov", "mp3",
+    "mp4", "odt", "ogg", "opus", "png", "pptx", "rar", "tbz2", "tgz", "txz", "webm", "webp",
+    "xlsx", "xz", "zip", "zst",
+];
+
+/// Subtypes of `application` MIME types of already-compressed data, stored as is by
+/// `CompressionMethod::for_mime_type` like the `image`, `audio` and `video` types.
+pub const STORED_APPLICATION_MIME_SUBTYPES: &[&str] = &[
+    "epub+zip",
+    "gzip",
+    "java-archive",
+    "vnd.rar",
+    "x-7z-compressed",
+    "x-bzip2",
+    "x-gzip",
+    "x-rar-compressed",
+    "x-xz",
+    "zip",
+    "zstd",
+];
+
 #[derive(Debug, Clone, Copy)]
 pub enum CompressionMethod {
     Store(),
@@ -17,10 +46,22 @@ pub enum CompressionMethod {
     Lzma(),
     Zstd(),
     Xz(),
+    /// Legacy PKZIP LZW method, recognized when reading but never written.
+    #[deprecated(note = "legacy PKZIP method, archives cannot be written with it")]
+    Shrink(),
+    /// Legacy PKZIP method with its compression factor (1 to 4), recognized when reading but never written.
+    #[deprecated(note = "legacy PKZIP method, archives cannot be written with it")]
+    Reduce(u8),
     Unknown(u16),
 }
 
 impl CompressionMethod {
+    /// Method code written in the ZIP headers.
+    ///
+    /// # Panics
+    ///
+    /// For a `Reduce` factor outside of 1 to 4, which has no code.
+    #[allow(deprecated)]
     pub fn zip_code(&self) -> u16 {
         match self {
             CompressionMethod::Store() => STORE,
@@ -29,10 +70,63 @@ impl CompressionMethod {
             CompressionMethod::Lzma() => LZMA,
             CompressionMethod::Zstd() => ZSTD,
             CompressionMethod::Xz() => XZ,
+            CompressionMethod::Shrink() => SHRINK,
+            CompressionMethod::Reduce(factor @ 1..=4) => REDUCE_1 - 1 + *factor as u16,
+            CompressionMethod::Reduce(factor) => {
+                panic!("reduce compression factor {} is not in 1..=4", factor)
+            }
             CompressionMethod::Unknown(comp_method_code) => *comp_method_code,
         }
     }
 
+    /// Compression method suited to a file extension: `Store` for already-compressed formats (see
+    /// `STORED_EXTENSIONS`), `Deflate` otherwise.
+    ///
+    /// The extension is matched case-insensitively, with or without its leading dot.
+    pub fn for_extension(ext: &str) -> CompressionMethod {
+        Self::for_extension_with_map(ext, &HashMap::new())
+    }
+
+    /// Same as `for_extension`, but the extensions of `custom` (lowercase, without dot) take
+    /// precedence over the default mapping.
+    pub fn for_extension_with_map(
+        ext: &str,
+        custom: &HashMap<&str, CompressionMethod>,
+    ) -> CompressionMethod {
+        let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+
+        if let Some(method) = custom.get(ext.as_str()) {
+            *method
+        } else if STORED_EXTENSIONS.contains(&ext.as_str()) {
+            CompressionMethod::Store()
+        } else {
+            CompressionMethod::Deflate()
+        }
+    }
+
+    /// Compression method suited to a MIME type: `Store` for images, audio, video and compressed
+    /// archives (see `STORED_APPLICATION_MIME_SUBTYPES`), `Deflate` otherwise, e.g. for text,
+    /// JSON, XML or arbitrary binary data.
+    ///
+    /// The type is matched case-insensitively and its parameters (`; charset=...`) are ignored.
+    /// XML or JSON based types such as `image/svg+xml` are text, so they are deflated.
+    pub fn for_mime_type(mime: &str) -> CompressionMethod {
+        let essence = mime.split(';').next().unwrap_or_default();
+        let essence = essence.trim().to_ascii_lowercase();
+        let (top_level, subtype) = essence.split_once('/').unwrap_or((&essence, ""));
+
+        if subtype.ends_with("+xml") || subtype.ends_with("+json") {
+            return CompressionMethod::Deflate();
+        }
+        match top_level {
+            "image" | "audio" | "video" => CompressionMethod::Store(),
+            "application" if STORED_APPLICATION_MIME_SUBTYPES.contains(&subtype) => {
+                CompressionMethod::Store()
+            }
+            _ => CompressionMethod::Deflate(),
+        }
+    }
+
     pub fn zip_version_needed(&self) -> u16 {
         // higher versions matched first
         match self {
@@ -43,6 +137,7 @@ impl CompressionMethod {
         }
     }
 
+    #[allow(deprecated)]
     pub fn from_compression_method(
         compression_method: u16,
     ) -> Result<CompressionMethod, ArchiveError> {
@@ -54,13 +149,18 @@ impl CompressionMethod {
             LZMA => Ok(CompressionMethod::Lzma()),
             ZSTD => Ok(CompressionMethod::Zstd()),
             XZ => Ok(CompressionMethod::Xz()),
+            SHRINK => Ok(CompressionMethod::Shrink()),
+            REDUCE_1..=REDUCE_4 => Ok(CompressionMethod::Reduce(
+                (compression_method - REDUCE_1 + 1) as u8,
+            )),
             _ => Err(ArchiveError::UnsuportedCompressionMethodCode(
                 compression_method,
             )),
         }
     }
 
-    pub fn label(&self) -> &str {
+    #[allow(deprecated)]
+    pub fn label(&self) -> &'static str {
         // higher versions matched first
         match self {
             CompressionMethod::Store() => "store",
@@ -69,10 +169,79 @@ impl CompressionMethod {
             CompressionMethod::Lzma() => "lzma",
             CompressionMethod::Zstd() => "zstd",
             CompressionMethod::Xz() => "xz",
+            CompressionMethod::Shrink() => "shrink",
+            CompressionMethod::Reduce(_) => "reduce",
             CompressionMethod::Unknown(_) => "unknown",
         }
     }
 
+    /// Cargo feature providing the compressor of this method, if that feature is disabled.
+    #[allow(clippy::match_single_binding)]
+    pub fn disabled_feature(&self) -> Option<&'static str> {
+        match self {
+            #[cfg(not(feature = "bzip2"))]
+            CompressionMethod::BZip2() => Some("bzip2"),
+            #[cfg(not(feature = "xz"))]
+            CompressionMethod::Lzma() | CompressionMethod::Xz() => Some("xz"),
+            #[cfg(not(feature = "zstd"))]
+            CompressionMethod::Zstd() => Some("zstd"),
+            _ => None,
+        }
+    }
+
+    /// Check that entries can be written with this method.
+    ///
+    /// # Error
+    ///
+    /// `ArchiveError::UnsuportedCompressionMethod` for the legacy methods and `Unknown` codes,
+    /// which can be read from an archive but have no compressor, and for the methods whose
+    /// feature is disabled (see `disabled_feature`).
+    #[allow(deprecated)]
+    pub fn check_writable(&self) -> Result<(), ArchiveError> {
+        match self {
+            CompressionMethod::Shrink()
+            | CompressionMethod::Reduce(_)
+            | CompressionMethod::Unknown(_) => {
+                Err(ArchiveError::UnsuportedCompressionMethod(*self))
+            }
+            _ if self.disabled_feature().is_some() => {
+                Err(ArchiveError::UnsuportedCompressionMethod(*self))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the compression level is meaningful for this method.
+    ///
+    /// `Level::Default` and `Level::None` are accepted for every method, the latter writing the
+    /// entry with `Store` (see `effective_method`). The named levels and the
+    /// `Level::Precise` ranges are the ones listed on `FileOptions::compression_level`.
+    pub fn check_level(&self, level: Level) -> Result<(), ArchiveError> {
+        let valid = match (self, level) {
+            (_, Level::Default | Level::None) => true,
+            (CompressionMethod::Deflate(), Level::Precise(val))
+            | (CompressionMethod::BZip2(), Level::Precise(val))
+            | (CompressionMethod::Lzma(), Level::Precise(val))
+            | (CompressionMethod::Xz(), Level::Precise(val)) => (0..=9).contains(&val),
+            (CompressionMethod::Zstd(), Level::Precise(val)) => (-7..=22).contains(&val),
+            (
+                CompressionMethod::Deflate()
+                | CompressionMethod::BZip2()
+                | CompressionMethod::Lzma()
+                | CompressionMethod::Xz()
+                | CompressionMethod::Zstd(),
+                Level::Fastest | Level::Best,
+            ) => true,
+            _ => false,
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(ArchiveError::UnsuportedCompressionLevel(*self))
+        }
+    }
+
     pub fn update_general_purpose_bit_flag(&self, flag: u16, level: Level) -> u16 {
         const BIT1: u16 = 1 << 1; //2
         const BIT2: u16 = 1 << 2; //4
@@ -95,13 +264,49 @@ impl CompressionMethod {
     }
 }
 
+impl CompressionMethod {
+    /// Method actually written for the requested level: `Store` for `Level::None`, as no method
+    /// has a level leaving the data uncompressed.
+    pub fn effective_method(&self, level: Level) -> CompressionMethod {
+        match level {
+            Level::None => CompressionMethod::Store(),
+            _ => *self,
+        }
+    }
+
+    /// Level the method actually applies for the requested one: `Level::None` for `Store`.
+    ///
+    /// Meant for the method returned by `effective_method`, so that `Level::None` is only ever
+    /// recorded together with `Store`.
+    pub fn effective_level(&self, level: Level) -> Level {
+        match self {
+            CompressionMethod::Store() => Level::None,
+            _ => level,
+        }
+    }
+
+    /// Level recorded by the Deflate option bits of the general purpose flag, the only trace of
+    /// the level in an archive; `Level::Default` for the other methods but `Store`.
+    pub fn level_from_general_purpose_bit_flag(&self, flag: u16) -> Level {
+        match self {
+            CompressionMethod::Store() => Level::None,
+            CompressionMethod::Deflate() => match (flag >> 1) & 0b11 {
+                0b01 => Level::Best,
+                0b10 | 0b11 => Level::Fastest,
+                _ => Level::Default,
+            },
+            _ => Level::Default,
+        }
+    }
+}
+
 impl Display for CompressionMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.label())
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Level {
     Fastest,
     Best,
@@ -113,6 +318,63 @@ pub enum Level {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::archive::FileOptions;
+
+    #[test]
+    #[allow(deprecated)]
+    fn legacy_compression_methods() {
+        assert!(matches!(
+            CompressionMethod::from_compression_method(1),
+            Ok(CompressionMethod::Shrink())
+        ));
+        for code in 2..=5 {
+            let method = CompressionMethod::from_compression_method(code).unwrap();
+            assert!(
+                matches!(method, CompressionMethod::Reduce(factor) if factor as u16 == code - 1)
+            );
+            assert_eq!(method.zip_code(), code);
+        }
+        assert_eq!(CompressionMethod::Shrink().zip_code(), 1);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    #[should_panic(expected = "not in 1..=4")]
+    fn reduce_factor_out_of_range() {
+        CompressionMethod::Reduce(5).zip_code();
+    }
+
+    #[test]
+    fn zip_code_round_trip() {
+        let methods = [
+            CompressionMethod::Store(),
+            CompressionMethod::Deflate(),
+            CompressionMethod::BZip2(),
+            CompressionMethod::Lzma(),
+            CompressionMethod::Zstd(),
+            CompressionMethod::Xz(),
+        ];
+        for method in methods {
+            let decoded = CompressionMethod::from_compression_method(method.zip_code()).unwrap();
+            assert_eq!(decoded.zip_code(), method.zip_code());
+            assert_eq!(decoded.label(), method.label());
+            assert_eq!(
+                method.check_writable().is_ok(),
+                method.disabled_feature().is_none()
+            );
+        }
+
+        let unknown = CompressionMethod::Unknown(1234);
+        assert_eq!(unknown.zip_code(), 1234);
+        assert_eq!(unknown.label(), "unknown");
+        assert!(CompressionMethod::from_compression_method(1234).is_err());
+        assert!(matches!(
+            unknown.check_writable(),
+            Err(ArchiveError::UnsuportedCompressionMethod(
+                CompressionMethod::Unknown(1234)
+            ))
+        ));
+    }
 
     #[test]
     fn update_general_purpose_bit_flag() {
@@ -157,4 +419,165 @@ mod test {
             0
         );
     }
+
+    #[test]
+    fn for_extension() {
+        let stored = [
+            "jpg", "JPG", ".jpeg", "png", "gif", "heic", "webp", "mp3", "flac", "ogg", "mp4",
+            "mkv", "mov", "gz", "bz2", "xz", "zst", "zip", "7z", "rar", "docx", "epub",
+        ];
+        for ext in stored {
+            assert!(
+                matches!(
+                    CompressionMethod::for_extension(ext),
+                    CompressionMethod::Store()
+                ),
+                "{} should be stored",
+                ext
+            );
+        }
+
+        let deflated = [
+            "txt", "TXT", ".csv", "json", "xml", "html", "rs", "log", "bmp", "wav", "tar", "",
+        ];
+        for ext in deflated {
+            assert!(
+                matches!(
+                    CompressionMethod::for_extension(ext),
+                    CompressionMethod::Deflate()
+                ),
+                "{} should be deflated",
+                ext
+            );
+        }
+    }
+
+    #[test]
+    fn for_mime_type() {
+        let stored = [
+            "image/jpeg",
+            "image/png",
+            "IMAGE/WEBP",
+            "audio/mpeg",
+            "audio/ogg",
+            "video/mp4",
+            "video/webm; codecs=vp9",
+            "application/zip",
+            "application/gzip",
+            "application/x-7z-compressed",
+        ];
+        for mime in stored {
+            assert!(
+                matches!(
+                    FileOptions::from_mimetype(mime).compressor,
+                    CompressionMethod::Store()
+                ),
+                "{} should be stored",
+                mime
+            );
+        }
+
+        let deflated = [
+            "text/plain",
+            "text/html; charset=utf-8",
+            "application/json",
+            "application/xml",
+            "image/svg+xml",
+            "application/octet-stream",
+            "application/x-tar",
+            "invalid",
+            "",
+        ];
+        for mime in deflated {
+            assert!(
+                matches!(
+                    FileOptions::from_mimetype(mime).compressor,
+                    CompressionMethod::Deflate()
+                ),
+                "{} should be deflated",
+                mime
+            );
+        }
+    }
+
+    #[test]
+    fn for_extension_default_map() {
+        for ext in STORED_EXTENSIONS {
+            assert_eq!(*ext, ext.to_ascii_lowercase());
+            assert!(!ext.starts_with('.'));
+            assert!(matches!(
+                CompressionMethod::for_extension(ext),
+                CompressionMethod::Store()
+            ));
+        }
+    }
+
+    #[test]
+    fn for_extension_with_map() {
+        let custom = HashMap::from([
+            ("png", CompressionMethod::Deflate()),
+            ("tar", CompressionMethod::Zstd()),
+        ]);
+
+        assert!(matches!(
+            CompressionMethod::for_extension_with_map("PNG", &custom),
+            CompressionMethod::Deflate()
+        ));
+        assert!(matches!(
+            CompressionMethod::for_extension_with_map(".tar", &custom),
+            CompressionMethod::Zstd()
+        ));
+        assert!(matches!(
+            CompressionMethod::for_extension_with_map("jpg", &custom),
+            CompressionMethod::Store()
+        ));
+        assert!(matches!(
+            CompressionMethod::for_extension_with_map("txt", &custom),
+            CompressionMethod::Deflate()
+        ));
+    }
+
+    #[test]
+    fn effective_method() {
+        let deflate = CompressionMethod::Deflate();
+        assert!(matches!(
+            deflate.effective_method(Level::None),
+            CompressionMethod::Store()
+        ));
+        assert!(matches!(
+            deflate.effective_method(Level::Best),
+            CompressionMethod::Deflate()
+        ));
+        let store = deflate.effective_method(Level::None);
+        assert_eq!(store.effective_level(Level::None), Level::None);
+    }
+
+    #[test]
+    fn check_level() {
+        let store = CompressionMethod::Store();
+        assert!(store.check_level(Level::Default).is_ok());
+        assert!(store.check_level(Level::None).is_ok());
+        assert!(store.check_level(Level::Best).is_err());
+        assert!(store.check_level(Level::Precise(0)).is_err());
+
+        let deflate = CompressionMethod::Deflate();
+        assert!(deflate.check_level(Level::Fastest).is_ok());
+        assert!(deflate.check_level(Level::Precise(0)).is_ok());
+        assert!(deflate.check_level(Level::Precise(9)).is_ok());
+        assert!(deflate.check_level(Level::Precise(10)).is_err());
+        assert!(deflate.check_level(Level::Precise(-1)).is_err());
+
+        let zstd = CompressionMethod::Zstd();
+        assert!(zstd.check_level(Level::Precise(-7)).is_ok());
+        assert!(zstd.check_level(Level::Precise(22)).is_ok());
+        assert!(zstd.check_level(Level::Precise(23)).is_err());
+        assert!(zstd.check_level(Level::Precise(-8)).is_err());
+
+        assert!(CompressionMethod::Xz()
+            .check_level(Level::Precise(15))
+            .is_err());
+        assert!(CompressionMethod::Unknown(99)
+            .check_level(Level::Fastest)
+            .is_err());
+    }
 }
@@ -2,16 +2,47 @@ use std::mem::size_of;
 
 pub const FILE_HEADER_BASE_SIZE: u64 = (7 * size_of::<u16>() + 4 * size_of::<u32>()) as u64;
 pub const DESCRIPTOR_SIZE: u64 = (4 * size_of::<u32>()) as u64;
+pub const ZIP64_DESCRIPTOR_SIZE: u64 = (2 * size_of::<u32>() + 2 * size_of::<u64>()) as u64;
 pub const CENTRAL_DIRECTORY_ENTRY_BASE_SIZE: u64 =
     (11 * size_of::<u16>() + 6 * size_of::<u32>()) as u64;
 pub const END_OF_CENTRAL_DIRECTORY_SIZE: u64 = (5 * size_of::<u16>() + 3 * size_of::<u32>()) as u64;
-pub const FILE_HEADER_CRC_OFFSET: u64 = 14;
+
+// Number of consecutive `Ok(0)` writes tolerated before giving up with `WriteZero`.
+#[cfg(any(feature = "std", feature = "tokio"))]
+pub const WRITE_ZERO_MAX_RETRY: u32 = 8;
+
+// Number of consecutive `ErrorKind::Interrupted` writes retried before returning the error.
+#[cfg(feature = "tokio")]
+pub const INTERRUPTED_MAX_RETRY: u32 = 8;
 
 pub const CENTRAL_DIRECTORY_END_SIGNATURE: u32 = 0x06054b50;
 pub const CENTRAL_DIRECTORY_ENTRY_SIGNATURE: u32 = 0x02014b50;
 pub const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50; // Local file header signature.
 pub const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50; // Data descriptor signature.
+pub const TEMPORARY_SPANNING_SIGNATURE: u32 = 0x30304b50; // "PK00" marker of a single-part archive.
+pub const DIGITAL_SIGNATURE_SIGNATURE: u32 = 0x05054b50; // Digital signature record, ending the central directory.
+
+pub const EXTENDED_TIMESTAMP_EXTRA_FIELD_ID: u16 = 0x5455; // "UT" extra field header id.
+pub const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001; // ZIP64 extended information extra field header id.
+pub const GROWTH_HINT_EXTRA_FIELD_ID: u16 = 0xa220; // Open Packaging growth hint, zero padding.
+pub const GROWTH_HINT_SIGNATURE: u16 = 0xa028;
+pub const GROWTH_HINT_MIN_SIZE: u64 = (4 * size_of::<u16>()) as u64;
+
+pub const ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE: u32 = 0x06064b50;
+pub const ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+pub const ZIP64_CENTRAL_DIRECTORY_END_SIZE: u64 =
+    (2 * size_of::<u16>() + 3 * size_of::<u32>() + 5 * size_of::<u64>()) as u64;
+pub const ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIZE: u64 =
+    (3 * size_of::<u32>() + size_of::<u64>()) as u64;
+pub const ZIP64_VERSION_NEEDED: u16 = 45;
+// Bytes to read from the end of an archive to find its end records, with the longest comment.
+pub const ARCHIVE_TAIL_MAX_SIZE: u64 = ZIP64_CENTRAL_DIRECTORY_END_SIZE
+    + ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIZE
+    + END_OF_CENTRAL_DIRECTORY_SIZE
+    + u16::MAX as u64;
 
 pub const DEFAULT_VERSION: u8 = 46;
 pub const UNIX: u8 = 3;
 pub const VERSION_MADE_BY: u16 = (UNIX as u16) << 8 | DEFAULT_VERSION as u16;
+
+pub const SHA256_MANIFEST_NAME: &str = "MANIFEST.sha256";
@@ -0,0 +1,50 @@
+//! CRC-32 as stored in the archive headers.
+//!
+//! The archive computes the CRC of every entry with these functions, so a value computed upfront
+//! (e.g. to pre-verify data) matches the one written.
+
+/// Compute the CRC-32 of a buffer.
+pub fn compute_crc32(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
+/// Incremental CRC-32, for data read in chunks.
+#[derive(Debug, Clone, Default)]
+pub struct Crc32Hasher(crc32fast::Hasher);
+
+impl Crc32Hasher {
+    pub fn new() -> Crc32Hasher {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finalize(self) -> u32 {
+        self.0.finalize()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(compute_crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(compute_crc32(b""), 0);
+    }
+
+    #[test]
+    fn crc32_incremental() {
+        let data = b"Some string data, hashed in several chunks";
+
+        let mut hasher = Crc32Hasher::new();
+        for chunk in data.chunks(5) {
+            hasher.update(chunk);
+        }
+
+        assert_eq!(hasher.finalize(), compute_crc32(data));
+    }
+}
@@ -1,5 +1,11 @@
+//! Error type shared by the std and tokio backends.
+//!
+//! Every public append, finalize and read operation returns `ArchiveError`; I/O failures are
+//! converted through `From<std::io::Error>` so callers handle a single error type.
+
 use std::fmt::{self, Debug, Display};
 
+use crate::archive::HashAlgorithm;
 use crate::compression::CompressionMethod;
 
 pub enum ArchiveError {
@@ -8,7 +14,89 @@ pub enum ArchiveError {
     UnsuportedCompressionMethodCode(u16),
     UnsuportedCompressionMethod(CompressionMethod),
     BadArchiveStructure(String),
+    #[cfg(feature = "xz")]
     LZMA(xz2::stream::Error),
+    /// The sink kept accepting zero bytes, e.g. a full disk; holds the `WriteZero` I/O error.
+    WriteZero(std::io::Error),
+    EntryNotFound(String),
+    /// The entry name contains a backslash, rejected in strict mode.
+    InvalidEntryName(String),
+    /// The entry name is empty or only made of `/`, so it has no path to be extracted to.
+    EmptyEntryName(String),
+    /// A size, offset or entry count does not fit in the 32 bits (16 bits for the count) fields
+    /// of a ZIP archive without the ZIP64 extensions.
+    Zip64Required {
+        field: &'static str,
+    },
+    CrcMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// An extracted entry exceeds the limits of `ExtractOptions`, e.g. a decompression bomb.
+    SuspiciousRatio {
+        compressed_size: u64,
+        output_size: u64,
+    },
+    /// An entry recompressed in place needs more room than its previous payload.
+    RecompressedEntryTooLarge {
+        available: u64,
+        required: u64,
+    },
+    /// An entry recompressed in place leaves a gap that a padding extra field cannot fill: fewer
+    /// bytes than the smallest padding record, or more than its length field allows.
+    RecompressedEntryGap {
+        available: u64,
+        required: u64,
+    },
+    /// An entry does not fit in a part of a split archive, even alone.
+    EntryExceedsPartSize {
+        required: u64,
+        max_size: u64,
+    },
+    /// The encoder of the given codec failed, as opposed to the sink it writes to.
+    Compression {
+        codec: &'static str,
+        source: std::io::Error,
+    },
+    /// The archive breaks a rule of the ZIP specification (APPNOTE), found by the checks of
+    /// `ArchiveOptions::strict`.
+    SpecViolation {
+        detail: String,
+    },
+    /// The key given to sign the archive is not a valid private key.
+    InvalidSigningKey,
+    /// An entry is appended to an archive already finalized.
+    ArchiveFinalized,
+    /// The digest of an entry cannot be listed in a manifest: it was not computed while the
+    /// entry was written, and a streaming archive cannot read the entry back.
+    DigestUnavailable {
+        algorithm: HashAlgorithm,
+        entry: String,
+    },
+    Entry {
+        name: String,
+        offset: u64,
+        source: Box<ArchiveError>,
+    },
+}
+
+impl ArchiveError {
+    /// Attach the name of the entry being written and the offset of its local header.
+    pub fn in_entry(self, name: &str, offset: u64) -> ArchiveError {
+        ArchiveError::Entry {
+            name: name.to_owned(),
+            offset,
+            source: Box::new(self),
+        }
+    }
+
+    /// The underlying error, stripped of any entry context.
+    pub fn root_cause(&self) -> &ArchiveError {
+        match self {
+            ArchiveError::Entry { source, .. } => source.root_cause(),
+            _ => self,
+        }
+    }
 }
 
 impl Display for ArchiveError {
@@ -24,16 +112,102 @@ impl Display for ArchiveError {
                 write!(f, "The compression method code '{:}' is not supported", val)
             }
             ArchiveError::UnsuportedCompressionMethod(compression_method) => {
-                write!(
-                    f,
-                    "The compression method '{:}' is not supported",
-                    compression_method
-                )
+                match compression_method.disabled_feature() {
+                    Some(feature) => write!(
+                        f,
+                        "The compression method '{:}' requires the '{}' feature, which is disabled",
+                        compression_method, feature
+                    ),
+                    None => write!(
+                        f,
+                        "The compression method '{:}' is not supported",
+                        compression_method
+                    ),
+                }
             }
             ArchiveError::BadArchiveStructure(detail) => {
                 write!(f, "Bad archive structure : {}", detail)
             }
+            #[cfg(feature = "xz")]
             ArchiveError::LZMA(e) => write!(f, "LZMA error : {}", e),
+            ArchiveError::WriteZero(e) => {
+                write!(f, "The underlying sink refused to accept more bytes: {}", e)
+            }
+            ArchiveError::EntryNotFound(name) => {
+                write!(f, "The entry '{}' is not in the archive", name)
+            }
+            ArchiveError::InvalidEntryName(name) => {
+                write!(f, "The entry name '{}' contains a backslash", name)
+            }
+            ArchiveError::EmptyEntryName(name) => {
+                write!(f, "The entry name '{}' has no path to extract to", name)
+            }
+            ArchiveError::Zip64Required { field } => {
+                write!(
+                    f,
+                    "The {} exceeds the ZIP format limits, ZIP64 is required",
+                    field
+                )
+            }
+            ArchiveError::CrcMismatch { expected, actual } => write!(
+                f,
+                "CRC mismatch: expected {:08x}, computed {:08x}",
+                expected, actual
+            ),
+            ArchiveError::SuspiciousRatio {
+                compressed_size,
+                output_size,
+            } => write!(
+                f,
+                "Extraction aborted: {} bytes inflated from {} compressed bytes exceed the limits",
+                output_size, compressed_size
+            ),
+            ArchiveError::RecompressedEntryTooLarge {
+                available,
+                required,
+            } => write!(
+                f,
+                "The recompressed entry needs {} bytes, only {} are available in place",
+                required, available
+            ),
+            ArchiveError::RecompressedEntryGap {
+                available,
+                required,
+            } => write!(
+                f,
+                "The recompressed entry needs {} bytes, the {} bytes left of the {} available in place cannot be padded",
+                required,
+                available - required,
+                available
+            ),
+            ArchiveError::EntryExceedsPartSize { required, max_size } => write!(
+                f,
+                "The entry needs a part of {} bytes, parts are limited to {} bytes",
+                required, max_size
+            ),
+            ArchiveError::Compression { codec, source } => {
+                write!(f, "compression failed ({}): {}", codec, source)
+            }
+            ArchiveError::SpecViolation { detail } => {
+                write!(f, "ZIP specification violation: {}", detail)
+            }
+            ArchiveError::InvalidSigningKey => {
+                write!(f, "The signing key is not a valid P-256 private key")
+            }
+            ArchiveError::ArchiveFinalized => {
+                write!(f, "The archive is finalized, no entry can be appended")
+            }
+            ArchiveError::DigestUnavailable { algorithm, entry } => write!(
+                f,
+                "The {} digest of entry '{}' is not available",
+                algorithm.name(),
+                entry
+            ),
+            ArchiveError::Entry {
+                name,
+                offset,
+                source,
+            } => write!(f, "Entry '{}' at offset {} : {}", name, offset, source),
         }
     }
 }
@@ -52,18 +226,45 @@ impl Debug for ArchiveError {
                 "The compression method code '{:?}' is not supported",
                 val
             ),
+            #[cfg(feature = "xz")]
             ArchiveError::LZMA(e) => write!(f, "LZMA error : {:?}", e),
+            ArchiveError::Compression { codec, source } => {
+                write!(f, "compression failed ({}): {:?}", codec, source)
+            }
+            ArchiveError::Entry {
+                name,
+                offset,
+                source,
+            } => write!(f, "Entry '{}' at offset {} : {:?}", name, offset, source),
             _ => (self as &dyn Display).fmt(f),
         }
     }
 }
 
+impl std::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ArchiveError::IoError(e) => Some(e),
+            ArchiveError::WriteZero(e) => Some(e),
+            #[cfg(feature = "xz")]
+            ArchiveError::LZMA(e) => Some(e),
+            ArchiveError::Compression { source, .. } => Some(source),
+            ArchiveError::Entry { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
 impl From<std::io::Error> for ArchiveError {
     fn from(value: std::io::Error) -> Self {
-        ArchiveError::IoError(value)
+        match value.kind() {
+            std::io::ErrorKind::WriteZero => ArchiveError::WriteZero(value),
+            _ => ArchiveError::IoError(value),
+        }
     }
 }
 
+#[cfg(feature = "xz")]
 impl From<xz2::stream::Error> for ArchiveError {
     fn from(value: xz2::stream::Error) -> Self {
         ArchiveError::LZMA(value)
@@ -4,7 +4,11 @@ pub mod archive;
 mod archive_common;
 pub mod compress;
 pub mod compression;
+pub mod crc;
 pub mod error;
+#[cfg(feature = "tar")]
+pub mod tar;
 pub mod tools;
 pub mod types;
 pub mod uncompress;
+pub mod verification;
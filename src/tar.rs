@@ -0,0 +1,332 @@
+//! TAR output, for consumers that need a TAR stream rather than a ZIP archive, e.g. container
+//! image layers.
+//!
+//! Entries are written as POSIX ustar records. A pax extended header is prepended to the entries
+//! whose name or size does not fit in the ustar fields.
+
+use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::archive::FileOptions;
+
+const BLOCK_SIZE: usize = 512;
+/// Largest value of the 12 bytes octal fields (size, mtime): 11 octal digits.
+const MAX_OCTAL_11: u64 = 0o77777777777;
+const NAME_LEN: usize = 100;
+const PREFIX_LEN: usize = 155;
+
+const REGULAR_FILE: u8 = b'0';
+const PAX_EXTENDED_HEADER: u8 = b'x';
+
+/// File to write in a TAR stream, see `to_tar_stream`.
+pub struct TarEntry {
+    pub name: String,
+    /// Payload of the entry, of exactly `size` bytes.
+    pub data: Box<dyn AsyncRead + Send + Unpin>,
+    pub size: u64,
+    /// Unix permissions, e.g. `0o644`.
+    pub mode: u32,
+    /// Modification time, in seconds since the Unix epoch.
+    pub mtime: u64,
+}
+
+impl TarEntry {
+    /// Create an entry with `rw-r--r--` permissions and a zero modification time.
+    pub fn new(name: &str, data: Box<dyn AsyncRead + Send + Unpin>, size: u64) -> TarEntry {
+        TarEntry {
+            name: name.to_owned(),
+            data,
+            size,
+            mode: 0o644,
+            mtime: 0,
+        }
+    }
+
+    /// Create an entry with the permissions and the Unix modification time of the options, as
+    /// they would be recorded in a ZIP archive.
+    pub fn with_options(
+        name: &str,
+        data: Box<dyn AsyncRead + Send + Unpin>,
+        size: u64,
+        options: &FileOptions,
+    ) -> TarEntry {
+        TarEntry {
+            mode: (options.external_file_attributes() >> 16) & 0o7777,
+            mtime: options.unix_mtime.unwrap_or(0) as u64,
+            ..TarEntry::new(name, data, size)
+        }
+    }
+}
+
+impl std::fmt::Debug for TarEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TarEntry")
+            .field("name", &self.name)
+            .field("size", &self.size)
+            .field("mode", &self.mode)
+            .field("mtime", &self.mtime)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Build a TAR stream of the entries, terminated by the two zero blocks of the end of archive.
+///
+/// The stream is lazy: each entry payload is read only when the stream reaches it.
+///
+/// # Error
+///
+/// Reading the stream fails with `ErrorKind::UnexpectedEof` if an entry payload is shorter than
+/// its `size`; bytes past `size` are ignored.
+pub fn to_tar_stream(entries: Vec<TarEntry>) -> TarStream {
+    TarStream {
+        entries: entries.into_iter(),
+        pending: Vec::new(),
+        pending_pos: 0,
+        current: None,
+        scratch: Vec::new(),
+        finished: false,
+    }
+}
+
+/// TAR stream returned by `to_tar_stream`.
+#[derive(Debug)]
+pub struct TarStream {
+    entries: std::vec::IntoIter<TarEntry>,
+    /// Headers, padding or end of archive blocks not read yet.
+    pending: Vec<u8>,
+    pending_pos: usize,
+    /// Entry whose payload is being read, with the number of bytes left.
+    current: Option<(TarEntry, u64)>,
+    scratch: Vec<u8>,
+    finished: bool,
+}
+
+impl AsyncRead for TarStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let stream = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        loop {
+            if stream.pending_pos < stream.pending.len() {
+                let count = buf
+                    .remaining()
+                    .min(stream.pending.len() - stream.pending_pos);
+                buf.put_slice(&stream.pending[stream.pending_pos..stream.pending_pos + count]);
+                stream.pending_pos += count;
+                return Poll::Ready(Ok(()));
+            }
+
+            match &mut stream.current {
+                Some((entry, remaining)) if *remaining > 0 => {
+                    let count = buf.remaining().min(*remaining as usize).min(64 * 1024);
+                    stream.scratch.resize(count, 0);
+                    let mut read_buf = ReadBuf::new(&mut stream.scratch);
+                    ready!(Pin::new(&mut entry.data).poll_read(cx, &mut read_buf))?;
+
+                    let read = read_buf.filled().len();
+                    if read == 0 {
+                        return Poll::Ready(Err(Error::new(
+                            ErrorKind::UnexpectedEof,
+                            format!(
+                                "the entry '{}' is shorter than its size of {} bytes",
+                                entry.name, entry.size
+                            ),
+                        )));
+                    }
+                    buf.put_slice(&stream.scratch[..read]);
+                    *remaining -= read as u64;
+                    return Poll::Ready(Ok(()));
+                }
+                Some((entry, _)) => {
+                    let padding = padding(entry.size);
+                    stream.current = None;
+                    stream.set_pending(vec![0; padding]);
+                }
+                None => match stream.entries.next() {
+                    Some(entry) => {
+                        stream.set_pending(entry_headers(&entry));
+                        let size = entry.size;
+                        stream.current = Some((entry, size));
+                    }
+                    None if !stream.finished => {
+                        stream.finished = true;
+                        stream.set_pending(vec![0; 2 * BLOCK_SIZE]);
+                    }
+                    None => return Poll::Ready(Ok(())),
+                },
+            }
+        }
+    }
+}
+
+impl TarStream {
+    fn set_pending(&mut self, bytes: Vec<u8>) {
+        self.pending = bytes;
+        self.pending_pos = 0;
+    }
+}
+
+/// Number of zero bytes completing a payload of `size` bytes to a whole block.
+fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+/// Headers of an entry: its ustar header, preceded by a pax extended header when needed.
+fn entry_headers(entry: &TarEntry) -> Vec<u8> {
+    let mut headers = Vec::with_capacity(BLOCK_SIZE);
+    let split_name = split_ustar_name(&entry.name);
+
+    let mut records = Vec::new();
+    if split_name.is_none() {
+        records.extend(pax_record("path", &entry.name));
+    }
+    if entry.size > MAX_OCTAL_11 {
+        records.extend(pax_record("size", &entry.size.to_string()));
+    }
+
+    if !records.is_empty() {
+        let pax_name = format!(
+            "PaxHeaders/{}",
+            truncate(base_name(&entry.name), NAME_LEN - 11)
+        );
+        headers.extend_from_slice(&ustar_header(
+            ("", &pax_name),
+            records.len() as u64,
+            0o644,
+            entry.mtime,
+            PAX_EXTENDED_HEADER,
+        ));
+        headers.extend_from_slice(&records);
+        headers.resize(headers.len() + padding(records.len() as u64), 0);
+    }
+
+    let (prefix, name) = split_name.unwrap_or(("", truncate(&entry.name, NAME_LEN)));
+    headers.extend_from_slice(&ustar_header(
+        (prefix, name),
+        entry.size,
+        entry.mode,
+        entry.mtime,
+        REGULAR_FILE,
+    ));
+    headers
+}
+
+/// Split a name into the ustar prefix and name fields, at a `/`, if it fits.
+fn split_ustar_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= NAME_LEN {
+        return Some(("", name));
+    }
+
+    name.match_indices('/')
+        .map(|(index, _)| (&name[..index], &name[index + 1..]))
+        .find(|(prefix, name)| {
+            prefix.len() <= PREFIX_LEN && name.len() <= NAME_LEN && !name.is_empty()
+        })
+}
+
+fn base_name(name: &str) -> &str {
+    name.rsplit('/')
+        .find(|part| !part.is_empty())
+        .unwrap_or(name)
+}
+
+/// Longest prefix of `value` of at most `len` bytes, cut on a character boundary.
+fn truncate(value: &str, len: usize) -> &str {
+    let mut end = value.len().min(len);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Build a pax record, `"<length> <key>=<value>\n"`, the length counting itself.
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let payload_len = key.len() + value.len() + 3; // space, '=' and newline
+    let mut length = payload_len + 1;
+    while length != payload_len + length.to_string().len() {
+        length = payload_len + length.to_string().len();
+    }
+    format!("{} {}={}\n", length, key, value).into_bytes()
+}
+
+fn ustar_header(
+    (prefix, name): (&str, &str),
+    size: u64,
+    mode: u32,
+    mtime: u64,
+    typeflag: u8,
+) -> [u8; BLOCK_SIZE] {
+    let mut header = [0; BLOCK_SIZE];
+    header[0..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], (mode & 0o7777777) as u64); // mode
+    write_octal(&mut header[108..116], 0); // uid
+    write_octal(&mut header[116..124], 0); // gid
+    write_octal(&mut header[124..136], size.min(MAX_OCTAL_11)); // size, a pax record holds larger ones
+    write_octal(&mut header[136..148], mtime.min(MAX_OCTAL_11)); // mtime
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0"); // magic
+    header[263..265].copy_from_slice(b"00"); // version
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // the checksum is computed with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    write_octal(&mut header[148..155], checksum as u64);
+    header
+}
+
+/// Write a value as zero-padded octal digits followed by a NUL byte, filling the field.
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let octal = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&octal.as_bytes()[octal.len() - digits..]);
+    field[digits] = 0;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pax_record_length() {
+        assert_eq!(pax_record("path", "a"), b"9 path=a\n");
+        // the length grows from one to two digits with itself
+        assert_eq!(pax_record("path", "ab"), b"11 path=ab\n");
+    }
+
+    #[test]
+    fn test_split_ustar_name() {
+        assert_eq!(split_ustar_name("dir/file.txt"), Some(("", "dir/file.txt")));
+
+        let long_dir = "d".repeat(120);
+        let name = format!("{}/file.txt", long_dir);
+        assert_eq!(
+            split_ustar_name(&name),
+            Some((long_dir.as_str(), "file.txt"))
+        );
+
+        assert_eq!(split_ustar_name(&"f".repeat(101)), None);
+    }
+
+    #[test]
+    fn test_header_checksum() {
+        let header = ustar_header(("", "file.txt"), 5, 0o644, 0, REGULAR_FILE);
+        let expected: u32 = header[..148]
+            .iter()
+            .chain(&[b' '; 8])
+            .chain(&header[156..])
+            .map(|&b| b as u32)
+            .sum();
+        assert_eq!(&header[148..155], format!("{:06o}\0", expected).as_bytes());
+        assert_eq!(&header[124..136], b"00000000005\0");
+    }
+}
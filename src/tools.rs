@@ -1 +1,60 @@
+//! Helpers around the archive writers.
 
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::AsyncWrite;
+
+/// Sink discarding every byte written to it while counting them.
+///
+/// Running an archive through it gives the exact size of the archive without keeping it, e.g. to
+/// benchmark or to compute a `Content-Length` in a dry run. Implements both `AsyncWrite` and
+/// `std::io::Write`, so either archive backend can use it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SinkCounter {
+    count: u64,
+}
+
+impl SinkCounter {
+    pub fn new() -> SinkCounter {
+        Self::default()
+    }
+
+    /// Number of bytes written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl AsyncWrite for SinkCounter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        self.get_mut().count += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl std::io::Write for SinkCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.count += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
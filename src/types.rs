@@ -1,10 +1,14 @@
 use core::fmt;
-use std::u16;
+use std::mem::size_of;
+use std::time::SystemTime;
 
-use crate::{compression::CompressionMethod, constants::VERSION_MADE_BY};
+use crate::{
+    compression::{CompressionMethod, Level},
+    constants::{VERSION_MADE_BY, ZIP64_VERSION_NEEDED},
+};
 use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Timelike, Utc};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ArchiveFileEntry {
     pub version_made_by: u16,
     pub version_needed: u16,
@@ -13,13 +17,22 @@ pub struct ArchiveFileEntry {
     pub last_mod_file_time: u16,
     pub last_mod_file_date: u16,
     pub crc32: u32,
+    /// SHA-256 of the uncompressed bytes, when the archive builds a SHA-256 manifest.
+    pub sha256: Option<[u8; 32]>,
     pub compressed_size: u64,
     pub uncompressed_size: u64,
     pub file_name_len: u16,
     pub extra_field_length: u16,
     pub file_name_as_bytes: Vec<u8>,
-    pub offset: u32,
+    /// Extra field as written in the central directory.
+    pub extra_field: Vec<u8>,
+    pub offset: u64,
+    /// Whether the local header holds a ZIP64 extra field and the data descriptor 8-byte sizes.
+    pub zip64: bool,
     pub compressor: CompressionMethod,
+    /// Effective compression level, `Level::None` for `Store`, the method `Level::None` selects.
+    /// For an entry read from an archive, only what the Deflate option bits tell is known.
+    pub compression_level: Level,
     pub file_comment_length: u16,
     pub file_disk_number: u16,
     pub internal_file_attributes: u16,
@@ -27,20 +40,84 @@ pub struct ArchiveFileEntry {
 }
 
 impl ArchiveFileEntry {
+    /// Version needed to extract recorded in the central directory: the one the method and the
+    /// ZIP64 fields require, or the `version_needed` field if higher, e.g. for an entry copied
+    /// from another archive.
     pub fn version_needed(&self) -> u16 {
         // higher versions matched first
-        match self.compressor {
+        let version_needed = match self.compressor {
             CompressionMethod::Lzma() => 63,
             CompressionMethod::Zstd() => 63,
             CompressionMethod::BZip2() => 46,
             _ => 20,
-        }
+        };
+
+        let version_needed = if self.zip64 || self.needs_zip64_extra_field() {
+            version_needed.max(ZIP64_VERSION_NEEDED)
+        } else {
+            version_needed
+        };
+        version_needed.max(self.version_needed)
+    }
+
+    /// Whether a size or the offset overflows its 32 bits central directory field.
+    pub fn needs_zip64_extra_field(&self) -> bool {
+        self.uncompressed_size >= u32::MAX as u64
+            || self.compressed_size >= u32::MAX as u64
+            || self.offset >= u32::MAX as u64
+    }
+
+    /// Offset of the CRC-32 field from the start of the local file header, where a seekable
+    /// writer patches the CRC and the sizes once the payload is written.
+    pub fn crc_field_offset(&self) -> u64 {
+        // signature, then version needed, flags, method, time and date
+        (size_of::<u32>() + 5 * size_of::<u16>()) as u64
+    }
+
+    /// Compression method the payload is written with.
+    pub fn compressor(&self) -> CompressionMethod {
+        self.compressor
+    }
+
+    /// Compression level the payload is written with, see `compression_level` field.
+    pub fn compression_level(&self) -> Level {
+        self.compression_level
     }
 
     pub fn version_made_by(&self) -> u16 {
         VERSION_MADE_BY
     }
 
+    /// Whether the general purpose bit 11 is set, i.e. the file name is encoded in UTF-8.
+    pub fn is_utf8(&self) -> bool {
+        self.general_purpose_flags & (1u16 << 11) != 0
+    }
+
+    /// Summarize the entry metadata, e.g. to list the content of an archive.
+    pub fn to_display_info(&self) -> EntryInfo {
+        let compression_ratio = if self.compressed_size == 0 {
+            1.0
+        } else {
+            self.uncompressed_size as f64 / self.compressed_size as f64
+        };
+
+        let last_modified =
+            DateTimeCS::from_msdos(self.last_mod_file_date, self.last_mod_file_time)
+                .to_time()
+                .format("%Y-%m-%d")
+                .to_string();
+
+        EntryInfo {
+            name: String::from_utf8_lossy(&self.file_name_as_bytes).into_owned(),
+            uncompressed_size: self.uncompressed_size,
+            compressed_size: self.compressed_size,
+            compression_ratio,
+            method: self.compressor.label(),
+            crc32: format!("{:08x}", self.crc32),
+            last_modified,
+        }
+    }
+
     fn extended_local_header(&self) -> bool {
         self.general_purpose_flags & (1u16 << 3) != 0
     }
@@ -153,6 +230,36 @@ impl fmt::Display for ArchiveFileEntry {
     }
 }
 
+/// Human-readable summary of an archive entry, see `ArchiveFileEntry::to_display_info`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryInfo {
+    pub name: String,
+    pub uncompressed_size: u64,
+    pub compressed_size: u64,
+    /// Uncompressed size / compressed size, `1.0` for an empty entry.
+    pub compression_ratio: f64,
+    pub method: &'static str,
+    /// CRC-32 in hexadecimal.
+    pub crc32: String,
+    /// Last modification date, as `YYYY-MM-DD`.
+    pub last_modified: String,
+}
+
+impl fmt::Display for EntryInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} → {} bytes, {}, CRC {}, {})",
+            self.name,
+            self.uncompressed_size,
+            self.compressed_size,
+            self.method,
+            self.crc32,
+            self.last_modified
+        )
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct DateTimeCS {
     year: u16,
@@ -271,13 +378,18 @@ impl fmt::Display for DateTimeCS {
 ///
 /// Use `FileDateTime::Zero` if the date and time are insignificant. This will set the value to 0 which is 1980, January 1th, 12AM.  
 /// Use `FileDateTime::Custom` if you need to set a custom date and time.  
-/// Use `FileDateTime::now()` if you want to use the current date and time (`chrono-datetime` feature required).
+/// Use `FileDateTime::Now` if you want to use the current date and time.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum FileDateTime {
     /// 1980, January 1th, 12AM.
     Zero,
     /// (year, month, day, hour, minute, second)
     Custom(DateTimeCS),
+    /// The local time when the entry is written.
+    ///
+    /// The time is not captured by the value: it is read on each `ms_dos` call, i.e. when the
+    /// local header of an entry is built by `append_file`. `FileOptions` reused across a long
+    /// loop hence give each entry its own time.
     Now,
 }
 
@@ -298,6 +410,14 @@ impl FileDateTime {
         self.tuple().to_time()
     }
 
+    /// Date and time of a `SystemTime`, e.g. the modification time of a file, in local time as
+    /// MS-DOS dates are.
+    pub fn from_system_time(time: SystemTime) -> FileDateTime {
+        FileDateTime::Custom(DateTimeCS::from_chrono_datetime(DateTime::<Local>::from(
+            time,
+        )))
+    }
+
     pub fn timestamp(&self) -> i32 {
         match self {
             FileDateTime::Zero => DateTimeCS::default().timestamp(),
@@ -344,6 +464,45 @@ mod test {
         println!("Time zero {}", date_time)
     }
 
+    #[test]
+    fn test_entry_display_info() {
+        let date_time =
+            DateTimeCS::from_chrono_datetime(Utc.with_ymd_and_hms(2024, 1, 1, 10, 20, 30).unwrap());
+        let (last_mod_file_date, last_mod_file_time) = date_time.ms_dos();
+
+        let entry = ArchiveFileEntry {
+            version_made_by: VERSION_MADE_BY,
+            version_needed: 20,
+            general_purpose_flags: 0,
+            compression_method: 8,
+            last_mod_file_time,
+            last_mod_file_date,
+            crc32: 0x12345678,
+            sha256: None,
+            compressed_size: 512,
+            uncompressed_size: 1024,
+            file_name_len: 8,
+            extra_field_length: 0,
+            file_name_as_bytes: b"file.txt".to_vec(),
+            extra_field: Vec::new(),
+            offset: 0,
+            zip64: false,
+            compressor: CompressionMethod::Deflate(),
+            compression_level: Level::Default,
+            file_comment_length: 0,
+            file_disk_number: 0,
+            internal_file_attributes: 0,
+            external_file_attributes: 0,
+        };
+
+        let info = entry.to_display_info();
+        assert_eq!(info.compression_ratio, 2.0);
+        assert_eq!(
+            info.to_string(),
+            "file.txt (1024 → 512 bytes, deflate, CRC 12345678, 2024-01-01)"
+        );
+    }
+
     #[test]
     fn test_time_display_time() {
         let time: FileDateTime = FileDateTime::Now;
@@ -1,12 +1,19 @@
+use crate::archive::ExtractOptions;
 use crate::archive_common::{ArchiveDescriptorReader, CentralDirectoryEnd};
 use crate::compression::CompressionMethod;
-use crate::constants::CENTRAL_DIRECTORY_ENTRY_SIGNATURE;
+use crate::constants::{
+    ARCHIVE_TAIL_MAX_SIZE, CENTRAL_DIRECTORY_ENTRY_BASE_SIZE, CENTRAL_DIRECTORY_ENTRY_SIGNATURE,
+    FILE_HEADER_BASE_SIZE, LOCAL_FILE_HEADER_SIGNATURE,
+    ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE, ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIZE,
+    ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE, ZIP64_CENTRAL_DIRECTORY_END_SIZE, ZIP64_EXTRA_FIELD_ID,
+};
+use crate::crc::compute_crc32;
 use crate::types::ArchiveFileEntry;
 use crate::{
     constants::{CENTRAL_DIRECTORY_END_SIGNATURE, END_OF_CENTRAL_DIRECTORY_SIZE},
     error::ArchiveError,
 };
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::borrow::Cow;
 use std::io::{Read, Seek, SeekFrom};
 
 pub struct ArchiveReader<R>
@@ -15,98 +22,417 @@ where
 {
     #[allow(dead_code)]
     reader: R,
+    comment: Vec<u8>,
 }
 
 impl<R: Read + Seek> ArchiveReader<R> {
     pub fn new(mut reader: R) -> Result<ArchiveReader<R>, ArchiveError> {
-        Self::parse(&mut reader)?;
+        let comment = Self::parse(&mut reader)?;
 
-        let ar = ArchiveReader { reader };
+        let ar = ArchiveReader { reader, comment };
         Ok(ar)
     }
 
-    fn parse(reader: &mut R) -> Result<(), ArchiveError> {
-        //find central dir end
+    /// Archive comment, as stored in the end of central directory record.
+    pub fn comment(&self) -> &[u8] {
+        &self.comment
+    }
+
+    /// Archive comment decoded as UTF-8, invalid sequences being replaced.
+    pub fn comment_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.comment)
+    }
 
+    /// Read the end of central directory record and the central directory, returning the archive
+    /// comment.
+    fn parse(reader: &mut R) -> Result<Vec<u8>, ArchiveError> {
         let file_length = reader.seek(SeekFrom::End(0))?;
-        let mut pos = file_length - END_OF_CENTRAL_DIRECTORY_SIZE;
-        //let mut pos = file_length - 4;
-        let search_upper_bound =
-            file_length.saturating_sub(END_OF_CENTRAL_DIRECTORY_SIZE + u16::MAX as u64);
-
-        loop {
-            if pos < search_upper_bound {
-                return Err(ArchiveError::BadArchiveStructure(
-                    "CENTRAL_DIRECTORY_END_SIGNATURE Not found".to_owned(),
-                ));
-            }
-            println!("pos {} >= search_upper_bound {}", pos, search_upper_bound);
-            reader.seek(SeekFrom::Start(pos))?;
+        let tail_length = file_length.min(ARCHIVE_TAIL_MAX_SIZE);
+        reader.seek(SeekFrom::Start(file_length - tail_length))?;
+        let mut tail = vec![0; tail_length as usize];
+        reader.read_exact(&mut tail)?;
 
-            let val = reader.read_u32::<LittleEndian>()?;
+        let (central_directory_end, comment) = read_archive_tail(&tail)?;
 
-            println!("val {:0X} ", val);
-            if val == CENTRAL_DIRECTORY_END_SIGNATURE {
-                println!("CENTRAL_DIRECTORY_END_SIGNATURE found at {}", pos);
-                break;
-            }
-            /*             if reader.read_u32::<LittleEndian>()? == CENTRAL_DIRECTORY_END_SIGNATURE {
-                reader.seek(io::SeekFrom::Current(
-                    BYTES_BETWEEN_MAGIC_AND_COMMENT_SIZE as i64,
-                ))?;
-                let cde_start_pos = reader.seek(io::SeekFrom::Start(pos))?;
-                return CentralDirectoryEnd::parse(reader).map(|cde| (cde, cde_start_pos));
-            }*/
-            pos = match pos.checked_sub(1) {
-                Some(p) => p,
-                None => {
-                    return Err(ArchiveError::BadArchiveStructure(
-                        "CENTRAL_DIRECTORY_END_SIGNATURE Not found".to_owned(),
-                    ))
-                }
-            };
-        }
+        Self::read_cental_directory(central_directory_end, reader)?;
 
-        let central_end_size: usize = (file_length - pos - 4) as usize;
-        let mut v: Vec<u8> = vec![0; central_end_size];
+        Ok(comment)
+    }
 
-        println!(
-            "central_end_size {} file_length {} pos {}",
-            central_end_size, file_length, pos
-        );
-        println!("vec len  {} ", v.len());
-        //reader.seek(SeekFrom::Start(pos))?;
+    fn read_cental_directory(
+        central_directory_end: CentralDirectoryLocation,
+        reader: &mut R,
+    ) -> Result<Vec<ArchiveFileEntry>, ArchiveError> {
+        reader.seek(SeekFrom::Start(central_directory_end.offset))?;
+
+        let mut central_directory_buffer: Vec<u8> = vec![0; central_directory_end.size as usize];
 
-        reader.read_exact(&mut v)?;
+        reader.read_exact(&mut central_directory_buffer)?;
 
-        let central_directory_end = Self::read_cental_directory_end(&v)?;
+        read_central_directory_entries(&central_directory_buffer, central_directory_end.entries)
+    }
+}
 
-        println!("central_directory_end {:#?}", central_directory_end);
+/// Extract a single entry of an in-memory archive.
+///
+/// The entry is looked up by name in the central directory, decompressed and checked against
+/// its CRC. Only `Store`, `Deflate` and, with the `zstd` feature, `Zstd` entries are supported.
+///
+/// No limit applies to the output; use `extract_entry_to_vec_with_options` for untrusted archives.
+///
+/// # Error
+///
+/// `ArchiveError::EntryNotFound` when no entry has this name, `ArchiveError::CrcMismatch` when the
+/// decompressed bytes are corrupted and `ArchiveError::UnsuportedCompressionMethod` for other methods.
+pub fn extract_entry_to_vec(archive_bytes: &[u8], name: &str) -> Result<Vec<u8>, ArchiveError> {
+    extract_entry_to_vec_with_options(archive_bytes, name, &ExtractOptions::default())
+}
 
-        let archive_file_entry = Self::read_cental_directory(central_directory_end, reader)?;
+/// Extract a single entry of an in-memory archive, within the limits of the options.
+///
+/// # Error
+///
+/// Same as `extract_entry_to_vec`, and `ArchiveError::SuspiciousRatio` as soon as the output
+/// exceeds the limits. Offsets and sizes read from the archive that point outside of it give
+/// `ArchiveError::BadArchiveStructure`.
+pub fn extract_entry_to_vec_with_options(
+    archive_bytes: &[u8],
+    name: &str,
+    options: &ExtractOptions,
+) -> Result<Vec<u8>, ArchiveError> {
+    let (entries, _) = read_in_memory_central_directory(archive_bytes)?;
+    let entry = entries
+        .into_iter()
+        .find(|entry| entry.file_name_as_bytes == name.as_bytes())
+        .ok_or_else(|| ArchiveError::EntryNotFound(name.to_owned()))?;
+
+    let header = archive_slice(
+        archive_bytes,
+        entry.offset,
+        FILE_HEADER_BASE_SIZE,
+        "Local header",
+    )?;
+
+    let data_start = entry.offset + local_header_size(header)?;
+    let compressed_data = archive_slice(
+        archive_bytes,
+        data_start,
+        entry.compressed_size,
+        "Entry data",
+    )?;
+
+    decompress_entry(&entry, compressed_data, options)
+}
 
-        //println!("archive_file_entry {:#?}", archive_file_entry);
-        println!("archive_file_entry file: {}", archive_file_entry);
+/// Entries of an in-memory archive with their raw bytes: local header, payload and data
+/// descriptor if any, as written. Feed them to `ZipArchive::append_raw` to copy the entries
+/// without decompressing them.
+///
+/// The bytes of an entry run up to the next entry, or to the central directory for the last one.
+///
+/// # Error
+///
+/// `ArchiveError::BadArchiveStructure` if the central directory cannot be read or an entry lies
+/// outside of the archive.
+pub fn raw_entries(archive_bytes: &[u8]) -> Result<Vec<(ArchiveFileEntry, &[u8])>, ArchiveError> {
+    let (entries, central_directory_start) = read_in_memory_central_directory(archive_bytes)?;
+
+    let mut ends: Vec<u64> = entries.iter().map(|entry| entry.offset).collect();
+    ends.push(central_directory_start);
+    ends.sort_unstable();
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            // an entry starting at or past the central directory has no end among the offsets
+            let end = ends
+                .get(ends.partition_point(|&offset| offset <= entry.offset))
+                .filter(|_| entry.offset < central_directory_start)
+                .copied()
+                .ok_or_else(|| {
+                    ArchiveError::BadArchiveStructure("Entry out of bounds!".to_owned())
+                })?;
+            let bytes = archive_slice(archive_bytes, entry.offset, end - entry.offset, "Entry")?;
+            Ok((entry, bytes))
+        })
+        .collect()
+}
 
-        Ok(())
+/// Central directory records of an in-memory archive, and the offset of the central directory.
+fn read_in_memory_central_directory(
+    archive_bytes: &[u8],
+) -> Result<(Vec<ArchiveFileEntry>, u64), ArchiveError> {
+    let tail_start = archive_bytes
+        .len()
+        .saturating_sub(ARCHIVE_TAIL_MAX_SIZE as usize);
+    let (central_directory_end, _) = read_archive_tail(&archive_bytes[tail_start..])?;
+
+    let central_directory = archive_slice(
+        archive_bytes,
+        central_directory_end.offset,
+        central_directory_end.size,
+        "Central directory",
+    )?;
+
+    let entries = read_central_directory_entries(central_directory, central_directory_end.entries)?;
+    Ok((entries, central_directory_end.offset))
+}
+
+/// The `len` bytes of the archive at `start`. Both are read from the archive and not trusted:
+/// `ArchiveError::BadArchiveStructure` is returned if the range overflows or lies outside of it.
+fn archive_slice<'a>(
+    archive_bytes: &'a [u8],
+    start: u64,
+    len: u64,
+    what: &str,
+) -> Result<&'a [u8], ArchiveError> {
+    start
+        .checked_add(len)
+        .and_then(|end| {
+            let start = usize::try_from(start).ok()?;
+            let end = usize::try_from(end).ok()?;
+            archive_bytes.get(start..end)
+        })
+        .ok_or_else(|| ArchiveError::BadArchiveStructure(format!("{} out of bounds!", what)))
+}
+
+/// Size of a local header, i.e. the offset of the entry data from its start, given the fixed
+/// size part of the header.
+pub(crate) fn local_header_size(header: &[u8]) -> Result<u64, ArchiveError> {
+    if header[0..4] != LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes() {
+        return Err(ArchiveError::BadArchiveStructure(
+            "Local header signature not found!".to_owned(),
+        ));
     }
 
-    fn read_cental_directory(
-        central_directory_end: CentralDirectoryEnd,
-        reader: &mut R,
-    ) -> Result<ArchiveFileEntry, ArchiveError> {
-        reader.seek(SeekFrom::Start(
-            central_directory_end.offset_of_start_of_central_directory as u64,
-        ))?;
+    // the local lengths may differ from the central directory ones (e.g. the extra field)
+    let file_name_len = u16::from_le_bytes([header[26], header[27]]) as u64;
+    let extra_field_length = u16::from_le_bytes([header[28], header[29]]) as u64;
 
-        let mut central_directory_buffer: Vec<u8> =
-            vec![0; central_directory_end.central_directory_size as usize];
+    Ok(FILE_HEADER_BASE_SIZE + file_name_len + extra_field_length)
+}
 
-        reader.read_exact(&mut central_directory_buffer)?;
+/// Decompress the data of an entry and check it against its CRC. Only `Store`, `Deflate` and,
+/// with the `zstd` feature, `Zstd` entries are supported.
+///
+/// The limits of the options are checked after each decompressed chunk.
+pub(crate) fn decompress_entry(
+    entry: &ArchiveFileEntry,
+    compressed_data: &[u8],
+    options: &ExtractOptions,
+) -> Result<Vec<u8>, ArchiveError> {
+    let compressed_size = compressed_data.len() as u64;
+    let limit = options.output_limit(compressed_size).unwrap_or(u64::MAX);
+    let check_limit = |output_size: u64| {
+        if output_size > limit {
+            Err(ArchiveError::SuspiciousRatio {
+                compressed_size,
+                output_size,
+            })
+        } else {
+            Ok(())
+        }
+    };
+
+    let data = match entry.compressor {
+        CompressionMethod::Store() => {
+            check_limit(compressed_size)?;
+            compressed_data.to_vec()
+        }
+        CompressionMethod::Deflate() => {
+            let decoder = flate2::read::DeflateDecoder::new(compressed_data);
+            read_decoder(decoder, entry.uncompressed_size.min(limit), check_limit)?
+        }
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd() => {
+            let decoder = zstd::stream::read::Decoder::new(compressed_data)?;
+            read_decoder(decoder, entry.uncompressed_size.min(limit), check_limit)?
+        }
+        compressor => return Err(ArchiveError::UnsuportedCompressionMethod(compressor)),
+    };
+
+    let actual = compute_crc32(&data);
+    if actual != entry.crc32 {
+        return Err(ArchiveError::CrcMismatch {
+            expected: entry.crc32,
+            actual,
+        });
+    }
 
+    Ok(data)
+}
+
+/// Read a decoder to its end, checking the output size after each chunk. The declared size is
+/// only used as a capacity, it is not trusted beyond the limit.
+fn read_decoder<R: Read>(
+    mut decoder: R,
+    capacity: u64,
+    check_limit: impl Fn(u64) -> Result<(), ArchiveError>,
+) -> Result<Vec<u8>, ArchiveError> {
+    let mut data = Vec::with_capacity(capacity as usize);
+    let mut chunk = vec![0; 16 * 1024];
+    loop {
+        let read = decoder.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&chunk[..read]);
+        check_limit(data.len() as u64)?;
+    }
+    Ok(data)
+}
+
+/// Number of entries, size and offset of the central directory, as read from the end records.
+#[derive(Debug)]
+pub(crate) struct CentralDirectoryLocation {
+    pub entries: u64,
+    pub size: u64,
+    pub offset: u64,
+}
+
+/// Locate the end of central directory record in the tail of an archive and parse it.
+///
+/// The tail is scanned backwards for the record signature. As the signature bytes may appear in
+/// the comment, the record whose comment length reaches exactly the end of the tail is preferred;
+/// otherwise the last signature found is used, e.g. for an archive followed by garbage.
+///
+/// The values the record saturates are taken from the ZIP64 end of central directory record,
+/// expected right before its locator, itself right before the record.
+///
+/// Returns the location of the central directory and the archive comment.
+pub(crate) fn read_archive_tail(
+    tail: &[u8],
+) -> Result<(CentralDirectoryLocation, Vec<u8>), ArchiveError> {
+    let signature = CENTRAL_DIRECTORY_END_SIGNATURE.to_le_bytes();
+    let record_size = END_OF_CENTRAL_DIRECTORY_SIZE as usize;
+
+    let mut candidates = (0..=tail.len().saturating_sub(record_size))
+        .rev()
+        .filter(|&pos| tail.len() >= record_size && tail[pos..pos + 4] == signature);
+    let last = candidates.next().ok_or_else(|| {
+        ArchiveError::BadArchiveStructure("CENTRAL_DIRECTORY_END_SIGNATURE Not found".to_owned())
+    })?;
+    let comment_length = |pos: usize| u16::from_le_bytes([tail[pos + 20], tail[pos + 21]]) as usize;
+    let position = std::iter::once(last)
+        .chain(candidates)
+        .find(|&pos| pos + record_size + comment_length(pos) == tail.len())
+        .unwrap_or(last);
+
+    let record = &tail[position + 4..];
+    let central_directory_end = read_cental_directory_end(record)?;
+
+    let comment_start = record_size - 4;
+    let comment_end = std::cmp::min(
+        comment_start + central_directory_end.zip_file_comment_length as usize,
+        record.len(),
+    );
+    let comment = record[comment_start..comment_end].to_owned();
+
+    let mut location = CentralDirectoryLocation {
+        entries: central_directory_end.total_number_of_entries as u64,
+        size: central_directory_end.central_directory_size as u64,
+        offset: central_directory_end.offset_of_start_of_central_directory as u64,
+    };
+
+    let saturated = central_directory_end.total_number_of_entries == u16::MAX
+        || central_directory_end.central_directory_size == u32::MAX
+        || central_directory_end.offset_of_start_of_central_directory == u32::MAX;
+    if saturated {
+        if let Some(zip64_end) = read_zip64_central_directory_end(&tail[..position]) {
+            location = zip64_end;
+        }
+    }
+
+    Ok((location, comment))
+}
+
+/// Parse the ZIP64 end of central directory record and its locator ending `stream`, if present.
+fn read_zip64_central_directory_end(stream: &[u8]) -> Option<CentralDirectoryLocation> {
+    let locator_start = stream
+        .len()
+        .checked_sub(ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIZE as usize)?;
+    let record_start = locator_start.checked_sub(ZIP64_CENTRAL_DIRECTORY_END_SIZE as usize)?;
+
+    let locator = &stream[locator_start..];
+    if locator[0..4] != ZIP64_CENTRAL_DIRECTORY_END_LOCATOR_SIGNATURE.to_le_bytes() {
+        return None;
+    }
+
+    let record = &stream[record_start..locator_start];
+    let mut indexer = ArchiveDescriptorReader::new();
+    if indexer.read_u32(record) != ZIP64_CENTRAL_DIRECTORY_END_SIGNATURE {
+        return None;
+    }
+    let _record_size = indexer.read_u64(record);
+    let _version_made_by = indexer.read_u16(record);
+    let _version_needed = indexer.read_u16(record);
+    let _disk_number = indexer.read_u32(record);
+    let _disk_with_central_directory = indexer.read_u32(record);
+    let _entries_on_this_disk = indexer.read_u64(record);
+
+    Some(CentralDirectoryLocation {
+        entries: indexer.read_u64(record),
+        size: indexer.read_u64(record),
+        offset: indexer.read_u64(record),
+    })
+}
+
+/// Remove the ZIP64 extended information block from the extra field of a central directory
+/// entry, replacing the sizes and offset it saturates with the 8-byte values of the block.
+///
+/// The block is stripped as the writers build it again from the entry values.
+fn take_zip64_extra_field(
+    extra_field: &mut Vec<u8>,
+    values: [&mut u64; 3],
+) -> Result<(), ArchiveError> {
+    let mut start = 0;
+    while start + 4 <= extra_field.len() {
+        let id = u16::from_le_bytes([extra_field[start], extra_field[start + 1]]);
+        let size = u16::from_le_bytes([extra_field[start + 2], extra_field[start + 3]]) as usize;
+        let end = (start + 4 + size).min(extra_field.len());
+        if id != ZIP64_EXTRA_FIELD_ID {
+            start = end;
+            continue;
+        }
+
+        let block = &extra_field[start + 4..end];
         let mut indexer = ArchiveDescriptorReader::new();
+        for value in values {
+            if *value == u32::MAX as u64 {
+                if block.len() < indexer.index() + 8 {
+                    return Err(ArchiveError::BadArchiveStructure(
+                        "ZIP64 extra field truncated!".to_owned(),
+                    ));
+                }
+                *value = indexer.read_u64(block);
+            }
+        }
+
+        extra_field.drain(start..end);
+        return Ok(());
+    }
 
-        let signature = indexer.read_u32(&central_directory_buffer);
+    Ok(())
+}
+
+/// Parse the `count` entries of a central directory.
+pub(crate) fn read_central_directory_entries(
+    central_directory_buffer: &[u8],
+    count: u64,
+) -> Result<Vec<ArchiveFileEntry>, ArchiveError> {
+    let mut indexer = ArchiveDescriptorReader::new();
+    let mut entries = Vec::with_capacity(count.min(u16::MAX as u64) as usize);
+
+    for _ in 0..count {
+        let entry_start = indexer.index();
+        if central_directory_buffer.len() < entry_start + CENTRAL_DIRECTORY_ENTRY_BASE_SIZE as usize
+        {
+            return Err(ArchiveError::BadArchiveStructure(
+                "Central directory truncated!".to_owned(),
+            ));
+        }
+
+        let signature = indexer.read_u32(central_directory_buffer);
 
         if signature != CENTRAL_DIRECTORY_ENTRY_SIGNATURE {
             return Err(ArchiveError::BadArchiveStructure(
@@ -114,26 +440,50 @@ impl<R: Read + Seek> ArchiveReader<R> {
             ));
         }
 
-        let version_made_by = indexer.read_u16(&central_directory_buffer); // Version made by.
-        let version_needed = indexer.read_u16(&central_directory_buffer); // Version needed to extract.
-        let general_purpose_flags = indexer.read_u16(&central_directory_buffer); // General purpose flag (temporary crc and sizes + UTF-8 filename).
-        let compression_method = indexer.read_u16(&central_directory_buffer); // Compression method .
-        let last_mod_file_time = indexer.read_u16(&central_directory_buffer); // Modification time.
-        let last_mod_file_date = indexer.read_u16(&central_directory_buffer); // Modification date.
-        let crc32 = indexer.read_u32(&central_directory_buffer); // CRC32.
-        let compressed_size = indexer.read_u32(&central_directory_buffer) as u64; // Compressed size.
-        let uncompressed_size = indexer.read_u32(&central_directory_buffer) as u64; // Uncompressed size.
-        let file_name_len = indexer.read_u16(&central_directory_buffer); // Filename length.
-        let extra_field_length = indexer.read_u16(&central_directory_buffer); // Extra field length.
-        let file_comment_length = indexer.read_u16(&central_directory_buffer); // File comment length.
-        let file_disk_number = indexer.read_u16(&central_directory_buffer); // File's Disk number.
-        let internal_file_attributes = indexer.read_u16(&central_directory_buffer); // Internal file attributes.
-        let external_file_attributes = indexer.read_u32(&central_directory_buffer); // External file attributes (regular file / rw-r--r--).
-        let file_info_offset = indexer.read_u32(&central_directory_buffer);
-        let file_name_as_bytes =
-            indexer.read_bytes(&central_directory_buffer, file_name_len as usize);
+        let version_made_by = indexer.read_u16(central_directory_buffer); // Version made by.
+        let version_needed = indexer.read_u16(central_directory_buffer); // Version needed to extract.
+        let general_purpose_flags = indexer.read_u16(central_directory_buffer); // General purpose flag (temporary crc and sizes + UTF-8 filename).
+        let compression_method = indexer.read_u16(central_directory_buffer); // Compression method .
+        let last_mod_file_time = indexer.read_u16(central_directory_buffer); // Modification time.
+        let last_mod_file_date = indexer.read_u16(central_directory_buffer); // Modification date.
+        let crc32 = indexer.read_u32(central_directory_buffer); // CRC32.
+        let mut compressed_size = indexer.read_u32(central_directory_buffer) as u64; // Compressed size.
+        let mut uncompressed_size = indexer.read_u32(central_directory_buffer) as u64; // Uncompressed size.
+        let file_name_len = indexer.read_u16(central_directory_buffer); // Filename length.
+        let extra_field_length = indexer.read_u16(central_directory_buffer); // Extra field length.
+        let file_comment_length = indexer.read_u16(central_directory_buffer); // File comment length.
+        let file_disk_number = indexer.read_u16(central_directory_buffer); // File's Disk number.
+        let internal_file_attributes = indexer.read_u16(central_directory_buffer); // Internal file attributes.
+        let external_file_attributes = indexer.read_u32(central_directory_buffer); // External file attributes (regular file / rw-r--r--).
+        let mut file_info_offset = indexer.read_u32(central_directory_buffer) as u64;
+
+        let variable_size =
+            file_name_len as usize + extra_field_length as usize + file_comment_length as usize;
+        if central_directory_buffer.len() < indexer.index() + variable_size {
+            return Err(ArchiveError::BadArchiveStructure(
+                "Central directory truncated!".to_owned(),
+            ));
+        }
 
-        let compressor = CompressionMethod::from_compression_method(compression_method)?;
+        let file_name_as_bytes =
+            indexer.read_bytes(central_directory_buffer, file_name_len as usize);
+        let mut extra_field =
+            indexer.read_bytes(central_directory_buffer, extra_field_length as usize);
+        let _file_comment =
+            indexer.read_bytes(central_directory_buffer, file_comment_length as usize);
+
+        take_zip64_extra_field(
+            &mut extra_field,
+            [
+                &mut uncompressed_size,
+                &mut compressed_size,
+                &mut file_info_offset,
+            ],
+        )?;
+        let extra_field_length = extra_field.len() as u16;
+
+        let compressor = CompressionMethod::from_compression_method(compression_method)
+            .unwrap_or(CompressionMethod::Unknown(compression_method));
         let a = ArchiveFileEntry {
             version_made_by,
             version_needed,
@@ -142,55 +492,83 @@ impl<R: Read + Seek> ArchiveReader<R> {
             last_mod_file_time,
             last_mod_file_date,
             crc32,
+            sha256: None,
             compressed_size,
             uncompressed_size,
             file_name_len,
             extra_field_length,
             file_name_as_bytes,
+            extra_field,
             offset: file_info_offset,
+            zip64: false,
             compressor,
+            compression_level: compressor
+                .level_from_general_purpose_bit_flag(general_purpose_flags),
             internal_file_attributes,
             external_file_attributes,
             file_comment_length,
             file_disk_number,
         };
 
-        Ok(a)
+        entries.push(a);
     }
 
-    fn read_cental_directory_end(stream: &[u8]) -> Result<CentralDirectoryEnd, ArchiveError> {
-        let mut indexer = ArchiveDescriptorReader::new();
-
-        //let _signature = indexer.read_u32(stream);
-        let disk_number = indexer.read_u16(stream);
-        let disk_with_central_directory = indexer.read_u16(stream);
-        let total_number_of_entries_on_this_disk = indexer.read_u16(stream);
-        let total_number_of_entries = indexer.read_u16(stream);
-        let central_directory_size = indexer.read_u32(stream);
-        let offset_of_start_of_central_directory = indexer.read_u32(stream);
-        let zip_file_comment_length = indexer.read_u16(stream);
-
-        let central_directory_end = CentralDirectoryEnd {
-            disk_number,
-            disk_with_central_directory,
-            total_number_of_entries_on_this_disk,
-            total_number_of_entries,
-            central_directory_size,
-            offset_of_start_of_central_directory,
-            zip_file_comment_length,
-        };
+    Ok(entries)
+}
 
-        Ok(central_directory_end)
+fn read_cental_directory_end(stream: &[u8]) -> Result<CentralDirectoryEnd, ArchiveError> {
+    if stream.len() < END_OF_CENTRAL_DIRECTORY_SIZE as usize - 4 {
+        return Err(ArchiveError::BadArchiveStructure(
+            "End of central directory truncated!".to_owned(),
+        ));
     }
+
+    let mut indexer = ArchiveDescriptorReader::new();
+
+    //let _signature = indexer.read_u32(stream);
+    let disk_number = indexer.read_u16(stream);
+    let disk_with_central_directory = indexer.read_u16(stream);
+    let total_number_of_entries_on_this_disk = indexer.read_u16(stream);
+    let total_number_of_entries = indexer.read_u16(stream);
+    let central_directory_size = indexer.read_u32(stream);
+    let offset_of_start_of_central_directory = indexer.read_u32(stream);
+    let zip_file_comment_length = indexer.read_u16(stream);
+
+    let central_directory_end = CentralDirectoryEnd {
+        disk_number,
+        disk_with_central_directory,
+        total_number_of_entries_on_this_disk,
+        total_number_of_entries,
+        central_directory_size,
+        offset_of_start_of_central_directory,
+        zip_file_comment_length,
+    };
+
+    Ok(central_directory_end)
 }
 
 #[cfg(test)]
 mod test {
     use std::{fs::File, io::Cursor, path::Path};
 
+    use crate::archive::ExtractOptions;
+    #[cfg(feature = "std")]
+    use crate::archive::FileOptions;
+    use crate::archive_common::ArchiveDescriptor;
+    #[cfg(feature = "std")]
+    use crate::compress::std::archive::ZipArchive;
+    #[cfg(feature = "std")]
+    use crate::compression::CompressionMethod;
+    use crate::constants::{
+        CENTRAL_DIRECTORY_END_SIGNATURE, CENTRAL_DIRECTORY_ENTRY_SIGNATURE,
+        LOCAL_FILE_HEADER_SIGNATURE, ZIP64_EXTRA_FIELD_ID,
+    };
     use crate::error::ArchiveError;
 
-    use super::ArchiveReader;
+    use super::{
+        extract_entry_to_vec, extract_entry_to_vec_with_options, take_zip64_extra_field,
+        ArchiveReader,
+    };
 
     #[test]
     fn test_mem_dump_rust_zip_lib_lzma() -> Result<(), ArchiveError> {
@@ -214,6 +592,142 @@ mod test {
         ArchiveReader::new(buff)?;
         Ok(())
     }
+    #[cfg(feature = "std")]
+    fn build_archive(compressor: CompressionMethod) -> Vec<u8> {
+        let options = FileOptions::default().compression_method(compressor);
+        let mut archive = ZipArchive::new(Vec::new());
+        archive
+            .append_file("first.txt", &mut b"first content".as_ref(), &options)
+            .unwrap();
+        archive
+            .append_file("config.toml", &mut &CONTENT[..], &options)
+            .unwrap();
+        archive.finalize().unwrap().1
+    }
+
+    #[cfg(feature = "std")]
+    const CONTENT: &[u8] = b"[section]\nkey = \"value\"\nkey = \"value\"\n";
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_extract_entry_to_vec() -> Result<(), ArchiveError> {
+        for compressor in [
+            CompressionMethod::Store(),
+            CompressionMethod::Deflate(),
+            #[cfg(feature = "zstd")]
+            CompressionMethod::Zstd(),
+        ] {
+            let archive = build_archive(compressor);
+            assert_eq!(extract_entry_to_vec(&archive, "config.toml")?, CONTENT);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_extract_entry_to_vec_errors() {
+        let mut archive = build_archive(CompressionMethod::Store());
+        assert!(matches!(
+            extract_entry_to_vec(&archive, "missing.txt"),
+            Err(ArchiveError::EntryNotFound(name)) if name == "missing.txt"
+        ));
+
+        let position = archive
+            .windows(CONTENT.len())
+            .position(|w| w == CONTENT)
+            .unwrap();
+        archive[position] = b'_';
+        assert!(matches!(
+            extract_entry_to_vec(&archive, "config.toml"),
+            Err(ArchiveError::CrcMismatch { .. })
+        ));
+
+        // the writer needs the codec, the reader never supports it
+        #[cfg(feature = "bzip2")]
+        {
+            let archive = build_archive(CompressionMethod::BZip2());
+            assert!(matches!(
+                extract_entry_to_vec(&archive, "config.toml"),
+                Err(ArchiveError::UnsuportedCompressionMethod(
+                    CompressionMethod::BZip2()
+                ))
+            ));
+        }
+    }
+
+    /// Archive of a single empty entry, whose compressed size is given by a ZIP64 extra field of
+    /// its central directory record.
+    fn build_zip64_sized_archive(compressed_size: u64) -> Vec<u8> {
+        let mut archive = ArchiveDescriptor::new(128);
+        archive.write_u32(LOCAL_FILE_HEADER_SIGNATURE);
+        archive.write_u16(20); // version needed
+        archive.write_bytes(&[0; 20]); // flags, method, date, time, crc and sizes
+        archive.write_u16(1); // file name length
+        archive.write_u16(0); // extra field length
+        archive.write_str("a");
+
+        let central_directory_offset = archive.len() as u32;
+        archive.write_u32(CENTRAL_DIRECTORY_ENTRY_SIGNATURE);
+        archive.write_u16(45); // version made by
+        archive.write_u16(45); // version needed
+        archive.write_bytes(&[0; 12]); // flags, method, date, time and crc
+        archive.write_u32(u32::MAX); // compressed size, in the ZIP64 extra field
+        archive.write_u32(0); // uncompressed size
+        archive.write_u16(1); // file name length
+        archive.write_u16(12); // extra field length
+        archive.write_bytes(&[0; 14]); // comment length, disk, attributes and offset
+        archive.write_str("a");
+        archive.write_u16(ZIP64_EXTRA_FIELD_ID);
+        archive.write_u16(8);
+        archive.write_u64(compressed_size);
+
+        let central_directory_size = archive.len() as u32 - central_directory_offset;
+        archive.write_u32(CENTRAL_DIRECTORY_END_SIGNATURE);
+        archive.write_bytes(&[0; 4]); // disk numbers
+        archive.write_u16(1);
+        archive.write_u16(1);
+        archive.write_u32(central_directory_size);
+        archive.write_u32(central_directory_offset);
+        archive.write_u16(0); // comment length
+        archive.finish()
+    }
+
+    #[test]
+    fn test_extract_entry_to_vec_crafted_sizes() {
+        assert_eq!(
+            extract_entry_to_vec(&build_zip64_sized_archive(0), "a").unwrap(),
+            b""
+        );
+
+        // the end of the entry data overflows
+        let archive = build_zip64_sized_archive(u64::MAX - 15);
+        let options = ExtractOptions::default().max_output(1 << 20);
+        assert!(matches!(
+            extract_entry_to_vec_with_options(&archive, "a", &options),
+            Err(ArchiveError::BadArchiveStructure(_))
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_archive_comment_round_trip() -> Result<(), ArchiveError> {
+        for comment in ["built by rill", "a comment with PK\x05\x06 inside, é"] {
+            let mut archive = ZipArchive::new(Vec::new());
+            archive.append_file("first.txt", &mut b"first".as_ref(), &FileOptions::default())?;
+            archive.set_archive_comment(comment);
+            let (_, out) = archive.finalize()?;
+
+            let reader = ArchiveReader::new(Cursor::new(&out))?;
+            assert_eq!(reader.comment(), comment.as_bytes());
+            assert_eq!(reader.comment_lossy(), comment);
+            assert_eq!(extract_entry_to_vec(&out, "first.txt")?, b"first");
+        }
+
+        let reader = ArchiveReader::new(Cursor::new(build_archive(CompressionMethod::Store())))?;
+        assert!(reader.comment().is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_file_rust_zip_lib_lzma() -> Result<(), ArchiveError> {
         let p = Path::new("res_test/outi2.zip");
@@ -221,4 +735,33 @@ mod test {
         ArchiveReader::new(f)?;
         Ok(())
     }
+
+    #[test]
+    fn test_take_zip64_extra_field() -> Result<(), ArchiveError> {
+        let five_gib: u64 = 5 << 30;
+        let mut extra_field = vec![0x55, 0x54, 1, 0, 0];
+        extra_field.extend_from_slice(&[0x01, 0x00, 16, 0]);
+        extra_field.extend_from_slice(&five_gib.to_le_bytes());
+        extra_field.extend_from_slice(&(five_gib + 1).to_le_bytes());
+
+        let (mut uncompressed_size, mut compressed_size, mut offset) =
+            (u32::MAX as u64, u32::MAX as u64, 42);
+        take_zip64_extra_field(
+            &mut extra_field,
+            [&mut uncompressed_size, &mut compressed_size, &mut offset],
+        )?;
+        assert_eq!(uncompressed_size, five_gib);
+        assert_eq!(compressed_size, five_gib + 1);
+        assert_eq!(offset, 42);
+        assert_eq!(extra_field, [0x55, 0x54, 1, 0, 0]);
+
+        let mut truncated = vec![0x01, 0x00, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut offset = u32::MAX as u64;
+        let (mut size, mut other_size) = (u32::MAX as u64, 0);
+        assert!(
+            take_zip64_extra_field(&mut truncated, [&mut size, &mut other_size, &mut offset])
+                .is_err()
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,80 @@
+//! Check the payload of an entry against the CRC-32 recorded for it.
+//!
+//! These are sanity checks for tests and tooling, e.g. comparing the source of an entry with the
+//! `ArchiveFileEntry` returned by `EntryWriter::finish`; the archive does not call them.
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::crc::{compute_crc32, Crc32Hasher};
+use crate::error::ArchiveError;
+use crate::types::ArchiveFileEntry;
+
+/// Whether `data` has the CRC-32 recorded for the entry.
+pub fn verify_entry(entry: &ArchiveFileEntry, data: &[u8]) -> bool {
+    compute_crc32(data) == entry.crc32
+}
+
+/// Whether the bytes read from `reader` until EOF have the CRC-32 recorded for the entry.
+///
+/// # Error
+///
+/// `ArchiveError::IoError` if reading fails.
+pub async fn verify_entry_async<R>(
+    entry: &ArchiveFileEntry,
+    reader: &mut R,
+) -> Result<bool, ArchiveError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut hasher = Crc32Hasher::new();
+    let mut buf = vec![0; 4096];
+
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(hasher.finalize() == entry.crc32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::archive::FileOptions;
+    use crate::archive_common::build_file_header;
+    use crate::compression::CompressionMethod;
+
+    fn entry_of(data: &[u8]) -> ArchiveFileEntry {
+        let (_, mut entry) = build_file_header(
+            "file.txt",
+            &FileOptions::default(),
+            CompressionMethod::Store(),
+            0,
+            false,
+            false,
+        );
+        entry.crc32 = compute_crc32(data);
+        entry
+    }
+
+    #[tokio::test]
+    async fn verify_byte_flip() {
+        let data = b"Some string data".to_vec();
+        let entry = entry_of(&data);
+
+        assert!(verify_entry(&entry, &data));
+        assert!(verify_entry_async(&entry, &mut data.as_slice())
+            .await
+            .unwrap());
+
+        let mut flipped = data.clone();
+        flipped[5] ^= 0x01;
+        assert!(!verify_entry(&entry, &flipped));
+        assert!(!verify_entry_async(&entry, &mut flipped.as_slice())
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,43 @@
+#![cfg(feature = "futures")]
+
+use std::path::Path;
+
+use rill::{
+    archive::FileOptions, compress::futures::archive::ZipArchive, compression::CompressionMethod,
+};
+mod common;
+use common::assert_local_header_fields;
+
+#[async_std::test]
+async fn archive_async_std() {
+    let path = Path::new("tests/resources").join("lorem_ipsum.txt");
+
+    let mut archive = ZipArchive::new(Vec::new());
+    for compressor in [
+        CompressionMethod::Store(),
+        CompressionMethod::Deflate(),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd(),
+    ] {
+        let mut in_file = async_std::fs::File::open(&path).await.unwrap();
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(&format!("{}.txt", compressor), &mut in_file, &options)
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+    assert!(archive.is_finalized());
+    let archive_size = archive.get_archive_size();
+
+    let out = archive.retrieve_writer();
+    assert_eq!(out.len() as u64, archive_size);
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        let name = format!("{}.txt", compressor);
+        assert_local_header_fields(&out, &name, true);
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, &name).unwrap(),
+            std::fs::read(&path).unwrap()
+        );
+    }
+}
@@ -1,12 +1,25 @@
+#![cfg(feature = "tokio")]
+
+use std::io::{Cursor, SeekFrom};
 use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::AsyncRead;
 
 use rill::{
-    archive::FileOptions, compress::tokio::archive::ZipArchiveNoStream,
-    compression::CompressionMethod,
+    archive::{ArchiveOptions, FileOptions, HashAlgorithm},
+    compress::tokio::archive::{ZipArchive, ZipArchiveNoStream},
+    compression::{CompressionMethod, Level},
+    error::ArchiveError,
+    types::FileDateTime,
 };
 mod common;
-use common::out_file_name;
 use common::tokio::create_new_clean_file;
+use common::{
+    assert_empty_archive, assert_empty_entries, assert_local_header_fields,
+    central_directory_entry, central_directory_names, central_directory_records, empty_entry_name,
+    out_file_name, LocalHeader, EMPTY_ENTRY_METHODS,
+};
 const TEST_ID: &str = "NE";
 const FILE_TO_COMPRESS: &str = "short_text_file.txt";
 
@@ -46,6 +59,7 @@ async fn archive_structure_zlib_deflate_tokio() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "bzip2")]
 #[tokio::test]
 async fn archive_structure_compress_bzip() {
     let compressor = CompressionMethod::BZip2();
@@ -54,6 +68,7 @@ async fn archive_structure_compress_bzip() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "xz")]
 #[tokio::test]
 async fn archive_structure_compress_lzma() {
     let compressor = CompressionMethod::Lzma();
@@ -62,6 +77,7 @@ async fn archive_structure_compress_lzma() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "zstd")]
 #[tokio::test]
 async fn archive_structure_compress_zstd() {
     let compressor = CompressionMethod::Zstd();
@@ -70,6 +86,7 @@ async fn archive_structure_compress_zstd() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "xz")]
 #[tokio::test]
 async fn archive_structure_compress_xz() {
     let compressor = CompressionMethod::Xz();
@@ -77,3 +94,786 @@ async fn archive_structure_compress_xz() {
 
     compress_file(compressor, &out_file_name).await;
 }
+
+#[tokio::test]
+async fn archive_from_existing() {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    archive
+        .append_file("file1.txt", &mut b"first".as_ref(), &options)
+        .await
+        .unwrap();
+    archive
+        .append_file("file2.txt", &mut b"second".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let mut archive = ZipArchiveNoStream::from_existing(archive.retrieve_writer())
+        .await
+        .unwrap();
+    archive
+        .append_file("file3.txt", &mut b"third".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let archive_size = archive.get_archive_size();
+    let out = archive.retrieve_writer().into_inner();
+    assert_eq!(out.len() as u64, archive_size);
+
+    assert_eq!(
+        central_directory_names(&out),
+        ["file1.txt", "file2.txt", "file3.txt"]
+    );
+}
+
+#[tokio::test]
+async fn archive_strict() -> Result<(), ArchiveError> {
+    let strict = ArchiveOptions::default().strict(true);
+    let mut archive =
+        ZipArchiveNoStream::with_options(std::io::Cursor::new(Vec::new()), strict.clone());
+    for compressor in [
+        CompressionMethod::Store(),
+        CompressionMethod::Deflate(),
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZip2(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Lzma(),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Xz(),
+    ] {
+        let options = FileOptions::default()
+            .compression_method(compressor)
+            .with_unix_timestamps(Some(1_700_000_000), Some(1_700_000_001), None);
+        archive
+            .append_file(
+                &format!("{}é.txt", compressor),
+                &mut b"data".as_ref(),
+                &options,
+            )
+            .await?;
+    }
+    archive
+        .append_file("empty.txt", &mut b"".as_ref(), &FileOptions::default())
+        .await?;
+    archive.finalize().await?;
+
+    // a deflated entry claiming to be extractable by version 1.0 tools
+    let mut out = archive.retrieve_writer().into_inner();
+    let (deflate_entry, _) = central_directory_records(&out)
+        .into_iter()
+        .find(|(_, record)| record.file_name == "deflateé.txt".as_bytes())
+        .unwrap();
+    out[deflate_entry + 6..deflate_entry + 8].copy_from_slice(&10u16.to_le_bytes());
+
+    let mut archive =
+        ZipArchiveNoStream::from_existing_with_options(std::io::Cursor::new(out), strict).await?;
+    archive
+        .append_file("new.txt", &mut b"new".as_ref(), &FileOptions::default())
+        .await?;
+    let error = archive.finalize().await.unwrap_err();
+    assert!(
+        matches!(error.root_cause(), ArchiveError::SpecViolation { detail } if detail.contains("version needed")),
+        "{}",
+        error
+    );
+    assert!(error.to_string().contains("deflateé.txt"), "{}", error);
+    Ok(())
+}
+
+#[tokio::test]
+async fn archive_empty() {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    archive.finalize().await.unwrap();
+
+    assert_eq!(archive.get_archive_size(), 22);
+    assert_empty_archive(archive.retrieve_writer().get_ref());
+}
+
+#[tokio::test]
+async fn archive_empty_entries() {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    for &compressor in EMPTY_ENTRY_METHODS {
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(
+                &empty_entry_name(compressor),
+                &mut tokio::io::empty(),
+                &options,
+            )
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer().into_inner();
+    assert_empty_entries(&out);
+}
+
+#[tokio::test]
+async fn archive_recompress_entry() {
+    let text = "Some string data, repeated. ".repeat(16);
+    let store = FileOptions::default().compression_method(CompressionMethod::Store());
+
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    for name in ["first.txt", "second.txt"] {
+        archive
+            .append_file(name, &mut text.as_bytes(), &store)
+            .await
+            .unwrap();
+    }
+    archive
+        .append_file("tiny.txt", &mut b"ab".as_ref(), &store)
+        .await
+        .unwrap();
+    let size = archive.get_archive_size();
+
+    archive
+        .recompress_entry(
+            "first.txt",
+            CompressionMethod::Deflate(),
+            &FileOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    let error = archive
+        .recompress_entry(
+            "tiny.txt",
+            CompressionMethod::Deflate(),
+            &FileOptions::default(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::RecompressedEntryTooLarge { available: 2, .. }
+    ));
+
+    let error = archive
+        .recompress_entry(
+            "missing.txt",
+            CompressionMethod::Deflate(),
+            &FileOptions::default(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ArchiveError::EntryNotFound(_)));
+
+    assert_eq!(archive.get_archive_size(), size);
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer().into_inner();
+    let entry = central_directory_entry(&out, "first.txt");
+    assert_eq!(entry.compression_method, 8);
+    // the local header is patched as well
+    let header = entry.local_header(&out);
+    assert_eq!(header.compression_method, entry.compression_method);
+    assert_eq!(
+        (
+            header.crc32,
+            header.compressed_size,
+            header.uncompressed_size
+        ),
+        (entry.crc32, entry.compressed_size, entry.uncompressed_size)
+    );
+    // the smaller payload ends where the previous one did, behind a padding extra field
+    let (_, raw) = rill::uncompress::raw_entries(&out)
+        .unwrap()
+        .into_iter()
+        .find(|(entry, _)| entry.file_name_as_bytes == b"first.txt")
+        .unwrap();
+    assert_eq!(header.size() + entry.compressed_size as usize, raw.len());
+    assert!(header.extra_field_block(0xa220).is_some());
+    for (name, content) in [
+        ("first.txt", text.as_bytes()),
+        ("second.txt", text.as_bytes()),
+        ("tiny.txt", b"ab".as_ref()),
+    ] {
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, name).unwrap(),
+            content
+        );
+    }
+}
+
+#[tokio::test]
+async fn archive_compression_level() {
+    let path = Path::new("tests/resources").join("lorem_ipsum.txt");
+    let mut sizes = Vec::new();
+    for level in [Level::Fastest, Level::Best] {
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflate())
+            .compression_level(level);
+        let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+        let mut in_file = tokio::fs::File::open(&path).await.unwrap();
+        archive
+            .append_file("lorem_ipsum.txt", &mut in_file, &options)
+            .await
+            .unwrap();
+        sizes.push(archive.get_archive_size());
+    }
+
+    assert!(
+        sizes[1] < sizes[0],
+        "best {} >= fastest {}",
+        sizes[1],
+        sizes[0]
+    );
+}
+
+/// A sparse file holding an empty archive whose central directory starts right below 4 GiB.
+async fn empty_archive_below_4_gib(file_name: &str) -> tokio::fs::File {
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    drop(create_new_clean_file(file_name).await);
+    let path = Path::new("/tmp")
+        .join(common::PACKAGE_NAME)
+        .join("tokio")
+        .join(file_name);
+    let mut file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .await
+        .unwrap();
+
+    let base = u32::MAX as u64 - 100;
+    let mut end_of_central_directory = vec![0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0, 0, 0, 0, 0];
+    end_of_central_directory.extend_from_slice(&0u32.to_le_bytes());
+    end_of_central_directory.extend_from_slice(&(base as u32).to_le_bytes());
+    end_of_central_directory.extend_from_slice(&0u16.to_le_bytes());
+    file.seek(std::io::SeekFrom::Start(base)).await.unwrap();
+    file.write_all(&end_of_central_directory).await.unwrap();
+    file.rewind().await.unwrap();
+    file
+}
+
+#[tokio::test]
+async fn archive_zip64_required() {
+    let file = empty_archive_below_4_gib("test_NE_zip64_required.zip").await;
+    let mut archive = ZipArchiveNoStream::from_existing(file).await.unwrap();
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive
+        .append_file("file1.txt", &mut [b'a'; 200].as_ref(), &options)
+        .await
+        .unwrap();
+
+    let error = archive
+        .append_file("file2.txt", &mut b"second".as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::Zip64Required {
+            field: "local header offset"
+        }
+    ));
+
+    let error = archive.finalize().await.unwrap_err();
+    assert!(matches!(
+        error,
+        ArchiveError::Zip64Required {
+            field: "central directory offset"
+        }
+    ));
+}
+
+#[tokio::test]
+async fn archive_local_header_fields() {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(
+                &format!("{}.txt", compressor),
+                &mut b"Some string data".as_ref(),
+                &options,
+            )
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer().into_inner();
+
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        assert_local_header_fields(&out, &format!("{}.txt", compressor), false);
+    }
+}
+
+/// Seekable in-memory sink holding back its writes until flushed, even across seeks, as some
+/// buffered or remote sinks do.
+#[derive(Default)]
+struct DeferredSink {
+    committed: Vec<u8>,
+    /// Writes not flushed yet, with their position.
+    pending: Vec<(u64, Vec<u8>)>,
+    position: u64,
+}
+
+impl DeferredSink {
+    fn len(&self) -> u64 {
+        self.pending
+            .iter()
+            .map(|(position, bytes)| position + bytes.len() as u64)
+            .fold(self.committed.len() as u64, u64::max)
+    }
+}
+
+impl tokio::io::AsyncWrite for DeferredSink {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        this.pending.push((this.position, buf.to_vec()));
+        this.position += buf.len() as u64;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        let this = self.get_mut();
+        for (position, bytes) in this.pending.drain(..) {
+            let (start, end) = (position as usize, position as usize + bytes.len());
+            if this.committed.len() < end {
+                this.committed.resize(end, 0);
+            }
+            this.committed[start..end].copy_from_slice(&bytes);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl tokio::io::AsyncSeek for DeferredSink {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        let this = self.get_mut();
+        this.position = match position {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => this.position.checked_add_signed(offset).unwrap(),
+            SeekFrom::End(offset) => this.len().checked_add_signed(offset).unwrap(),
+        };
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
+    }
+}
+
+#[tokio::test]
+async fn archive_deferred_sink_patch_committed() {
+    let mut archive = ZipArchiveNoStream::new(DeferredSink::default());
+    let payload = b"Some string data";
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive
+        .append_file("file1.txt", &mut payload.as_ref(), &options)
+        .await
+        .unwrap();
+
+    // the patched local header is flushed to the sink before the archive is finalized
+    let sink = archive.writer_ref();
+    assert!(sink.pending.is_empty(), "writes not flushed");
+    let header = LocalHeader::parse(&sink.committed, 0);
+    assert_eq!(header.crc32, crc32fast::hash(payload), "local header CRC");
+    assert_eq!(
+        header.compressed_size,
+        payload.len() as u32,
+        "local header compressed size"
+    );
+    assert_eq!(
+        header.uncompressed_size,
+        payload.len() as u32,
+        "local header uncompressed size"
+    );
+}
+
+#[tokio::test]
+async fn archive_append_file_buf() {
+    let lorem = std::fs::read(Path::new("tests/resources").join("lorem_ipsum.txt")).unwrap();
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    // a byte slice is its own buffer
+    let crc32 = archive
+        .append_file_buf("lorem_ipsum.txt", &mut lorem.as_slice(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+    assert_eq!(crc32, crc32fast::hash(&lorem));
+
+    let out = archive.retrieve_writer().into_inner();
+    let entry = central_directory_entry(&out, "lorem_ipsum.txt");
+    assert_eq!(entry.crc32, crc32);
+    assert_eq!(entry.uncompressed_size, lorem.len() as u32);
+}
+
+/// Seekable in-memory sink failing once the write position reaches `fail_at`, or on the query of
+/// its position after `position_queries_before_failure` successful ones.
+#[derive(Default)]
+struct FlakySink {
+    inner: Cursor<Vec<u8>>,
+    fail_at: Option<u64>,
+    position_queries_before_failure: Option<usize>,
+}
+
+impl tokio::io::AsyncWrite for FlakySink {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        let position = this.inner.position();
+        match this.fail_at {
+            Some(fail_at) if position >= fail_at => {
+                this.fail_at = None;
+                Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()))
+            }
+            // write up to the failure point first
+            Some(fail_at) => {
+                let len = buf.len().min((fail_at - position) as usize);
+                Pin::new(&mut this.inner).poll_write(cx, &buf[..len])
+            }
+            None => Pin::new(&mut this.inner).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl tokio::io::AsyncSeek for FlakySink {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        let this = self.get_mut();
+        if position == SeekFrom::Current(0) {
+            match this.position_queries_before_failure {
+                Some(0) => {
+                    this.position_queries_before_failure = None;
+                    return Err(std::io::ErrorKind::BrokenPipe.into());
+                }
+                Some(ref mut count) => *count -= 1,
+                None => {}
+            }
+        }
+        Pin::new(&mut this.inner).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Pin::new(&mut self.get_mut().inner).poll_complete(cx)
+    }
+}
+
+#[tokio::test]
+async fn archive_finalize_retry() {
+    let options = FileOptions::default().last_modified_time(FileDateTime::Zero);
+    let build = |sink| async {
+        let mut archive = ZipArchiveNoStream::new(sink);
+        for name in ["file1.txt", "file2.txt", "file3.txt"] {
+            archive
+                .append_file(name, &mut b"Some string data".as_ref(), &options)
+                .await
+                .unwrap();
+        }
+        archive
+    };
+
+    let mut expected = build(FlakySink::default()).await;
+    expected.finalize().await.unwrap();
+    let expected = expected.retrieve_writer().inner.into_inner();
+
+    let mut archive = build(FlakySink::default()).await;
+    // fail after the first central directory record
+    archive.writer_mut().fail_at = Some(archive.get_archive_size() + 60);
+    let error = archive.finalize().await.unwrap_err();
+    assert!(matches!(error, ArchiveError::IoError(_)));
+    assert!(!archive.is_finalized());
+
+    archive.finalize().await.unwrap();
+    assert!(archive.is_finalized());
+    assert_eq!(archive.get_archive_size(), expected.len() as u64);
+    // finalizing again writes the same bytes over
+    archive.finalize().await.unwrap();
+
+    // a finalized archive takes no more entries
+    let error = archive
+        .append_file("late.txt", &mut b"late".as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(error.root_cause(), ArchiveError::ArchiveFinalized));
+    let error = archive
+        .replace_last_entry(&mut b"late".as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(error.root_cause(), ArchiveError::ArchiveFinalized));
+    assert_eq!(archive.get_archive_size(), expected.len() as u64);
+
+    // a failing finalize leaves the archive unfinalized until retried
+    archive.writer_mut().fail_at = Some(archive.get_archive_size() - 1);
+    archive.finalize().await.unwrap_err();
+    assert!(!archive.is_finalized());
+    archive.finalize().await.unwrap();
+    assert!(archive.is_finalized());
+    assert_eq!(archive.retrieve_writer().inner.into_inner(), expected);
+}
+
+#[tokio::test]
+async fn archive_replace_last_entry_shrink() {
+    let mut archive = ZipArchiveNoStream::new(Cursor::new(Vec::new()));
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive
+        .append_file("file1.txt", &mut b"first".as_ref(), &options)
+        .await
+        .unwrap();
+    let large = vec![b'a'; 200_000];
+    archive
+        .append_file("file2.txt", &mut large.as_slice(), &options)
+        .await
+        .unwrap();
+    archive
+        .replace_last_entry(&mut b"right".as_ref(), &options)
+        .await
+        .unwrap();
+
+    // the tail of the large entry is zero-filled before the central directory, the end records
+    // being the last bytes of the sink
+    archive.finalize().await.unwrap();
+    let archive_size = archive.get_archive_size();
+    let out = archive.retrieve_writer().into_inner();
+    assert_eq!(out.len() as u64, archive_size);
+
+    assert_eq!(central_directory_names(&out), ["file1.txt", "file2.txt"]);
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file2.txt").unwrap(),
+        b"right"
+    );
+}
+
+#[cfg(feature = "sha2")]
+#[tokio::test]
+async fn archive_finalize_retry_sha256_manifest() {
+    let options = ArchiveOptions::default().sha256_manifest(true);
+    let mut archive = ZipArchiveNoStream::with_options(FlakySink::default(), options);
+    archive
+        .append_file(
+            "file1.txt",
+            &mut b"Some string data".as_ref(),
+            &FileOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    // the manifest entry queries the position twice, then finalize fails on the next query,
+    // once the manifest is appended
+    archive.writer_mut().position_queries_before_failure = Some(2);
+    let error = archive.finalize().await.unwrap_err();
+    assert!(matches!(error, ArchiveError::IoError(_)));
+    assert!(!archive.is_finalized());
+
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer().inner.into_inner();
+    assert_eq!(
+        central_directory_names(&out),
+        ["file1.txt", "MANIFEST.sha256"]
+    );
+    assert!(rill::uncompress::extract_entry_to_vec(&out, "MANIFEST.sha256").is_ok());
+}
+
+#[tokio::test]
+async fn archive_add_manifest() {
+    let mut archive = ZipArchiveNoStream::new(Cursor::new(Vec::new()));
+    for (name, method) in [
+        ("file1.txt", CompressionMethod::Store()),
+        ("file2.txt", CompressionMethod::Deflate()),
+    ] {
+        let options = FileOptions::default().compression_method(method);
+        archive
+            .append_file(name, &mut b"abc".as_ref(), &options)
+            .await
+            .unwrap();
+    }
+
+    // the entries are read back from the sink to be hashed
+    let result = archive
+        .add_manifest("META-INF/SHA256.MF", HashAlgorithm::Sha256)
+        .await;
+    #[cfg(feature = "sha2")]
+    result.unwrap();
+    #[cfg(not(feature = "sha2"))]
+    assert!(matches!(
+        result,
+        Err(ArchiveError::DigestUnavailable { ref entry, .. }) if entry == "file1.txt"
+    ));
+    // the next entry is appended where the entries end, not where they were read back
+    archive
+        .append_file("file3.txt", &mut b"abc".as_ref(), &FileOptions::default())
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer().into_inner();
+    #[cfg(feature = "sha2")]
+    {
+        let manifest = rill::uncompress::extract_entry_to_vec(&out, "META-INF/SHA256.MF").unwrap();
+        let digest = "SHA-256-Digest: ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=";
+        assert_eq!(
+            String::from_utf8(manifest).unwrap(),
+            format!(
+                "Manifest-Version: 1.0\r\nCreated-By: rill\r\n\r\nName: file1.txt\r\n{}\r\n\r\nName: file2.txt\r\n{}\r\n\r\n",
+                digest, digest
+            )
+        );
+    }
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file3.txt").unwrap(),
+        b"abc"
+    );
+}
+
+/// Reader failing once its data is read, leaving the entry unpatched.
+struct FailAtEnd<'a>(&'a [u8]);
+
+impl AsyncRead for FailAtEnd<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.0.is_empty() {
+            return Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()));
+        }
+        Pin::new(&mut this.0).poll_read(cx, buf)
+    }
+}
+
+#[tokio::test]
+async fn archive_stored_sizes_in_local_header() {
+    let payload = b"Some string data";
+
+    for method in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        let options = FileOptions::default().compression_method(method);
+        let mut archive = ZipArchiveNoStream::new(Cursor::new(Vec::new()));
+        archive
+            .append_file_sized(
+                "file1.txt",
+                &mut FailAtEnd(payload),
+                payload.len() as u64,
+                &options,
+            )
+            .await
+            .unwrap_err();
+
+        // the header as written before the payload, the CRC not patched yet
+        let header = LocalHeader::parse(archive.writer_ref().get_ref(), 0);
+        assert_eq!(header.crc32, 0, "{} CRC", method);
+        let size = match method {
+            CompressionMethod::Store() => payload.len() as u32,
+            _ => 0,
+        };
+        assert_eq!(header.compressed_size, size, "{} compressed size", method);
+        assert_eq!(
+            header.uncompressed_size, size,
+            "{} uncompressed size",
+            method
+        );
+    }
+
+    // a complete entry has its CRC patched in
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    let mut archive = ZipArchiveNoStream::new(Cursor::new(Vec::new()));
+    archive
+        .append_file_sized(
+            "file1.txt",
+            &mut payload.as_ref(),
+            payload.len() as u64,
+            &options,
+        )
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer().into_inner();
+    let header = LocalHeader::parse(&out, 0);
+    assert_eq!(header.crc32, crc32fast::hash(payload));
+    assert_eq!(header.compressed_size, payload.len() as u32);
+    assert_eq!(header.uncompressed_size, payload.len() as u32);
+}
+
+#[tokio::test]
+async fn archive_estimate_central_directory_size() {
+    let mut archive = ZipArchiveNoStream::new(Cursor::new(Vec::new()));
+    assert_eq!(archive.estimate_central_directory_size(), 22);
+
+    let timestamped = FileOptions::default().with_unix_timestamps(Some(1_700_000_000), None, None);
+    for i in 0..20 {
+        let name = format!("{}/file{}.txt", "dir".repeat(i % 5 + 1), i);
+        let options = if i % 2 == 0 {
+            FileOptions::default()
+        } else {
+            timestamped.clone()
+        };
+        archive
+            .append_file(&name, &mut b"Some string data".as_ref(), &options)
+            .await
+            .unwrap();
+    }
+    archive.set_archive_comment("archive comment");
+
+    let estimate = archive.estimate_central_directory_size();
+    let before = archive.get_archive_size();
+    archive.finalize().await.unwrap();
+    assert_eq!(archive.get_archive_size() - before, estimate);
+}
+
+#[tokio::test]
+async fn archive_spanning_marker_after_prefix() {
+    const STUB_SIZE: usize = 64;
+    let mut empty = ZipArchive::with_base_offset(vec![b'#'; STUB_SIZE], STUB_SIZE as u64);
+    empty.finalize().await.unwrap();
+
+    // the marker goes before the first entry, wherever the archive starts
+    let options = ArchiveOptions::default().spanning_marker(true);
+    let mut archive = ZipArchiveNoStream::from_existing_with_options(
+        Cursor::new(empty.retrieve_writer()),
+        options,
+    )
+    .await
+    .unwrap();
+    archive
+        .append_file(
+            "file1.txt",
+            &mut b"Some string data".as_ref(),
+            &FileOptions::default(),
+        )
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer().into_inner();
+
+    assert_eq!(&out[STUB_SIZE..STUB_SIZE + 4], b"PK00");
+    let entry = central_directory_entry(&out, "file1.txt");
+    assert_eq!(entry.local_header_offset, STUB_SIZE as u32 + 4);
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file1.txt").unwrap(),
+        b"Some string data"
+    );
+}
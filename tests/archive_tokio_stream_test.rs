@@ -1,11 +1,21 @@
+#![cfg(feature = "tokio")]
+
 use std::path::Path;
 
+use rill::error::ArchiveError;
 use rill::{
-    archive::FileOptions, compress::tokio::archive::ZipArchive, compression::CompressionMethod,
+    archive::{ArchiveOptions, FileOptions, HashAlgorithm, PlannedEntry},
+    compress::tokio::archive::ZipArchive,
+    compression::{CompressionMethod, Level},
+    types::{DateTimeCS, FileDateTime},
 };
 mod common;
-use common::out_file_name;
 use common::tokio::create_new_clean_file;
+use common::{
+    assert_empty_archive, assert_empty_entries, assert_empty_entry, assert_local_header_fields,
+    central_directory_entry, central_directory_names, empty_entry_name, out_file_name,
+    CentralDirectoryRecord, EMPTY_ENTRY_METHODS,
+};
 
 const TEST_ID: &str = "1";
 const FILE_TO_COMPRESS: &str = "file1.txt";
@@ -45,6 +55,7 @@ async fn archive_structure_zlib_deflate_tokio() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "bzip2")]
 #[tokio::test]
 async fn archive_structure_compress_bzip() {
     let compressor = CompressionMethod::BZip2();
@@ -53,6 +64,7 @@ async fn archive_structure_compress_bzip() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "xz")]
 #[tokio::test]
 async fn archive_structure_compress_lzma() {
     let compressor = CompressionMethod::Lzma();
@@ -61,6 +73,7 @@ async fn archive_structure_compress_lzma() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "zstd")]
 #[tokio::test]
 async fn archive_structure_compress_zstd() {
     let compressor = CompressionMethod::Zstd();
@@ -69,6 +82,7 @@ async fn archive_structure_compress_zstd() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "xz")]
 #[tokio::test]
 async fn archive_structure_compress_xz() {
     let compressor = CompressionMethod::Xz();
@@ -76,3 +90,2097 @@ async fn archive_structure_compress_xz() {
 
     compress_file(compressor, &out_file_name).await;
 }
+
+struct FullSink;
+
+impl tokio::io::AsyncWrite for FullSink {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        _buf: &[u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        std::task::Poll::Ready(Ok(0))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn archive_full_sink() {
+    let mut archive = ZipArchive::new(FullSink);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    let result = archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await;
+
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error.root_cause(), ArchiveError::WriteZero(e) if e.kind() == std::io::ErrorKind::WriteZero)
+    );
+    assert!(matches!(error, ArchiveError::Entry { name, offset: 0, .. } if name == "file1.txt"));
+}
+
+#[tokio::test]
+async fn archive_with_buffer_size() {
+    let mut archive = ZipArchive::with_buffer_size(Vec::new(), 64);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let archive_size = archive.get_archive_size();
+    let out = archive.retrieve_writer().into_inner();
+    assert_eq!(out.len() as u64, archive_size);
+}
+
+struct BlockingOnceSink {
+    blocked: bool,
+    out: Vec<u8>,
+}
+
+impl tokio::io::AsyncWrite for BlockingOnceSink {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        let sink = self.get_mut();
+        if !sink.blocked {
+            sink.blocked = true;
+            return std::task::Poll::Ready(Err(std::io::ErrorKind::WouldBlock.into()));
+        }
+        sink.out.extend_from_slice(buf);
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn archive_would_block_sink() {
+    let sink = BlockingOnceSink {
+        blocked: false,
+        out: Vec::new(),
+    };
+    let mut archive = ZipArchive::new(sink);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let archive_size = archive.get_archive_size();
+    assert_eq!(archive.retrieve_writer().out.len() as u64, archive_size);
+}
+
+/// Sink reporting `WouldBlock` as an error, without registering the waker, until `until`.
+struct WouldBlockUntilSink {
+    until: std::time::Instant,
+    polls: usize,
+    out: Vec<u8>,
+}
+
+impl tokio::io::AsyncWrite for WouldBlockUntilSink {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        let sink = self.get_mut();
+        sink.polls += 1;
+        if std::time::Instant::now() < sink.until {
+            return std::task::Poll::Ready(Err(std::io::ErrorKind::WouldBlock.into()));
+        }
+        sink.out.extend_from_slice(buf);
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn archive_would_block_sink_backs_off() {
+    let sink = WouldBlockUntilSink {
+        until: std::time::Instant::now() + std::time::Duration::from_millis(50),
+        polls: 0,
+        out: Vec::new(),
+    };
+    let mut archive = ZipArchive::new(sink);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    // with delays doubling from 1 ms, 50 ms of blocking take a handful of polls, not a busy loop
+    let sink = archive.retrieve_writer();
+    assert!(sink.polls < 20, "{} polls", sink.polls);
+    assert!(!sink.out.is_empty());
+}
+
+/// Sink failing with `Interrupted` on its first writes.
+struct InterruptedSink {
+    interruptions: usize,
+    out: Vec<u8>,
+}
+
+impl tokio::io::AsyncWrite for InterruptedSink {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        let sink = self.get_mut();
+        if sink.interruptions > 0 {
+            sink.interruptions -= 1;
+            return std::task::Poll::Ready(Err(std::io::ErrorKind::Interrupted.into()));
+        }
+        sink.out.extend_from_slice(buf);
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn archive_interrupted_sink() {
+    let sink = InterruptedSink {
+        interruptions: 3,
+        out: Vec::new(),
+    };
+    let mut archive = ZipArchive::new(sink);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let archive_size = archive.get_archive_size();
+    let out = archive.retrieve_writer().out;
+    assert_eq!(out.len() as u64, archive_size);
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file1.txt").unwrap(),
+        b"Some string data"
+    );
+}
+
+#[tokio::test]
+async fn archive_always_interrupted_sink() {
+    let sink = InterruptedSink {
+        interruptions: usize::MAX,
+        out: Vec::new(),
+    };
+    let mut archive = ZipArchive::new(sink);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    let error = archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error.root_cause(), ArchiveError::IoError(e) if e.kind() == std::io::ErrorKind::Interrupted)
+    );
+}
+
+#[tokio::test]
+async fn archive_progress() {
+    let content = tokio::fs::read("tests/resources/file1.txt").await.unwrap();
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(32);
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Store())
+        .with_progress_sink(sender);
+    archive
+        .append_file_with_progress("file1.txt", &mut std::io::Cursor::new(content), &options)
+        .await
+        .unwrap();
+    drop(options);
+
+    let mut progress = Vec::new();
+    while let Some(percentage) = receiver.recv().await {
+        progress.push(percentage);
+    }
+
+    assert!(progress.windows(2).all(|w| w[0] < w[1]));
+    assert!(progress.len() > 2);
+    assert_eq!(progress.last(), Some(&100));
+}
+
+/// Reader of an entry payload, counting the entries read to the end.
+struct CountedReader {
+    data: &'static [u8],
+    read_entries: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl tokio::io::AsyncRead for CountedReader {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        let reader = self.get_mut();
+        if reader.data.is_empty() {
+            reader
+                .read_entries
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        } else {
+            let len = reader.data.len().min(buf.remaining());
+            buf.put_slice(&reader.data[..len]);
+            reader.data = &reader.data[len..];
+        }
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn archive_from_stream() {
+    use futures_util::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let read_entries = Arc::new(AtomicUsize::new(0));
+    let max_in_flight = Arc::new(AtomicUsize::new(0));
+
+    let entries = futures_util::stream::iter(0..5).map({
+        let read_entries = read_entries.clone();
+        let max_in_flight = max_in_flight.clone();
+        move |index| {
+            // entries pulled from the stream and not yet read to the end, this one included
+            let in_flight = index + 1 - read_entries.load(Ordering::SeqCst);
+            max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            let reader = CountedReader {
+                data: b"Some string data",
+                read_entries: read_entries.clone(),
+            };
+            (format!("file{}.txt", index), reader)
+        }
+    });
+
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    archive.append_from_stream(entries, &options).await.unwrap();
+
+    // the next entry is only pulled once the previous one is written
+    assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
+
+    let out = archive.retrieve_writer();
+    let entry_count = out
+        .windows(4)
+        .filter(|w| w == &[0x50, 0x4b, 0x01, 0x02])
+        .count();
+    assert_eq!(entry_count, 5);
+}
+
+#[tokio::test]
+async fn archive_concurrent() {
+    let options = ArchiveOptions::default().max_concurrent_compressions(2);
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+
+    let entries = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|name| {
+            let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+            (
+                name.to_string(),
+                std::io::Cursor::new(name.repeat(100)),
+                options,
+            )
+        })
+        .collect();
+
+    archive.append_file_concurrent(entries).await.unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    assert_eq!(central_directory_names(&out), ["a.txt", "b.txt", "c.txt"]);
+}
+
+/// Reader failing at once, or never ready, flagging when it is dropped.
+struct StalledReader {
+    fail: bool,
+    dropped: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl tokio::io::AsyncRead for StalledReader {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        _buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        if self.fail {
+            std::task::Poll::Ready(Err(std::io::Error::other("read failure")))
+        } else {
+            std::task::Poll::Pending
+        }
+    }
+}
+
+impl Drop for StalledReader {
+    fn drop(&mut self) {
+        self.dropped
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+#[tokio::test]
+async fn archive_concurrent_aborts_on_error() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let options = ArchiveOptions::default().max_concurrent_compressions(2);
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+
+    let stalled_dropped = Arc::new(AtomicBool::new(false));
+    let options = FileOptions::default();
+    let entries = vec![
+        (
+            "failing.txt".to_owned(),
+            StalledReader {
+                fail: true,
+                dropped: Arc::new(AtomicBool::new(false)),
+            },
+            options.clone(),
+        ),
+        (
+            "stalled.txt".to_owned(),
+            StalledReader {
+                fail: false,
+                dropped: stalled_dropped.clone(),
+            },
+            options,
+        ),
+    ];
+
+    let error = archive.append_file_concurrent(entries).await.unwrap_err();
+    assert!(matches!(error, ArchiveError::Entry { name, .. } if name == "failing.txt"));
+
+    // the task compressing the stalled entry is aborted, dropping its reader
+    for _ in 0..100 {
+        if stalled_dropped.load(Ordering::SeqCst) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+    }
+    assert!(stalled_dropped.load(Ordering::SeqCst));
+}
+
+#[cfg(feature = "sha2")]
+#[tokio::test]
+async fn archive_sha256_manifest() {
+    let options = ArchiveOptions::default().sha256_manifest(true);
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive
+        .append_file("file1.txt", &mut b"abc".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    assert_eq!(
+        central_directory_names(&out),
+        ["file1.txt", "MANIFEST.sha256"]
+    );
+}
+
+#[cfg(feature = "sha2")]
+#[tokio::test]
+async fn archive_sha256_manifest_entry() {
+    let manifest_options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    let options = ArchiveOptions::default()
+        .sha256_manifest(true)
+        .sha256_manifest_entry("META-INF/SHA256SUMS", manifest_options);
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive
+        .append_file("file1.txt", &mut b"abc".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    let entries: Vec<_> = common::central_directory_records(&out)
+        .into_iter()
+        .map(|(_, record)| record)
+        .collect();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].compression_method, 0);
+    assert_eq!(entries[1].compression_method, 8);
+    assert_eq!(entries[1].file_name, b"META-INF/SHA256SUMS");
+}
+
+#[tokio::test]
+async fn archive_add_manifest() {
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file("file1.txt", &mut b"abc".as_ref(), &FileOptions::default())
+        .await
+        .unwrap();
+
+    let error = archive
+        .add_manifest("META-INF/MANIFEST.MF", HashAlgorithm::Sha256)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ArchiveError::DigestUnavailable {
+            algorithm: HashAlgorithm::Sha256,
+            ref entry,
+        } if entry == "file1.txt"
+    ));
+
+    archive
+        .add_manifest("META-INF/MANIFEST.MF", HashAlgorithm::Crc32)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    let manifest = rill::uncompress::extract_entry_to_vec(&out, "META-INF/MANIFEST.MF").unwrap();
+    assert_eq!(
+        manifest,
+        b"Manifest-Version: 1.0\r\nCreated-By: rill\r\n\r\nName: file1.txt\r\nCRC-32-Digest: NSRBwg==\r\n\r\n"
+    );
+}
+
+#[cfg(feature = "signature")]
+#[tokio::test]
+async fn archive_digital_signature() {
+    use p256::ecdsa::{signature::DigestVerifier, Signature, SigningKey};
+    use sha2::{Digest, Sha256};
+
+    let private_key = [0x2a; 32];
+    let mut archive = ZipArchive::new(Vec::new());
+    assert!(matches!(
+        archive.sign(&[0; 32]),
+        Err(ArchiveError::InvalidSigningKey)
+    ));
+    archive.sign(&private_key).unwrap();
+    for name in ["file1.txt", "file2.txt"] {
+        archive
+            .append_file(
+                name,
+                &mut b"Some string data".as_ref(),
+                &FileOptions::default(),
+            )
+            .await
+            .unwrap();
+    }
+    assert!(archive.sign(&private_key).is_err());
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer();
+
+    // the record closes the central directory, after the last entry
+    let end = out.len() - 22;
+    let u32_at = |offset: usize| u32::from_le_bytes(out[offset..offset + 4].try_into().unwrap());
+    let central_directory_end = u32_at(end + 16) as usize + u32_at(end + 12) as usize;
+    assert_eq!(central_directory_end, end);
+    let (position, entry) = common::central_directory_records(&out).pop().unwrap();
+    assert_eq!(entry.file_name, b"file2.txt");
+    let record = position + entry.size();
+    assert_eq!(u32_at(record), 0x05054b50);
+    let signature_size = u16::from_le_bytes([out[record + 4], out[record + 5]]) as usize;
+    assert_eq!(record + 6 + signature_size, end);
+    let signature = Signature::from_der(&out[record + 6..end]).unwrap();
+
+    let verifying_key = *SigningKey::from_slice(&private_key)
+        .unwrap()
+        .verifying_key();
+    let digest = |bytes: &[u8]| Sha256::new_with_prefix(bytes);
+    verifying_key
+        .verify_digest(digest(&out[..record]), &signature)
+        .unwrap();
+
+    let mut tampered = out[..record].to_vec();
+    tampered[40] ^= 0x01;
+    assert!(verifying_key
+        .verify_digest(digest(&tampered), &signature)
+        .is_err());
+    assert!(rill::uncompress::ArchiveReader::new(std::io::Cursor::new(out)).is_ok());
+}
+
+#[cfg(feature = "stats")]
+#[tokio::test]
+async fn archive_stats_callback() {
+    use rill::archive::EntryStats;
+    use std::sync::{Arc, Mutex};
+
+    let stats: Arc<Mutex<Vec<EntryStats>>> = Default::default();
+    let recorded = stats.clone();
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflate())
+        .with_stats_callback(Arc::new(move |entry| recorded.lock().unwrap().push(entry)));
+
+    let mut archive = ZipArchive::new(Vec::new());
+    let payload = "compressible ".repeat(1000);
+    archive
+        .append_file("file1.txt", &mut payload.as_bytes(), &options)
+        .await
+        .unwrap();
+    archive
+        .append_file(
+            "file2.txt",
+            &mut b"stored".as_ref(),
+            &FileOptions::default().compression_method(CompressionMethod::Store()),
+        )
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer();
+
+    let stats = stats.lock().unwrap();
+    assert_eq!(
+        stats.len(),
+        1,
+        "only the entry with the callback is reported"
+    );
+    let entry = central_directory_entry(&out, "file1.txt");
+    assert_eq!(stats[0].name, "file1.txt");
+    assert!(matches!(stats[0].method, CompressionMethod::Deflate()));
+    assert_eq!(stats[0].uncompressed, payload.len() as u64);
+    assert_eq!(stats[0].uncompressed, entry.uncompressed_size as u64);
+    assert_eq!(stats[0].compressed, entry.compressed_size as u64);
+    assert!(stats[0].compressed < stats[0].uncompressed);
+}
+
+#[derive(Default)]
+struct FlushCountingSink {
+    out: Vec<u8>,
+    flush_offsets: Vec<usize>,
+}
+
+impl tokio::io::AsyncWrite for FlushCountingSink {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<Result<usize, std::io::Error>> {
+        self.get_mut().out.extend_from_slice(buf);
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        let sink = self.get_mut();
+        sink.flush_offsets.push(sink.out.len());
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), std::io::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn archive_flush_every() {
+    const FLUSH_EVERY: usize = 64 * 1024;
+    let content = tokio::fs::read("tests/resources/file1.txt").await.unwrap();
+
+    let archive_options = ArchiveOptions::default().flush_every(FLUSH_EVERY as u64);
+    let mut archive = ZipArchive::with_options(FlushCountingSink::default(), archive_options);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive
+        .append_file("file1.txt", &mut content.as_slice(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    // the compressor and finalize flush on their own, on top of the cadence
+    let sink = archive.retrieve_writer();
+    let expected_flushes = sink.out.len() / FLUSH_EVERY;
+    assert!(sink.flush_offsets.len() >= expected_flushes);
+    assert!(sink.flush_offsets.len() <= expected_flushes + 3);
+
+    // at most one write of the compressor (4 KiB) goes past the threshold before the flush
+    let mut last_flush = 0;
+    for offset in sink.flush_offsets {
+        assert!(offset - last_flush < FLUSH_EVERY + 4096);
+        last_flush = offset;
+    }
+}
+
+#[tokio::test]
+async fn archive_cancellable_append() {
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+
+    // the reader never reaches its end: the entry is still being compressed when cancelled
+    let (mut writer, mut reader) = tokio::io::duplex(1024);
+    tokio::io::AsyncWriteExt::write_all(&mut writer, b"partial data")
+        .await
+        .unwrap();
+
+    tokio::select! {
+        _ = archive.append_file_cancellable("cancelled.txt", &mut reader, &options) => {
+            panic!("the reader has no end");
+        }
+        _ = tokio::time::sleep(std::time::Duration::from_millis(20)) => {}
+    }
+    assert_eq!(archive.get_archive_size(), 0);
+
+    archive
+        .append_file_cancellable("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    assert_eq!(central_directory_names(&out), ["file1.txt"]);
+    assert_eq!(&out[0..4], &[0x50, 0x4b, 0x03, 0x04]);
+}
+
+#[tokio::test]
+async fn archive_auto() {
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    let has_data_descriptor = |out: &[u8]| out.windows(4).any(|w| w == [0x50, 0x4b, 0x07, 0x08]);
+
+    let mut archive = ZipArchive::auto(std::io::Cursor::new(Vec::new()));
+    assert!(archive.is_seekable());
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+    let archive_size = archive.get_archive_size();
+    let out = archive.retrieve_writer().into_inner();
+    assert_eq!(out.len() as u64, archive_size);
+    assert!(!has_data_descriptor(&out));
+
+    let (writer, mut reader) = tokio::io::duplex(64 * 1024);
+    let mut archive = ZipArchive::auto(writer);
+    assert!(!archive.is_seekable());
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+    drop(archive.retrieve_writer());
+
+    let mut out = Vec::new();
+    tokio::io::AsyncReadExt::read_to_end(&mut reader, &mut out)
+        .await
+        .unwrap();
+    assert!(has_data_descriptor(&out));
+}
+
+#[tokio::test]
+async fn archive_append_file_is_lazy() {
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+
+    let mut reader: &[u8] = b"never written";
+    let pending = archive.append_file("never.txt", &mut reader, &options);
+    drop(pending);
+    assert_eq!(archive.get_archive_size(), 0);
+
+    for i in 0..10 {
+        let file_name = format!("file{}.txt", i);
+        let mut reader: &[u8] = b"Some string data";
+        let entry = archive.append_file(&file_name, &mut reader, &options);
+        entry.await.unwrap();
+    }
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    let expected: Vec<String> = (0..10).map(|i| format!("file{}.txt", i)).collect();
+    assert_eq!(central_directory_names(&out), expected);
+}
+
+#[tokio::test]
+async fn archive_invalid_compression_level() {
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Store())
+        .compression_level(Level::Best);
+    assert!(options.validate().is_err());
+
+    let error = archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::UnsuportedCompressionLevel(CompressionMethod::Store())
+    ));
+    assert_eq!(archive.get_archive_size(), 0);
+
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflate())
+        .compression_level(Level::Precise(15));
+    let error = archive
+        .append_file_cancellable("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::UnsuportedCompressionLevel(CompressionMethod::Deflate())
+    ));
+    assert_eq!(archive.get_archive_size(), 0);
+}
+
+#[tokio::test]
+async fn archive_level_none_stores() {
+    use tokio::io::AsyncWriteExt;
+
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default()
+        .compression_method(CompressionMethod::Deflate())
+        .compression_level(Level::None);
+    assert!(matches!(
+        options.effective_compressor(),
+        CompressionMethod::Store()
+    ));
+
+    archive
+        .append_file("appended.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    let mut entry = archive.start_entry("written.txt", &options).await.unwrap();
+    entry.write_all(b"Some string data").await.unwrap();
+    entry.shutdown().await.unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    for (entry, _) in rill::uncompress::raw_entries(&out).unwrap() {
+        assert_eq!(entry.compression_method, 0);
+        assert!(matches!(entry.compressor(), CompressionMethod::Store()));
+        assert_eq!(entry.compression_level(), Level::None);
+        assert_eq!(entry.compressed_size, entry.uncompressed_size);
+    }
+    for name in ["appended.txt", "written.txt"] {
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, name).unwrap(),
+            b"Some string data"
+        );
+        assert_eq!(central_directory_entry(&out, name).compression_method, 0);
+    }
+}
+
+#[tokio::test]
+async fn archive_append_file_owned() {
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let path = Path::new("tests/resources").join(FILE_TO_COMPRESS);
+    let in_file = tokio::fs::File::open(&path).await.unwrap();
+    archive
+        .append_file_owned(FILE_TO_COMPRESS, in_file, &FileOptions::default())
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    let content = rill::uncompress::extract_entry_to_vec(&out, FILE_TO_COMPRESS).unwrap();
+    assert_eq!(content, std::fs::read(path).unwrap());
+}
+
+#[tokio::test]
+async fn archive_into_pipe() {
+    use tokio::io::AsyncReadExt;
+
+    let (mut archive, mut reader) = ZipArchive::into_pipe(64);
+
+    let writer = tokio::spawn(async move {
+        let options = FileOptions::default();
+        for i in 0..3 {
+            let file_name = format!("file{}.txt", i);
+            archive
+                .append_file(&file_name, &mut b"Some string data".as_ref(), &options)
+                .await
+                .unwrap();
+        }
+        archive.finalize().await.unwrap();
+    });
+
+    let mut out = Vec::new();
+    reader.read_to_end(&mut out).await.unwrap();
+    writer.await.unwrap();
+
+    for i in 0..3 {
+        let file_name = format!("file{}.txt", i);
+        let content = rill::uncompress::extract_entry_to_vec(&out, &file_name).unwrap();
+        assert_eq!(content, b"Some string data");
+    }
+}
+
+#[tokio::test]
+async fn archive_unknown_method_unsupported() {
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Unknown(1234));
+    let error = archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::UnsuportedCompressionMethod(CompressionMethod::Unknown(1234))
+    ));
+    assert_eq!(archive.get_archive_size(), 0);
+}
+
+#[tokio::test]
+async fn archive_reserve_entry() {
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default();
+
+    let content = b"Some string data, written in several chunks";
+    let mut entry = archive
+        .reserve_entry("chunks.txt", content.len() as u64, &options)
+        .await
+        .unwrap();
+    for chunk in content.chunks(7) {
+        entry.write(chunk).await.unwrap();
+    }
+    let entry = entry.finish().await.unwrap();
+    assert_eq!(entry.uncompressed_size, content.len() as u64);
+    assert_eq!(entry.crc32, rill::crc::compute_crc32(content));
+
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+
+    let mut entry = archive
+        .reserve_entry("short.txt", 10, &options)
+        .await
+        .unwrap();
+    entry.write(b"too short").await.unwrap();
+    let error = entry.finish().await.unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::BadArchiveStructure(_)
+    ));
+
+    let error = archive
+        .reserve_entry("huge.bin", u64::from(u32::MAX) + 1, &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::Zip64Required { .. }
+    ));
+
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "chunks.txt").unwrap(),
+        content
+    );
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file1.txt").unwrap(),
+        b"Some string data"
+    );
+}
+
+#[tokio::test]
+async fn archive_start_entry_copy() {
+    use tokio::io::AsyncWriteExt;
+
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default();
+
+    let path = Path::new("tests/resources").join(FILE_TO_COMPRESS);
+    let mut source = tokio::fs::File::open(&path).await.unwrap();
+    let mut entry = archive.start_entry("copied.txt", &options).await.unwrap();
+    tokio::io::copy(&mut source, &mut entry).await.unwrap();
+    entry.shutdown().await.unwrap();
+
+    let mut entry = archive
+        .start_entry(
+            "stored.txt",
+            &options
+                .clone()
+                .compression_method(CompressionMethod::Store()),
+        )
+        .await
+        .unwrap();
+    entry.write_all(b"Some ").await.unwrap();
+    entry.write_all(b"string data").await.unwrap();
+    let stored = entry.finish().await.unwrap();
+    assert_eq!(stored.uncompressed_size, 16);
+    assert_eq!(stored.compressed_size, 16);
+    assert!(matches!(stored.compressor(), CompressionMethod::Store()));
+    assert_eq!(stored.compression_level(), Level::None);
+
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "copied.txt").unwrap(),
+        std::fs::read(path).unwrap()
+    );
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "stored.txt").unwrap(),
+        b"Some string data"
+    );
+}
+
+#[tokio::test]
+async fn archive_start_entry_copy_every_method() {
+    use tokio::io::AsyncWriteExt;
+
+    // several times the buffer of `tokio::io::copy`
+    let path = Path::new("tests/resources").join("file1.txt");
+    let expected = std::fs::read(&path).unwrap();
+    let methods = [
+        CompressionMethod::Store(),
+        CompressionMethod::Deflate(),
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZip2(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Lzma(),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Xz(),
+    ];
+
+    let mut archive = ZipArchive::new(Vec::new());
+    for method in methods {
+        let options = FileOptions::default().compression_method(method);
+        let mut entry = archive
+            .start_entry(&format!("{}.bin", method), &options)
+            .await
+            .unwrap();
+        let mut source = tokio::fs::File::open(&path).await.unwrap();
+        let copied = tokio::io::copy(&mut source, &mut entry).await.unwrap();
+        entry.shutdown().await.unwrap();
+        assert_eq!(copied, expected.len() as u64);
+
+        // writing past the end of the entry is refused
+        assert!(entry.write_all(b"late").await.is_err());
+    }
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer();
+
+    for method in methods {
+        let entry = central_directory_entry(&out, &format!("{}.bin", method));
+        assert_eq!(entry.compression_method, method.zip_code(), "{}", method);
+        assert_eq!(entry.crc32, crc32fast::hash(&expected));
+        assert_eq!(entry.uncompressed_size, expected.len() as u32);
+    }
+    for method in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, &format!("{}.bin", method)).unwrap(),
+            expected
+        );
+    }
+    assert!(rill::uncompress::ArchiveReader::new(std::io::Cursor::new(out)).is_ok());
+}
+
+#[tokio::test]
+async fn archive_verify_entry() {
+    use rill::verification::{verify_entry, verify_entry_async};
+    use tokio::io::AsyncWriteExt;
+
+    let path = Path::new("tests/resources").join("lorem_ipsum.txt");
+    let mut data = std::fs::read(&path).unwrap();
+
+    let mut archive = ZipArchive::new(Vec::new());
+    let mut entry = archive
+        .start_entry("lorem_ipsum.txt", &FileOptions::default())
+        .await
+        .unwrap();
+    entry.write_all(&data).await.unwrap();
+    let entry = entry.finish().await.unwrap();
+
+    assert!(verify_entry(&entry, &data));
+    let mut file = tokio::fs::File::open(&path).await.unwrap();
+    assert!(verify_entry_async(&entry, &mut file).await.unwrap());
+
+    data[100] ^= 0x80;
+    assert!(!verify_entry(&entry, &data));
+    assert!(!verify_entry_async(&entry, &mut data.as_slice())
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn archive_sink_counter() {
+    let options = FileOptions::default();
+
+    let mut counted = ZipArchive::new(rill::tools::SinkCounter::new());
+    let mut kept = ZipArchive::new(Vec::new());
+    for i in 0..3 {
+        let file_name = format!("file{}.txt", i);
+        let data = file_name.repeat(100);
+        counted
+            .append_file(&file_name, &mut data.as_bytes(), &options)
+            .await
+            .unwrap();
+        kept.append_file(&file_name, &mut data.as_bytes(), &options)
+            .await
+            .unwrap();
+    }
+    counted.finalize().await.unwrap();
+    kept.finalize().await.unwrap();
+
+    let size = kept.retrieve_writer().len() as u64;
+    assert_eq!(counted.get_archive_size(), size);
+    assert_eq!(counted.retrieve_writer().count(), size);
+}
+
+#[tokio::test]
+async fn archive_spanning_marker() {
+    let stored = FileOptions::default().compression_method(CompressionMethod::Store());
+    let options = ArchiveOptions::default().spanning_marker(true);
+
+    let mut archive = ZipArchive::with_options(Vec::new(), options.clone());
+    let planned = [PlannedEntry::new("file1.txt", 16, &stored)];
+    let estimate = archive.estimated_size(&planned).unwrap();
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &stored)
+        .await
+        .unwrap();
+    archive
+        .append_file(
+            "file2.txt",
+            &mut b"Other data".as_ref(),
+            &FileOptions::default(),
+        )
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer();
+
+    assert_eq!(&out[..4], b"PK00");
+    assert_eq!(&out[4..8], &[0x50, 0x4b, 0x03, 0x04]);
+    let entry = central_directory_entry(&out, "file1.txt");
+    assert_eq!(entry.local_header_offset, 4);
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file2.txt").unwrap(),
+        b"Other data"
+    );
+
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+    archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &stored)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+    assert_eq!(archive.get_archive_size(), estimate);
+
+    let mut empty =
+        ZipArchive::with_options(Vec::new(), ArchiveOptions::default().spanning_marker(true));
+    // the marker is written by finalize
+    assert_eq!(empty.estimate_central_directory_size(), 4 + 22);
+    empty.finalize().await.unwrap();
+    let out = empty.retrieve_writer();
+    assert_eq!(&out[..4], b"PK00");
+    assert_eq!(out.len(), 4 + 22);
+}
+
+#[tokio::test]
+async fn archive_estimate_central_directory_size() {
+    for options in [
+        ArchiveOptions::default(),
+        ArchiveOptions::default().zip64(true),
+    ] {
+        let mut archive = ZipArchive::with_options(Vec::new(), options);
+        assert_eq!(archive.estimate_central_directory_size(), 22);
+
+        let timestamped =
+            FileOptions::default().with_unix_timestamps(Some(1_700_000_000), None, None);
+        for i in 0..50 {
+            let name = format!("{}/file{}.txt", "long_directory_name".repeat(i % 7 + 1), i);
+            let options = if i % 2 == 0 {
+                FileOptions::default()
+            } else {
+                timestamped.clone()
+            };
+            archive
+                .append_file(&name, &mut b"Some string data".as_ref(), &options)
+                .await
+                .unwrap();
+        }
+        archive.set_archive_comment("archive comment");
+
+        let estimate = archive.estimate_central_directory_size();
+        let before = archive.get_archive_size();
+        archive.finalize().await.unwrap();
+        assert_eq!(archive.get_archive_size() - before, estimate);
+    }
+}
+
+#[tokio::test]
+async fn archive_append_path_mtime() {
+    use chrono::{Local, TimeZone};
+
+    let path = std::env::temp_dir().join(format!("rill_append_path_{}.txt", std::process::id()));
+    std::fs::write(&path, "Some string data").unwrap();
+    let modified = Local.with_ymd_and_hms(2021, 6, 15, 13, 45, 30).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified.into())
+        .unwrap();
+
+    let mut archive = ZipArchive::new(Vec::new());
+    // the file's modification time takes precedence over the configured one
+    let options = FileOptions::default().last_modified_time(FileDateTime::Now);
+    let crc = archive
+        .append_path("file.txt", &path, &options)
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(crc, crc32fast::hash(b"Some string data"));
+    archive.finalize().await.unwrap();
+
+    let entry = central_directory_entry(archive.writer_ref(), "file.txt");
+    let time = (13 << 11) | (45 << 5) | (30 / 2);
+    let date = ((2021 - 1980) << 9) | (6 << 5) | 15;
+    assert_eq!(entry.last_mod_file_time, time);
+    assert_eq!(entry.last_mod_file_date, date);
+
+    let error = archive
+        .append_path("missing.txt", "tests/resources/missing.txt", &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(error.root_cause(), ArchiveError::IoError(_)));
+}
+
+#[tokio::test]
+async fn archive_utf8_flag() {
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    for name in ["smile_😀.txt", "plain.txt"] {
+        archive
+            .append_file(name, &mut b"data".as_ref(), &options)
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+
+    let flags = |name| central_directory_entry(archive.writer_ref(), name).general_purpose_flags;
+    assert_ne!(flags("smile_😀.txt") & (1 << 11), 0);
+    assert_eq!(flags("plain.txt") & (1 << 11), 0);
+}
+
+#[tokio::test]
+async fn archive_comment() {
+    let mut archive = ZipArchive::new(Vec::new());
+    assert_eq!(archive.comment(), None);
+    assert_eq!(archive.comment_or_empty(), "");
+
+    // too long for the end of central directory record
+    let long_comment = "c".repeat(70_000);
+    assert_eq!(archive.set_archive_comment(&long_comment), 65535);
+    assert_eq!(archive.comment_bytes(), &long_comment.as_bytes()[..65535]);
+
+    // cut before a character straddling the limit, 'é' taking 2 bytes
+    let long_comment = "é".repeat(40_000);
+    assert_eq!(archive.set_archive_comment(&long_comment), 65534);
+    assert_eq!(archive.comment(), Some(&long_comment[..65534]));
+
+    assert_eq!(archive.set_archive_comment("archive comment"), 15);
+    assert_eq!(archive.comment(), Some("archive comment"));
+    assert_eq!(archive.comment_or_empty(), "archive comment");
+    assert_eq!(archive.comment_bytes(), b"archive comment");
+
+    archive.finalize().await.unwrap();
+    assert!(archive.writer_ref().ends_with(b"archive comment"));
+}
+
+#[tokio::test]
+async fn archive_base_offset_self_extracting() {
+    const STUB_SIZE: usize = 1024;
+    let stub = vec![b'#'; STUB_SIZE];
+
+    let mut archive = ZipArchive::with_base_offset(stub, STUB_SIZE as u64);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    for (name, data) in [
+        ("file1.txt", "Some string data"),
+        ("dir/file2.txt", "Other data"),
+    ] {
+        archive
+            .append_file(name, &mut data.as_bytes(), &options)
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+    let size = archive.get_archive_size();
+    let out = archive.retrieve_writer();
+    assert_eq!(out.len() as u64, size);
+
+    let entry = central_directory_entry(&out, "file1.txt");
+    assert_eq!(entry.local_header_offset, STUB_SIZE as u32);
+
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "dir/file2.txt").unwrap(),
+        b"Other data"
+    );
+
+    let path = std::env::temp_dir().join("rill_base_offset_sfx.zip");
+    std::fs::write(&path, &out).unwrap();
+    // unzip exits with 1 and warns about extra bytes when the offsets ignore the prefix
+    let output = match std::process::Command::new("unzip")
+        .arg("-t")
+        .arg(&path)
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("unzip is not installed, skipping the check of the archive by unzip");
+            std::fs::remove_file(&path).unwrap();
+            return;
+        }
+        Err(e) => panic!("unzip failed to run: {}", e),
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "unzip -t failed: {}", stdout);
+    assert!(stdout.contains("No errors detected"), "{}", stdout);
+
+    let output = std::process::Command::new("unzip")
+        .args(["-p".as_ref(), path.as_os_str(), "dir/file2.txt".as_ref()])
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"Other data");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn archive_estimated_size() {
+    let stored = FileOptions::default().compression_method(CompressionMethod::Store());
+    let timestamped =
+        stored
+            .clone()
+            .with_unix_timestamps(Some(1_700_000_000), Some(1_700_000_000), None);
+    let contents: [(&str, Vec<u8>, &FileOptions); 3] = [
+        ("file1.txt", b"Some string data".to_vec(), &stored),
+        ("dir\\héhé.bin", vec![7; 5000], &timestamped),
+        ("empty", Vec::new(), &stored),
+    ];
+
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file("first.txt", &mut b"already there".as_ref(), &stored)
+        .await
+        .unwrap();
+
+    let planned: Vec<PlannedEntry> = contents
+        .iter()
+        .map(|(name, data, options)| PlannedEntry::new(name, data.len() as u64, options))
+        .collect();
+    let estimate = archive.estimated_size(&planned).unwrap();
+
+    let deflated = FileOptions::default();
+    assert_eq!(
+        archive.estimated_size(&[PlannedEntry::new("file.txt", 10, &deflated)]),
+        None
+    );
+
+    for (name, data, options) in &contents {
+        archive
+            .append_file(name, &mut data.as_slice(), options)
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+
+    assert_eq!(archive.retrieve_writer().len() as u64, estimate);
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn archive_append_file_from_url() {
+    const CONTENT: &str = "Some string data, served over HTTP. Some string data, served over HTTP.";
+
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("GET", "/file.txt")
+        .with_body(CONTENT)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/moved")
+        .with_status(301)
+        .with_header("Location", "/file.txt")
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/missing")
+        .with_status(404)
+        .create_async()
+        .await;
+    server
+        .mock("GET", "/broken")
+        .with_chunked_body(|writer| {
+            writer.write_all(CONTENT.as_bytes())?;
+            Err(std::io::Error::other("connection lost"))
+        })
+        .create_async()
+        .await;
+
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default();
+
+    for path in ["file.txt", "moved"] {
+        let entry = archive
+            .append_file_from_url(path, &format!("{}/{}", server.url(), path), &options)
+            .await
+            .unwrap();
+        assert_eq!(entry.uncompressed_size, CONTENT.len() as u64);
+    }
+
+    let archive_size = archive.get_archive_size();
+    let error = archive
+        .append_file_from_url("missing", &format!("{}/missing", server.url()), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(error.root_cause(), ArchiveError::IoError(_)));
+    assert!(error.to_string().contains("HTTP status"));
+
+    // the entry interrupted midway is not written at all
+    let error = archive
+        .append_file_from_url("broken", &format!("{}/broken", server.url()), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(error.root_cause(), ArchiveError::IoError(_)));
+    assert_eq!(archive.get_archive_size(), archive_size);
+
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    for name in ["file.txt", "moved"] {
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, name).unwrap(),
+            CONTENT.as_bytes()
+        );
+    }
+    assert!(rill::uncompress::extract_entry_to_vec(&out, "broken").is_err());
+}
+
+#[cfg(feature = "process")]
+#[tokio::test]
+async fn archive_append_file_from_process_stdout() {
+    use tokio::process::Command;
+
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+
+    let entry = archive
+        .append_file_from_process_stdout(
+            "echo.txt",
+            Command::new("echo").arg("Some string data"),
+            &options,
+        )
+        .await
+        .unwrap();
+    assert_eq!(entry.uncompressed_size, 17);
+
+    let error = archive
+        .append_file_from_process_stdout("false.txt", &mut Command::new("false"), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(error.root_cause(), ArchiveError::IoError(_)));
+
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "echo.txt").unwrap(),
+        b"Some string data\n"
+    );
+}
+
+#[tokio::test]
+async fn archive_writer_ref() {
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file(
+            "file.txt",
+            &mut b"Some string data".as_ref(),
+            &FileOptions::default(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        archive.writer_ref().len() as u64,
+        archive.get_archive_size()
+    );
+    archive.writer_mut().reserve(1024);
+
+    archive.finalize().await.unwrap();
+    assert_eq!(
+        archive.writer_ref().len() as u64,
+        archive.get_archive_size()
+    );
+}
+
+#[tokio::test]
+async fn archive_special_file_modes() {
+    let mut archive = ZipArchive::new(Vec::new());
+    let entries = [
+        ("file.txt", FileOptions::default(), 0o100644),
+        (
+            "script.sh",
+            FileOptions::default().unix_permissions(0o755),
+            0o100755,
+        ),
+        ("fifo", FileOptions::default().unix_mode(0o010644), 0o010644),
+        ("tty", FileOptions::default().unix_mode(0o020620), 0o020620),
+        ("sda", FileOptions::default().unix_mode(0o060660), 0o060660),
+        ("no_type", FileOptions::default().unix_mode(0o600), 0o100600),
+    ];
+    for (name, options, _) in &entries {
+        archive
+            .append_file(name, &mut tokio::io::empty(), options)
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    for (name, _, mode) in entries {
+        let entry = central_directory_entry(&out, name);
+        assert_eq!(
+            entry.external_file_attributes >> 16,
+            mode,
+            "mode of {}",
+            name
+        );
+        assert_empty_entry(&out, name);
+    }
+}
+
+#[tokio::test]
+async fn archive_raw_external_attributes() {
+    let mut archive = ZipArchive::new(Vec::new());
+    let entries = [
+        // MS-DOS read-only and archive bits, no Unix mode
+        (
+            "dos.txt",
+            FileOptions::default().external_attributes(0x21),
+            0x21,
+        ),
+        // takes precedence over the mode
+        (
+            "raw.txt",
+            FileOptions::default()
+                .unix_mode(0o100755)
+                .external_attributes(0x8180_0020),
+            0x8180_0020,
+        ),
+        ("mode.txt", FileOptions::default(), 0o100644 << 16),
+    ];
+    for (name, options, _) in &entries {
+        archive
+            .append_file(name, &mut b"abc".as_ref(), options)
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    for (name, _, attributes) in entries {
+        let entry = central_directory_entry(&out, name);
+        assert_eq!(
+            entry.external_file_attributes, attributes,
+            "external attributes of {}",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn archive_default_options() {
+    let text = "Some string data, repeated. ".repeat(8);
+    let mut archive = ZipArchive::new(Vec::new());
+    archive.set_default_options(
+        FileOptions::default()
+            .compression_method(CompressionMethod::Store())
+            .unix_permissions(0o600),
+    );
+
+    for i in 0..10 {
+        let name = format!("file{}.txt", i);
+        if i == 4 {
+            let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+            archive
+                .append_file(&name, &mut text.as_bytes(), &options)
+                .await
+                .unwrap();
+        } else {
+            archive
+                .append_file_default(&name, &mut text.as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    for i in 0..10 {
+        let name = format!("file{}.txt", i);
+        let entry = central_directory_entry(&out, &name);
+        let method = entry.compression_method;
+        let mode = entry.external_file_attributes >> 16;
+        if i == 4 {
+            assert_eq!((method, mode), (8, 0o100644), "{}", name);
+        } else {
+            assert_eq!((method, mode), (0, 0o100600), "{}", name);
+        }
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, &name).unwrap(),
+            text.as_bytes()
+        );
+    }
+}
+
+/// Reader yielding some bytes, then failing with `ConnectionReset`.
+struct ResetReader {
+    sent: bool,
+}
+
+impl tokio::io::AsyncRead for ResetReader {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<std::io::Result<()>> {
+        if self.sent {
+            return std::task::Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()));
+        }
+        self.sent = true;
+        buf.put_slice(&[b'a'; 1000]);
+        std::task::Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn archive_reader_error_kind() {
+    for compressor in [
+        CompressionMethod::Store(),
+        CompressionMethod::Deflate(),
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZip2(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Lzma(),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Xz(),
+    ] {
+        let mut archive = ZipArchive::new(Vec::new());
+        let options = FileOptions::default().compression_method(compressor);
+        let error = archive
+            .append_file("file.txt", &mut ResetReader { sent: false }, &options)
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(error.root_cause(), ArchiveError::IoError(e) if e.kind() == std::io::ErrorKind::ConnectionReset),
+            "{}: {:?}",
+            compressor,
+            error
+        );
+    }
+}
+
+#[tokio::test]
+async fn archive_time_now_evaluated_on_append() {
+    let options = FileOptions::default().last_modified_time(FileDateTime::Now);
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file("file.txt", &mut b"Some string data".as_ref(), &options)
+        .await
+        .unwrap();
+    let appended_at = chrono::Local::now().naive_local();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    let entry = central_directory_entry(&out, "file.txt");
+    let written =
+        DateTimeCS::from_msdos(entry.last_mod_file_date, entry.last_mod_file_time).to_time();
+
+    // MS-DOS times have a two seconds resolution
+    let delta = (appended_at - written).num_seconds();
+    assert!((0..=2).contains(&delta), "{} vs {}", written, appended_at);
+}
+
+#[tokio::test]
+async fn archive_empty() {
+    let mut archive = ZipArchive::new(Vec::new());
+    archive.finalize().await.unwrap();
+
+    assert_eq!(archive.get_archive_size(), 22);
+    assert_empty_archive(&archive.retrieve_writer());
+}
+
+#[tokio::test]
+async fn archive_empty_entries() {
+    let mut archive = ZipArchive::new(Vec::new());
+    for &compressor in EMPTY_ENTRY_METHODS {
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(
+                &empty_entry_name(compressor),
+                &mut tokio::io::empty(),
+                &options,
+            )
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    assert_empty_entries(&out);
+}
+
+#[tokio::test]
+async fn archive_is_finalized() {
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file("file1.txt", &mut b"data".as_ref(), &FileOptions::default())
+        .await
+        .unwrap();
+    assert!(!archive.is_finalized());
+
+    archive.finalize().await.unwrap();
+    assert!(archive.is_finalized());
+    let out = archive.retrieve_writer();
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file1.txt").unwrap(),
+        b"data"
+    );
+}
+
+#[cfg(debug_assertions)]
+#[tokio::test]
+#[should_panic(expected = "before the archive is finalized")]
+async fn archive_retrieve_writer_not_finalized() {
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file("file1.txt", &mut b"data".as_ref(), &FileOptions::default())
+        .await
+        .unwrap();
+    archive.retrieve_writer();
+}
+
+#[tokio::test]
+async fn archive_buffered_tcp_stream() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let receiver = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut out = Vec::new();
+        socket.read_to_end(&mut out).await.unwrap();
+        out
+    });
+
+    let stream = TcpStream::connect(address).await.unwrap();
+    let mut archive = ZipArchive::with_buffer_size(stream, 64 * 1024);
+    // the socket stays reachable for diagnostics and options while the archive is written
+    assert_eq!(archive.writer_ref().get_ref().peer_addr().unwrap(), address);
+    archive.writer_mut().get_mut().set_nodelay(true).unwrap();
+    let path = Path::new("tests/resources").join("lorem_ipsum.txt");
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    for name in ["first.txt", "second.txt"] {
+        let mut in_file = tokio::fs::File::open(&path).await.unwrap();
+        archive
+            .append_file(name, &mut in_file, &options)
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+    let archive_size = archive.get_archive_size();
+    archive.retrieve_writer().shutdown().await.unwrap();
+
+    let out = receiver.await.unwrap();
+    assert_eq!(out.len() as u64, archive_size);
+    for name in ["first.txt", "second.txt"] {
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, name).unwrap(),
+            std::fs::read(&path).unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn archive_local_header_fields() {
+    let mut archive = ZipArchive::new(Vec::new());
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(
+                &format!("{}.txt", compressor),
+                &mut b"Some string data".as_ref(),
+                &options,
+            )
+            .await
+            .unwrap();
+    }
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer();
+
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        assert_local_header_fields(&out, &format!("{}.txt", compressor), true);
+    }
+}
+
+#[tokio::test]
+async fn archive_returned_crc() {
+    let lorem = std::fs::read(Path::new("tests/resources").join("lorem_ipsum.txt")).unwrap();
+    let mut archive = ZipArchive::new(Vec::new());
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        let options = FileOptions::default().compression_method(compressor);
+        let crc32 = archive
+            .append_file(
+                &format!("{}.txt", compressor),
+                &mut lorem.as_slice(),
+                &options,
+            )
+            .await
+            .unwrap();
+        assert_eq!(crc32, crc32fast::hash(&lorem));
+    }
+    archive.finalize().await.unwrap();
+    let out = archive.retrieve_writer();
+
+    let entry = central_directory_entry(&out, &format!("{}.txt", CompressionMethod::Deflate()));
+    assert_eq!(entry.crc32, crc32fast::hash(&lorem));
+}
+
+#[tokio::test]
+async fn archive_append_file_buf() {
+    let path = Path::new("tests/resources").join("lorem_ipsum.txt");
+    for compressor in [
+        CompressionMethod::Store(),
+        CompressionMethod::Deflate(),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd(),
+    ] {
+        let options = FileOptions::default()
+            .compression_method(compressor)
+            .last_modified_time(FileDateTime::Zero);
+
+        let mut archive = ZipArchive::new(Vec::new());
+        let mut file = tokio::fs::File::open(&path).await.unwrap();
+        archive
+            .append_file("lorem_ipsum.txt", &mut file, &options)
+            .await
+            .unwrap();
+        archive.finalize().await.unwrap();
+
+        // same chunks as `append_file`, read from the buffer of the `BufReader`
+        let mut buffered_archive = ZipArchive::new(Vec::new());
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let crc32 = buffered_archive
+            .append_file_buf(
+                "lorem_ipsum.txt",
+                &mut tokio::io::BufReader::with_capacity(4096, file),
+                &options,
+            )
+            .await
+            .unwrap();
+        buffered_archive.finalize().await.unwrap();
+
+        assert_eq!(crc32, crc32fast::hash(&std::fs::read(&path).unwrap()));
+        assert_eq!(
+            archive.retrieve_writer(),
+            buffered_archive.retrieve_writer(),
+            "{}",
+            compressor
+        );
+    }
+}
+
+#[tokio::test]
+async fn archive_append_raw_zip64() {
+    let lorem = std::fs::read(Path::new("tests/resources").join("lorem_ipsum.txt")).unwrap();
+    let names = ["stored.txt", "deflated.txt"];
+
+    let mut source = ZipArchive::with_options(Vec::new(), ArchiveOptions::default().zip64(true));
+    for (name, method) in names
+        .into_iter()
+        .zip([CompressionMethod::Store(), CompressionMethod::Deflate()])
+    {
+        let options = FileOptions::default()
+            .compression_method(method)
+            .with_unix_timestamps(Some(1_600_000_000), None, None);
+        source
+            .append_file(name, &mut lorem.as_slice(), &options)
+            .await
+            .unwrap();
+    }
+    source.finalize().await.unwrap();
+    let source = source.retrieve_writer();
+
+    // behind another entry, to move the copied ones
+    let mut copy = ZipArchive::new(Vec::new());
+    copy.append_file("first.txt", &mut b"data".as_ref(), &FileOptions::default())
+        .await
+        .unwrap();
+    for (entry, bytes) in rill::uncompress::raw_entries(&source).unwrap() {
+        let crc32 = entry.crc32;
+        assert_eq!(copy.append_raw(entry, bytes).await.unwrap(), crc32);
+    }
+    copy.finalize().await.unwrap();
+    let copy = copy.retrieve_writer();
+
+    for name in names {
+        let source_entry = central_directory_entry(&source, name);
+        let copied_entry = central_directory_entry(&copy, name);
+        // ZIP64 version needed
+        assert_eq!(copied_entry.version_needed, 45);
+        // everything but the offset of the local header is kept
+        let local_header_offset = source_entry.local_header_offset;
+        assert_eq!(
+            CentralDirectoryRecord {
+                local_header_offset,
+                ..copied_entry
+            },
+            source_entry,
+            "{}",
+            name
+        );
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&copy, name).unwrap(),
+            lorem
+        );
+    }
+
+    let error = ZipArchive::new(Vec::new())
+        .append_raw(
+            rill::uncompress::raw_entries(&source).unwrap()[0].0.clone(),
+            &[0; 10],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::BadArchiveStructure(_)
+    ));
+
+    // a local header offset at or past the central directory
+    let (record_offset, _) = common::central_directory_records(&source)[0];
+    let central_directory_offset = record_offset as u32;
+    for local_header_offset in [central_directory_offset, 0x7000_0000] {
+        let mut corrupted = source.clone();
+        corrupted[record_offset + 42..record_offset + 46]
+            .copy_from_slice(&local_header_offset.to_le_bytes());
+        assert!(matches!(
+            rill::uncompress::raw_entries(&corrupted),
+            Err(ArchiveError::BadArchiveStructure(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn archive_split() {
+    const MAX_SIZE: u64 = 1500;
+
+    let lorem = std::fs::read(Path::new("tests/resources").join("lorem_ipsum.txt")).unwrap();
+    let contents: Vec<(String, &[u8])> = (0..12)
+        .map(|i| (format!("file{}.txt", i), &lorem[..100 + 100 * i]))
+        .collect();
+
+    let mut archive = ZipArchive::split(|_| Vec::new(), MAX_SIZE);
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    for (name, content) in &contents {
+        archive
+            .append_file(name, &mut &content[..], &options)
+            .await
+            .unwrap();
+    }
+    let part_count = archive.finalize().await.unwrap();
+    let parts = archive.into_parts();
+    assert_eq!(parts.len(), part_count);
+    assert!(part_count > 1);
+
+    for part in &parts {
+        assert!(
+            part.len() as u64 <= MAX_SIZE,
+            "part of {} bytes",
+            part.len()
+        );
+        rill::uncompress::ArchiveReader::new(std::io::Cursor::new(part)).unwrap();
+    }
+    for (name, content) in &contents {
+        let found: Vec<Vec<u8>> = parts
+            .iter()
+            .filter_map(|part| rill::uncompress::extract_entry_to_vec(part, name).ok())
+            .collect();
+        assert_eq!(found, vec![content.to_vec()], "entry {}", name);
+    }
+}
+
+#[tokio::test]
+async fn archive_split_entry_too_large() {
+    use tokio::io::AsyncReadExt;
+
+    let mut archive = ZipArchive::split(|_| Vec::new(), 100);
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    let error = archive
+        .append_file("large.txt", &mut [b'a'; 200].as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::EntryExceedsPartSize { max_size: 100, .. }
+    ));
+
+    // the compression stops once the entry outgrows a part, rather than buffering it whole
+    let mut endless = tokio::io::repeat(b'a').take(u64::MAX);
+    let error = archive
+        .append_file("endless.txt", &mut endless, &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::EntryExceedsPartSize { required, max_size: 100 } if *required > 100
+    ));
+
+    // an archive without entries still has a part, empty
+    assert_eq!(archive.finalize().await.unwrap(), 1);
+    assert_empty_archive(&archive.into_parts()[0]);
+}
+
+#[cfg(not(feature = "zstd"))]
+#[tokio::test]
+async fn archive_disabled_codec() {
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default().compression_method(CompressionMethod::Zstd());
+    let error = archive
+        .append_file("file1.txt", &mut b"abc".as_ref(), &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::UnsuportedCompressionMethod(CompressionMethod::Zstd())
+    ));
+    assert_eq!(
+        error.root_cause().to_string(),
+        "The compression method 'zstd' requires the 'zstd' feature, which is disabled"
+    );
+
+    // the archive is still usable
+    archive.finalize().await.unwrap();
+    assert_empty_archive(&archive.retrieve_writer());
+}
+
+#[tokio::test]
+async fn archive_append_data() {
+    let mut archive = ZipArchive::new(Vec::new());
+    let options = FileOptions::default();
+    let slice: &[u8] = b"slice";
+    archive
+        .append_data("slice.txt", slice, &options)
+        .await
+        .unwrap();
+    archive
+        .append_data("array.txt", b"array", &options)
+        .await
+        .unwrap();
+    archive
+        .append_data("vec.txt", b"vec".to_vec(), &options)
+        .await
+        .unwrap();
+    archive
+        .append_data("str.txt", "str", &options)
+        .await
+        .unwrap();
+    let crc = archive
+        .append_data("string.txt", String::from("string"), &options)
+        .await
+        .unwrap();
+    assert_eq!(crc, crc32fast::hash(b"string"));
+    #[cfg(feature = "bytes")]
+    archive
+        .append_data("bytes.txt", bytes::Bytes::from_static(b"bytes"), &options)
+        .await
+        .unwrap();
+    archive.finalize().await.unwrap();
+
+    let out = archive.retrieve_writer();
+    let mut expected = vec![
+        ("slice.txt", "slice"),
+        ("array.txt", "array"),
+        ("vec.txt", "vec"),
+        ("str.txt", "str"),
+        ("string.txt", "string"),
+    ];
+    if cfg!(feature = "bytes") {
+        expected.push(("bytes.txt", "bytes"));
+    }
+    for (name, content) in expected {
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, name).unwrap(),
+            content.as_bytes()
+        );
+    }
+}
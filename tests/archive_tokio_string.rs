@@ -1,3 +1,5 @@
+#![cfg(feature = "tokio")]
+
 use rill::{
     archive::FileOptions, compress::tokio::archive::ZipArchiveNoStream,
     compression::CompressionMethod,
@@ -46,6 +48,7 @@ async fn archive_structure_zlib_deflate_tokio() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "bzip2")]
 #[tokio::test]
 async fn archive_structure_compress_bzip() {
     let compressor = CompressionMethod::BZip2();
@@ -53,6 +56,7 @@ async fn archive_structure_compress_bzip() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "xz")]
 #[tokio::test]
 async fn archive_structure_compress_lzma() {
     let compressor = CompressionMethod::Lzma();
@@ -61,6 +65,7 @@ async fn archive_structure_compress_lzma() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "zstd")]
 #[tokio::test]
 async fn archive_structure_compress_zstd() {
     let compressor = CompressionMethod::Zstd();
@@ -69,6 +74,7 @@ async fn archive_structure_compress_zstd() {
     compress_file(compressor, &out_file_name).await;
 }
 
+#[cfg(feature = "xz")]
 #[tokio::test]
 async fn archive_structure_compress_xz() {
     let compressor = CompressionMethod::Xz();
@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 use rill::error::ArchiveError;
 use rill::{
     archive::FileOptions, compress::std::archive::ZipArchiveNoStream,
@@ -44,6 +46,7 @@ fn archive_structure_zlib_deflate_tokio() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "bzip2")]
 #[test]
 fn archive_structure_compress_bzip() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::BZip2();
@@ -53,6 +56,7 @@ fn archive_structure_compress_bzip() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "xz")]
 #[test]
 fn archive_structure_compress_lzma() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Lzma();
@@ -62,6 +66,7 @@ fn archive_structure_compress_lzma() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "zstd")]
 #[test]
 fn archive_structure_compress_zstd() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Zstd();
@@ -71,6 +76,7 @@ fn archive_structure_compress_zstd() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "xz")]
 #[test]
 fn archive_structure_compress_xz() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Xz();
@@ -1,12 +1,20 @@
+#![cfg(feature = "std")]
+
 use std::{fs::File, path::Path};
 
 use rill::{
-    archive::FileOptions, compress::std::archive::ZipArchiveNoStream,
-    compression::CompressionMethod, error::ArchiveError,
+    archive::{ArchiveOptions, FileOptions},
+    compress::std::archive::ZipArchiveNoStream,
+    compression::{CompressionMethod, Level},
+    error::ArchiveError,
 };
 mod common;
-use common::out_file_name;
 use common::std::create_new_clean_file;
+use common::{
+    assert_empty_entries, assert_local_header_fields, central_directory_entry,
+    central_directory_names, central_directory_records, empty_entry_name, out_file_name,
+    LocalHeader, EMPTY_ENTRY_METHODS,
+};
 const TEST_ID: &str = "nostream";
 const FILE_TO_COMPRESS: &str = "short_text_file.txt";
 
@@ -45,6 +53,7 @@ fn archive_structure_compress_deflate() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "bzip2")]
 #[test]
 fn archive_structure_compress_bzip() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::BZip2();
@@ -54,6 +63,7 @@ fn archive_structure_compress_bzip() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "xz")]
 #[test]
 fn archive_structure_compress_lzma() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Lzma();
@@ -62,6 +72,7 @@ fn archive_structure_compress_lzma() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "zstd")]
 #[test]
 fn archive_structure_compress_zstd() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Zstd();
@@ -70,6 +81,7 @@ fn archive_structure_compress_zstd() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "xz")]
 #[test]
 fn archive_structure_compress_xz() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Xz();
@@ -77,3 +89,516 @@ fn archive_structure_compress_xz() -> Result<(), ArchiveError> {
     compress_file(compressor, &out_file_name)?;
     Ok(())
 }
+
+#[test]
+fn archive_replace_last_entry() -> Result<(), ArchiveError> {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut b"first".as_ref(), &options)?;
+    archive.append_file("file2.txt", &mut b"wrong content".as_ref(), &options)?;
+    archive.replace_last_entry(&mut b"right".as_ref(), &options)?;
+
+    let (archive_size, out) = archive.finalize()?;
+    let out = out.into_inner();
+    assert_eq!(out.len() as u64, archive_size);
+
+    assert_eq!(central_directory_names(&out), ["file1.txt", "file2.txt"]);
+    let last = central_directory_entry(&out, "file2.txt");
+    assert_eq!(last.crc32, crc32fast::hash(b"right"));
+    Ok(())
+}
+
+#[test]
+fn archive_replace_last_entry_shrink() -> Result<(), ArchiveError> {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut b"first".as_ref(), &options)?;
+    let large = vec![b'a'; 200_000];
+    archive.append_file("file2.txt", &mut large.as_slice(), &options)?;
+    archive.replace_last_entry(&mut b"right".as_ref(), &options)?;
+
+    // the tail of the large entry is zero-filled before the central directory, the end records
+    // being the last bytes of the sink
+    let (archive_size, out) = archive.finalize()?;
+    let out = out.into_inner();
+    assert_eq!(out.len() as u64, archive_size);
+
+    assert_eq!(central_directory_names(&out), ["file1.txt", "file2.txt"]);
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file1.txt")?,
+        b"first"
+    );
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file2.txt")?,
+        b"right"
+    );
+    Ok(())
+}
+
+#[test]
+fn archive_from_existing() -> Result<(), ArchiveError> {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflate());
+    archive.append_file("file1.txt", &mut b"first".as_ref(), &options)?;
+    archive.append_file("file2.txt", &mut b"second".as_ref(), &options)?;
+    let (_, out) = archive.finalize()?;
+
+    let mut archive = ZipArchiveNoStream::from_existing(out)?;
+    archive.append_file("file3.txt", &mut b"third".as_ref(), &options)?;
+    let (archive_size, out) = archive.finalize()?;
+    let out = out.into_inner();
+    assert_eq!(out.len() as u64, archive_size);
+
+    assert_eq!(
+        central_directory_names(&out),
+        ["file1.txt", "file2.txt", "file3.txt"]
+    );
+    Ok(())
+}
+
+#[test]
+fn archive_comment() -> Result<(), ArchiveError> {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    assert_eq!(archive.comment(), None);
+    assert_eq!(archive.comment_or_empty(), "");
+    assert!(archive.comment_bytes().is_empty());
+
+    archive.set_archive_comment("Archive commentée");
+    assert_eq!(archive.comment(), Some("Archive commentée"));
+    assert_eq!(archive.comment_bytes(), "Archive commentée".as_bytes());
+    let (_, out) = archive.finalize()?;
+
+    // read back from the end of central directory record
+    let archive = ZipArchiveNoStream::from_existing(out)?;
+    assert_eq!(archive.comment(), Some("Archive commentée"));
+    assert_eq!(archive.comment_or_empty(), "Archive commentée");
+
+    // a comment of another tool, not UTF-8
+    let mut out = archive.finalize()?.1.into_inner();
+    let last = out.len() - 1;
+    out[last] = 0xff;
+    let archive = ZipArchiveNoStream::from_existing(std::io::Cursor::new(out))?;
+    assert_eq!(archive.comment(), None);
+    assert_eq!(archive.comment_or_empty(), "");
+    assert_eq!(archive.comment_bytes().last(), Some(&0xff));
+    Ok(())
+}
+
+/// Sink holding only the bytes written from `base`, the ones before read as zeros.
+#[derive(Debug)]
+struct SparseSink {
+    base: u64,
+    tail: Vec<u8>,
+    position: u64,
+}
+
+impl std::io::Write for SparseSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let start = (self.position - self.base) as usize;
+        if self.tail.len() < start + buf.len() {
+            self.tail.resize(start + buf.len(), 0);
+        }
+        self.tail[start..start + buf.len()].copy_from_slice(buf);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::io::Read for SparseSink {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let end = self.base + self.tail.len() as u64;
+        let len = buf.len().min((end - self.position) as usize);
+        for byte in buf[..len].iter_mut() {
+            *byte = match self.position.checked_sub(self.base) {
+                Some(index) => self.tail[index as usize],
+                None => 0,
+            };
+            self.position += 1;
+        }
+        Ok(len)
+    }
+}
+
+impl std::io::Seek for SparseSink {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.position = match pos {
+            std::io::SeekFrom::Start(offset) => offset,
+            std::io::SeekFrom::End(offset) => {
+                (self.base + self.tail.len() as u64).saturating_add_signed(offset)
+            }
+            std::io::SeekFrom::Current(offset) => self.position.saturating_add_signed(offset),
+        };
+        Ok(self.position)
+    }
+}
+
+/// An empty archive whose central directory starts right below 4 GiB.
+fn empty_archive_below_4_gib() -> SparseSink {
+    let base = u32::MAX as u64 - 100;
+    let mut end_of_central_directory = vec![0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0, 0, 0, 0, 0];
+    end_of_central_directory.extend_from_slice(&0u32.to_le_bytes());
+    end_of_central_directory.extend_from_slice(&(base as u32).to_le_bytes());
+    end_of_central_directory.extend_from_slice(&0u16.to_le_bytes());
+    SparseSink {
+        base,
+        tail: end_of_central_directory,
+        position: 0,
+    }
+}
+
+#[test]
+fn archive_zip64_required() -> Result<(), ArchiveError> {
+    let mut archive = ZipArchiveNoStream::from_existing(empty_archive_below_4_gib())?;
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut [b'a'; 200].as_ref(), &options)?;
+
+    let error = archive
+        .append_file("file2.txt", &mut b"second".as_ref(), &options)
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::Zip64Required {
+            field: "local header offset"
+        }
+    ));
+
+    let error = archive.finalize().unwrap_err();
+    assert!(matches!(
+        error,
+        ArchiveError::Zip64Required {
+            field: "central directory offset"
+        }
+    ));
+    Ok(())
+}
+
+#[test]
+fn archive_zip64_large_offset() -> Result<(), ArchiveError> {
+    let archive_options = ArchiveOptions::default().zip64(true);
+    let mut archive = ZipArchiveNoStream::from_existing_with_options(
+        empty_archive_below_4_gib(),
+        archive_options,
+    )?;
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut [b'a'; 200].as_ref(), &options)?;
+    archive.append_file("file2.txt", &mut b"second".as_ref(), &options)?;
+    let (_, sink) = archive.finalize()?;
+
+    let file2_offset = sink.base + 30 + 9 + 200;
+    let out = &sink.tail;
+    let entries: Vec<_> = central_directory_records(out)
+        .into_iter()
+        .map(|(_, record)| record)
+        .collect();
+    assert_eq!(entries.len(), 2);
+
+    // below 4 GiB, the offset stays in the legacy field
+    assert_eq!(entries[0].local_header_offset, sink.base as u32);
+    assert!(entries[0].extra_field.is_empty());
+
+    assert_eq!(entries[1].local_header_offset, u32::MAX);
+    assert_eq!(entries[1].extra_field.len(), 12);
+    assert_eq!(
+        entries[1].extra_field_block(0x0001),
+        Some(file2_offset.to_le_bytes().as_ref())
+    );
+
+    // the central directory itself starts past 4 GiB
+    assert!(out.windows(4).any(|w| w == [0x50, 0x4b, 0x06, 0x06]));
+    assert!(out.windows(4).any(|w| w == [0x50, 0x4b, 0x06, 0x07]));
+    assert_eq!(&out[out.len() - 6..out.len() - 2], &[0xff; 4]);
+    Ok(())
+}
+
+#[test]
+fn archive_zip64_from_existing() -> Result<(), ArchiveError> {
+    let archive_options = ArchiveOptions::default().zip64(true);
+    let mut archive = ZipArchiveNoStream::from_existing_with_options(
+        empty_archive_below_4_gib(),
+        archive_options.clone(),
+    )?;
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut [b'a'; 200].as_ref(), &options)?;
+    archive.append_file("file2.txt", &mut b"second".as_ref(), &options)?;
+    let (_, sink) = archive.finalize()?;
+
+    // the central directory is found through the ZIP64 end records
+    let mut archive = ZipArchiveNoStream::from_existing_with_options(sink, archive_options)?;
+    archive.append_file("file3.txt", &mut b"third".as_ref(), &options)?;
+    let (_, mut sink) = archive.finalize()?;
+
+    let file2_offset = sink.base + 30 + 9 + 200;
+    let out = &sink.tail;
+    let entries: Vec<_> = central_directory_records(out)
+        .into_iter()
+        .map(|(_, record)| record)
+        .collect();
+    assert_eq!(entries.len(), 3);
+
+    // the ZIP64 extra field read back is not written twice
+    assert_eq!(entries[1].local_header_offset, u32::MAX);
+    assert_eq!(entries[1].extra_field.len(), 12);
+    assert_eq!(
+        entries[1].extra_field_block(0x0001),
+        Some(file2_offset.to_le_bytes().as_ref())
+    );
+
+    let file3_offset = file2_offset + 30 + 9 + 6;
+    assert_eq!(entries[2].extra_field.len(), 12);
+    assert_eq!(
+        entries[2].extra_field_block(0x0001),
+        Some(file3_offset.to_le_bytes().as_ref())
+    );
+
+    sink.position = 0;
+    rill::uncompress::ArchiveReader::new(sink)?;
+    Ok(())
+}
+
+#[test]
+fn archive_empty_entries() {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    for &compressor in EMPTY_ENTRY_METHODS {
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(
+                &empty_entry_name(compressor),
+                &mut std::io::empty(),
+                &options,
+            )
+            .unwrap();
+    }
+    let (_, out) = archive.finalize().unwrap();
+    let out = out.into_inner();
+    assert_empty_entries(&out);
+}
+
+#[test]
+fn archive_recompress_entry() {
+    let text = "Some string data, repeated. ".repeat(16);
+    let store = FileOptions::default().compression_method(CompressionMethod::Store());
+
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    for name in ["first.txt", "second.txt"] {
+        archive
+            .append_file(name, &mut text.as_bytes(), &store)
+            .unwrap();
+    }
+    archive
+        .append_file("tiny.txt", &mut b"ab".as_ref(), &store)
+        .unwrap();
+
+    archive
+        .recompress_entry(
+            "first.txt",
+            CompressionMethod::Deflate(),
+            &FileOptions::default(),
+        )
+        .unwrap();
+    let error = archive
+        .recompress_entry(
+            "tiny.txt",
+            CompressionMethod::Deflate(),
+            &FileOptions::default(),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::RecompressedEntryTooLarge { available: 2, .. }
+    ));
+
+    let (_, out) = archive.finalize().unwrap();
+    let out = out.into_inner();
+    // the smaller payload ends where the previous one did, behind a padding extra field
+    let (entry, raw) = rill::uncompress::raw_entries(&out)
+        .unwrap()
+        .into_iter()
+        .find(|(entry, _)| entry.file_name_as_bytes == b"first.txt")
+        .unwrap();
+    let header = LocalHeader::parse(raw, 0);
+    assert_eq!(header.size() + entry.compressed_size as usize, raw.len());
+    assert!(header.extra_field_block(0xa220).is_some());
+    for (name, content) in [
+        ("first.txt", text.as_bytes()),
+        ("second.txt", text.as_bytes()),
+        ("tiny.txt", b"ab".as_ref()),
+    ] {
+        assert_eq!(
+            rill::uncompress::extract_entry_to_vec(&out, name).unwrap(),
+            content
+        );
+    }
+}
+
+#[test]
+fn archive_compression_level() {
+    let path = Path::new("tests/resources").join("lorem_ipsum.txt");
+    let mut sizes = Vec::new();
+    for level in [Level::Fastest, Level::Best] {
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflate())
+            .compression_level(level);
+        let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+        let mut in_file = File::open(&path).unwrap();
+        archive
+            .append_file("lorem_ipsum.txt", &mut in_file, &options)
+            .unwrap();
+        sizes.push(archive.get_archive_size().unwrap());
+    }
+
+    assert!(
+        sizes[1] < sizes[0],
+        "best {} >= fastest {}",
+        sizes[1],
+        sizes[0]
+    );
+}
+
+/// Forward to a file, recording the largest single read and write.
+struct ChunkRecorder {
+    file: File,
+    largest: usize,
+}
+
+impl std::io::Read for ChunkRecorder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.largest = self.largest.max(buf.len());
+        self.file.read(buf)
+    }
+}
+
+impl std::io::Write for ChunkRecorder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.largest = self.largest.max(buf.len());
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl std::io::Seek for ChunkRecorder {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+#[test]
+fn archive_large_file_streamed() -> Result<(), ArchiveError> {
+    use std::io::Write;
+
+    const FILE_SIZE: usize = 16 * 1024 * 1024;
+    const CHUNK_BOUND: usize = 1024 * 1024;
+
+    let in_file_name = "large_input.bin";
+    let mut in_file = create_new_clean_file(in_file_name);
+    let line: Vec<u8> = (0..=255u8).collect();
+    for _ in 0..FILE_SIZE / line.len() {
+        in_file.write_all(&line)?;
+    }
+    let in_path = Path::new("/tmp").join(common::PACKAGE_NAME).join("std");
+
+    for compressor in [
+        CompressionMethod::Store(),
+        CompressionMethod::Deflate(),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd(),
+    ] {
+        let mut reader = ChunkRecorder {
+            file: File::open(in_path.join(in_file_name))?,
+            largest: 0,
+        };
+        let sink = ChunkRecorder {
+            file: create_new_clean_file(&out_file_name(compressor, "large_file")),
+            largest: 0,
+        };
+        let mut archive = ZipArchiveNoStream::new(sink);
+        let mut options = FileOptions::default().compression_method(compressor);
+        if !matches!(compressor, CompressionMethod::Store()) {
+            options = options.compression_level(Level::Fastest);
+        }
+        archive.append_file(in_file_name, &mut reader, &options)?;
+        let (_, sink) = archive.finalize()?;
+
+        // The file goes through the compressor in chunks, never held in memory as a whole.
+        assert!(
+            reader.largest <= CHUNK_BOUND,
+            "read of {} bytes",
+            reader.largest
+        );
+        assert!(
+            sink.largest <= CHUNK_BOUND,
+            "write of {} bytes",
+            sink.largest
+        );
+        assert!(sink.file.metadata()?.len() > 0);
+    }
+    Ok(())
+}
+
+#[test]
+fn archive_local_header_fields() {
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(
+                &format!("{}.txt", compressor),
+                &mut b"Some string data".as_ref(),
+                &options,
+            )
+            .unwrap();
+    }
+    let (_, out) = archive.finalize().unwrap();
+    let out = out.into_inner();
+
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        assert_local_header_fields(&out, &format!("{}.txt", compressor), false);
+    }
+}
+
+#[test]
+fn archive_spanning_marker() {
+    let options = ArchiveOptions::default().spanning_marker(true);
+    let mut archive = ZipArchiveNoStream::with_options(std::io::Cursor::new(Vec::new()), options);
+    archive
+        .append_file(
+            "file1.txt",
+            &mut b"Some string data".as_ref(),
+            &FileOptions::default(),
+        )
+        .unwrap();
+    let (_, out) = archive.finalize().unwrap();
+    let out = out.into_inner();
+
+    assert_eq!(&out[..4], b"PK00");
+    assert_local_header_fields(&out, "file1.txt", false);
+    assert_eq!(
+        rill::uncompress::extract_entry_to_vec(&out, "file1.txt").unwrap(),
+        b"Some string data"
+    );
+}
+
+#[test]
+fn archive_returned_crc() {
+    let lorem = std::fs::read(Path::new("tests/resources").join("lorem_ipsum.txt")).unwrap();
+    let mut archive = ZipArchiveNoStream::new(std::io::Cursor::new(Vec::new()));
+    let crc32 = archive
+        .append_file(
+            "lorem_ipsum.txt",
+            &mut lorem.as_slice(),
+            &FileOptions::default(),
+        )
+        .unwrap();
+    assert_eq!(crc32, crc32fast::hash(&lorem));
+}
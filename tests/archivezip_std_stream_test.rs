@@ -1,12 +1,20 @@
+#![cfg(feature = "std")]
+
 use std::{fs::File, path::Path};
 
 use rill::error::ArchiveError;
+use rill::uncompress::extract_entry_to_vec_with_options;
 use rill::{
-    archive::FileOptions, compress::std::archive::ZipArchive, compression::CompressionMethod,
+    archive::{ArchiveOptions, ExtractOptions, FileOptions, ListOptions, OsMadeBy, PlannedEntry},
+    compress::std::archive::ZipArchive,
+    compression::CompressionMethod,
 };
 mod common;
-use common::out_file_name;
 use common::std::create_new_clean_file;
+use common::{
+    assert_empty_archive, assert_empty_entries, assert_local_header_fields,
+    central_directory_entry, empty_entry_name, out_file_name, LocalHeader, EMPTY_ENTRY_METHODS,
+};
 
 const TEST_ID: &str = "stream";
 const FILE_TO_COMPRESS: &str = "file1.txt";
@@ -46,6 +54,7 @@ fn archive_structure_compress_deflate() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "bzip2")]
 #[test]
 fn archive_structure_compress_bzip() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::BZip2();
@@ -55,6 +64,7 @@ fn archive_structure_compress_bzip() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "xz")]
 #[test]
 fn archive_structure_compress_lzma() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Lzma();
@@ -64,6 +74,7 @@ fn archive_structure_compress_lzma() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "zstd")]
 #[test]
 fn archive_structure_compress_zstd() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Zstd();
@@ -73,6 +84,7 @@ fn archive_structure_compress_zstd() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "xz")]
 #[test]
 fn archive_structure_compress_xz() -> Result<(), ArchiveError> {
     let compressor = CompressionMethod::Xz();
@@ -82,6 +94,7 @@ fn archive_structure_compress_xz() -> Result<(), ArchiveError> {
     Ok(())
 }
 
+#[cfg(feature = "xz")]
 #[test]
 fn archive_multiple() -> Result<(), ArchiveError> {
     let out_file_name = "test_multiple.zip";
@@ -113,3 +126,505 @@ fn archive_multiple() -> Result<(), ArchiveError> {
 
     Ok(())
 }
+
+#[test]
+fn archive_data_descriptor() -> Result<(), ArchiveError> {
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut b"Some string data".as_ref(), &options)?;
+    let (_, out) = archive.finalize()?;
+
+    // the data descriptor sits between the payload and the central directory
+    let descriptor_offset = 30 + 9 + 16;
+    assert_eq!(
+        &out[descriptor_offset..descriptor_offset + 4],
+        &[0x50, 0x4b, 0x07, 0x08]
+    );
+    assert_eq!(
+        &out[descriptor_offset + 8..descriptor_offset + 16],
+        &[16, 0, 0, 0, 16, 0, 0, 0]
+    );
+    assert_eq!(
+        &out[descriptor_offset + 16..descriptor_offset + 20],
+        &[0x50, 0x4b, 0x01, 0x02]
+    );
+    Ok(())
+}
+
+struct FullSink;
+
+impl std::io::Write for FullSink {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn archive_full_sink() {
+    let mut archive = ZipArchive::new(FullSink);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    let result = archive.append_file("file1.txt", &mut b"Some string data".as_ref(), &options);
+
+    let error = result.unwrap_err();
+    assert!(
+        matches!(error.root_cause(), ArchiveError::WriteZero(e) if e.kind() == std::io::ErrorKind::WriteZero)
+    );
+    assert!(matches!(error, ArchiveError::Entry { name, offset: 0, .. } if name == "file1.txt"));
+}
+
+#[test]
+fn archive_version_made_by() -> Result<(), ArchiveError> {
+    let options = ArchiveOptions::default().version_made_by(OsMadeBy::MsDos, 20);
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut b"Some string data".as_ref(), &options)?;
+
+    let (_, out) = archive.finalize()?;
+
+    assert_eq!(
+        central_directory_entry(&out, "file1.txt").version_made_by,
+        20
+    );
+    Ok(())
+}
+
+#[derive(Default)]
+struct FlushCountingSink {
+    out: Vec<u8>,
+    flush_offsets: Vec<usize>,
+}
+
+impl std::io::Write for FlushCountingSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.out.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_offsets.push(self.out.len());
+        Ok(())
+    }
+}
+
+#[test]
+fn archive_flush_every() -> Result<(), ArchiveError> {
+    const FLUSH_EVERY: usize = 1000;
+
+    let archive_options = ArchiveOptions::default().flush_every(FLUSH_EVERY as u64);
+    let mut archive = ZipArchive::with_options(FlushCountingSink::default(), archive_options);
+
+    let content = vec![b'a'; 10 * FLUSH_EVERY];
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut content.as_slice(), &options)?;
+
+    let sink = archive.retrieve_writer();
+    assert!(!sink.flush_offsets.is_empty());
+    let mut last_flush = 0;
+    for offset in sink.flush_offsets {
+        assert!(offset - last_flush >= FLUSH_EVERY);
+        last_flush = offset;
+    }
+    assert!(sink.out.len() - last_flush <= FLUSH_EVERY + 4096);
+    Ok(())
+}
+
+#[test]
+fn archive_list() -> Result<(), ArchiveError> {
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut [b'a'; 1024].as_ref(), &options)?;
+    archive.append_file("dir/file2.txt", &mut b"Some string data".as_ref(), &options)?;
+
+    let mut out = Vec::new();
+    archive.list(&mut out)?;
+    let listing = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+
+    for header in ["Length", "Size", "Ratio", "Date", "Time", "Name"] {
+        assert!(lines[0].contains(header));
+    }
+    assert_eq!(lines[1].len(), 80);
+    assert!(lines[2].contains("1024") && lines[2].ends_with("file1.txt"));
+    assert!(lines[3].contains("16") && lines[3].ends_with("dir/file2.txt"));
+    assert!(lines[5].contains("1040") && lines[5].ends_with("2 files"));
+
+    let mut out = Vec::new();
+    archive.list_with_options(&mut out, &ListOptions::default().width(120))?;
+    let listing = String::from_utf8(out).unwrap();
+    assert_eq!(listing.lines().nth(1).unwrap().len(), 120);
+    Ok(())
+}
+
+#[test]
+fn archive_zip64_data_descriptor() -> Result<(), ArchiveError> {
+    let archive_options = ArchiveOptions::default().zip64(true);
+    let mut archive = ZipArchive::with_options(Vec::new(), archive_options);
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Store());
+    archive.append_file("file1.txt", &mut b"Some string data".as_ref(), &options)?;
+    let (_, out) = archive.finalize()?;
+
+    // local header: ZIP64 version, sizes deferred to the ZIP64 extra field
+    let header = LocalHeader::parse(&out, 0);
+    assert_eq!(header.version_needed, 45);
+    assert_eq!(
+        (header.compressed_size, header.uncompressed_size),
+        (u32::MAX, u32::MAX)
+    );
+    assert_eq!(header.extra_field_block(0x0001).map(<[u8]>::len), Some(16));
+
+    // data descriptor with 8-byte sizes
+    let descriptor_offset = out
+        .windows(4)
+        .position(|w| w == [0x50, 0x4b, 0x07, 0x08])
+        .unwrap();
+    assert_eq!(
+        &out[descriptor_offset + 8..descriptor_offset + 24],
+        &[16, 0, 0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        &out[descriptor_offset + 24..descriptor_offset + 28],
+        &[0x50, 0x4b, 0x01, 0x02]
+    );
+    Ok(())
+}
+
+#[test]
+#[allow(deprecated)]
+fn archive_legacy_method_unsupported() {
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Shrink());
+    let error = archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::UnsuportedCompressionMethod(CompressionMethod::Shrink())
+    ));
+}
+
+#[test]
+fn archive_append_file_owned() {
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let path = Path::new("tests/resources").join(FILE_TO_COMPRESS);
+    let in_file = File::open(&path).unwrap();
+    archive
+        .append_file_owned(FILE_TO_COMPRESS, in_file, &FileOptions::default())
+        .unwrap();
+    let (_, out) = archive.finalize().unwrap();
+
+    let content = rill::uncompress::extract_entry_to_vec(&out, FILE_TO_COMPRESS).unwrap();
+    assert_eq!(content, std::fs::read(path).unwrap());
+}
+
+#[test]
+fn archive_backslash_names() {
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file(
+            "a\\b\\c.txt",
+            &mut b"Some string data".as_ref(),
+            &FileOptions::default(),
+        )
+        .unwrap();
+    let (_, out) = archive.finalize().unwrap();
+
+    let content = rill::uncompress::extract_entry_to_vec(&out, "a/b/c.txt").unwrap();
+    assert_eq!(content, b"Some string data");
+
+    let options = ArchiveOptions::default().strict_entry_names(true);
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+    let error = archive
+        .append_file(
+            "a\\b\\c.txt",
+            &mut b"Some string data".as_ref(),
+            &FileOptions::default(),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::InvalidEntryName(name) if name == "a\\b\\c.txt"
+    ));
+    assert!(error.to_string().contains("contains a backslash"));
+    assert_eq!(archive.get_archive_size().unwrap(), 0);
+}
+
+#[test]
+fn archive_empty_entry_name() {
+    let mut archive = ZipArchive::new(Vec::new());
+    for name in ["", "/"] {
+        let error = archive
+            .append_file(
+                name,
+                &mut b"Some string data".as_ref(),
+                &FileOptions::default(),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            error.root_cause(),
+            ArchiveError::EmptyEntryName(invalid) if invalid == name
+        ));
+        assert!(error.to_string().contains("has no path to extract to"));
+    }
+    assert_eq!(archive.get_archive_size().unwrap(), 0);
+
+    let options = ArchiveOptions::default().allow_empty_entry_names(true);
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+    archive
+        .append_file(
+            "",
+            &mut b"Some string data".as_ref(),
+            &FileOptions::default(),
+        )
+        .unwrap();
+}
+
+#[test]
+fn archive_unknown_method_unsupported() {
+    let mut archive = ZipArchive::new(Vec::new());
+
+    let options = FileOptions::default().compression_method(CompressionMethod::Unknown(1234));
+    let error = archive
+        .append_file("file1.txt", &mut b"Some string data".as_ref(), &options)
+        .unwrap_err();
+    assert!(matches!(
+        error.root_cause(),
+        ArchiveError::UnsuportedCompressionMethod(CompressionMethod::Unknown(1234))
+    ));
+    assert_eq!(archive.get_archive_size().unwrap(), 0);
+}
+
+#[test]
+fn archive_sink_counter() {
+    let options = FileOptions::default();
+
+    let mut counted = ZipArchive::new(rill::tools::SinkCounter::new());
+    let mut kept = ZipArchive::new(Vec::new());
+    for i in 0..3 {
+        let file_name = format!("file{}.txt", i);
+        let data = file_name.repeat(100);
+        counted
+            .append_file(&file_name, &mut data.as_bytes(), &options)
+            .unwrap();
+        kept.append_file(&file_name, &mut data.as_bytes(), &options)
+            .unwrap();
+    }
+    let (_, counter) = counted.finalize().unwrap();
+    let (_, out) = kept.finalize().unwrap();
+
+    assert_eq!(counter.count(), out.len() as u64);
+}
+
+#[test]
+fn archive_estimated_size() {
+    let stored = FileOptions::default().compression_method(CompressionMethod::Store());
+    let options = ArchiveOptions::default().zip64(true);
+    let mut archive = ZipArchive::with_options(Vec::new(), options);
+
+    let contents: [(&str, Vec<u8>); 2] = [
+        ("a.txt", b"Some string data".to_vec()),
+        ("b/c.bin", vec![1; 300]),
+    ];
+    let planned: Vec<PlannedEntry> = contents
+        .iter()
+        .map(|(name, data)| PlannedEntry::new(name, data.len() as u64, &stored))
+        .collect();
+    let estimate = archive.estimated_size(&planned).unwrap();
+
+    for (name, data) in &contents {
+        archive
+            .append_file(name, &mut data.as_slice(), &stored)
+            .unwrap();
+    }
+    let (size, out) = archive.finalize().unwrap();
+
+    assert_eq!(size, estimate);
+    assert_eq!(out.len() as u64, estimate);
+}
+
+#[test]
+fn archive_writer_ref() {
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file(
+            "file.txt",
+            &mut b"Some string data".as_ref(),
+            &FileOptions::default(),
+        )
+        .unwrap();
+
+    assert_eq!(
+        archive.writer_ref().len() as u64,
+        archive.get_archive_size().unwrap()
+    );
+    archive.writer_mut().reserve(1024);
+
+    let (size, out) = archive.finalize().unwrap();
+    assert_eq!(out.len() as u64, size);
+}
+
+#[test]
+fn archive_extract_limits() {
+    let zeros = vec![0u8; 1 << 20];
+    let mut archive = ZipArchive::new(Vec::new());
+    archive
+        .append_file("zeros.bin", &mut zeros.as_slice(), &FileOptions::default())
+        .unwrap();
+    let (_, out) = archive.finalize().unwrap();
+
+    for options in [
+        ExtractOptions::default().max_ratio(10),
+        ExtractOptions::default().max_output(64 * 1024),
+    ] {
+        let error = extract_entry_to_vec_with_options(&out, "zeros.bin", &options).unwrap_err();
+        match error {
+            ArchiveError::SuspiciousRatio {
+                compressed_size,
+                output_size,
+            } => {
+                assert!(compressed_size < 2048);
+                // aborted well before the whole entry is inflated
+                assert!(output_size < zeros.len() as u64 / 4, "{}", output_size);
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    let options = ExtractOptions::default()
+        .max_ratio(5000)
+        .max_output(zeros.len() as u64);
+    assert_eq!(
+        extract_entry_to_vec_with_options(&out, "zeros.bin", &options).unwrap(),
+        zeros
+    );
+}
+
+/// Reader yielding some bytes, then failing with `ConnectionReset`.
+struct ResetReader {
+    sent: bool,
+}
+
+impl std::io::Read for ResetReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.sent {
+            return Err(std::io::ErrorKind::ConnectionReset.into());
+        }
+        self.sent = true;
+        let count = buf.len().min(1000);
+        buf[..count].fill(b'a');
+        Ok(count)
+    }
+}
+
+#[test]
+fn archive_reader_error_kind() {
+    for compressor in [
+        CompressionMethod::Store(),
+        CompressionMethod::Deflate(),
+        #[cfg(feature = "bzip2")]
+        CompressionMethod::BZip2(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Lzma(),
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd(),
+        #[cfg(feature = "xz")]
+        CompressionMethod::Xz(),
+    ] {
+        let mut archive = ZipArchive::new(Vec::new());
+        let options = FileOptions::default().compression_method(compressor);
+        let error = archive
+            .append_file("file.txt", &mut ResetReader { sent: false }, &options)
+            .unwrap_err();
+
+        assert!(
+            matches!(error.root_cause(), ArchiveError::IoError(e) if e.kind() == std::io::ErrorKind::ConnectionReset),
+            "{}: {:?}",
+            compressor,
+            error
+        );
+    }
+}
+
+#[test]
+fn archive_empty() {
+    let archive = ZipArchive::new(Vec::new());
+    let (size, out) = archive.finalize().unwrap();
+
+    assert_eq!(size, 22);
+    assert_empty_archive(&out);
+}
+
+#[test]
+fn archive_empty_entries() {
+    let mut archive = ZipArchive::new(Vec::new());
+    for &compressor in EMPTY_ENTRY_METHODS {
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(
+                &empty_entry_name(compressor),
+                &mut std::io::empty(),
+                &options,
+            )
+            .unwrap();
+    }
+    let (_, out) = archive.finalize().unwrap();
+    assert_empty_entries(&out);
+}
+
+#[test]
+fn archive_local_header_fields() {
+    let mut archive = ZipArchive::new(Vec::new());
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        let options = FileOptions::default().compression_method(compressor);
+        archive
+            .append_file(
+                &format!("{}.txt", compressor),
+                &mut b"Some string data".as_ref(),
+                &options,
+            )
+            .unwrap();
+    }
+    let (_, out) = archive.finalize().unwrap();
+
+    for compressor in [CompressionMethod::Store(), CompressionMethod::Deflate()] {
+        assert_local_header_fields(&out, &format!("{}.txt", compressor), true);
+    }
+}
+
+#[test]
+fn archive_estimate_central_directory_size() {
+    for options in [
+        ArchiveOptions::default(),
+        ArchiveOptions::default().zip64(true),
+    ] {
+        let mut archive = ZipArchive::with_options(Vec::new(), options);
+        assert_eq!(archive.estimate_central_directory_size(), 22);
+
+        for i in 0..20 {
+            let name = format!("{}/file{}.txt", "dir".repeat(i % 5 + 1), i);
+            archive
+                .append_file(
+                    &name,
+                    &mut b"Some string data".as_ref(),
+                    &FileOptions::default(),
+                )
+                .unwrap();
+        }
+        archive.set_archive_comment("archive comment");
+
+        let estimate = archive.estimate_central_directory_size();
+        let before = archive.get_archive_size().unwrap();
+        let (archive_size, _) = archive.finalize().unwrap();
+        assert_eq!(archive_size - before, estimate);
+    }
+}
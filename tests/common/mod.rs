@@ -1,4 +1,5 @@
 use rill::compression::CompressionMethod;
+use rill::types::ArchiveFileEntry;
 
 pub mod std;
 pub mod tokio;
@@ -9,3 +10,283 @@ pub const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
 pub fn out_file_name(compressor: CompressionMethod, test_id: &str) -> String {
     ["test_", &compressor.to_string(), "_", test_id, ".zip"].join("")
 }
+
+/// Check that an archive holds no entry: nothing but an end of central directory record.
+#[allow(dead_code)]
+pub fn assert_empty_archive(archive: &[u8]) {
+    assert_eq!(archive.len(), 22);
+    assert_eq!(&archive[..4], &[0x50, 0x4b, 0x05, 0x06]);
+    // entries on this disk, total entries
+    assert_eq!(&archive[8..12], &[0, 0, 0, 0]);
+    // central directory size and offset
+    assert_eq!(&archive[12..20], &[0; 8]);
+    assert!(rill::uncompress::ArchiveReader::new(::std::io::Cursor::new(archive)).is_ok());
+}
+
+/// Little-endian reads of the header fields, panicking past the end of `bytes`.
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Data of the block `id` of an extra field, if any.
+#[allow(dead_code)]
+pub fn extra_field_block(extra_field: &[u8], id: u16) -> Option<&[u8]> {
+    let mut rest = extra_field;
+    while rest.len() >= 4 {
+        let size = u16_at(rest, 2) as usize;
+        let (block, next) = rest[4..].split_at(size);
+        if u16_at(rest, 0) == id {
+            return Some(block);
+        }
+        rest = next;
+    }
+    None
+}
+
+/// Local file header, the fields as written, a saturated size not resolved from the ZIP64 extra
+/// field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalHeader {
+    pub version_needed: u16,
+    pub general_purpose_flags: u16,
+    pub compression_method: u16,
+    pub last_mod_file_time: u16,
+    pub last_mod_file_date: u16,
+    pub crc32: u32,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub file_name: Vec<u8>,
+    pub extra_field: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl LocalHeader {
+    /// Parse the local header starting at `offset` of `archive`.
+    pub fn parse(archive: &[u8], offset: usize) -> Self {
+        let header = &archive[offset..];
+        assert_eq!(u32_at(header, 0), 0x04034b50, "local header signature");
+        let file_name_length = u16_at(header, 26) as usize;
+        let extra_field_length = u16_at(header, 28) as usize;
+        let extra_field_offset = 30 + file_name_length;
+        LocalHeader {
+            version_needed: u16_at(header, 4),
+            general_purpose_flags: u16_at(header, 6),
+            compression_method: u16_at(header, 8),
+            last_mod_file_time: u16_at(header, 10),
+            last_mod_file_date: u16_at(header, 12),
+            crc32: u32_at(header, 14),
+            compressed_size: u32_at(header, 18),
+            uncompressed_size: u32_at(header, 22),
+            file_name: header[30..extra_field_offset].to_vec(),
+            extra_field: header[extra_field_offset..extra_field_offset + extra_field_length]
+                .to_vec(),
+        }
+    }
+
+    /// Size of the header, the payload starts right after.
+    pub fn size(&self) -> usize {
+        30 + self.file_name.len() + self.extra_field.len()
+    }
+
+    pub fn extra_field_block(&self, id: u16) -> Option<&[u8]> {
+        extra_field_block(&self.extra_field, id)
+    }
+}
+
+/// Central directory record, the fields as written, a saturated size or offset not resolved from
+/// the ZIP64 extra field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CentralDirectoryRecord {
+    pub version_made_by: u16,
+    pub version_needed: u16,
+    pub general_purpose_flags: u16,
+    pub compression_method: u16,
+    pub last_mod_file_time: u16,
+    pub last_mod_file_date: u16,
+    pub crc32: u32,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub file_disk_number: u16,
+    pub internal_file_attributes: u16,
+    pub external_file_attributes: u32,
+    pub local_header_offset: u32,
+    pub file_name: Vec<u8>,
+    pub extra_field: Vec<u8>,
+    pub file_comment: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl CentralDirectoryRecord {
+    /// Parse the central directory record starting at `offset` of `archive`.
+    pub fn parse(archive: &[u8], offset: usize) -> Self {
+        let record = &archive[offset..];
+        assert_eq!(
+            u32_at(record, 0),
+            0x02014b50,
+            "central directory record signature"
+        );
+        let extra_field_offset = 46 + u16_at(record, 28) as usize;
+        let comment_offset = extra_field_offset + u16_at(record, 30) as usize;
+        let end = comment_offset + u16_at(record, 32) as usize;
+        CentralDirectoryRecord {
+            version_made_by: u16_at(record, 4),
+            version_needed: u16_at(record, 6),
+            general_purpose_flags: u16_at(record, 8),
+            compression_method: u16_at(record, 10),
+            last_mod_file_time: u16_at(record, 12),
+            last_mod_file_date: u16_at(record, 14),
+            crc32: u32_at(record, 16),
+            compressed_size: u32_at(record, 20),
+            uncompressed_size: u32_at(record, 24),
+            file_disk_number: u16_at(record, 34),
+            internal_file_attributes: u16_at(record, 36),
+            external_file_attributes: u32_at(record, 38),
+            local_header_offset: u32_at(record, 42),
+            file_name: record[46..extra_field_offset].to_vec(),
+            extra_field: record[extra_field_offset..comment_offset].to_vec(),
+            file_comment: record[comment_offset..end].to_vec(),
+        }
+    }
+
+    /// Size of the record, the next one starts right after.
+    pub fn size(&self) -> usize {
+        46 + self.file_name.len() + self.extra_field.len() + self.file_comment.len()
+    }
+
+    pub fn extra_field_block(&self, id: u16) -> Option<&[u8]> {
+        extra_field_block(&self.extra_field, id)
+    }
+
+    /// Local header of the entry, for an archive whose offsets start at the beginning of
+    /// `archive`.
+    pub fn local_header(&self, archive: &[u8]) -> LocalHeader {
+        LocalHeader::parse(archive, self.local_header_offset as usize)
+    }
+}
+
+/// Central directory records of an archive, with their position. They are found by signature, so
+/// `archive` can be any part of an archive holding its central directory.
+#[allow(dead_code)]
+pub fn central_directory_records(archive: &[u8]) -> Vec<(usize, CentralDirectoryRecord)> {
+    archive
+        .windows(4)
+        .enumerate()
+        .filter(|(_, w)| *w == [0x50, 0x4b, 0x01, 0x02])
+        .map(|(position, _)| (position, CentralDirectoryRecord::parse(archive, position)))
+        .collect()
+}
+
+/// Names of the central directory records of an archive, in order.
+#[allow(dead_code)]
+pub fn central_directory_names(archive: &[u8]) -> Vec<String> {
+    central_directory_records(archive)
+        .into_iter()
+        .map(|(_, record)| String::from_utf8(record.file_name).unwrap())
+        .collect()
+}
+
+/// Central directory record of the entry `name`.
+#[allow(dead_code)]
+pub fn central_directory_entry(archive: &[u8], name: &str) -> CentralDirectoryRecord {
+    central_directory_records(archive)
+        .into_iter()
+        .map(|(_, record)| record)
+        .find(|record| record.file_name == name.as_bytes())
+        .unwrap_or_else(|| panic!("entry {} not found", name))
+}
+
+/// Central directory record of the entry `name`, read back by the crate reader.
+#[allow(dead_code)]
+pub fn archive_entry(archive: &[u8], name: &str) -> ArchiveFileEntry {
+    rill::uncompress::raw_entries(archive)
+        .unwrap()
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .find(|entry| entry.file_name_as_bytes == name.as_bytes())
+        .unwrap_or_else(|| panic!("entry {} not found", name))
+}
+
+/// Compression methods of the empty entries tests, the ones whose feature is enabled.
+#[allow(dead_code)]
+pub const EMPTY_ENTRY_METHODS: &[CompressionMethod] = &[
+    CompressionMethod::Store(),
+    CompressionMethod::Deflate(),
+    #[cfg(feature = "zstd")]
+    CompressionMethod::Zstd(),
+];
+
+/// Name of the empty entry written with `compressor` by the empty entries tests.
+#[allow(dead_code)]
+pub fn empty_entry_name(compressor: CompressionMethod) -> String {
+    format!("empty_{}.txt", compressor)
+}
+
+/// Check that the entries of `EMPTY_ENTRY_METHODS`, named by `empty_entry_name`, are read back
+/// by the crate reader as empty files written with their method.
+#[allow(dead_code)]
+pub fn assert_empty_entries(archive: &[u8]) {
+    for &compressor in EMPTY_ENTRY_METHODS {
+        let name = empty_entry_name(compressor);
+        assert_empty_entry(archive, &name);
+        let entry = archive_entry(archive, &name);
+        assert_eq!(entry.compression_method, compressor.zip_code(), "{}", name);
+    }
+}
+
+/// Check that the entry `name` of an archive is an empty file: CRC and uncompressed size 0 in the
+/// central directory, and nothing once extracted.
+#[allow(dead_code)]
+pub fn assert_empty_entry(archive: &[u8], name: &str) {
+    let entry = archive_entry(archive, name);
+    assert_eq!(entry.crc32, 0, "central directory CRC of {}", name);
+    assert_eq!(entry.uncompressed_size, 0, "uncompressed size of {}", name);
+
+    let content = rill::uncompress::extract_entry_to_vec(archive, name).unwrap();
+    assert!(content.is_empty(), "content of {}", name);
+}
+
+/// Check where the CRC and sizes of an entry are recorded. A streamed entry has bit 3 set, zero
+/// CRC and sizes in its local header and the values of the central directory in its data
+/// descriptor; otherwise the local header holds them and no data descriptor follows.
+#[allow(dead_code)]
+pub fn assert_local_header_fields(archive: &[u8], name: &str, streamed: bool) {
+    let entry = central_directory_entry(archive, name);
+    assert_ne!(entry.crc32, 0, "central directory CRC");
+    let central_fields = (entry.crc32, entry.compressed_size, entry.uncompressed_size);
+
+    let header = entry.local_header(archive);
+    let header_fields = (
+        header.crc32,
+        header.compressed_size,
+        header.uncompressed_size,
+    );
+    let descriptor = &archive
+        [entry.local_header_offset as usize + header.size() + entry.compressed_size as usize..];
+    let has_descriptor = u32_at(descriptor, 0) == 0x08074b50;
+
+    let data_descriptor_flag = header.general_purpose_flags & 0x08 != 0;
+    assert_eq!(
+        data_descriptor_flag, streamed,
+        "bit 3 of the general purpose flag"
+    );
+    if streamed {
+        assert_eq!(header_fields, (0, 0, 0), "local header CRC and sizes");
+        assert!(has_descriptor, "data descriptor signature");
+        assert_eq!(
+            (
+                u32_at(descriptor, 4),
+                u32_at(descriptor, 8),
+                u32_at(descriptor, 12)
+            ),
+            central_fields,
+            "data descriptor CRC and sizes"
+        );
+    } else {
+        assert_eq!(header_fields, central_fields, "local header CRC and sizes");
+        assert!(!has_descriptor, "no data descriptor");
+    }
+}
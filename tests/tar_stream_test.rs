@@ -0,0 +1,83 @@
+#![cfg(feature = "tar")]
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use rill::archive::FileOptions;
+use rill::tar::{to_tar_stream, TarEntry};
+use tokio::io::AsyncReadExt;
+
+const CONTENT: &[u8] = b"Some string data, in a TAR stream.";
+
+fn tar(args: &[&str], archive: &[u8]) -> String {
+    let mut child = Command::new("tar")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(archive).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "tar {:?} failed", args);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[tokio::test]
+async fn tar_stream_listed_by_tar() {
+    let long_name = format!("{}.txt", "n".repeat(120));
+    let long_path = format!("{}/file.txt", "d".repeat(120));
+    let script_options = FileOptions::default()
+        .unix_permissions(0o755)
+        .with_unix_timestamps(Some(1_700_000_000), None, None);
+
+    let entries = vec![
+        TarEntry::new("file.txt", Box::new(CONTENT), CONTENT.len() as u64),
+        TarEntry::with_options(
+            "bin/run.sh",
+            Box::new(CONTENT),
+            CONTENT.len() as u64,
+            &script_options,
+        ),
+        TarEntry::new("empty.txt", Box::new(tokio::io::empty()), 0),
+        TarEntry::new(&long_name, Box::new(CONTENT), CONTENT.len() as u64),
+        TarEntry::new(&long_path, Box::new(CONTENT), CONTENT.len() as u64),
+    ];
+
+    let mut archive = Vec::new();
+    to_tar_stream(entries)
+        .read_to_end(&mut archive)
+        .await
+        .unwrap();
+    assert_eq!(archive.len() % 512, 0);
+
+    let listing = tar(&["tvf", "-"], &archive);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), 5, "{}", listing);
+    for (line, (mode, size, name)) in lines.iter().zip([
+        ("-rw-r--r--", CONTENT.len(), "file.txt"),
+        ("-rwxr-xr-x", CONTENT.len(), "bin/run.sh"),
+        ("-rw-r--r--", 0, "empty.txt"),
+        ("-rw-r--r--", CONTENT.len(), long_name.as_str()),
+        ("-rw-r--r--", CONTENT.len(), long_path.as_str()),
+    ]) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert_eq!(fields[0], mode, "{}", line);
+        assert_eq!(fields[2], size.to_string(), "{}", line);
+        assert_eq!(*fields.last().unwrap(), name, "{}", line);
+    }
+
+    for name in ["file.txt", "bin/run.sh", &long_name, &long_path] {
+        assert_eq!(tar(&["xOf", "-", name], &archive).as_bytes(), CONTENT);
+    }
+}
+
+#[tokio::test]
+async fn tar_stream_short_payload() {
+    let entries = vec![TarEntry::new("file.txt", Box::new(CONTENT), 100)];
+
+    let error = to_tar_stream(entries)
+        .read_to_end(&mut Vec::new())
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+}